@@ -366,6 +366,16 @@ impl<T: BeaconChainTypes> BeaconChain<T> {
         self.canonical_head.read().clone()
     }
 
+    /// Calls `f` with the head block and state, without cloning them.
+    ///
+    /// The head cannot change whilst `f` runs, so `f` should return promptly.
+    pub fn with_head<U, F>(&self, f: F) -> U
+    where
+        F: FnOnce(&CheckPoint<T::EthSpec>) -> U,
+    {
+        f(&self.canonical_head.read())
+    }
+
     /// Returns the `BeaconState` at the given slot.
     ///
     ///  Returns `None` when the state is not found in the database or there is an error skipping
@@ -632,6 +642,18 @@ impl<T: BeaconChainTypes> BeaconChain<T> {
         })
     }
 
+    /// Returns the attestation in the operation pool for `data` with the most signers, for
+    /// publication by an aggregator.
+    pub fn get_aggregate_attestation(
+        &self,
+        data: &AttestationData,
+    ) -> Option<Attestation<T::EthSpec>> {
+        self.with_head(|head| {
+            self.op_pool
+                .get_aggregate_attestation(data, &head.beacon_state, &self.spec)
+        })
+    }
+
     /// Accept a new, potentially invalid attestation from the network.
     ///
     /// If valid, the attestation is added to `self.op_pool` and `self.fork_choice`.
@@ -1266,16 +1288,19 @@ impl<T: BeaconChainTypes> BeaconChain<T> {
     ///
     /// The produced block will not be inherently valid, it must be signed by a block producer.
     /// Block signing is out of the scope of this function and should be done by a separate program.
+    ///
+    /// If `graffiti` is `None`, the default `GRAFFITI` is used.
     pub fn produce_block(
         &self,
         randao_reveal: Signature,
         slot: Slot,
+        graffiti: Option<[u8; 32]>,
     ) -> Result<BeaconInfo<T::EthSpec>, BlockProductionError> {
         let state = self
             .state_at_slot(slot - 1)
             .map_err(|_| BlockProductionError::UnableToProduceAtSlot(slot))?;
 
-        self.produce_block_on_state(state.clone(), slot, randao_reveal)
+        self.produce_block_on_state(state.clone(), slot, randao_reveal, graffiti)
     }
 
     /// Produce a block for some `slot` upon the given `state`.
@@ -1285,12 +1310,13 @@ impl<T: BeaconChainTypes> BeaconChain<T> {
     /// non-current slots.
     ///
     /// The given state will be advanced to the given `produce_at_slot`, then a block will be
-    /// produced at that slot height.
+    /// produced at that slot height. If `graffiti` is `None`, the default `GRAFFITI` is used.
     pub fn produce_block_on_state(
         &self,
         mut state: BeaconState<T::EthSpec>,
         produce_at_slot: Slot,
         randao_reveal: Signature,
+        graffiti: Option<[u8; 32]>,
     ) -> Result<BeaconInfo<T::EthSpec>, BlockProductionError> {
         metrics::inc_counter(&metrics::BLOCK_PRODUCTION_REQUESTS);
         let timer = metrics::start_timer(&metrics::BLOCK_PRODUCTION_TIMES);
@@ -1310,8 +1336,11 @@ impl<T: BeaconChainTypes> BeaconChain<T> {
             state.latest_block_header.canonical_root()
         };
 
-        let mut graffiti: [u8; 32] = [0; 32];
-        graffiti.copy_from_slice(GRAFFITI.as_bytes());
+        let graffiti = graffiti.unwrap_or_else(|| {
+            let mut graffiti: [u8; 32] = [0; 32];
+            graffiti.copy_from_slice(GRAFFITI.as_bytes());
+            graffiti
+        });
 
         let (proposer_slashings, attester_slashings) =
             self.op_pool.get_slashings(&state, &self.spec);
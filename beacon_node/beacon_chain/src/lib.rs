@@ -20,7 +20,7 @@ pub use self::beacon_chain::{
 pub use self::checkpoint::CheckPoint;
 pub use self::errors::{BeaconChainError, BlockProductionError};
 pub use beacon_chain_builder::BeaconChainBuilder;
-pub use eth1_chain::{Eth1ChainBackend, InteropEth1ChainBackend};
+pub use eth1_chain::{Eth1ChainBackend, Error as Eth1ChainError, InteropEth1ChainBackend};
 pub use lmd_ghost;
 pub use metrics::scrape_for_metrics;
 pub use parking_lot;
@@ -232,7 +232,7 @@ where
 
         let (mut block, state) = self
             .chain
-            .produce_block_on_state(state, slot, randao_reveal)
+            .produce_block_on_state(state, slot, randao_reveal, None)
             .expect("should produce block");
 
         block.signature = {
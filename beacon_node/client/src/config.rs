@@ -9,6 +9,10 @@ use std::sync::Mutex;
 /// The number initial validators when starting the `Minimal`.
 const TESTNET_SPEC_CONSTANTS: &str = "minimal";
 
+/// The deposit contract address of each network, by the name of its spec constants.
+const KNOWN_DEPOSIT_CONTRACTS: &[(&str, &str)] =
+    &[("mainnet", "0x00000000219ab540356cbb839cbe05303d7705fa")];
+
 /// The core configuration of a Lighthouse beacon node.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -24,6 +28,8 @@ pub struct Config {
     #[serde(skip)]
     pub beacon_chain_start_method: BeaconChainStartMethod,
     pub eth1_backend_method: Eth1BackendMethod,
+    #[serde(default)]
+    pub eth1: crate::eth1::Config,
     pub network: network::NetworkConfig,
     pub rpc: rpc::RPCConfig,
     pub rest_api: rest_api::ApiConfig,
@@ -78,7 +84,41 @@ pub enum Eth1BackendMethod {
     /// Use the mocked eth1 backend used in interop testing
     Interop,
     /// Use a web3 connection to a running Eth1 node.
-    Web3 { server: String },
+    Web3 {
+        server: String,
+        /// The address of the deposit contract whose logs are read.
+        deposit_contract: String,
+    },
+}
+
+/// Checks that `address` is the deposit contract of the network with the given
+/// `spec_constants`.
+///
+/// Returns an error if `address` is malformed, differs from the known address, or the network
+/// has no known deposit contract (e.g., a custom testnet).
+pub fn verify_deposit_contract(spec_constants: &str, address: &str) -> Result<(), String> {
+    let is_address = address.starts_with("0x")
+        && address.len() == 42
+        && address[2..].chars().all(|c| c.is_ascii_hexdigit());
+    if !is_address {
+        return Err(format!("Invalid deposit contract address: {}", address));
+    }
+
+    match KNOWN_DEPOSIT_CONTRACTS
+        .iter()
+        .find(|(network, _)| *network == spec_constants)
+    {
+        Some((_, expected)) if expected.eq_ignore_ascii_case(address) => Ok(()),
+        Some((_, expected)) => Err(format!(
+            "Deposit contract {} is not the {} deposit contract {}",
+            address, spec_constants, expected
+        )),
+        None => Err(format!(
+            "No deposit contract is known for the {} spec; use --allow-unknown-deposit-contract \
+             to use {}",
+            spec_constants, address
+        )),
+    }
 }
 
 impl Default for Eth1BackendMethod {
@@ -101,6 +141,7 @@ impl Default for Config {
             spec_constants: TESTNET_SPEC_CONSTANTS.into(),
             beacon_chain_start_method: <_>::default(),
             eth1_backend_method: <_>::default(),
+            eth1: <_>::default(),
         }
     }
 }
@@ -174,6 +215,7 @@ impl Config {
         self.rpc.apply_cli_args(args)?;
         self.rest_api.apply_cli_args(args)?;
         self.websocket_server.apply_cli_args(args)?;
+        self.eth1.apply_cli_args(args)?;
 
         if let Some(log_file) = args.value_of("logfile") {
             self.log_file = PathBuf::from(log_file);
@@ -183,3 +225,28 @@ impl Config {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAINNET_DEPOSIT_CONTRACT: &str = "0x00000000219ab540356cBB839Cbe05303d7705Fa";
+
+    #[test]
+    fn known_deposit_contract_accepted() {
+        assert_eq!(
+            verify_deposit_contract("mainnet", MAINNET_DEPOSIT_CONTRACT),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn wrong_deposit_contract_rejected() {
+        let wrong = "0x1111111111111111111111111111111111111111";
+        assert!(verify_deposit_contract("mainnet", wrong).is_err());
+        assert!(verify_deposit_contract("mainnet", "0x1234").is_err());
+        assert!(verify_deposit_contract("mainnet", &MAINNET_DEPOSIT_CONTRACT[2..]).is_err());
+        // No contract is known for the testnet specs.
+        assert!(verify_deposit_contract("minimal", MAINNET_DEPOSIT_CONTRACT).is_err());
+    }
+}
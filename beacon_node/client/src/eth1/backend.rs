@@ -0,0 +1,177 @@
+use super::{
+    update_deposit_cache, Config, DepositCache, Eth1DataCache, HttpTransport, RateLimitedFetcher,
+    UpdateThrottle, Web3DataFetcher,
+};
+use beacon_chain::parking_lot::RwLock;
+use beacon_chain::{Eth1ChainBackend, Eth1ChainError, InteropEth1ChainBackend};
+use exit_future::Exit;
+use futures::{future, Future, Stream};
+use slog::{info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::TaskExecutor;
+use tokio::timer::Interval;
+use types::{BeaconState, Deposit, Eth1Data, EthSpec};
+
+/// A cache of the blocks of an Eth1 node reached over HTTP.
+pub type HttpEth1DataCache = Eth1DataCache<RateLimitedFetcher<Web3DataFetcher<HttpTransport>>>;
+
+/// The Eth1 backend of the client's `BeaconChain`.
+pub enum ClientEth1Backend<E: EthSpec> {
+    /// Produces fake `Eth1Data`, for interop testing.
+    Interop(InteropEth1ChainBackend<E>),
+    /// Serves the `Eth1Data` of blocks cached from an Eth1 node, and the deposits made up to
+    /// the highest cached block.
+    Web3 {
+        cache: Arc<HttpEth1DataCache>,
+        deposits: Arc<RwLock<DepositCache>>,
+    },
+}
+
+impl<E: EthSpec> ClientEth1Backend<E> {
+    /// Returns a backend reading the `deposit_contract` from the Eth1 node at `server`.
+    ///
+    /// The caches are empty until updated by the backend's `updater`.
+    pub fn web3(
+        server: &str,
+        deposit_contract: String,
+        config: &Config,
+        log: slog::Logger,
+    ) -> Result<Self, String> {
+        let timeout = Duration::from_millis(config.request_timeout_millis);
+        let transport = HttpTransport::new(server, timeout)?;
+        let mut fetcher = Web3DataFetcher::new(Arc::new(transport), deposit_contract);
+        fetcher.set_memo_ttl(Duration::from_millis(config.memo_ttl_millis));
+        if config.prefer_finalized {
+            fetcher.set_prefer_finalized(config.follow_distance);
+        }
+        let fetcher = RateLimitedFetcher::new(fetcher, config.requests_per_second);
+        let mut cache = Eth1DataCache::new(
+            Arc::new(fetcher),
+            config.max_cache_len,
+            config.follow_distance,
+            log,
+        );
+        cache.set_min_confirmations(config.min_confirmations);
+        cache.set_progress_log_interval(Duration::from_millis(config.progress_log_interval_millis));
+        Ok(ClientEth1Backend::Web3 {
+            cache: Arc::new(cache),
+            deposits: Arc::new(RwLock::new(DepositCache::new())),
+        })
+    }
+
+    /// Returns the cache of a web3 backend.
+    pub fn cache(&self) -> Option<Arc<HttpEth1DataCache>> {
+        match self {
+            ClientEth1Backend::Interop(_) => None,
+            ClientEth1Backend::Web3 { cache, .. } => Some(cache.clone()),
+        }
+    }
+
+    /// Returns the updater of the caches of a web3 backend, which may be spawned once the
+    /// backend is in use.
+    pub fn updater(&self) -> Option<Eth1Updater> {
+        match self {
+            ClientEth1Backend::Interop(_) => None,
+            ClientEth1Backend::Web3 { cache, deposits } => Some(Eth1Updater {
+                cache: cache.clone(),
+                deposits: deposits.clone(),
+            }),
+        }
+    }
+}
+
+/// Updates the caches of a web3 `ClientEth1Backend`.
+pub struct Eth1Updater {
+    cache: Arc<HttpEth1DataCache>,
+    deposits: Arc<RwLock<DepositCache>>,
+}
+
+impl Eth1Updater {
+    /// Updates the caches every `interval` until `exit` fires: first the `Eth1Data` cache, then
+    /// the deposits up to its highest block. Failed updates are retried at the next interval.
+    ///
+    /// The `Eth1Data` cache is first warmed from the `snapshot` file, if it exists. With a
+    /// `throttle`, only the intervals it selects update the caches.
+    pub fn spawn(
+        self,
+        interval: Duration,
+        snapshot: Option<PathBuf>,
+        mut throttle: Option<UpdateThrottle>,
+        executor: &TaskExecutor,
+        exit: Exit,
+        log: slog::Logger,
+    ) {
+        let Eth1Updater { cache, deposits } = self;
+        let warmup = match snapshot.filter(|path| path.exists()) {
+            Some(path) => {
+                let log = log.clone();
+                future::Either::A(cache.load_from_file(&path).then(move |result| {
+                    match result {
+                        Ok(blocks) => info!(log, "Loaded the Eth1 cache"; "blocks" => blocks),
+                        Err(e) => warn!(log, "Unable to load the Eth1 cache"; "error" => e),
+                    }
+                    Ok(())
+                }))
+            }
+            None => future::Either::B(future::ok(())),
+        };
+        let updates = Interval::new(Instant::now(), interval)
+            .map_err(|_| ())
+            .filter(move |_| throttle.as_mut().map_or(true, UpdateThrottle::tick))
+            .for_each(move |_| {
+                let cache = cache.clone();
+                let deposits = deposits.clone();
+                let log = log.clone();
+                cache
+                    .update_cache()
+                    .then(move |_| match cache.last_block() {
+                        Some(last_block) => future::Either::A(
+                            update_deposit_cache(cache.fetcher(), deposits, last_block).map_err(
+                                move |e| {
+                                    warn!(
+                                        log,
+                                        "Eth1 deposit cache update failed";
+                                        "error" => format!("{:?}", e)
+                                    )
+                                },
+                            ),
+                        ),
+                        None => future::Either::B(future::ok(())),
+                    })
+                    .then(|_| Ok(()))
+            });
+        executor.spawn(exit.until(warmup.and_then(|()| updates)).map(|_| ()));
+    }
+}
+
+impl<E: EthSpec> Eth1ChainBackend<E> for ClientEth1Backend<E> {
+    fn new(server: String) -> Result<Self, Eth1ChainError> {
+        InteropEth1ChainBackend::new(server).map(ClientEth1Backend::Interop)
+    }
+
+    fn eth1_data(&self, state: &BeaconState<E>) -> Result<Eth1Data, Eth1ChainError> {
+        match self {
+            ClientEth1Backend::Interop(backend) => backend.eth1_data(state),
+            ClientEth1Backend::Web3 { cache, .. } => cache.latest_eth1_data().ok_or_else(|| {
+                Eth1ChainError::BackendError("No Eth1 blocks are cached yet".to_string())
+            }),
+        }
+    }
+
+    fn queued_deposits(&self, state: &BeaconState<E>) -> Result<Vec<Deposit>, Eth1ChainError> {
+        match self {
+            ClientEth1Backend::Interop(backend) => backend.queued_deposits(state),
+            ClientEth1Backend::Web3 { deposits, .. } => {
+                let start = state.eth1_deposit_index;
+                let count = state.eth1_data.deposit_count.saturating_sub(start);
+                deposits
+                    .read()
+                    .get_deposits(start, count, state.eth1_data.deposit_root)
+                    .map(|(deposits, _)| deposits)
+                    .map_err(|e| Eth1ChainError::BackendError(format!("{:?}", e)))
+            }
+        }
+    }
+}
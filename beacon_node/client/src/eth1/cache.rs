@@ -0,0 +1,880 @@
+use super::{fetch_eth1_data, metrics, Eth1DataFetcher, Eth1Error, FetcherFuture};
+use beacon_chain::parking_lot::RwLock;
+use futures::{future, stream, Future, Stream};
+use serde_derive::{Deserialize, Serialize};
+use slog::{error, info, warn};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use types::Eth1Data;
+
+/// The most blocks read from the Eth1 node at once when filling a range.
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+/// The most blocks read by each batch of requests in `update_cache`.
+const MAX_BATCH_SIZE: u64 = 128;
+
+/// The progress of an `update_cache`, in blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateProgress {
+    /// The blocks read so far.
+    pub scanned: u64,
+    /// The blocks to be read.
+    pub total: u64,
+}
+
+impl UpdateProgress {
+    /// Returns the percentage of the blocks read, rounded down.
+    pub fn percentage(&self) -> u64 {
+        if self.total == 0 {
+            100
+        } else {
+            self.scanned * 100 / self.total
+        }
+    }
+}
+
+/// The contents of an `Eth1DataCache`, as saved to disk.
+#[derive(Serialize, Deserialize)]
+struct CacheSnapshot {
+    last_block: Option<u64>,
+    blocks: Vec<(u64, Eth1Data)>,
+}
+
+/// The `Eth1Data` of Eth1 blocks, by block number.
+///
+/// Blocks missing from the cache are read from the `fetcher` when requested. At most `max_len`
+/// blocks are kept; beyond that, the oldest blocks are evicted first.
+///
+/// Blocks less than `follow_distance` blocks behind the head may yet be reorged out, so they are
+/// neither cached nor served.
+pub struct Eth1DataCache<F: Eth1DataFetcher> {
+    cache: Arc<RwLock<BTreeMap<u64, Eth1Data>>>,
+    fetcher: Arc<F>,
+    max_len: usize,
+    follow_distance: u64,
+    /// The fewest confirmations (the head's block number less the block's) of a served block.
+    min_confirmations: u64,
+    /// The highest block read by `update_cache`, if any.
+    last_block: Arc<RwLock<Option<u64>>>,
+    /// The progress of the current or last `update_cache`, if any.
+    progress: Arc<RwLock<Option<UpdateProgress>>>,
+    /// The least time between progress logs of an update reading more than one batch.
+    progress_log_interval: Duration,
+    log: slog::Logger,
+}
+
+impl<F: Eth1DataFetcher> Eth1DataCache<F> {
+    pub fn new(fetcher: Arc<F>, max_len: usize, follow_distance: u64, log: slog::Logger) -> Self {
+        Self {
+            cache: Arc::new(RwLock::new(BTreeMap::new())),
+            fetcher,
+            max_len,
+            follow_distance,
+            min_confirmations: 0,
+            last_block: Arc::new(RwLock::new(None)),
+            progress: Arc::new(RwLock::new(None)),
+            progress_log_interval: Duration::from_secs(10),
+            log,
+        }
+    }
+
+    /// Sets the fewest confirmations a block must have to be served by `get_eth1_data`.
+    pub fn set_min_confirmations(&mut self, min_confirmations: u64) {
+        self.min_confirmations = min_confirmations;
+    }
+
+    /// Sets the least time between progress logs of an update, e.g. the first, which reads more
+    /// than one batch of blocks.
+    pub fn set_progress_log_interval(&mut self, interval: Duration) {
+        self.progress_log_interval = interval;
+    }
+
+    /// Returns the progress of the current or last `update_cache`, if any.
+    pub fn update_progress(&self) -> Option<UpdateProgress> {
+        *self.progress.read()
+    }
+
+    /// Returns the fetcher from which blocks are read.
+    pub fn fetcher(&self) -> Arc<F> {
+        self.fetcher.clone()
+    }
+
+    /// Returns the highest block read by `update_cache`, if any.
+    pub fn last_block(&self) -> Option<u64> {
+        *self.last_block.read()
+    }
+
+    /// Returns the `Eth1Data` of the highest cached block, without querying the Eth1 node.
+    pub fn latest_eth1_data(&self) -> Option<Eth1Data> {
+        let cache = self.cache.read();
+        cache.values().next_back().cloned()
+    }
+
+    /// Reads the `Eth1Data` of each block after the `last_block` up to `follow_distance` blocks
+    /// behind the current head into the cache.
+    ///
+    /// First, cached blocks which have been reorged out of the Eth1 chain are removed, and
+    /// `last_block` is moved back to re-read them.
+    ///
+    /// The `last_block` advances as each block is inserted, so a failed update resumes from the
+    /// first block it could not read. Failures are logged, as errors if a retry will not help.
+    ///
+    /// The progress of the update is kept for `update_progress` and the metrics. An update
+    /// reading more than one batch, e.g. the first, also logs its progress as it warms the cache.
+    pub fn update_cache(&self) -> FetcherFuture<()> {
+        let cache = self.cache.clone();
+        let fetcher = self.fetcher.clone();
+        let head_fetcher = self.fetcher.clone();
+        let max_len = self.max_len;
+        let follow_distance = self.follow_distance;
+        let last_block = self.last_block.clone();
+        let progress = self.progress.clone();
+        let progress_log_interval = self.progress_log_interval;
+        let progress_log = self.log.clone();
+        let log = self.log.clone();
+        Box::new(
+            remove_reorged_blocks(
+                self.fetcher.clone(),
+                self.cache.clone(),
+                self.last_block.clone(),
+                self.log.clone(),
+            )
+            .and_then(move |()| head_fetcher.get_current_block_number())
+            .and_then(move |current_block_number| {
+                let end_block = current_block_number
+                    .checked_sub(follow_distance)
+                    .map_or(0, |follow_block| follow_block + 1);
+                // The first update reads only the blocks that fit in the cache.
+                let first_block = last_block.read().map_or_else(
+                    || end_block.saturating_sub(max_len as u64),
+                    |block_number| block_number + 1,
+                );
+                let windows: Vec<Range<u64>> = (first_block..end_block)
+                    .step_by(MAX_BATCH_SIZE as usize)
+                    .map(|start| start..(start + MAX_BATCH_SIZE).min(end_block))
+                    .collect();
+                let warmup = windows.len() > 1;
+                let mut update_progress = UpdateProgress {
+                    scanned: 0,
+                    total: end_block.saturating_sub(first_block),
+                };
+                set_progress(&progress, update_progress);
+                let started = Instant::now();
+                let mut last_logged = started;
+                stream::iter_ok(windows)
+                    .and_then(move |window| fetch_eth1_data_batch(&*fetcher, window))
+                    .for_each(move |blocks| {
+                        update_progress.scanned += blocks.len() as u64;
+                        {
+                            let mut cache = cache.write();
+                            for (block_number, eth1_data) in blocks {
+                                insert(&mut cache, block_number, eth1_data, max_len);
+                                *last_block.write() = Some(block_number);
+                            }
+                        }
+                        set_progress(&progress, update_progress);
+
+                        let done = update_progress.scanned == update_progress.total;
+                        if warmup && (done || last_logged.elapsed() >= progress_log_interval) {
+                            last_logged = Instant::now();
+                            let remaining = update_progress.total - update_progress.scanned;
+                            let eta_secs = started.elapsed().as_millis() as u64 * remaining
+                                / update_progress.scanned.max(1)
+                                / 1_000;
+                            info!(
+                                progress_log,
+                                "Eth1 cache warming up";
+                                "blocks_scanned" => update_progress.scanned,
+                                "blocks_total" => update_progress.total,
+                                "percentage" => update_progress.percentage(),
+                                "eta_secs" => eta_secs
+                            );
+                        }
+                        Ok(())
+                    })
+            })
+            .map_err(move |e| {
+                if e.is_transient() {
+                    warn!(log, "Eth1 cache update failed"; "error" => format!("{:?}", e));
+                } else {
+                    error!(
+                        log,
+                        "Eth1 cache update failed, check the eth1 node";
+                        "error" => format!("{:?}", e)
+                    );
+                }
+                e
+            }),
+        )
+    }
+
+    /// Returns the `Eth1Data` of the block `distance` blocks behind the current head, or
+    /// `follow_distance` blocks behind if `distance` is less.
+    ///
+    /// If that block has fewer than `min_confirmations` confirmations, the most recent cached
+    /// block which has enough is served instead.
+    ///
+    /// Resolves to `None` if the chain is shorter than `distance` blocks, the block is unknown
+    /// to the Eth1 node, or no cached block has enough confirmations.
+    pub fn get_eth1_data(&self, distance: u64) -> FetcherFuture<Option<Eth1Data>> {
+        let cache = self.cache.clone();
+        let fetcher = self.fetcher.clone();
+        let max_len = self.max_len;
+        let distance = distance.max(self.follow_distance);
+        let min_confirmations = self.min_confirmations;
+        Box::new(
+            self.fetcher
+                .get_current_block_number()
+                .and_then(move |current_block_number| {
+                    let block_number = match current_block_number.checked_sub(distance) {
+                        Some(block_number) => block_number,
+                        None => return future::Either::A(future::ok(None)),
+                    };
+                    if current_block_number - block_number < min_confirmations {
+                        let confirmed = current_block_number
+                            .checked_sub(min_confirmations)
+                            .and_then(|confirmed_block| {
+                                let cache = cache.read();
+                                let newest = cache.range(..=confirmed_block).next_back();
+                                newest.map(|(_, eth1_data)| eth1_data.clone())
+                            });
+                        return future::Either::A(future::ok(confirmed));
+                    }
+                    if let Some(eth1_data) = cache.read().get(&block_number) {
+                        return future::Either::A(future::ok(Some(eth1_data.clone())));
+                    }
+                    future::Either::B(fetch_eth1_data(&fetcher, block_number).map(
+                        move |eth1_data| {
+                            if let Some(eth1_data) = &eth1_data {
+                                insert(
+                                    &mut cache.write(),
+                                    block_number,
+                                    eth1_data.clone(),
+                                    max_len,
+                                );
+                            }
+                            eth1_data
+                        },
+                    ))
+                }),
+        )
+    }
+
+    /// Returns the `Eth1Data` of each block from `start` up to, but excluding, `end`.
+    ///
+    /// Blocks missing from the cache are read concurrently. Fails if any block cannot be read or
+    /// is within the follow distance of the head, rather than returning fewer blocks.
+    pub fn get_eth1_data_in_range(&self, start: u64, end: u64) -> FetcherFuture<Vec<Eth1Data>> {
+        let cache = self.cache.clone();
+        let fetcher = self.fetcher.clone();
+        let max_len = self.max_len;
+        let follow_distance = self.follow_distance;
+        Box::new(
+            self.fetcher
+                .get_current_block_number()
+                .and_then(move |current_block_number| {
+                    check_follow_distance(current_block_number, follow_distance, end)
+                })
+                .and_then(move |()| {
+                    let fetches = fetch_missing_blocks(fetcher, &cache.read(), start..end);
+                    fetches.map(move |fetched| {
+                        let mut cache = cache.write();
+                        // Taken before inserting, which may evict blocks of the range.
+                        let mut range: BTreeMap<u64, Eth1Data> = cache
+                            .range(start..end)
+                            .map(|(block_number, eth1_data)| (*block_number, eth1_data.clone()))
+                            .collect();
+                        for (block_number, eth1_data) in fetched {
+                            range.insert(block_number, eth1_data.clone());
+                            insert(&mut cache, block_number, eth1_data, max_len);
+                        }
+                        range.into_iter().map(|(_, eth1_data)| eth1_data).collect()
+                    })
+                }),
+        )
+    }
+
+    /// Reads each block from `start` up to, but excluding, `end` which is missing from the cache
+    /// into the cache, e.g. to recover blocks missed whilst the Eth1 node was unreachable.
+    /// Resolves to the number of blocks read.
+    ///
+    /// Blocks are read concurrently, as by `get_eth1_data_in_range`, and the `last_block` is
+    /// unchanged. Blocks older than the `max_len` newest cached blocks are evicted at once.
+    pub fn backfill(&self, start: u64, end: u64) -> FetcherFuture<usize> {
+        let cache = self.cache.clone();
+        let fetcher = self.fetcher.clone();
+        let max_len = self.max_len;
+        let follow_distance = self.follow_distance;
+        Box::new(
+            self.fetcher
+                .get_current_block_number()
+                .and_then(move |current_block_number| {
+                    check_follow_distance(current_block_number, follow_distance, end)
+                })
+                .and_then(move |()| {
+                    let fetches = fetch_missing_blocks(fetcher, &cache.read(), start..end);
+                    fetches.map(move |fetched| {
+                        let mut cache = cache.write();
+                        let block_count = fetched.len();
+                        for (block_number, eth1_data) in fetched {
+                            insert(&mut cache, block_number, eth1_data, max_len);
+                        }
+                        block_count
+                    })
+                }),
+        )
+    }
+
+    /// Writes the cached blocks and the `last_block` to `path`, to be read by `load_from_file`
+    /// after a restart.
+    pub fn save_to_file(&self, path: &Path) -> Result<(), String> {
+        let snapshot = CacheSnapshot {
+            last_block: *self.last_block.read(),
+            blocks: self
+                .cache
+                .read()
+                .iter()
+                .map(|(block_number, eth1_data)| (*block_number, eth1_data.clone()))
+                .collect(),
+        };
+        let file =
+            File::create(path).map_err(|e| format!("Unable to create {:?}: {:?}", path, e))?;
+        serde_json::to_writer(file, &snapshot)
+            .map_err(|e| format!("Unable to write the eth1 cache: {:?}", e))
+    }
+
+    /// Reads the blocks written by `save_to_file` into the cache, resolving to the number of
+    /// blocks kept.
+    ///
+    /// Blocks within the follow distance of the current head are discarded, as though the
+    /// chain had been reorged since, as are the oldest blocks beyond `max_len`. The `last_block`
+    /// is moved forward to that of the snapshot, unless it was discarded. Blocks which have
+    /// been reorged out since are removed by the next `update_cache`.
+    pub fn load_from_file(
+        &self,
+        path: &Path,
+    ) -> Box<dyn Future<Item = usize, Error = String> + Send> {
+        let snapshot = match read_snapshot(path) {
+            Ok(snapshot) => snapshot,
+            Err(e) => return Box::new(future::err(e)),
+        };
+        let cache = self.cache.clone();
+        let last_block = self.last_block.clone();
+        let max_len = self.max_len;
+        let follow_distance = self.follow_distance;
+        Box::new(
+            self.fetcher
+                .get_current_block_number()
+                .map_err(|e| format!("Unable to read the eth1 head: {:?}", e))
+                .map(move |current_block_number| {
+                    let follow_block = match current_block_number.checked_sub(follow_distance) {
+                        Some(follow_block) => follow_block,
+                        None => return 0,
+                    };
+                    let mut cache = cache.write();
+                    for (block_number, eth1_data) in snapshot.blocks {
+                        if block_number <= follow_block {
+                            insert(&mut cache, block_number, eth1_data, max_len);
+                        }
+                    }
+                    if let Some(snapshot_last_block) = snapshot.last_block {
+                        let mut last_block = last_block.write();
+                        let loaded = snapshot_last_block.min(follow_block);
+                        if last_block.map_or(true, |last_block| last_block < loaded) {
+                            *last_block = Some(loaded);
+                        }
+                    }
+                    cache.len()
+                }),
+        )
+    }
+
+    /// Returns the number of cached blocks.
+    pub fn len(&self) -> usize {
+        self.cache.read().len()
+    }
+
+    /// Returns `true` if no blocks are cached.
+    pub fn is_empty(&self) -> bool {
+        self.cache.read().is_empty()
+    }
+}
+
+fn read_snapshot(path: &Path) -> Result<CacheSnapshot, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {:?}: {:?}", path, e))?;
+    serde_json::from_reader(file).map_err(|e| format!("Unable to read the eth1 cache: {:?}", e))
+}
+
+/// Inserts `eth1_data` into `cache`, then evicts the lowest block numbers until at most
+/// `max_len` blocks remain.
+fn insert(
+    cache: &mut BTreeMap<u64, Eth1Data>,
+    block_number: u64,
+    eth1_data: Eth1Data,
+    max_len: usize,
+) {
+    cache.insert(block_number, eth1_data);
+    while cache.len() > max_len {
+        match cache.keys().next().cloned() {
+            Some(oldest) => cache.remove(&oldest),
+            None => break,
+        };
+    }
+}
+
+/// Records the `update_progress` of an update, in the cache and as metrics.
+fn set_progress(progress: &RwLock<Option<UpdateProgress>>, update_progress: UpdateProgress) {
+    *progress.write() = Some(update_progress);
+    metrics::set_gauge(
+        &metrics::ETH1_CACHE_UPDATE_BLOCKS_SCANNED,
+        update_progress.scanned as i64,
+    );
+    metrics::set_gauge(
+        &metrics::ETH1_CACHE_UPDATE_BLOCKS_TOTAL,
+        update_progress.total as i64,
+    );
+}
+
+/// Returns `BlockTooRecent` if any block before `end` is within the follow distance of the
+/// head.
+fn check_follow_distance(
+    current_block_number: u64,
+    follow_distance: u64,
+    end: u64,
+) -> Result<(), Eth1Error> {
+    if let Some(last_block) = end.checked_sub(1) {
+        let follow_block = current_block_number.checked_sub(follow_distance);
+        if follow_block.map_or(true, |follow_block| last_block > follow_block) {
+            return Err(Eth1Error::BlockTooRecent(last_block));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the `Eth1Data` of each block in `block_numbers` missing from `cache`, up to
+/// `MAX_CONCURRENT_FETCHES` blocks at once.
+///
+/// Fails if the Eth1 node does not know any of the blocks.
+fn fetch_missing_blocks<F: Eth1DataFetcher>(
+    fetcher: Arc<F>,
+    cache: &BTreeMap<u64, Eth1Data>,
+    block_numbers: Range<u64>,
+) -> FetcherFuture<Vec<(u64, Eth1Data)>> {
+    let missing: Vec<u64> = block_numbers
+        .filter(|block_number| !cache.contains_key(block_number))
+        .collect();
+    Box::new(
+        stream::iter_ok(missing)
+            .map(move |block_number| {
+                fetch_eth1_data(&*fetcher, block_number).and_then(move |eth1_data| {
+                    eth1_data
+                        .map(|eth1_data| (block_number, eth1_data))
+                        .ok_or_else(|| Eth1Error::UnknownBlock(block_number))
+                })
+            })
+            .buffer_unordered(MAX_CONCURRENT_FETCHES)
+            .collect(),
+    )
+}
+
+/// Reads the `Eth1Data` of each block in `block_numbers` with one batch of requests.
+///
+/// Fails if the Eth1 node does not know any of the blocks.
+fn fetch_eth1_data_batch<F: Eth1DataFetcher>(
+    fetcher: &F,
+    block_numbers: Range<u64>,
+) -> FetcherFuture<Vec<(u64, Eth1Data)>> {
+    Box::new(
+        fetcher
+            .get_eth1_data_batch(block_numbers.clone())
+            .and_then(move |blocks| {
+                block_numbers
+                    .zip(blocks)
+                    .map(|(block_number, eth1_data)| {
+                        eth1_data
+                            .map(|eth1_data| (block_number, eth1_data))
+                            .ok_or_else(|| Eth1Error::UnknownBlock(block_number))
+                    })
+                    .collect()
+            }),
+    )
+}
+
+/// Compares the hashes of cached blocks with the Eth1 node's, from the highest down, removing
+/// each block whose hash has changed.
+///
+/// If any block was removed, `last_block` is moved to the block before the lowest removed block.
+fn remove_reorged_blocks<F: Eth1DataFetcher>(
+    fetcher: Arc<F>,
+    cache: Arc<RwLock<BTreeMap<u64, Eth1Data>>>,
+    last_block: Arc<RwLock<Option<u64>>>,
+    log: slog::Logger,
+) -> FetcherFuture<()> {
+    let highest_cached = cache.read().keys().next_back().cloned();
+    let removal_cache = cache.clone();
+    let check = future::loop_fn(
+        (highest_cached, None),
+        move |(height, lowest_stale): (Option<u64>, Option<u64>)| {
+            let height = match height {
+                Some(height) => height,
+                None => return future::Either::A(future::ok(future::Loop::Break(lowest_stale))),
+            };
+            let cache = cache.clone();
+            future::Either::B(
+                fetcher
+                    .get_block_hash_by_height(height)
+                    .map(move |block_hash| {
+                        let cache = cache.read();
+                        let cached_hash = cache.get(&height).map(|eth1_data| eth1_data.block_hash);
+                        if cached_hash.is_some() && block_hash == cached_hash {
+                            future::Loop::Break(lowest_stale)
+                        } else {
+                            let lower = cache.range(..height).next_back();
+                            future::Loop::Continue((
+                                lower.map(|(block_number, _)| *block_number),
+                                Some(height),
+                            ))
+                        }
+                    }),
+            )
+        },
+    );
+    Box::new(check.map(move |lowest_stale| {
+        if let (Some(lowest_stale), Some(highest_cached)) = (lowest_stale, highest_cached) {
+            let removed = removal_cache.write().split_off(&lowest_stale);
+            let mut last_block = last_block.write();
+            if last_block.map_or(false, |last_block| last_block >= lowest_stale) {
+                *last_block = lowest_stale.checked_sub(1);
+            }
+            warn!(
+                log,
+                "Eth1 chain reorganised";
+                "depth" => highest_cached + 1 - lowest_stale,
+                "removed_blocks" => removed.len(),
+                "first_removed_block" => lowest_stale,
+            );
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth1::mock_node::{MockEth1Node, DEPOSIT_CONTRACT};
+    use crate::eth1::Web3DataFetcher;
+    use tempfile::tempdir;
+    use types::Hash256;
+
+    fn cache(
+        node: &Arc<MockEth1Node>,
+        max_len: usize,
+    ) -> Eth1DataCache<Web3DataFetcher<MockEth1Node>> {
+        following_cache(node, max_len, 0)
+    }
+
+    fn following_cache(
+        node: &Arc<MockEth1Node>,
+        max_len: usize,
+        follow_distance: u64,
+    ) -> Eth1DataCache<Web3DataFetcher<MockEth1Node>> {
+        Eth1DataCache::new(
+            Arc::new(Web3DataFetcher::new(
+                node.clone(),
+                DEPOSIT_CONTRACT.to_string(),
+            )),
+            max_len,
+            follow_distance,
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+    }
+
+    fn cached_block_numbers<F: Eth1DataFetcher>(cache: &Eth1DataCache<F>) -> Vec<u64> {
+        cache.cache.read().keys().cloned().collect()
+    }
+
+    #[test]
+    fn get_eth1_data_populates_then_reads_cache() {
+        let node = Arc::new(MockEth1Node::new(20));
+        let cache = cache(&node, 100);
+
+        assert_eq!(cache.get_eth1_data(5).wait(), Ok(Some(node.block(14))));
+        assert_eq!(cache.len(), 1);
+
+        // The second read is served from the cache, only querying the head.
+        let calls = node.calls();
+        assert_eq!(cache.get_eth1_data(5).wait(), Ok(Some(node.block(14))));
+        assert_eq!(node.calls(), calls + 1);
+
+        assert_eq!(cache.get_eth1_data(20).wait(), Ok(None));
+        assert_eq!(cache.get_eth1_data(19).wait(), Ok(Some(node.block(0))));
+    }
+
+    #[test]
+    fn oldest_blocks_evicted_beyond_max_len() {
+        let node = Arc::new(MockEth1Node::new(12));
+        let cache = cache(&node, 5);
+
+        // The first update skips blocks which would be evicted.
+        assert_eq!(cache.latest_eth1_data(), None);
+        cache.update_cache().wait().unwrap();
+        assert_eq!(node.calls(), 2);
+        assert_eq!(cached_block_numbers(&cache), vec![7, 8, 9, 10, 11]);
+        assert_eq!(cache.latest_eth1_data(), Some(node.block(11)));
+        assert_eq!(cache.cache.read().get(&11).cloned(), Some(node.block(11)));
+
+        node.push_blocks(2);
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cached_block_numbers(&cache), vec![9, 10, 11, 12, 13]);
+    }
+
+    #[test]
+    fn update_cache_resumes_from_last_block() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let cache = cache(&node, 100);
+
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.last_block(), Some(9));
+        assert_eq!(cache.len(), 10);
+
+        // Only the highest cached block, the head and a batch of the three new blocks are read.
+        node.push_blocks(3);
+        let calls = node.calls();
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.last_block(), Some(12));
+        assert_eq!(node.calls(), calls + 3);
+        assert_eq!(cached_block_numbers(&cache), (0..13).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn warmup_progress_reported_per_batch() {
+        let node = Arc::new(MockEth1Node::new(300));
+        let cache = cache(&node, 1_000);
+        assert_eq!(cache.update_progress(), None);
+
+        // The head and two of the three batches are read before the node stops responding.
+        node.set_call_limit(3);
+        assert!(cache.update_cache().wait().is_err());
+        let progress = cache.update_progress().unwrap();
+        assert_eq!(
+            progress,
+            UpdateProgress {
+                scanned: 256,
+                total: 300
+            }
+        );
+        assert_eq!(progress.percentage(), 85);
+
+        // The retry reads the blocks it had left.
+        node.set_call_limit(usize::max_value());
+        cache.update_cache().wait().unwrap();
+        assert_eq!(
+            cache.update_progress(),
+            Some(UpdateProgress {
+                scanned: 44,
+                total: 44
+            })
+        );
+        assert_eq!(cache.update_progress().unwrap().percentage(), 100);
+    }
+
+    #[test]
+    fn update_failures_are_typed_and_resumable() {
+        let node = Arc::new(MockEth1Node::new(4));
+        let cache = cache(&node, 100);
+
+        node.set_unresponsive(true);
+        let error = cache.update_cache().wait().unwrap_err();
+        assert_eq!(error, Eth1Error::RpcTimeout);
+        assert!(error.is_transient());
+        assert_eq!(cache.last_block(), None);
+
+        node.set_unresponsive(false);
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.last_block(), Some(3));
+    }
+
+    #[test]
+    fn blocks_within_follow_distance_never_cached() {
+        let node = Arc::new(MockEth1Node::new(20));
+        let cache = following_cache(&node, 100, 8);
+
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.last_block(), Some(11));
+        assert_eq!(cached_block_numbers(&cache), (0..12).collect::<Vec<_>>());
+
+        // Reads inside the follow distance are served from the follow distance.
+        assert_eq!(cache.get_eth1_data(2).wait(), Ok(Some(node.block(11))));
+        assert_eq!(cache.get_eth1_data(10).wait(), Ok(Some(node.block(9))));
+        assert_eq!(cached_block_numbers(&cache), (0..12).collect::<Vec<_>>());
+
+        // Nothing is cached whilst the chain is shorter than the follow distance.
+        let short_node = Arc::new(MockEth1Node::new(5));
+        let short_cache = following_cache(&short_node, 100, 8);
+        short_cache.update_cache().wait().unwrap();
+        assert!(short_cache.is_empty());
+        assert_eq!(short_cache.get_eth1_data(0).wait(), Ok(None));
+    }
+
+    #[test]
+    fn under_confirmed_blocks_not_served() {
+        let node = Arc::new(MockEth1Node::new(20));
+        let mut cache = cache(&node, 100);
+        cache.set_min_confirmations(5);
+
+        // Nothing with five confirmations is cached yet.
+        assert_eq!(cache.get_eth1_data(2).wait(), Ok(None));
+        assert!(cache.is_empty());
+
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.get_eth1_data(2).wait(), Ok(Some(node.block(14))));
+        assert_eq!(cache.get_eth1_data(5).wait(), Ok(Some(node.block(14))));
+        assert_eq!(cache.get_eth1_data(6).wait(), Ok(Some(node.block(13))));
+    }
+
+    #[test]
+    fn range_filled_from_cache_and_node() {
+        let node = Arc::new(MockEth1Node::new(30));
+        let cache = following_cache(&node, 100, 4);
+        cache.get_eth1_data(25).wait().unwrap();
+
+        let range = cache.get_eth1_data_in_range(2, 12).wait().unwrap();
+        assert_eq!(range.len(), 12 - 2);
+        assert_eq!(range, (2..12).map(|n| node.block(n)).collect::<Vec<_>>());
+        assert_eq!(cached_block_numbers(&cache), (2..12).collect::<Vec<_>>());
+
+        assert_eq!(cache.get_eth1_data_in_range(5, 5).wait(), Ok(vec![]));
+        assert_eq!(
+            cache.get_eth1_data_in_range(20, 27).wait(),
+            Err(Eth1Error::BlockTooRecent(26))
+        );
+        assert_eq!(
+            cache.get_eth1_data_in_range(20, 26).wait().unwrap().len(),
+            6
+        );
+    }
+
+    #[test]
+    fn reorged_blocks_evicted_and_refetched() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let cache = cache(&node, 100);
+        cache.update_cache().wait().unwrap();
+        let stale = cache.cache.read().get(&8).cloned().unwrap();
+
+        node.set_block_hash(8, Hash256::from_low_u64_be(88));
+        node.set_block_hash(9, Hash256::from_low_u64_be(99));
+        node.push_blocks(1);
+        cache.update_cache().wait().unwrap();
+
+        assert_eq!(cache.last_block(), Some(10));
+        assert_eq!(cached_block_numbers(&cache), (0..11).collect::<Vec<_>>());
+        for block_number in 0..11 {
+            assert_eq!(
+                cache.cache.read().get(&block_number),
+                Some(&node.block(block_number))
+            );
+        }
+        assert_ne!(cache.cache.read().get(&8), Some(&stale));
+
+        // Without a reorg, only the highest cached block is checked.
+        let calls = node.calls();
+        cache.update_cache().wait().unwrap();
+        assert_eq!(node.calls(), calls + 2);
+    }
+
+    #[test]
+    fn backfill_fills_gaps_without_moving_last_block() {
+        let node = Arc::new(MockEth1Node::new(30));
+        let cache = following_cache(&node, 100, 4);
+        cache.update_cache().wait().unwrap();
+        assert_eq!(cache.last_block(), Some(25));
+
+        // Blocks lost, e.g. to an outage.
+        cache
+            .cache
+            .write()
+            .retain(|block_number, _| *block_number < 5 || *block_number > 14);
+
+        let calls = node.calls();
+        assert_eq!(cache.backfill(0, 20).wait(), Ok(10));
+        // The head, then three queries of each of the ten missing blocks.
+        assert_eq!(node.calls(), calls + 1 + 30);
+        assert_eq!(cached_block_numbers(&cache), (0..26).collect::<Vec<_>>());
+        assert_eq!(cache.cache.read().get(&9), Some(&node.block(9)));
+        assert_eq!(cache.last_block(), Some(25));
+
+        assert_eq!(cache.backfill(3, 8).wait(), Ok(0));
+        assert_eq!(
+            cache.backfill(20, 27).wait(),
+            Err(Eth1Error::BlockTooRecent(26))
+        );
+        assert_eq!(cache.last_block(), Some(25));
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("eth1_cache.json");
+        let node = Arc::new(MockEth1Node::new(20));
+        let saved = following_cache(&node, 100, 2);
+        saved.update_cache().wait().unwrap();
+        saved.save_to_file(&path).unwrap();
+
+        let loaded = following_cache(&node, 100, 2);
+        assert_eq!(loaded.load_from_file(&path).wait(), Ok(18));
+        assert_eq!(loaded.last_block(), Some(17));
+        assert_eq!(cached_block_numbers(&loaded), cached_block_numbers(&saved));
+        assert_eq!(loaded.cache.read().get(&9), Some(&node.block(9)));
+
+        // The next update only reads the new blocks.
+        node.push_blocks(3);
+        let calls = node.calls();
+        loaded.update_cache().wait().unwrap();
+        assert_eq!(node.calls(), calls + 3);
+        assert_eq!(loaded.last_block(), Some(20));
+    }
+
+    #[test]
+    fn snapshot_blocks_beyond_follow_head_discarded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("eth1_cache.json");
+        let node = Arc::new(MockEth1Node::new(20));
+        let saved = cache(&node, 100);
+        saved.update_cache().wait().unwrap();
+        saved.save_to_file(&path).unwrap();
+
+        // A node which has yet to sync as far.
+        let short_node = Arc::new(MockEth1Node::new(15));
+        let loaded = following_cache(&short_node, 10, 2);
+        assert_eq!(loaded.load_from_file(&path).wait(), Ok(10));
+        assert_eq!(loaded.last_block(), Some(12));
+        assert_eq!(cached_block_numbers(&loaded), (3..13).collect::<Vec<_>>());
+
+        assert!(loaded
+            .load_from_file(&dir.path().join("missing.json"))
+            .wait()
+            .is_err());
+    }
+
+    #[test]
+    fn update_cache_batches_requests() {
+        let node = Arc::new(MockEth1Node::new(100));
+        let cache = cache(&node, 1000);
+
+        // The head, then one batch for the 100 blocks.
+        cache.update_cache().wait().unwrap();
+        assert_eq!(node.calls(), 2);
+        assert_eq!(cached_block_numbers(&cache), (0..100).collect::<Vec<_>>());
+        assert_eq!(cache.cache.read().get(&57), Some(&node.block(57)));
+
+        // The highest cached block, the head, then a batch for each window of 128 blocks.
+        node.push_blocks(200);
+        let calls = node.calls();
+        cache.update_cache().wait().unwrap();
+        assert_eq!(node.calls(), calls + 4);
+        assert_eq!(cache.last_block(), Some(299));
+    }
+}
@@ -0,0 +1,124 @@
+use clap::ArgMatches;
+use serde_derive::{Deserialize, Serialize};
+
+/// Configuration of the web3 Eth1 backend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Blocks less than this many blocks behind the Eth1 head are neither cached nor voted for.
+    pub follow_distance: u64,
+    /// The most blocks kept in the cache.
+    pub max_cache_len: usize,
+    /// The fewest confirmations of a block served from the cache.
+    pub min_confirmations: u64,
+    /// Read the latest deposit root and count at the `finalized` block, where supported.
+    pub prefer_finalized: bool,
+    /// The time for which identical requests to the Eth1 node are answered from memory.
+    pub memo_ttl_millis: u64,
+    /// The most requests sent to the Eth1 node each second.
+    pub requests_per_second: u32,
+    /// The time between updates of the cache.
+    pub update_interval_millis: u64,
+    /// The time to wait for each response of the Eth1 node.
+    pub request_timeout_millis: u64,
+    /// The least time between progress logs while the cache warms up.
+    pub progress_log_interval_millis: u64,
+    /// If set, the cache is updated each interval only while one of these validators proposes
+    /// within `proposal_lookahead_epochs` epochs, and otherwise each `heartbeat_interval_millis`.
+    pub proposer_indices: Option<Vec<usize>>,
+    /// The epochs after the current epoch in which a proposal is near.
+    pub proposal_lookahead_epochs: u64,
+    /// The time between updates of the cache while no proposal is near.
+    pub heartbeat_interval_millis: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            follow_distance: 1024,
+            max_cache_len: 2048,
+            min_confirmations: 0,
+            prefer_finalized: false,
+            memo_ttl_millis: 500,
+            requests_per_second: 50,
+            update_interval_millis: 7_000,
+            request_timeout_millis: 10_000,
+            progress_log_interval_millis: 10_000,
+            proposer_indices: None,
+            proposal_lookahead_epochs: 2,
+            heartbeat_interval_millis: 600_000,
+        }
+    }
+}
+
+impl Config {
+    pub fn apply_cli_args(&mut self, args: &ArgMatches) -> Result<(), &'static str> {
+        if let Some(follow_distance) = args.value_of("eth1-follow-distance") {
+            self.follow_distance = follow_distance
+                .parse::<u64>()
+                .map_err(|_| "eth1-follow-distance is not a valid u64.")?;
+        }
+
+        if let Some(max_cache_len) = args.value_of("eth1-cache-len") {
+            self.max_cache_len = max_cache_len
+                .parse::<usize>()
+                .map_err(|_| "eth1-cache-len is not a valid usize.")?;
+        }
+
+        if let Some(min_confirmations) = args.value_of("eth1-min-confirmations") {
+            self.min_confirmations = min_confirmations
+                .parse::<u64>()
+                .map_err(|_| "eth1-min-confirmations is not a valid u64.")?;
+        }
+
+        if args.is_present("eth1-prefer-finalized") {
+            self.prefer_finalized = true;
+        }
+
+        if let Some(memo_ttl_millis) = args.value_of("eth1-memo-ttl") {
+            self.memo_ttl_millis = memo_ttl_millis
+                .parse::<u64>()
+                .map_err(|_| "eth1-memo-ttl is not a valid u64.")?;
+        }
+
+        if let Some(requests_per_second) = args.value_of("eth1-requests-per-second") {
+            self.requests_per_second = requests_per_second
+                .parse::<u32>()
+                .map_err(|_| "eth1-requests-per-second is not a valid u32.")?;
+        }
+
+        if let Some(interval) = args.value_of("eth1-progress-log-interval") {
+            self.progress_log_interval_millis = interval
+                .parse::<u64>()
+                .map_err(|_| "eth1-progress-log-interval is not a valid u64.")?;
+        }
+
+        if let Some(indices) = args.value_of("eth1-update-near-proposals-of") {
+            let indices = indices
+                .split(',')
+                .map(|index| index.trim().parse::<usize>())
+                .collect::<Result<_, _>>()
+                .map_err(|_| "eth1-update-near-proposals-of is not a list of validator indices.")?;
+            self.proposer_indices = Some(indices);
+        }
+
+        if let Some(epochs) = args.value_of("eth1-proposal-lookahead") {
+            self.proposal_lookahead_epochs = epochs
+                .parse::<u64>()
+                .map_err(|_| "eth1-proposal-lookahead is not a valid u64.")?;
+        }
+
+        if let Some(interval) = args.value_of("eth1-heartbeat-interval") {
+            self.heartbeat_interval_millis = interval
+                .parse::<u64>()
+                .map_err(|_| "eth1-heartbeat-interval is not a valid u64.")?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of update intervals in each heartbeat interval, at least one.
+    pub fn heartbeat_ticks(&self) -> u64 {
+        (self.heartbeat_interval_millis / self.update_interval_millis.max(1)).max(1)
+    }
+}
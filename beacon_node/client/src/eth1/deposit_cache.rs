@@ -0,0 +1,344 @@
+use super::{DepositLog, Eth1DataFetcher, Eth1Error, FetcherFuture};
+use beacon_chain::parking_lot::RwLock;
+use eth2_hashing::hash;
+use futures::{future, Future};
+use merkle_proof::MerkleTree;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tree_hash::TreeHash;
+use types::{Deposit, Hash256};
+
+/// The depth of the deposit contract's merkle tree, `spec.deposit_contract_tree_depth`.
+const DEPOSIT_TREE_DEPTH: usize = 32;
+
+/// The most blocks whose logs are read by each request of `update_deposit_cache`.
+const MAX_LOG_BLOCKS: u64 = 1_000;
+
+#[derive(Debug, PartialEq)]
+pub enum DepositCacheError {
+    /// A deposit with the given index has already been inserted.
+    DuplicateDeposit(u64),
+    /// A deposit was skipped; `expected` is the index of the next deposit.
+    NonConsecutiveDeposit { index: u64, expected: u64 },
+    /// No number of deposits has the given deposit root.
+    UnknownDepositRoot(Hash256),
+    /// The deposits requested are beyond those of the deposit root.
+    InsufficientDeposits { end: u64, deposit_count: u64 },
+}
+
+/// The deposits made to the deposit contract, in index order, and their merkle tree.
+///
+/// The tree is built incrementally, as by the deposit contract, keeping the deposit root after
+/// each deposit so that proofs may be made against any of them.
+pub struct DepositCache {
+    logs: Vec<DepositLog>,
+    leaves: Vec<Hash256>,
+    /// The left siblings of the path to the next leaf, by height.
+    branch: Vec<Hash256>,
+    /// The root of a tree of zero leaves of each height.
+    zero_hashes: Vec<Hash256>,
+    /// The number of deposits in the tree, by deposit root.
+    deposit_counts: HashMap<Hash256, u64>,
+    /// The highest block whose logs have been read, if any.
+    last_block: Option<u64>,
+}
+
+impl Default for DepositCache {
+    fn default() -> Self {
+        let mut zero_hashes = vec![Hash256::zero()];
+        for height in 0..DEPOSIT_TREE_DEPTH {
+            zero_hashes.push(hash_concat(zero_hashes[height], zero_hashes[height]));
+        }
+        let mut cache = Self {
+            logs: vec![],
+            leaves: vec![],
+            branch: vec![Hash256::zero(); DEPOSIT_TREE_DEPTH],
+            zero_hashes,
+            deposit_counts: HashMap::new(),
+            last_block: None,
+        };
+        let empty_root = cache.deposit_root();
+        cache.deposit_counts.insert(empty_root, 0);
+        cache
+    }
+}
+
+impl DepositCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts the next deposit into the tree.
+    ///
+    /// Fails if the deposit is not the next by index, e.g. if it has already been inserted.
+    pub fn insert_log(&mut self, log: DepositLog) -> Result<(), DepositCacheError> {
+        let expected = self.logs.len() as u64;
+        if log.index < expected {
+            return Err(DepositCacheError::DuplicateDeposit(log.index));
+        } else if log.index > expected {
+            return Err(DepositCacheError::NonConsecutiveDeposit {
+                index: log.index,
+                expected,
+            });
+        }
+
+        let leaf = Hash256::from_slice(&log.deposit_data.tree_hash_root());
+        self.leaves.push(leaf);
+        self.logs.push(log);
+
+        let mut node = leaf;
+        let mut size = self.leaves.len();
+        for height in 0..DEPOSIT_TREE_DEPTH {
+            if size & 1 == 1 {
+                self.branch[height] = node;
+                break;
+            }
+            node = hash_concat(self.branch[height], node);
+            size /= 2;
+        }
+
+        let deposit_root = self.deposit_root();
+        self.deposit_counts
+            .insert(deposit_root, self.leaves.len() as u64);
+        Ok(())
+    }
+
+    /// Returns the deposit root of the deposits inserted, as returned by the deposit contract's
+    /// `get_deposit_root()`: the root of the tree with the number of deposits mixed in.
+    pub fn deposit_root(&self) -> Hash256 {
+        let mut node = Hash256::zero();
+        let mut size = self.leaves.len();
+        for height in 0..DEPOSIT_TREE_DEPTH {
+            node = if size & 1 == 1 {
+                hash_concat(self.branch[height], node)
+            } else {
+                hash_concat(node, self.zero_hashes[height])
+            };
+            size /= 2;
+        }
+        hash_concat(node, length_mixin(self.leaves.len()))
+    }
+
+    /// Returns `count` deposits from index `start`, each with a proof of its inclusion in the
+    /// tree with the `deposit_root`, and the leaf of each deposit.
+    ///
+    /// Fails if no number of deposits has the `deposit_root`, or if the deposits requested are
+    /// not all in its tree.
+    pub fn get_deposits(
+        &self,
+        start: u64,
+        count: u64,
+        deposit_root: Hash256,
+    ) -> Result<(Vec<Deposit>, Vec<Hash256>), DepositCacheError> {
+        let deposit_count = *self
+            .deposit_counts
+            .get(&deposit_root)
+            .ok_or_else(|| DepositCacheError::UnknownDepositRoot(deposit_root))?;
+        let end = start.saturating_add(count);
+        if end > deposit_count {
+            return Err(DepositCacheError::InsufficientDeposits { end, deposit_count });
+        }
+
+        let tree = MerkleTree::create(&self.leaves[..deposit_count as usize], DEPOSIT_TREE_DEPTH);
+        let deposits = (start..end)
+            .map(|index| {
+                let (_, mut proof) = tree.generate_proof(index as usize, DEPOSIT_TREE_DEPTH);
+                proof.push(length_mixin(deposit_count as usize));
+                Deposit {
+                    proof: proof.into(),
+                    data: self.logs[index as usize].deposit_data.clone(),
+                }
+            })
+            .collect();
+        let leaves = self.leaves[start as usize..end as usize].to_vec();
+        Ok((deposits, leaves))
+    }
+
+    /// Returns the number of deposits inserted.
+    pub fn len(&self) -> usize {
+        self.logs.len()
+    }
+
+    /// Returns `true` if no deposits have been inserted.
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_empty()
+    }
+
+    /// Returns the highest block whose logs have been read by `update_deposit_cache`, if any.
+    pub fn last_block(&self) -> Option<u64> {
+        self.last_block
+    }
+}
+
+/// Reads the deposits made in each block after the `last_block` of the `cache`, up to and
+/// including `to_block`, into the `cache`.
+///
+/// The logs are read `MAX_LOG_BLOCKS` blocks at a time, so that a failed update loses little.
+pub fn update_deposit_cache<F: Eth1DataFetcher>(
+    fetcher: Arc<F>,
+    cache: Arc<RwLock<DepositCache>>,
+    to_block: u64,
+) -> FetcherFuture<()> {
+    Box::new(future::loop_fn((), move |()| {
+        let from_block = cache.read().last_block.map_or(0, |block| block + 1);
+        if from_block > to_block {
+            return future::Either::A(future::ok(future::Loop::Break(())));
+        }
+        let chunk_end = to_block.min(from_block + MAX_LOG_BLOCKS - 1);
+        let cache = cache.clone();
+        future::Either::B(
+            fetcher
+                .get_deposit_logs_in_range(from_block, chunk_end)
+                .and_then(move |logs| {
+                    let mut cache = cache.write();
+                    // Checked first, so that a failed chunk leaves the cache unchanged.
+                    let next_index = cache.len() as u64;
+                    let in_order = logs
+                        .iter()
+                        .zip(next_index..)
+                        .all(|(log, index)| log.index == index);
+                    if !in_order {
+                        return Err(Eth1Error::DecodeError(format!(
+                            "Deposit logs of blocks {} to {} are not in index order",
+                            from_block, chunk_end
+                        )));
+                    }
+                    for log in logs {
+                        cache.insert_log(log).map_err(|e| {
+                            Eth1Error::DecodeError(format!("Invalid deposit log: {:?}", e))
+                        })?;
+                    }
+                    cache.last_block = Some(chunk_end);
+                    Ok(future::Loop::Continue(()))
+                }),
+        )
+    }))
+}
+
+/// Returns the hash of `left` followed by `right`.
+fn hash_concat(left: Hash256, right: Hash256) -> Hash256 {
+    let mut preimage = left.as_bytes().to_vec();
+    preimage.extend_from_slice(right.as_bytes());
+    Hash256::from_slice(&hash(&preimage))
+}
+
+/// Returns the number of deposits as the little-endian 32 bytes mixed into the deposit root.
+fn length_mixin(deposit_count: usize) -> Hash256 {
+    let mut bytes = (deposit_count as u64).to_le_bytes().to_vec();
+    bytes.resize(32, 0);
+    Hash256::from_slice(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth1::mock_node::{deposit_data, MockEth1Node, DEPOSIT_CONTRACT};
+    use crate::eth1::Web3DataFetcher;
+    use merkle_proof::verify_merkle_proof;
+
+    fn deposit_log(index: u64) -> DepositLog {
+        DepositLog {
+            deposit_data: deposit_data(index),
+            block_number: index * 3,
+            index,
+        }
+    }
+
+    #[test]
+    fn proofs_verify_against_each_deposit_root() {
+        let mut cache = DepositCache::new();
+        let mut deposit_roots = vec![cache.deposit_root()];
+        for index in 0..7 {
+            cache.insert_log(deposit_log(index)).unwrap();
+            deposit_roots.push(cache.deposit_root());
+        }
+        assert_eq!(cache.len(), 7);
+
+        // The incremental tree has the root of the whole tree.
+        let tree = MerkleTree::create(&cache.leaves, DEPOSIT_TREE_DEPTH);
+        assert_eq!(
+            cache.deposit_root(),
+            hash_concat(tree.hash(), length_mixin(7))
+        );
+
+        for (deposit_count, deposit_root) in deposit_roots.iter().enumerate() {
+            let (deposits, leaves) = cache
+                .get_deposits(0, deposit_count as u64, *deposit_root)
+                .unwrap();
+            assert_eq!(deposits.len(), deposit_count);
+            for (index, (deposit, leaf)) in deposits.iter().zip(leaves).enumerate() {
+                assert_eq!(deposit.data, deposit_data(index as u64));
+                assert!(verify_merkle_proof(
+                    leaf,
+                    &deposit.proof[..],
+                    DEPOSIT_TREE_DEPTH + 1,
+                    index,
+                    *deposit_root
+                ));
+            }
+        }
+
+        let (deposits, _) = cache.get_deposits(3, 2, deposit_roots[6]).unwrap();
+        assert_eq!(deposits[0].data, deposit_data(3));
+        assert_eq!(deposits[1].data, deposit_data(4));
+        assert_eq!(
+            cache.get_deposits(3, 4, deposit_roots[6]),
+            Err(DepositCacheError::InsufficientDeposits {
+                end: 7,
+                deposit_count: 6
+            })
+        );
+        assert_eq!(
+            cache.get_deposits(0, 1, Hash256::zero()),
+            Err(DepositCacheError::UnknownDepositRoot(Hash256::zero()))
+        );
+    }
+
+    #[test]
+    fn out_of_order_and_duplicate_deposits_rejected() {
+        let mut cache = DepositCache::new();
+        cache.insert_log(deposit_log(0)).unwrap();
+        cache.insert_log(deposit_log(1)).unwrap();
+        let deposit_root = cache.deposit_root();
+
+        assert_eq!(
+            cache.insert_log(deposit_log(1)),
+            Err(DepositCacheError::DuplicateDeposit(1))
+        );
+        assert_eq!(
+            cache.insert_log(deposit_log(3)),
+            Err(DepositCacheError::NonConsecutiveDeposit {
+                index: 3,
+                expected: 2
+            })
+        );
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.deposit_root(), deposit_root);
+    }
+
+    #[test]
+    fn deposits_read_from_logs() {
+        let node = Arc::new(MockEth1Node::new(2_500));
+        for index in 0..5 {
+            node.add_deposit(index * 600, deposit_data(index));
+        }
+        let fetcher = Arc::new(Web3DataFetcher::new(
+            node.clone(),
+            DEPOSIT_CONTRACT.to_string(),
+        ));
+        let cache = Arc::new(RwLock::new(DepositCache::new()));
+
+        update_deposit_cache(fetcher.clone(), cache.clone(), 1_500)
+            .wait()
+            .unwrap();
+        assert_eq!(cache.read().len(), 3);
+        assert_eq!(cache.read().last_block(), Some(1_500));
+        assert_eq!(node.calls(), 2);
+
+        update_deposit_cache(fetcher, cache.clone(), 2_499)
+            .wait()
+            .unwrap();
+        assert_eq!(cache.read().len(), 5);
+        assert_eq!(cache.read().logs[4].block_number, 2_400);
+    }
+}
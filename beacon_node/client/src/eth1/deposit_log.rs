@@ -0,0 +1,194 @@
+use super::web3_fetcher::{parse_bytes, parse_quantity};
+use super::Eth1Error;
+use serde_json::Value;
+use types::{DepositData, Hash256, PublicKeyBytes, SignatureBytes};
+
+/// The topic of the deposit contract's
+/// `DepositEvent(bytes,bytes,bytes,bytes,bytes)`.
+pub const DEPOSIT_EVENT_TOPIC: &str =
+    "0x649bbc62d0e31342afea4e5cd82d4049e7e1ee912fc0889aa790803be39038c5";
+
+const PUBKEY_LEN: usize = 48;
+const WITHDRAWAL_CREDENTIALS_LEN: usize = 32;
+const AMOUNT_LEN: usize = 8;
+const SIGNATURE_LEN: usize = 96;
+const INDEX_LEN: usize = 8;
+
+/// A deposit read from a `DepositEvent` of the deposit contract.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepositLog {
+    pub deposit_data: DepositData,
+    /// The number of the block in which the deposit was made.
+    pub block_number: u64,
+    /// The index of the deposit in the deposit contract's merkle tree.
+    pub index: u64,
+}
+
+impl DepositLog {
+    /// Decodes a log returned by `eth_getLogs`.
+    pub fn from_log(log: &Value) -> Result<Self, Eth1Error> {
+        let block_number = parse_quantity(&log["blockNumber"])?;
+        let data = parse_bytes(&log["data"])?;
+
+        let pubkey = decode_bytes_param(&data, 0, PUBKEY_LEN)?;
+        let withdrawal_credentials = decode_bytes_param(&data, 1, WITHDRAWAL_CREDENTIALS_LEN)?;
+        let amount = decode_bytes_param(&data, 2, AMOUNT_LEN)?;
+        let signature = decode_bytes_param(&data, 3, SIGNATURE_LEN)?;
+        let index = decode_bytes_param(&data, 4, INDEX_LEN)?;
+
+        let deposit_data = DepositData {
+            pubkey: PublicKeyBytes::from_bytes(pubkey)
+                .map_err(|e| Eth1Error::DecodeError(format!("Invalid pubkey: {:?}", e)))?,
+            withdrawal_credentials: Hash256::from_slice(withdrawal_credentials),
+            amount: u64_from_le_bytes(amount),
+            signature: SignatureBytes::from_bytes(signature)
+                .map_err(|e| Eth1Error::DecodeError(format!("Invalid signature: {:?}", e)))?,
+        };
+        Ok(Self {
+            deposit_data,
+            block_number,
+            index: u64_from_le_bytes(index),
+        })
+    }
+}
+
+/// Returns the `position`th of the ABI-encoded `bytes` params in `data`, which must be `len`
+/// bytes long.
+fn decode_bytes_param(data: &[u8], position: usize, len: usize) -> Result<&[u8], Eth1Error> {
+    let invalid = || {
+        Eth1Error::DecodeError(format!(
+            "Invalid DepositEvent param {}: 0x{}",
+            position,
+            hex::encode(data)
+        ))
+    };
+    let offset = read_word(data, position * 32).ok_or_else(invalid)?;
+    let param_len = read_word(data, offset).ok_or_else(invalid)?;
+    if param_len != len {
+        return Err(invalid());
+    }
+    data.get(offset + 32..offset + 32 + len).ok_or_else(invalid)
+}
+
+/// Reads the big-endian 32 byte word at `offset` as a `usize`, or `None` if it is out of bounds
+/// or too large.
+fn read_word(data: &[u8], offset: usize) -> Option<usize> {
+    let word = data.get(offset..offset.checked_add(32)?)?;
+    if word[..24].iter().any(|b| *b != 0) {
+        return None;
+    }
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&word[24..]);
+    Some(u64::from_be_bytes(bytes) as usize)
+}
+
+fn u64_from_le_bytes(bytes: &[u8]) -> u64 {
+    let mut array = [0; 8];
+    array.copy_from_slice(bytes);
+    u64::from_le_bytes(array)
+}
+
+/// Returns the data of the `DepositEvent` of a deposit.
+#[cfg(test)]
+pub(crate) fn encode_deposit_event(deposit_data: &DepositData, index: u64) -> Vec<u8> {
+    let params = vec![
+        deposit_data.pubkey.as_bytes(),
+        deposit_data.withdrawal_credentials.as_bytes().to_vec(),
+        deposit_data.amount.to_le_bytes().to_vec(),
+        deposit_data.signature.as_bytes(),
+        index.to_le_bytes().to_vec(),
+    ];
+    let mut head = vec![];
+    let mut tail = vec![];
+    for param in params {
+        let offset = 5 * 32 + tail.len();
+        head.extend_from_slice(&word(offset));
+        tail.extend_from_slice(&word(param.len()));
+        let padded_len = (param.len() + 31) / 32 * 32;
+        tail.extend_from_slice(&param);
+        tail.resize(tail.len() + padded_len - param.len(), 0);
+    }
+    head.append(&mut tail);
+    head
+}
+
+#[cfg(test)]
+fn word(value: usize) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&(value as u64).to_be_bytes());
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The data of a deposit of 32 ETH as the 4th deposit, laid out as the deposit contract
+    /// emits it.
+    fn known_deposit_event() -> String {
+        let words = vec![
+            // The offsets of the five params.
+            format!("{:064x}", 0xa0),
+            format!("{:064x}", 0x100),
+            format!("{:064x}", 0x140),
+            format!("{:064x}", 0x180),
+            format!("{:064x}", 0x200),
+            // The pubkey.
+            format!("{:064x}", 48),
+            "a1".repeat(32),
+            format!("{:0<64}", "a1".repeat(16)),
+            // The withdrawal credentials.
+            format!("{:064x}", 32),
+            format!("00{}", "b2".repeat(31)),
+            // 32 ETH, in Gwei, as 8 little-endian bytes.
+            format!("{:064x}", 8),
+            format!("{:0<64}", "0040597307000000"),
+            // The signature.
+            format!("{:064x}", 96),
+            "c3".repeat(96),
+            // The index, as 8 little-endian bytes.
+            format!("{:064x}", 8),
+            format!("{:0<64}", "0300000000000000"),
+        ];
+        format!("0x{}", words.concat())
+    }
+
+    #[test]
+    fn known_deposit_decoded() {
+        let log = json!({
+            "address": "0x1111111111111111111111111111111111111111",
+            "blockNumber": "0x1c",
+            "data": known_deposit_event(),
+            "topics": [DEPOSIT_EVENT_TOPIC],
+        });
+        let deposit = DepositLog::from_log(&log).unwrap();
+
+        assert_eq!(deposit.block_number, 28);
+        assert_eq!(deposit.index, 3);
+        assert_eq!(deposit.deposit_data.pubkey.as_bytes(), vec![0xa1; 48]);
+        let mut withdrawal_credentials = vec![0xb2; 32];
+        withdrawal_credentials[0] = 0;
+        assert_eq!(
+            deposit.deposit_data.withdrawal_credentials,
+            Hash256::from_slice(&withdrawal_credentials)
+        );
+        assert_eq!(deposit.deposit_data.amount, 32_000_000_000);
+        assert_eq!(deposit.deposit_data.signature.as_bytes(), vec![0xc3; 96]);
+
+        let data = parse_bytes(&json!(known_deposit_event())).unwrap();
+        assert_eq!(encode_deposit_event(&deposit.deposit_data, 3), data);
+    }
+
+    #[test]
+    fn truncated_events_are_decode_errors() {
+        let data = known_deposit_event();
+        for len in &[2, 66, 400, data.len() - 64] {
+            let log = json!({ "blockNumber": "0x1c", "data": data[..*len] });
+            match DepositLog::from_log(&log) {
+                Err(Eth1Error::DecodeError(_)) => (),
+                other => panic!("Expected a decode error, got {:?}", other),
+            }
+        }
+    }
+}
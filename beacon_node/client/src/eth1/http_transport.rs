@@ -0,0 +1,268 @@
+use super::web3_fetcher::Request;
+use super::{Eth1Error, FetcherFuture, Transport};
+use futures::Future;
+use reqwest::r#async::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use url::Url;
+
+/// Sends JSON-RPC requests to an Eth1 node over HTTP or HTTPS, e.g. to a hosted node.
+pub struct HttpTransport {
+    client: Client,
+    url: Url,
+    next_id: AtomicU64,
+}
+
+impl HttpTransport {
+    /// Returns a transport to the node at `url`, waiting up to `timeout` for each response.
+    pub fn new(url: &str, timeout: Duration) -> Result<Self, String> {
+        let url = Url::parse(url).map_err(|e| format!("Invalid eth1 server {}: {:?}", url, e))?;
+        if url.scheme() != "http" && url.scheme() != "https" {
+            return Err(format!(
+                "The eth1 server {} must be an http:// or https:// URL",
+                url
+            ));
+        }
+        let client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Unable to build HTTP client: {:?}", e))?;
+        Ok(Self {
+            client,
+            url,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Returns the JSON-RPC request object of `method`, with a fresh id.
+    fn request_object(&self, method: &str, params: Vec<Value>) -> (u64, Value) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        (id, request)
+    }
+
+    /// Posts `body`, returning the decoded JSON response.
+    fn post(&self, body: &Value) -> FetcherFuture<Value> {
+        Box::new(
+            self.client
+                .post(self.url.clone())
+                .json(body)
+                .send()
+                .and_then(|response| response.error_for_status())
+                .and_then(|mut response| response.json::<Value>())
+                .map_err(|e| {
+                    if e.is_timeout() {
+                        Eth1Error::RpcTimeout
+                    } else if e.is_serialization() {
+                        Eth1Error::DecodeError(format!("Invalid JSON-RPC response: {:?}", e))
+                    } else {
+                        Eth1Error::FetchFailed(format!("{:?}", e))
+                    }
+                }),
+        )
+    }
+}
+
+impl Transport for HttpTransport {
+    fn execute(&self, method: &'static str, params: Vec<Value>) -> FetcherFuture<Value> {
+        let (_, request) = self.request_object(method, params);
+        Box::new(
+            self.post(&request)
+                .and_then(|response| into_result(&response)),
+        )
+    }
+
+    /// Sends the requests as a single JSON-RPC batch.
+    fn execute_batch(
+        &self,
+        requests: Vec<Request>,
+    ) -> FetcherFuture<Vec<Result<Value, Eth1Error>>> {
+        let (ids, batch): (Vec<u64>, Vec<Value>) = requests
+            .into_iter()
+            .map(|(method, params)| self.request_object(method, params))
+            .unzip();
+        Box::new(self.post(&Value::Array(batch)).and_then(move |response| {
+            let responses = response.as_array().ok_or_else(|| {
+                Eth1Error::DecodeError(format!("Expected a batch response, got {}", response))
+            })?;
+            // The responses of a batch may be in any order.
+            let by_id: HashMap<u64, &Value> = responses
+                .iter()
+                .filter_map(|response| Some((response["id"].as_u64()?, response)))
+                .collect();
+            Ok(ids
+                .iter()
+                .map(|id| match by_id.get(id) {
+                    Some(response) => into_result(response),
+                    None => Err(Eth1Error::DecodeError(format!(
+                        "No response to request {}",
+                        id
+                    ))),
+                })
+                .collect())
+        }))
+    }
+}
+
+/// Returns the `result` of a JSON-RPC response, or its `error`.
+fn into_result(response: &Value) -> Result<Value, Eth1Error> {
+    if let Some(error) = response.get("error") {
+        return Err(Eth1Error::FetchFailed(format!(
+            "Eth1 node error: {}",
+            error
+        )));
+    }
+    response
+        .get("result")
+        .cloned()
+        .ok_or_else(|| Eth1Error::DecodeError(format!("Response has no result: {}", response)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_http_urls_accepted() {
+        let timeout = Duration::from_secs(1);
+        assert!(HttpTransport::new("https://mainnet.example.com/v3/key", timeout).is_ok());
+        assert!(HttpTransport::new("http://localhost:8545", timeout).is_ok());
+        assert!(HttpTransport::new("ws://localhost:8546", timeout).is_err());
+        assert!(HttpTransport::new("localhost:8545", timeout).is_err());
+    }
+
+    #[test]
+    fn responses_decoded() {
+        assert_eq!(
+            into_result(&json!({ "jsonrpc": "2.0", "id": 1, "result": "0x2a" })),
+            Ok(json!("0x2a"))
+        );
+        match into_result(&json!({ "id": 1, "error": { "code": -32000, "message": "nope" } })) {
+            Err(Eth1Error::FetchFailed(message)) => assert!(message.contains("nope")),
+            other => panic!("Expected a node error, got {:?}", other),
+        }
+        assert!(into_result(&json!({ "id": 1 })).is_err());
+    }
+}
+
+/// Serves JSON-RPC over HTTP on a local port, to test the transport end to end.
+#[cfg(all(test, feature = "eth1_http_tests"))]
+mod http_tests {
+    use super::*;
+    use crate::eth1::{Eth1DataFetcher, Web3DataFetcher};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use tokio::runtime::Runtime;
+
+    /// Answers `eth_blockNumber` with 42 and `eth_getBlockByNumber` with a block whose hash is
+    /// its number. Other methods are answered with an error.
+    fn respond(request: &Value) -> Value {
+        let result = match request["method"].as_str() {
+            Some("eth_blockNumber") => json!("0x2a"),
+            Some("eth_getBlockByNumber") => {
+                let number = request["params"][0]
+                    .as_str()
+                    .unwrap()
+                    .trim_start_matches("0x");
+                json!({ "hash": format!("0x{:0>64}", number) })
+            }
+            _ => {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                    "error": { "code": -32601, "message": "Method not found" },
+                })
+            }
+        };
+        json!({ "jsonrpc": "2.0", "id": request["id"], "result": result })
+    }
+
+    /// Starts a server on a free port, returning its URL.
+    fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    let line = line.to_lowercase();
+                    if line.starts_with("content-length:") {
+                        content_length = line[15..].trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                let response = match request.as_array() {
+                    Some(batch) => Value::Array(batch.iter().rev().map(respond).collect()),
+                    None => respond(&request),
+                };
+                let response = response.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn fetcher_reads_from_http_node() {
+        let url = serve();
+        let transport = HttpTransport::new(&url, Duration::from_secs(5)).unwrap();
+        let fetcher = Web3DataFetcher::new(Arc::new(transport), "0x00".to_string());
+        let mut runtime = Runtime::new().unwrap();
+
+        assert_eq!(runtime.block_on(fetcher.get_current_block_number()), Ok(42));
+        assert_eq!(
+            runtime.block_on(fetcher.get_block_hash_by_height(7)),
+            Ok(Some(types::Hash256::from_low_u64_be(7)))
+        );
+        assert!(runtime.block_on(fetcher.get_deposit_root(None)).is_err());
+    }
+
+    #[test]
+    fn batch_responses_matched_by_id() {
+        let url = serve();
+        let transport = HttpTransport::new(&url, Duration::from_secs(5)).unwrap();
+        let mut runtime = Runtime::new().unwrap();
+
+        let requests = vec![
+            ("eth_getBlockByNumber", vec![json!("0x1"), json!(false)]),
+            ("eth_chainId", vec![]),
+            ("eth_getBlockByNumber", vec![json!("0x2"), json!(false)]),
+        ];
+        let results = runtime.block_on(transport.execute_batch(requests)).unwrap();
+        assert_eq!(
+            results[0].as_ref().unwrap()["hash"],
+            format!("0x{:0>64}", 1)
+        );
+        assert!(results[1].is_err());
+        assert_eq!(
+            results[2].as_ref().unwrap()["hash"],
+            format!("0x{:0>64}", 2)
+        );
+    }
+}
@@ -0,0 +1,12 @@
+pub use lighthouse_metrics::*;
+
+lazy_static! {
+    pub static ref ETH1_CACHE_UPDATE_BLOCKS_SCANNED: Result<IntGauge> = try_create_int_gauge(
+        "eth1_cache_update_blocks_scanned",
+        "Blocks read by the current or last update of the eth1 cache"
+    );
+    pub static ref ETH1_CACHE_UPDATE_BLOCKS_TOTAL: Result<IntGauge> = try_create_int_gauge(
+        "eth1_cache_update_blocks_total",
+        "Blocks to be read by the current or last update of the eth1 cache"
+    );
+}
@@ -0,0 +1,204 @@
+//! An in-memory Eth1 node, answering JSON-RPC requests for the eth1 tests.
+
+use super::deposit_log::{encode_deposit_event, DEPOSIT_EVENT_TOPIC};
+use super::web3_fetcher::Request;
+use super::web3_fetcher::{encode_deposit_count, parse_bytes, parse_quantity};
+use super::{Eth1Error, FetcherFuture, Transport};
+use beacon_chain::parking_lot::RwLock;
+use futures::future;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use types::{DepositData, Eth1Data, Hash256, PublicKeyBytes, SignatureBytes};
+
+pub const DEPOSIT_CONTRACT: &str = "0x1111111111111111111111111111111111111111";
+
+/// Serves a chain of blocks, where block `n` has made `n / 2` deposits.
+pub struct MockEth1Node {
+    blocks: RwLock<Vec<Eth1Data>>,
+    calls: AtomicUsize,
+    /// If true, every request times out.
+    unresponsive: AtomicBool,
+    /// The most requests answered; later requests time out.
+    call_limit: AtomicUsize,
+    /// The block selected by the `finalized` tag, if the tag is supported.
+    finalized: RwLock<Option<u64>>,
+    /// The block number of each deposit made, in index order.
+    deposits: RwLock<Vec<(u64, DepositData)>>,
+}
+
+impl MockEth1Node {
+    /// Returns a node with blocks `0..block_count`.
+    pub fn new(block_count: u64) -> Self {
+        let node = Self {
+            blocks: RwLock::new(vec![]),
+            calls: AtomicUsize::new(0),
+            unresponsive: AtomicBool::new(false),
+            call_limit: AtomicUsize::new(usize::max_value()),
+            finalized: RwLock::new(None),
+            deposits: RwLock::new(vec![]),
+        };
+        node.push_blocks(block_count);
+        node
+    }
+
+    /// Adds `count` blocks to the head of the chain.
+    pub fn push_blocks(&self, count: u64) {
+        let mut blocks = self.blocks.write();
+        for _ in 0..count {
+            let number = blocks.len() as u64;
+            blocks.push(Eth1Data {
+                deposit_root: Hash256::from_low_u64_be(number / 2 + 1),
+                deposit_count: number / 2,
+                block_hash: Hash256::from_low_u64_be((1 << 32) + number),
+            });
+        }
+    }
+
+    /// Replaces the hash of block `number`, as though the chain had reorganised.
+    pub fn set_block_hash(&self, number: u64, block_hash: Hash256) {
+        self.blocks.write()[number as usize].block_hash = block_hash;
+    }
+
+    /// Returns the `Eth1Data` of block `number`.
+    pub fn block(&self, number: u64) -> Eth1Data {
+        self.blocks.read()[number as usize].clone()
+    }
+
+    /// Returns the number of requests served.
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Makes every subsequent request time out, or respond again.
+    pub fn set_unresponsive(&self, unresponsive: bool) {
+        self.unresponsive.store(unresponsive, Ordering::SeqCst);
+    }
+
+    /// Makes every request after the first `limit` requests served time out.
+    pub fn set_call_limit(&self, limit: usize) {
+        self.call_limit.store(limit, Ordering::SeqCst);
+    }
+
+    /// Counts a request, returning `true` if it times out.
+    fn times_out(&self) -> bool {
+        let calls = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+        self.unresponsive.load(Ordering::SeqCst) || calls > self.call_limit.load(Ordering::SeqCst)
+    }
+
+    /// Supports the `finalized` tag, selecting block `number`, or stops supporting it.
+    pub fn set_finalized(&self, number: Option<u64>) {
+        *self.finalized.write() = number;
+    }
+
+    /// Makes a deposit in block `block_number`, emitting its `DepositEvent`.
+    ///
+    /// The `Eth1Data` of the blocks are not changed.
+    pub fn add_deposit(&self, block_number: u64, deposit_data: DepositData) {
+        self.deposits.write().push((block_number, deposit_data));
+    }
+
+    /// Returns the `DepositEvent` logs of the blocks selected by an `eth_getLogs` filter.
+    fn deposit_logs(&self, filter: &Value) -> Result<Value, Eth1Error> {
+        if filter["address"] != DEPOSIT_CONTRACT || filter["topics"][0] != DEPOSIT_EVENT_TOPIC {
+            return Ok(json!([]));
+        }
+        let from_block = parse_quantity(&filter["fromBlock"])?;
+        let to_block = parse_quantity(&filter["toBlock"])?;
+        let logs = self
+            .deposits
+            .read()
+            .iter()
+            .enumerate()
+            .filter(|(_, (block_number, _))| (from_block..=to_block).contains(block_number))
+            .map(|(index, (block_number, deposit_data))| {
+                let data = encode_deposit_event(deposit_data, index as u64);
+                json!({
+                    "address": DEPOSIT_CONTRACT,
+                    "blockNumber": format!("0x{:x}", block_number),
+                    "data": format!("0x{}", hex::encode(data)),
+                    "topics": [DEPOSIT_EVENT_TOPIC],
+                })
+            })
+            .collect();
+        Ok(Value::Array(logs))
+    }
+
+    /// Returns the block selected by a JSON-RPC block parameter.
+    fn block_at(&self, tag: &Value) -> Result<Option<Eth1Data>, Eth1Error> {
+        let blocks = self.blocks.read();
+        let number = match tag.as_str() {
+            Some("latest") => blocks.len() as u64 - 1,
+            Some("finalized") => self
+                .finalized
+                .read()
+                .ok_or_else(|| Eth1Error::FetchFailed("Unknown block tag finalized".to_string()))?,
+            _ => parse_quantity(tag)?,
+        };
+        Ok(blocks.get(number as usize).cloned())
+    }
+
+    fn respond(&self, method: &str, params: &[Value]) -> Result<Value, Eth1Error> {
+        match method {
+            "eth_blockNumber" => Ok(json!(format!("0x{:x}", self.blocks.read().len() - 1))),
+            "eth_getBlockByNumber" => Ok(match self.block_at(&params[0])? {
+                Some(block) => json!({ "hash": format!("0x{}", hex::encode(block.block_hash)) }),
+                None => Value::Null,
+            }),
+            "eth_call" => {
+                if params[0]["to"] != DEPOSIT_CONTRACT {
+                    return Err(Eth1Error::FetchFailed("Unknown contract".to_string()));
+                }
+                let block = self
+                    .block_at(&params[1])?
+                    .ok_or_else(|| Eth1Error::FetchFailed("Unknown block".to_string()))?;
+                let selector = parse_bytes(&params[0]["data"])?;
+                let result = match hex::encode(selector).as_str() {
+                    "c5f2892f" => block.deposit_root.as_bytes().to_vec(),
+                    "621fd130" => encode_deposit_count(block.deposit_count),
+                    _ => return Err(Eth1Error::FetchFailed("Unknown function".to_string())),
+                };
+                Ok(json!(format!("0x{}", hex::encode(result))))
+            }
+            "eth_getLogs" => self.deposit_logs(&params[0]),
+            _ => Err(Eth1Error::FetchFailed(format!(
+                "Unsupported method {}",
+                method
+            ))),
+        }
+    }
+}
+
+/// Returns the `DepositData` of a deposit, distinct for each `seed`.
+pub fn deposit_data(seed: u64) -> DepositData {
+    let byte = seed as u8;
+    DepositData {
+        pubkey: PublicKeyBytes::from_bytes(&[byte; 48]).unwrap(),
+        withdrawal_credentials: Hash256::from_low_u64_be(seed),
+        amount: 32_000_000_000 + seed,
+        signature: SignatureBytes::from_bytes(&[byte; 96]).unwrap(),
+    }
+}
+
+impl Transport for MockEth1Node {
+    fn execute(&self, method: &'static str, params: Vec<Value>) -> FetcherFuture<Value> {
+        if self.times_out() {
+            return Box::new(future::err(Eth1Error::RpcTimeout));
+        }
+        Box::new(future::result(self.respond(method, &params)))
+    }
+
+    /// Serves the whole batch as a single request.
+    fn execute_batch(
+        &self,
+        requests: Vec<Request>,
+    ) -> FetcherFuture<Vec<Result<Value, Eth1Error>>> {
+        if self.times_out() {
+            return Box::new(future::err(Eth1Error::RpcTimeout));
+        }
+        let results = requests
+            .iter()
+            .map(|(method, params)| self.respond(method, params))
+            .collect();
+        Box::new(future::ok(results))
+    }
+}
@@ -0,0 +1,128 @@
+//! Reads the `Eth1Data` required for block production from an Eth1 node.
+//!
+//! The `Eth1DataCache` keeps the `Eth1Data` of recent Eth1 blocks, filled by an `Eth1DataFetcher`
+//! which queries the node over JSON-RPC. The `ClientEth1Backend` serves the cache to the
+//! `BeaconChain`.
+
+mod backend;
+mod cache;
+mod config;
+mod deposit_cache;
+mod deposit_log;
+mod http_transport;
+mod metrics;
+#[cfg(test)]
+mod mock_node;
+mod rate_limit;
+mod throttle;
+mod web3_fetcher;
+
+use futures::{future, Future};
+use std::ops::Range;
+use types::{Eth1Data, Hash256};
+
+pub use backend::{ClientEth1Backend, Eth1Updater, HttpEth1DataCache};
+pub use cache::{Eth1DataCache, UpdateProgress};
+pub use config::Config;
+pub use deposit_cache::{update_deposit_cache, DepositCache, DepositCacheError};
+pub use deposit_log::DepositLog;
+pub use http_transport::HttpTransport;
+pub use rate_limit::RateLimitedFetcher;
+pub use throttle::{proposal_near, UpdateThrottle};
+pub use web3_fetcher::{Transport, Web3DataFetcher};
+
+/// The future returned by each query of an `Eth1DataFetcher`.
+pub type FetcherFuture<T> = Box<dyn Future<Item = T, Error = Eth1Error> + Send>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Eth1Error {
+    /// The request to the Eth1 node failed, or the node returned an error.
+    FetchFailed(String),
+    /// The Eth1 node did not respond in time.
+    RpcTimeout,
+    /// The response of the Eth1 node could not be decoded.
+    DecodeError(String),
+    /// The Eth1 node does not know a block below its head.
+    UnknownBlock(u64),
+    /// The block is within the follow distance of the head.
+    BlockTooRecent(u64),
+}
+
+impl Eth1Error {
+    /// Returns `true` if retrying the request may succeed.
+    ///
+    /// A decode error means the node or the deposit contract is not what we expect, which a retry
+    /// will not fix.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Eth1Error::FetchFailed(_)
+            | Eth1Error::RpcTimeout
+            | Eth1Error::UnknownBlock(_)
+            | Eth1Error::BlockTooRecent(_) => true,
+            Eth1Error::DecodeError(_) => false,
+        }
+    }
+}
+
+/// Queries an Eth1 node for blocks and the state of the deposit contract.
+///
+/// Where a query takes a `block_number`, `None` queries the latest block.
+pub trait Eth1DataFetcher: Send + Sync + 'static {
+    /// Returns the number of the latest block.
+    fn get_current_block_number(&self) -> FetcherFuture<u64>;
+
+    /// Returns the hash of the block at `height`, or `None` if there is no such block.
+    fn get_block_hash_by_height(&self, height: u64) -> FetcherFuture<Option<Hash256>>;
+
+    /// Returns the root of the deposit contract's merkle tree.
+    fn get_deposit_root(&self, block_number: Option<u64>) -> FetcherFuture<Hash256>;
+
+    /// Returns the number of deposits made to the deposit contract.
+    fn get_deposit_count(&self, block_number: Option<u64>) -> FetcherFuture<u64>;
+
+    /// Returns the deposits made from block `from_block` up to and including `to_block`, in the
+    /// order they were made.
+    fn get_deposit_logs_in_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> FetcherFuture<Vec<DepositLog>>;
+
+    /// Returns the `Eth1Data` of each block in `block_numbers`, or `None` for each block unknown
+    /// to the node.
+    ///
+    /// Unless overridden, each block is read with separate queries.
+    fn get_eth1_data_batch(
+        &self,
+        block_numbers: Range<u64>,
+    ) -> FetcherFuture<Vec<Option<Eth1Data>>> {
+        let blocks: Vec<_> = block_numbers
+            .map(|block_number| fetch_eth1_data(self, block_number))
+            .collect();
+        Box::new(future::join_all(blocks))
+    }
+}
+
+/// Reads the `Eth1Data` of block `block_number` from the Eth1 node.
+///
+/// Resolves to `None` if the node does not know the block.
+pub(crate) fn fetch_eth1_data<F: Eth1DataFetcher + ?Sized>(
+    fetcher: &F,
+    block_number: u64,
+) -> FetcherFuture<Option<Eth1Data>> {
+    Box::new(
+        fetcher
+            .get_block_hash_by_height(block_number)
+            .join3(
+                fetcher.get_deposit_root(Some(block_number)),
+                fetcher.get_deposit_count(Some(block_number)),
+            )
+            .map(|(block_hash, deposit_root, deposit_count)| {
+                block_hash.map(|block_hash| Eth1Data {
+                    deposit_root,
+                    deposit_count,
+                    block_hash,
+                })
+            }),
+    )
+}
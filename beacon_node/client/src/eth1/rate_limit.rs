@@ -0,0 +1,157 @@
+use super::{DepositLog, Eth1DataFetcher, Eth1Error, FetcherFuture};
+use beacon_chain::parking_lot::Mutex;
+use futures::Future;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use types::{Eth1Data, Hash256};
+
+/// Sends the queries of `fetcher` at no more than `requests_per_second`, to stay within the
+/// rate limits of hosted Eth1 nodes.
+///
+/// Queries over the limit are delayed rather than refused. Up to a second's worth of queries may
+/// be sent at once. A batch counts as one query.
+pub struct RateLimitedFetcher<F: Eth1DataFetcher> {
+    fetcher: Arc<F>,
+    bucket: Mutex<TokenBucket>,
+}
+
+impl<F: Eth1DataFetcher> RateLimitedFetcher<F> {
+    pub fn new(fetcher: F, requests_per_second: u32) -> Self {
+        Self {
+            fetcher: Arc::new(fetcher),
+            bucket: Mutex::new(TokenBucket::new(requests_per_second.max(1), Instant::now())),
+        }
+    }
+
+    /// Sends `query` once the bucket holds a token for it.
+    fn throttle<T, Q>(&self, query: Q) -> FetcherFuture<T>
+    where
+        T: Send + 'static,
+        Q: FnOnce(&F) -> FetcherFuture<T> + Send + 'static,
+    {
+        let fetcher = self.fetcher.clone();
+        let send_at = match self.bucket.lock().take(Instant::now()) {
+            Some(send_at) => send_at,
+            None => return query(&fetcher),
+        };
+        Box::new(
+            Delay::new(send_at)
+                .map_err(|e| Eth1Error::FetchFailed(format!("Rate limit timer failed: {:?}", e)))
+                .and_then(move |()| query(&fetcher)),
+        )
+    }
+}
+
+impl<F: Eth1DataFetcher> Eth1DataFetcher for RateLimitedFetcher<F> {
+    fn get_current_block_number(&self) -> FetcherFuture<u64> {
+        self.throttle(|fetcher| fetcher.get_current_block_number())
+    }
+
+    fn get_block_hash_by_height(&self, height: u64) -> FetcherFuture<Option<Hash256>> {
+        self.throttle(move |fetcher| fetcher.get_block_hash_by_height(height))
+    }
+
+    fn get_deposit_root(&self, block_number: Option<u64>) -> FetcherFuture<Hash256> {
+        self.throttle(move |fetcher| fetcher.get_deposit_root(block_number))
+    }
+
+    fn get_deposit_count(&self, block_number: Option<u64>) -> FetcherFuture<u64> {
+        self.throttle(move |fetcher| fetcher.get_deposit_count(block_number))
+    }
+
+    fn get_deposit_logs_in_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> FetcherFuture<Vec<DepositLog>> {
+        self.throttle(move |fetcher| fetcher.get_deposit_logs_in_range(from_block, to_block))
+    }
+
+    fn get_eth1_data_batch(
+        &self,
+        block_numbers: Range<u64>,
+    ) -> FetcherFuture<Vec<Option<Eth1Data>>> {
+        self.throttle(move |fetcher| fetcher.get_eth1_data_batch(block_numbers))
+    }
+}
+
+/// A token bucket holding up to a second's worth of tokens, refilled at `1 / interval` tokens
+/// per second.
+///
+/// Rather than tracking the tokens, tracks the time at which the bucket would be empty if no
+/// further tokens were taken.
+struct TokenBucket {
+    interval: Duration,
+    /// The time taken to refill all but one of the tokens.
+    burst: Duration,
+    empty_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(requests_per_second: u32, now: Instant) -> Self {
+        let interval = Duration::from_secs(1) / requests_per_second;
+        Self {
+            interval,
+            burst: interval * (requests_per_second - 1),
+            empty_at: now,
+        }
+    }
+
+    /// Takes a token, returning the time at which it is available if that is after `now`.
+    fn take(&mut self, now: Instant) -> Option<Instant> {
+        let empty_at = self.empty_at.max(now);
+        self.empty_at = empty_at + self.interval;
+        empty_at
+            .checked_sub(self.burst)
+            .filter(|available_at| *available_at > now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth1::mock_node::{MockEth1Node, DEPOSIT_CONTRACT};
+    use crate::eth1::Web3DataFetcher;
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+
+    #[test]
+    fn bucket_spaces_tokens_beyond_burst() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(4, now);
+        for _ in 0..4 {
+            assert_eq!(bucket.take(now), None);
+        }
+        assert_eq!(bucket.take(now), Some(now + Duration::from_millis(250)));
+        assert_eq!(bucket.take(now), Some(now + Duration::from_millis(500)));
+
+        // Tokens refill whilst none are taken.
+        let later = now + Duration::from_secs(2);
+        assert_eq!(bucket.take(later), None);
+    }
+
+    #[test]
+    fn burst_of_requests_spaced_out() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let fetcher = RateLimitedFetcher::new(
+            Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string()),
+            40,
+        );
+        let mut runtime = Runtime::new().unwrap();
+
+        let started = Instant::now();
+        let queries: Vec<_> = (0..60)
+            .map(|_| fetcher.get_current_block_number())
+            .collect();
+        // A second's worth of requests is sent at once; the rest are queued.
+        assert_eq!(node.calls(), 40);
+
+        let block_numbers = runtime.block_on(future::join_all(queries)).unwrap();
+        assert_eq!(block_numbers, vec![9; 60]);
+        assert_eq!(node.calls(), 60);
+        // The last request waited for 20 tokens, at 25 ms each.
+        assert!(started.elapsed() >= Duration::from_millis(450));
+    }
+}
@@ -0,0 +1,135 @@
+use beacon_chain::{BeaconChain, BeaconChainError, BeaconChainTypes};
+use std::sync::Arc;
+use types::{Epoch, EthSpec, RelativeEpoch, Slot};
+
+/// Selects the intervals at which the `Eth1Updater` updates the caches, so that a node whose
+/// validators rarely propose spends little of the Eth1 node's bandwidth.
+///
+/// Every interval is selected while `proposal_near` returns `true`; otherwise only one in each
+/// `heartbeat_ticks` intervals is. The first interval is always selected, to warm the caches.
+pub struct UpdateThrottle {
+    heartbeat_ticks: u64,
+    ticks_since_update: u64,
+    proposal_near: Box<dyn FnMut() -> bool + Send>,
+}
+
+impl UpdateThrottle {
+    pub fn new<P: FnMut() -> bool + Send + 'static>(
+        heartbeat_ticks: u64,
+        proposal_near: P,
+    ) -> Self {
+        Self {
+            heartbeat_ticks,
+            ticks_since_update: heartbeat_ticks,
+            proposal_near: Box::new(proposal_near),
+        }
+    }
+
+    /// Returns `true` if this interval updates the caches.
+    pub fn tick(&mut self) -> bool {
+        let heartbeat_due = self.ticks_since_update >= self.heartbeat_ticks;
+        if heartbeat_due || (self.proposal_near)() {
+            self.ticks_since_update = 1;
+            true
+        } else {
+            self.ticks_since_update += 1;
+            false
+        }
+    }
+}
+
+/// Returns a `proposal_near` for an `UpdateThrottle`, which returns `true` if any of the
+/// `validators` proposes a block from the current slot to the end of the `lookahead_epochs`
+/// epochs after the current epoch.
+///
+/// The proposers are read once per epoch. If they cannot be read, e.g. before genesis, a
+/// proposal is assumed to be near.
+pub fn proposal_near<T: BeaconChainTypes>(
+    chain: Arc<BeaconChain<T>>,
+    validators: Vec<usize>,
+    lookahead_epochs: u64,
+) -> impl FnMut() -> bool + Send {
+    let mut proposals: Option<(Epoch, Vec<Slot>)> = None;
+    move || {
+        let slot = match chain.slot() {
+            Ok(slot) => slot,
+            Err(_) => return true,
+        };
+        let epoch = slot.epoch(T::EthSpec::slots_per_epoch());
+        if proposals.as_ref().map(|(read_at, _)| *read_at) != Some(epoch) {
+            match proposal_slots(&chain, &validators, epoch, lookahead_epochs) {
+                Ok(slots) => proposals = Some((epoch, slots)),
+                Err(_) => return true,
+            }
+        }
+        proposals.as_ref().map_or(true, |(_, slots)| {
+            slots.iter().any(|proposal| *proposal >= slot)
+        })
+    }
+}
+
+/// Returns each slot of the `epoch` and the `lookahead_epochs` after it in which one of the
+/// `validators` proposes.
+fn proposal_slots<T: BeaconChainTypes>(
+    chain: &BeaconChain<T>,
+    validators: &[usize],
+    epoch: Epoch,
+    lookahead_epochs: u64,
+) -> Result<Vec<Slot>, BeaconChainError> {
+    let slots_per_epoch = T::EthSpec::slots_per_epoch();
+    let head_slot = chain.head().beacon_state.slot;
+    let mut slots = vec![];
+    for epoch in (0..=lookahead_epochs).map(|offset| epoch + offset) {
+        let mut state = chain.state_at_slot(epoch.start_slot(slots_per_epoch).max(head_slot))?;
+        if state.current_epoch() != epoch {
+            continue;
+        }
+        state.build_committee_cache(RelativeEpoch::Current, &chain.spec)?;
+        for slot in epoch.slot_iter(slots_per_epoch) {
+            let proposer =
+                state.get_beacon_proposer_index(slot, RelativeEpoch::Current, &chain.spec)?;
+            if validators.contains(&proposer) {
+                slots.push(slot);
+            }
+        }
+    }
+    Ok(slots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn updates_throttled_until_proposal_near() {
+        let near = Arc::new(AtomicBool::new(false));
+        let proposal_near = {
+            let near = near.clone();
+            move || near.load(Ordering::SeqCst)
+        };
+        let mut throttle = UpdateThrottle::new(4, proposal_near);
+
+        // The first interval warms the caches, then only every fourth is a heartbeat.
+        let ticks: Vec<bool> = (0..9).map(|_| throttle.tick()).collect();
+        assert_eq!(
+            ticks,
+            vec![true, false, false, false, true, false, false, false, true]
+        );
+
+        // Each interval updates while a proposal is near.
+        near.store(true, Ordering::SeqCst);
+        assert!((0..5).all(|_| throttle.tick()));
+
+        // Once it has passed, the heartbeat resumes from the last update.
+        near.store(false, Ordering::SeqCst);
+        let ticks: Vec<bool> = (0..4).map(|_| throttle.tick()).collect();
+        assert_eq!(ticks, vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn every_interval_updates_with_unit_heartbeat() {
+        let mut throttle = UpdateThrottle::new(1, || false);
+        assert!((0..5).all(|_| throttle.tick()));
+    }
+}
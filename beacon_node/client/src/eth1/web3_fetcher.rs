@@ -0,0 +1,585 @@
+use super::deposit_log::DEPOSIT_EVENT_TOPIC;
+use super::{DepositLog, Eth1DataFetcher, Eth1Error, FetcherFuture};
+use beacon_chain::parking_lot::Mutex;
+use futures::{future, Future};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use types::{Eth1Data, Hash256};
+
+/// A JSON-RPC method and its params.
+pub type Request = (&'static str, Vec<Value>);
+
+/// The function selector of the deposit contract's `get_deposit_root()`.
+const GET_DEPOSIT_ROOT_SELECTOR: &str = "0xc5f2892f";
+
+/// The function selector of the deposit contract's `get_deposit_count()`.
+const GET_DEPOSIT_COUNT_SELECTOR: &str = "0x621fd130";
+
+/// Sends JSON-RPC requests to an Eth1 node.
+pub trait Transport: Send + Sync + 'static {
+    /// Calls `method` with `params`, returning the `result` of the response.
+    fn execute(&self, method: &'static str, params: Vec<Value>) -> FetcherFuture<Value>;
+
+    /// Sends `requests` as one JSON-RPC batch, returning the result of each request in order.
+    ///
+    /// Unless overridden, each request is sent separately.
+    fn execute_batch(
+        &self,
+        requests: Vec<Request>,
+    ) -> FetcherFuture<Vec<Result<Value, Eth1Error>>> {
+        let calls: Vec<_> = requests
+            .into_iter()
+            .map(|(method, params)| {
+                self.execute(method, params)
+                    .then(|result| Ok::<_, Eth1Error>(result))
+            })
+            .collect();
+        Box::new(future::join_all(calls))
+    }
+}
+
+/// Reads blocks and the deposit contract through the JSON-RPC API of an Eth1 node.
+///
+/// The result of each request is reused by identical requests for `memo_ttl`, so a burst of
+/// queries (e.g., by a cache update) sends each request once. Batches are not memoized.
+pub struct Web3DataFetcher<T: Transport> {
+    transport: Arc<T>,
+    /// The address of the deposit contract, as `0x`-prefixed hex.
+    deposit_contract: String,
+    memo_ttl: Duration,
+    /// The time and result of each recent request, by method and params.
+    memo: Arc<Mutex<HashMap<String, (Instant, Value)>>>,
+    /// If set, the deposit contract is read at the `finalized` block, else at this many blocks
+    /// behind the head.
+    finalized_fallback: Option<u64>,
+    /// Set once the node is found not to support the `finalized` block tag.
+    finalized_unsupported: Arc<AtomicBool>,
+}
+
+impl<T: Transport> Clone for Web3DataFetcher<T> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            deposit_contract: self.deposit_contract.clone(),
+            memo_ttl: self.memo_ttl,
+            memo: self.memo.clone(),
+            finalized_fallback: self.finalized_fallback,
+            finalized_unsupported: self.finalized_unsupported.clone(),
+        }
+    }
+}
+
+impl<T: Transport> Web3DataFetcher<T> {
+    pub fn new(transport: Arc<T>, deposit_contract: String) -> Self {
+        Self {
+            transport,
+            deposit_contract,
+            memo_ttl: Duration::from_secs(0),
+            memo: Arc::new(Mutex::new(HashMap::new())),
+            finalized_fallback: None,
+            finalized_unsupported: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets how long the result of a request is reused. Zero disables memoization.
+    pub fn set_memo_ttl(&mut self, memo_ttl: Duration) {
+        self.memo_ttl = memo_ttl;
+    }
+
+    /// Reads the deposit root and count at the `finalized` block, rather than the latest block,
+    /// where no block number is given. This avoids votes for deposits which are reorged out.
+    ///
+    /// If the node does not support the `finalized` tag, they are read `follow_distance` blocks
+    /// behind the head instead.
+    pub fn set_prefer_finalized(&mut self, follow_distance: u64) {
+        self.finalized_fallback = Some(follow_distance);
+    }
+
+    /// Sends a request, unless an identical request was answered within `memo_ttl`.
+    fn execute(&self, method: &'static str, params: Vec<Value>) -> FetcherFuture<Value> {
+        if self.memo_ttl == Duration::from_secs(0) {
+            return self.transport.execute(method, params);
+        }
+
+        let key = format!("{}{}", method, Value::Array(params.clone()));
+        if let Some((answered, result)) = self.memo.lock().get(&key) {
+            if answered.elapsed() < self.memo_ttl {
+                return Box::new(future::ok(result.clone()));
+            }
+        }
+
+        let memo = self.memo.clone();
+        let memo_ttl = self.memo_ttl;
+        Box::new(self.transport.execute(method, params).map(move |result| {
+            let mut memo = memo.lock();
+            memo.retain(|_, (answered, _)| answered.elapsed() < memo_ttl);
+            memo.insert(key, (Instant::now(), result.clone()));
+            result
+        }))
+    }
+
+    /// Returns a call of a function of the deposit contract which takes no arguments, at the
+    /// block selected by `tag`.
+    fn deposit_contract_request(&self, selector: &str, tag: Value) -> Request {
+        let call = json!({ "to": self.deposit_contract, "data": selector });
+        ("eth_call", vec![call, tag])
+    }
+
+    /// Calls a function of the deposit contract which takes no arguments, returning the
+    /// ABI-encoded result.
+    ///
+    /// Without a `block_number`, reads the `finalized` block if preferred and supported.
+    fn call_deposit_contract(
+        &self,
+        selector: &'static str,
+        block_number: Option<u64>,
+    ) -> FetcherFuture<Vec<u8>> {
+        let follow_distance = match (block_number, self.finalized_fallback) {
+            (None, Some(follow_distance)) => follow_distance,
+            _ => return self.call_deposit_contract_at(selector, block_tag(block_number)),
+        };
+        if self.finalized_unsupported.load(Ordering::Relaxed) {
+            return self.call_deposit_contract_behind_head(selector, follow_distance);
+        }
+
+        let fetcher = self.clone();
+        Box::new(
+            self.call_deposit_contract_at(selector, json!("finalized"))
+                .or_else(move |e| match e {
+                    // Only once the fallback succeeds is the node known to be reachable, so the
+                    // failure must be due to the tag.
+                    Eth1Error::FetchFailed(_) => {
+                        let unsupported = fetcher.finalized_unsupported.clone();
+                        future::Either::A(
+                            fetcher
+                                .call_deposit_contract_behind_head(selector, follow_distance)
+                                .map(move |result| {
+                                    unsupported.store(true, Ordering::Relaxed);
+                                    result
+                                }),
+                        )
+                    }
+                    e => future::Either::B(future::err(e)),
+                }),
+        )
+    }
+
+    /// Calls a function of the deposit contract at `follow_distance` blocks behind the head, or
+    /// at the first block if the chain is shorter.
+    fn call_deposit_contract_behind_head(
+        &self,
+        selector: &'static str,
+        follow_distance: u64,
+    ) -> FetcherFuture<Vec<u8>> {
+        let fetcher = self.clone();
+        Box::new(
+            self.get_current_block_number()
+                .and_then(move |current_block_number| {
+                    let block_number = current_block_number.saturating_sub(follow_distance);
+                    fetcher.call_deposit_contract_at(selector, block_tag(Some(block_number)))
+                }),
+        )
+    }
+
+    fn call_deposit_contract_at(&self, selector: &str, tag: Value) -> FetcherFuture<Vec<u8>> {
+        let (method, params) = self.deposit_contract_request(selector, tag);
+        Box::new(
+            self.execute(method, params)
+                .and_then(|result| parse_bytes(&result)),
+        )
+    }
+}
+
+impl<T: Transport> Eth1DataFetcher for Web3DataFetcher<T> {
+    fn get_current_block_number(&self) -> FetcherFuture<u64> {
+        Box::new(
+            self.execute("eth_blockNumber", vec![])
+                .and_then(|result| parse_quantity(&result)),
+        )
+    }
+
+    fn get_block_hash_by_height(&self, height: u64) -> FetcherFuture<Option<Hash256>> {
+        let (method, params) = block_request(height);
+        Box::new(
+            self.execute(method, params)
+                .and_then(|block| parse_block_hash(&block)),
+        )
+    }
+
+    fn get_deposit_root(&self, block_number: Option<u64>) -> FetcherFuture<Hash256> {
+        Box::new(
+            self.call_deposit_contract(GET_DEPOSIT_ROOT_SELECTOR, block_number)
+                .and_then(|bytes| decode_deposit_root(&bytes)),
+        )
+    }
+
+    fn get_deposit_count(&self, block_number: Option<u64>) -> FetcherFuture<u64> {
+        Box::new(
+            self.call_deposit_contract(GET_DEPOSIT_COUNT_SELECTOR, block_number)
+                .and_then(|bytes| decode_deposit_count(&bytes)),
+        )
+    }
+
+    fn get_deposit_logs_in_range(
+        &self,
+        from_block: u64,
+        to_block: u64,
+    ) -> FetcherFuture<Vec<DepositLog>> {
+        let filter = json!({
+            "address": self.deposit_contract,
+            "fromBlock": block_tag(Some(from_block)),
+            "toBlock": block_tag(Some(to_block)),
+            "topics": [DEPOSIT_EVENT_TOPIC],
+        });
+        Box::new(
+            self.execute("eth_getLogs", vec![filter])
+                .and_then(|result| {
+                    result
+                        .as_array()
+                        .ok_or_else(|| {
+                            Eth1Error::DecodeError(format!(
+                                "Expected a list of logs, got {}",
+                                result
+                            ))
+                        })?
+                        .iter()
+                        .map(DepositLog::from_log)
+                        .collect()
+                }),
+        )
+    }
+
+    /// Reads the blocks with a single batch of three requests per block.
+    fn get_eth1_data_batch(
+        &self,
+        block_numbers: Range<u64>,
+    ) -> FetcherFuture<Vec<Option<Eth1Data>>> {
+        let block_count = block_numbers.end.saturating_sub(block_numbers.start) as usize;
+        let mut requests = Vec::with_capacity(block_count * 3);
+        for block_number in block_numbers {
+            requests.push(block_request(block_number));
+            let tag = block_tag(Some(block_number));
+            requests.push(self.deposit_contract_request(GET_DEPOSIT_ROOT_SELECTOR, tag.clone()));
+            requests.push(self.deposit_contract_request(GET_DEPOSIT_COUNT_SELECTOR, tag));
+        }
+        Box::new(
+            self.transport
+                .execute_batch(requests)
+                .and_then(move |results| {
+                    if results.len() != block_count * 3 {
+                        return Err(Eth1Error::DecodeError(format!(
+                            "Expected {} batch results, got {}",
+                            block_count * 3,
+                            results.len()
+                        )));
+                    }
+                    results
+                        .chunks(3)
+                        .map(|results| decode_eth1_data(&results[0], &results[1], &results[2]))
+                        .collect()
+                }),
+        )
+    }
+}
+
+/// Returns a request for the block at `height`, without its transactions.
+fn block_request(height: u64) -> Request {
+    (
+        "eth_getBlockByNumber",
+        vec![block_tag(Some(height)), json!(false)],
+    )
+}
+
+/// Decodes the `Eth1Data` of a block from the results of its requests in a batch, or `None` if
+/// the block is unknown.
+fn decode_eth1_data(
+    block: &Result<Value, Eth1Error>,
+    deposit_root: &Result<Value, Eth1Error>,
+    deposit_count: &Result<Value, Eth1Error>,
+) -> Result<Option<Eth1Data>, Eth1Error> {
+    let block_hash = match parse_block_hash(block.as_ref().map_err(Clone::clone)?)? {
+        Some(block_hash) => block_hash,
+        None => return Ok(None),
+    };
+    let deposit_root = parse_bytes(deposit_root.as_ref().map_err(Clone::clone)?)?;
+    let deposit_count = parse_bytes(deposit_count.as_ref().map_err(Clone::clone)?)?;
+    Ok(Some(Eth1Data {
+        deposit_root: decode_deposit_root(&deposit_root)?,
+        deposit_count: decode_deposit_count(&deposit_count)?,
+        block_hash,
+    }))
+}
+
+/// Parses the hash of a block returned by `eth_getBlockByNumber`, or `None` if there is no such
+/// block.
+fn parse_block_hash(block: &Value) -> Result<Option<Hash256>, Eth1Error> {
+    match block {
+        Value::Null => Ok(None),
+        block => parse_hash(&block["hash"]).map(Some),
+    }
+}
+
+/// Returns the JSON-RPC block parameter for `block_number`, or the latest block if `None`.
+pub(crate) fn block_tag(block_number: Option<u64>) -> Value {
+    match block_number {
+        Some(number) => json!(format!("0x{:x}", number)),
+        None => json!("latest"),
+    }
+}
+
+/// Parses a hex-encoded quantity, e.g. `"0x1a"`.
+pub(crate) fn parse_quantity(value: &Value) -> Result<u64, Eth1Error> {
+    let string = value
+        .as_str()
+        .ok_or_else(|| Eth1Error::DecodeError(format!("Expected a quantity, got {}", value)))?;
+    let digits = string.trim_start_matches("0x");
+    u64::from_str_radix(digits, 16)
+        .map_err(|e| Eth1Error::DecodeError(format!("Invalid quantity {}: {:?}", string, e)))
+}
+
+/// Parses hex-encoded data, e.g. `"0x00ff"`.
+pub(crate) fn parse_bytes(value: &Value) -> Result<Vec<u8>, Eth1Error> {
+    let string = value
+        .as_str()
+        .ok_or_else(|| Eth1Error::DecodeError(format!("Expected hex data, got {}", value)))?;
+    hex::decode(string.trim_start_matches("0x"))
+        .map_err(|e| Eth1Error::DecodeError(format!("Invalid hex data {}: {:?}", string, e)))
+}
+
+/// Parses a hex-encoded 32 byte hash.
+pub(crate) fn parse_hash(value: &Value) -> Result<Hash256, Eth1Error> {
+    let bytes = parse_bytes(value)?;
+    if bytes.len() == 32 {
+        Ok(Hash256::from_slice(&bytes))
+    } else {
+        Err(Eth1Error::DecodeError(format!(
+            "Invalid hash length: {}",
+            bytes.len()
+        )))
+    }
+}
+
+/// Decodes the result of `get_deposit_root()`.
+fn decode_deposit_root(bytes: &[u8]) -> Result<Hash256, Eth1Error> {
+    if bytes.len() == 32 {
+        Ok(Hash256::from_slice(bytes))
+    } else {
+        Err(Eth1Error::DecodeError(format!(
+            "Invalid deposit root length: {}",
+            bytes.len()
+        )))
+    }
+}
+
+/// Decodes the result of `get_deposit_count()`: ABI-encoded `bytes` holding the count as 8
+/// little-endian bytes.
+fn decode_deposit_count(bytes: &[u8]) -> Result<u64, Eth1Error> {
+    if bytes.len() < 72 || bytes[32..63].iter().any(|b| *b != 0) || bytes[63] != 8 {
+        return Err(Eth1Error::DecodeError(format!(
+            "Invalid deposit count encoding: 0x{}",
+            hex::encode(bytes)
+        )));
+    }
+    let mut count = [0; 8];
+    count.copy_from_slice(&bytes[64..72]);
+    Ok(u64::from_le_bytes(count))
+}
+
+/// Returns `count` encoded as the result of `get_deposit_count()`.
+#[cfg(test)]
+pub(crate) fn encode_deposit_count(count: u64) -> Vec<u8> {
+    let mut bytes = vec![0; 96];
+    bytes[31] = 32;
+    bytes[63] = 8;
+    bytes[64..72].copy_from_slice(&count.to_le_bytes());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth1::mock_node::{deposit_data, MockEth1Node, DEPOSIT_CONTRACT};
+
+    #[test]
+    fn reads_blocks_and_deposit_contract() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+
+        assert_eq!(fetcher.get_current_block_number().wait(), Ok(9));
+        assert_eq!(
+            fetcher.get_block_hash_by_height(4).wait(),
+            Ok(Some(node.block(4).block_hash))
+        );
+        assert_eq!(fetcher.get_block_hash_by_height(10).wait(), Ok(None));
+        assert_eq!(
+            fetcher.get_deposit_root(Some(4)).wait(),
+            Ok(node.block(4).deposit_root)
+        );
+        assert_eq!(
+            fetcher.get_deposit_count(Some(4)).wait(),
+            Ok(node.block(4).deposit_count)
+        );
+        assert_eq!(
+            fetcher.get_deposit_count(None).wait(),
+            Ok(node.block(9).deposit_count)
+        );
+    }
+
+    #[test]
+    fn batch_matches_separate_queries() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+
+        let batch = fetcher.get_eth1_data_batch(6..12).wait().unwrap();
+        assert_eq!(node.calls(), 1);
+        let expected: Vec<_> = (6..10).map(|n| Some(node.block(n))).collect();
+        assert_eq!(batch[..4], expected[..]);
+        assert_eq!(batch[4..], [None, None]);
+        assert_eq!(fetcher.get_eth1_data_batch(3..3).wait(), Ok(vec![]));
+    }
+
+    #[test]
+    fn repeated_queries_memoized_within_ttl() {
+        let node = Arc::new(MockEth1Node::new(10));
+        let mut fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+        fetcher.set_memo_ttl(Duration::from_millis(200));
+
+        assert_eq!(fetcher.get_current_block_number().wait(), Ok(9));
+        node.push_blocks(1);
+        assert_eq!(fetcher.get_current_block_number().wait(), Ok(9));
+        assert_eq!(node.calls(), 1);
+
+        // Different params are a different query.
+        fetcher.get_deposit_root(Some(3)).wait().unwrap();
+        fetcher.get_deposit_root(Some(4)).wait().unwrap();
+        fetcher.get_deposit_root(Some(3)).wait().unwrap();
+        assert_eq!(node.calls(), 3);
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert_eq!(fetcher.get_current_block_number().wait(), Ok(10));
+        assert_eq!(node.calls(), 4);
+
+        // Without a TTL, every query is sent.
+        let fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+        fetcher.get_current_block_number().wait().unwrap();
+        fetcher.get_current_block_number().wait().unwrap();
+        assert_eq!(node.calls(), 6);
+    }
+
+    #[test]
+    fn finalized_tag_preferred_where_supported() {
+        let node = Arc::new(MockEth1Node::new(20));
+        node.set_finalized(Some(15));
+        let mut fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+        fetcher.set_prefer_finalized(8);
+
+        assert_eq!(
+            fetcher.get_deposit_count(None).wait(),
+            Ok(node.block(15).deposit_count)
+        );
+        assert_eq!(
+            fetcher.get_deposit_root(None).wait(),
+            Ok(node.block(15).deposit_root)
+        );
+        assert_eq!(node.calls(), 2);
+        // Queries of a given block are unaffected.
+        assert_eq!(
+            fetcher.get_deposit_count(Some(4)).wait(),
+            Ok(node.block(4).deposit_count)
+        );
+    }
+
+    #[test]
+    fn follow_distance_read_where_finalized_unsupported() {
+        let node = Arc::new(MockEth1Node::new(20));
+        let mut fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+        fetcher.set_prefer_finalized(8);
+
+        // The `finalized` query fails, then the head and the block behind it are read.
+        assert_eq!(
+            fetcher.get_deposit_count(None).wait(),
+            Ok(node.block(11).deposit_count)
+        );
+        assert_eq!(node.calls(), 3);
+
+        // The tag is not tried again.
+        assert_eq!(
+            fetcher.get_deposit_root(None).wait(),
+            Ok(node.block(11).deposit_root)
+        );
+        assert_eq!(node.calls(), 5);
+
+        // An unreachable node is not recorded as lacking the tag.
+        let node = Arc::new(MockEth1Node::new(20));
+        let mut fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+        fetcher.set_prefer_finalized(8);
+        node.set_unresponsive(true);
+        assert!(fetcher.get_deposit_count(None).wait().is_err());
+        node.set_unresponsive(false);
+        node.set_finalized(Some(15));
+        assert_eq!(
+            fetcher.get_deposit_count(None).wait(),
+            Ok(node.block(15).deposit_count)
+        );
+    }
+
+    #[test]
+    fn deposit_logs_read_in_range() {
+        let node = Arc::new(MockEth1Node::new(20));
+        let deposits: Vec<_> = (0..4).map(deposit_data).collect();
+        node.add_deposit(3, deposits[0].clone());
+        node.add_deposit(7, deposits[1].clone());
+        node.add_deposit(7, deposits[2].clone());
+        node.add_deposit(12, deposits[3].clone());
+        let fetcher = Web3DataFetcher::new(node.clone(), DEPOSIT_CONTRACT.to_string());
+
+        let logs = fetcher.get_deposit_logs_in_range(4, 12).wait().unwrap();
+        assert_eq!(
+            logs,
+            vec![
+                DepositLog {
+                    deposit_data: deposits[1].clone(),
+                    block_number: 7,
+                    index: 1,
+                },
+                DepositLog {
+                    deposit_data: deposits[2].clone(),
+                    block_number: 7,
+                    index: 2,
+                },
+                DepositLog {
+                    deposit_data: deposits[3].clone(),
+                    block_number: 12,
+                    index: 3,
+                },
+            ]
+        );
+        assert_eq!(fetcher.get_deposit_logs_in_range(13, 19).wait(), Ok(vec![]));
+    }
+
+    #[test]
+    fn deposit_count_round_trip() {
+        let bytes = encode_deposit_count(1234);
+        assert_eq!(decode_deposit_count(&bytes), Ok(1234));
+        assert!(decode_deposit_count(&bytes[..64]).is_err());
+    }
+
+    #[test]
+    fn malformed_responses_are_decode_errors() {
+        for error in vec![
+            parse_quantity(&json!("0xzz")).unwrap_err(),
+            parse_quantity(&json!(12)).unwrap_err(),
+            parse_hash(&json!("0x00ff")).unwrap_err(),
+            decode_deposit_count(&[0; 8]).unwrap_err(),
+        ] {
+            match error {
+                Eth1Error::DecodeError(_) => assert!(!error.is_transient()),
+                other => panic!("Expected a decode error, got {:?}", other),
+            }
+        }
+    }
+}
@@ -1,14 +1,18 @@
+#[macro_use]
+extern crate lazy_static;
 extern crate slog;
 
 mod config;
 
 pub mod error;
+pub mod eth1;
 pub mod notifier;
 
 use beacon_chain::{
     lmd_ghost::ThreadSafeReducedTree, slot_clock::SystemTimeSlotClock, store::Store,
     test_utils::generate_deterministic_keypairs, BeaconChain, BeaconChainBuilder,
 };
+use eth1::{ClientEth1Backend, UpdateThrottle};
 use exit_future::Signal;
 use futures::{future::Future, Stream};
 use network::Service as NetworkService;
@@ -16,6 +20,7 @@ use rest_api::NetworkInfo;
 use slog::{crit, debug, error, info, o};
 use slot_clock::SlotClock;
 use std::marker::PhantomData;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime::TaskExecutor;
@@ -24,9 +29,14 @@ use types::EthSpec;
 use websocket_server::WebSocketSender;
 
 pub use beacon_chain::{BeaconChainTypes, Eth1ChainBackend, InteropEth1ChainBackend};
-pub use config::{BeaconChainStartMethod, Config as ClientConfig, Eth1BackendMethod};
+pub use config::{
+    verify_deposit_contract, BeaconChainStartMethod, Config as ClientConfig, Eth1BackendMethod,
+};
 pub use eth2_config::Eth2Config;
 
+/// The file in the data directory to which the Eth1 cache is saved on shutdown.
+const ETH1_CACHE_FILE: &str = "eth1_cache.json";
+
 #[derive(Clone)]
 pub struct RuntimeBeaconChainTypes<S: Store, E: EthSpec> {
     _phantom_s: PhantomData<S>,
@@ -41,7 +51,7 @@ where
     type Store = S;
     type SlotClock = SystemTimeSlotClock;
     type LmdGhost = ThreadSafeReducedTree<S, E>;
-    type Eth1Chain = InteropEth1ChainBackend<E>;
+    type Eth1Chain = ClientEth1Backend<E>;
     type EthSpec = E;
     type EventHandler = WebSocketSender<E>;
 }
@@ -63,6 +73,10 @@ where
     pub rpc_exit_signal: Option<Signal>,
     /// Signal to terminate the slot timer.
     pub slot_timer_exit_signal: Option<Signal>,
+    /// Signal to terminate the updates of the Eth1 cache.
+    pub eth1_exit_signal: Option<Signal>,
+    /// The Eth1 cache and the file to which it is saved when the client is dropped.
+    eth1_cache_snapshot: Option<(Arc<eth1::HttpEth1DataCache>, PathBuf)>,
     /// Signal to terminate the API
     pub api_exit_signal: Option<Signal>,
     /// Signal to terminate the websocket server
@@ -177,8 +191,34 @@ where
             }
         };
 
-        let eth1_backend =
-            InteropEth1ChainBackend::new(String::new()).map_err(|e| format!("{:?}", e))?;
+        let eth1_cache_file = client_config
+            .data_dir()
+            .map(|data_dir| data_dir.join(ETH1_CACHE_FILE));
+        let (eth1_backend, eth1_updater) = match &client_config.eth1_backend_method {
+            Eth1BackendMethod::Interop => (
+                ClientEth1Backend::new(String::new()).map_err(|e| format!("{:?}", e))?,
+                None,
+            ),
+            Eth1BackendMethod::Web3 {
+                server,
+                deposit_contract,
+            } => {
+                info!(
+                    log,
+                    "Following the Eth1 chain";
+                    "deposit_contract" => deposit_contract,
+                    "follow_distance" => client_config.eth1.follow_distance,
+                );
+                let backend = ClientEth1Backend::web3(
+                    server,
+                    deposit_contract.clone(),
+                    &client_config.eth1,
+                    log.new(o!("Service" => "Eth1")),
+                )?;
+                let updater = backend.updater();
+                (backend, updater)
+            }
+        };
 
         // Start the websocket server.
         let (websocket_sender, websocket_exit_signal): (WebSocketSender<E>, Option<_>) =
@@ -193,12 +233,39 @@ where
                 (WebSocketSender::dummy(), None)
             };
 
+        let eth1_cache_snapshot = eth1_backend
+            .cache()
+            .and_then(|cache| Some((cache, eth1_cache_file.clone()?)));
+
         let beacon_chain: Arc<BeaconChain<RuntimeBeaconChainTypes<S, E>>> = Arc::new(
             beacon_chain_builder
                 .build(store, eth1_backend, websocket_sender)
                 .map_err(error::Error::from)?,
         );
 
+        // Spawn the eth1 cache updater, now that the chain can tell when a proposal is near.
+        let eth1_exit_signal = eth1_updater.map(|updater| {
+            let eth1_config = &client_config.eth1;
+            let throttle = eth1_config.proposer_indices.clone().map(|validators| {
+                let proposal_near = eth1::proposal_near(
+                    beacon_chain.clone(),
+                    validators,
+                    eth1_config.proposal_lookahead_epochs,
+                );
+                UpdateThrottle::new(eth1_config.heartbeat_ticks(), proposal_near)
+            });
+            let (eth1_exit_signal, exit) = exit_future::signal();
+            updater.spawn(
+                Duration::from_millis(eth1_config.update_interval_millis),
+                eth1_cache_file,
+                throttle,
+                executor,
+                exit,
+                log.new(o!("Service" => "Eth1")),
+            );
+            eth1_exit_signal
+        });
+
         let since_epoch = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .map_err(|e| format!("Unable to read system time: {}", e))?;
@@ -286,6 +353,8 @@ where
             beacon_chain,
             rpc_exit_signal,
             slot_timer_exit_signal: Some(slot_timer_exit_signal),
+            eth1_exit_signal,
+            eth1_cache_snapshot,
             api_exit_signal,
             websocket_exit_signal,
             log,
@@ -298,6 +367,12 @@ impl<S: Store + Clone, E: EthSpec> Drop for Client<S, E> {
     fn drop(&mut self) {
         // Save the beacon chain to it's store before dropping.
         let _result = self.beacon_chain.persist();
+
+        if let Some((cache, path)) = &self.eth1_cache_snapshot {
+            if let Err(e) = cache.save_to_file(path) {
+                error!(self.log, "Unable to save the Eth1 cache"; "error" => e);
+            }
+        }
     }
 }
 
@@ -177,7 +177,7 @@ pub fn get_new_beacon_block<T: BeaconChainTypes + 'static>(req: Request<Body>) -
     })?;
 
     let (new_block, _state) = beacon_chain
-        .produce_block(randao_reveal, slot)
+        .produce_block(randao_reveal, slot, None)
         .map_err(|e| {
             ApiError::ServerError(format!(
                 "Beacon node is not able to produce a block: {:?}",
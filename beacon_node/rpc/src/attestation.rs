@@ -1,4 +1,4 @@
-use beacon_chain::{BeaconChain, BeaconChainError, BeaconChainTypes};
+use beacon_chain::{AttestationProcessingOutcome, BeaconChain, BeaconChainError, BeaconChainTypes};
 use eth2_libp2p::PubsubMessage;
 use eth2_libp2p::Topic;
 use eth2_libp2p::{BEACON_ATTESTATION_TOPIC, TOPIC_ENCODING_POSTFIX, TOPIC_PREFIX};
@@ -6,15 +6,18 @@ use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
 use network::NetworkMessage;
 use protos::services::{
-    AttestationData as AttestationDataProto, ProduceAttestationDataRequest,
-    ProduceAttestationDataResponse, PublishAttestationRequest, PublishAttestationResponse,
+    Attestation as AttestationProto, AttestationData as AttestationDataProto,
+    ProduceAggregateAttestationRequest, ProduceAggregateAttestationResponse,
+    ProduceAttestationDataRequest, ProduceAttestationDataResponse, PublishAggregateAndProofRequest,
+    PublishAttestationRequest, PublishAttestationResponse,
 };
 use protos::services_grpc::AttestationService;
 use slog::{error, info, trace, warn};
 use ssz::{ssz_encode, Decode, Encode};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use types::{Attestation, Slot};
+use types::aggregate_and_proof::is_aggregator;
+use types::{AggregateAndProof, Attestation, EthSpec, Slot};
 
 #[derive(Clone)]
 pub struct AttestationServiceInstance<T: BeaconChainTypes> {
@@ -81,7 +84,6 @@ impl<T: BeaconChainTypes> AttestationService for AttestationServiceInstance<T> {
     ) {
         trace!(self.log, "Publishing attestation");
 
-        let mut resp = PublishAttestationResponse::new();
         let ssz_serialized_attestation = req.get_attestation().get_ssz();
 
         let attestation = match Attestation::from_ssz_bytes(ssz_serialized_attestation) {
@@ -98,7 +100,153 @@ impl<T: BeaconChainTypes> AttestationService for AttestationServiceInstance<T> {
             }
         };
 
+        let resp = self.process_and_publish(attestation);
+
+        let error_log = self.log.clone();
+        let f = sink
+            .success(resp)
+            .map_err(move |e| error!(error_log, "failed to reply {:?}: {:?}", req, e));
+        ctx.spawn(f)
+    }
+
+    /// Produce the aggregate of the attestations in the operation pool for the committee of a
+    /// `shard` at a `slot`, for publication by an aggregator.
+    fn produce_aggregate_attestation(
+        &mut self,
+        ctx: RpcContext,
+        req: ProduceAggregateAttestationRequest,
+        sink: UnarySink<ProduceAggregateAttestationResponse>,
+    ) {
+        trace!(
+            self.log,
+            "Producing aggregate attestation";
+            "slot" => req.get_slot(),
+            "shard" => req.get_shard(),
+        );
+
+        let attestation_data = match self
+            .chain
+            .produce_attestation_data(req.get_shard(), Slot::from(req.get_slot()))
+        {
+            Ok(v) => v,
+            Err(e) => {
+                let log_clone = self.log.clone();
+                let f = sink
+                    .fail(RpcStatus::new(
+                        RpcStatusCode::Unknown,
+                        Some(format!("Could not produce an attestation: {:?}", e)),
+                    ))
+                    .map_err(move |e| warn!(log_clone, "failed to reply {:?}: {:?}", req, e));
+                return ctx.spawn(f);
+            }
+        };
+
+        let mut resp = ProduceAggregateAttestationResponse::new();
+        if let Some(aggregate) = self.chain.get_aggregate_attestation(&attestation_data) {
+            let mut aggregate_proto = AttestationProto::new();
+            aggregate_proto.set_ssz(aggregate.as_ssz_bytes());
+            resp.set_aggregate(aggregate_proto);
+        }
+
+        let error_log = self.log.clone();
+        let f = sink
+            .success(resp)
+            .map_err(move |e| error!(error_log, "failed to reply {:?}: {:?}", req, e));
+        ctx.spawn(f)
+    }
+
+    /// Accept an aggregate from a validator selected to aggregate the attestations of its
+    /// committee, publishing the aggregate if the selection proof and signature are valid.
+    fn publish_aggregate_and_proof(
+        &mut self,
+        ctx: RpcContext,
+        req: PublishAggregateAndProofRequest,
+        sink: UnarySink<PublishAttestationResponse>,
+    ) {
+        trace!(self.log, "Publishing aggregate and proof");
+
+        let ssz_serialized = req.get_aggregate_and_proof().get_ssz();
+        let aggregate_and_proof = match AggregateAndProof::from_ssz_bytes(ssz_serialized) {
+            Ok(v) => v,
+            Err(_) => {
+                let log_clone = self.log.clone();
+                let f = sink
+                    .fail(RpcStatus::new(
+                        RpcStatusCode::InvalidArgument,
+                        Some("Invalid aggregate and proof".to_string()),
+                    ))
+                    .map_err(move |_| warn!(log_clone, "failed to reply {:?}", req));
+                return ctx.spawn(f);
+            }
+        };
+
+        let resp = match self.verify_aggregator(&aggregate_and_proof) {
+            Ok(()) => self.process_and_publish(aggregate_and_proof.aggregate),
+            Err(msg) => {
+                warn!(
+                    self.log,
+                    "Invalid aggregate and proof from RPC";
+                    "aggregator_index" => aggregate_and_proof.aggregator_index,
+                    "reason" => &msg,
+                );
+                let mut resp = PublishAttestationResponse::new();
+                resp.set_success(false);
+                resp.set_msg(msg.as_bytes().to_vec());
+                resp
+            }
+        };
+
+        let error_log = self.log.clone();
+        let f = sink
+            .success(resp)
+            .map_err(move |e| error!(error_log, "failed to reply {:?}: {:?}", req, e));
+        ctx.spawn(f)
+    }
+}
+
+impl<T: BeaconChainTypes> AttestationServiceInstance<T> {
+    /// Processes `attestation`, publishing it to the network if it is valid, and returns the
+    /// response describing the outcome.
+    fn process_and_publish(
+        &self,
+        attestation: Attestation<T::EthSpec>,
+    ) -> PublishAttestationResponse {
+        let mut resp = PublishAttestationResponse::new();
+
+        if let Some(msg) = self.inclusion_window_passed(&attestation) {
+            warn!(
+                self.log,
+                "Attestation from RPC too old to be included";
+                "reason" => &msg,
+            );
+            resp.set_success(false);
+            resp.set_too_old(true);
+            resp.set_msg(msg.as_bytes().to_vec());
+            return resp;
+        }
+
         match self.chain.process_attestation(attestation.clone()) {
+            Ok(AttestationProcessingOutcome::FinalizedSlot {
+                attestation: attestation_epoch,
+                finalized,
+            }) => {
+                warn!(
+                    self.log,
+                    "Attestation from RPC too old to be included";
+                    "attestation_epoch" => attestation_epoch,
+                    "finalized_epoch" => finalized,
+                );
+                resp.set_success(false);
+                resp.set_too_old(true);
+                resp.set_msg(
+                    format!(
+                        "Attestation epoch {} is not after the finalized epoch {}",
+                        attestation_epoch, finalized
+                    )
+                    .as_bytes()
+                    .to_vec(),
+                );
+            }
             Ok(_) => {
                 // Attestation was successfully processed.
                 info!(
@@ -149,6 +297,7 @@ impl<T: BeaconChainTypes> AttestationService for AttestationServiceInstance<T> {
                     "error" => format!("{:?}", e),
                 );
                 resp.set_success(false);
+                resp.set_retryable(true);
                 resp.set_msg(
                     format!("There was a beacon chain error: {:?}", e)
                         .as_bytes()
@@ -157,10 +306,64 @@ impl<T: BeaconChainTypes> AttestationService for AttestationServiceInstance<T> {
             }
         };
 
-        let error_log = self.log.clone();
-        let f = sink
-            .success(resp)
-            .map_err(move |e| error!(error_log, "failed to reply {:?}: {:?}", req, e));
-        ctx.spawn(f)
+        resp
+    }
+
+    /// Checks that the aggregator of `aggregate_and_proof` was selected to aggregate its
+    /// committee's attestations, and signed the aggregate, using the fork of the head state.
+    fn verify_aggregator(
+        &self,
+        aggregate_and_proof: &AggregateAndProof<T::EthSpec>,
+    ) -> Result<(), String> {
+        let spec = &self.chain.spec;
+        self.chain.with_head(|head| {
+            let state = &head.beacon_state;
+            let aggregator_index = aggregate_and_proof.aggregator_index;
+            let aggregator = state
+                .validators
+                .get(aggregator_index as usize)
+                .ok_or_else(|| format!("Unknown aggregator {}", aggregator_index))?;
+            let aggregate = &aggregate_and_proof.aggregate;
+            let slot = state
+                .get_attestation_data_slot(&aggregate.data)
+                .map_err(|e| format!("Unable to find the slot of the aggregate: {:?}", e))?;
+            let pubkey = &aggregator.pubkey;
+
+            if !aggregate_and_proof.is_valid_selection_proof(slot, pubkey, &state.fork, spec) {
+                Err("Invalid selection proof".to_string())
+            } else if !is_aggregator(
+                aggregate.aggregation_bits.len(),
+                &aggregate_and_proof.selection_proof,
+            ) {
+                Err("The validator was not selected to aggregate".to_string())
+            } else if !aggregate_and_proof.is_valid_signature(slot, pubkey, &state.fork, spec) {
+                Err("Invalid aggregate and proof signature".to_string())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// Returns the reason `attestation` can no longer be included in a block, if its inclusion
+    /// window has passed. Returns `None` if the window cannot be determined from the head state.
+    fn inclusion_window_passed(&self, attestation: &Attestation<T::EthSpec>) -> Option<String> {
+        let current_slot = self.chain.slot().ok()?;
+        let attestation_slot = self
+            .chain
+            .with_head(|head| {
+                head.beacon_state
+                    .get_attestation_data_slot(&attestation.data)
+            })
+            .ok()?;
+        let last_inclusion_slot = attestation_slot + T::EthSpec::slots_per_epoch();
+
+        if current_slot > last_inclusion_slot {
+            Some(format!(
+                "Attestation for slot {} cannot be included after slot {}, the current slot is {}",
+                attestation_slot, last_inclusion_slot, current_slot
+            ))
+        } else {
+            None
+        }
     }
 }
@@ -14,7 +14,7 @@ use slog::{error, info, trace, warn};
 use ssz::{ssz_encode, Decode, Encode};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use types::{BeaconBlock, Signature, Slot};
+use types::{BeaconBlock, RelativeEpoch, Signature, Slot};
 
 #[derive(Clone)]
 pub struct BeaconBlockServiceInstance<T: BeaconChainTypes> {
@@ -50,22 +50,48 @@ impl<T: BeaconChainTypes> BeaconBlockService for BeaconBlockServiceInstance<T> {
             }
         };
 
-        let produced_block = match self.chain.produce_block(randao_reveal, requested_slot) {
-            Ok((block, _state)) => block,
-            Err(e) => {
-                // could not produce a block
+        let graffiti = match req.get_graffiti().len() {
+            0 => None,
+            32 => {
+                let mut graffiti = [0; 32];
+                graffiti.copy_from_slice(req.get_graffiti());
+                Some(graffiti)
+            }
+            _ => {
                 let log_clone = self.log.clone();
-                warn!(self.log, "RPC Error"; "Error" => format!("Could not produce a block:{:?}",e));
                 let f = sink
                     .fail(RpcStatus::new(
-                        RpcStatusCode::Unknown,
-                        Some(format!("Could not produce a block: {:?}", e)),
+                        RpcStatusCode::InvalidArgument,
+                        Some("Graffiti must be 32 bytes".to_string()),
                     ))
                     .map_err(move |e| warn!(log_clone, "failed to reply {:?}: {:?}", req, e));
                 return ctx.spawn(f);
             }
         };
 
+        let produced = self
+            .chain
+            .produce_block(randao_reveal, requested_slot, graffiti)
+            .map_err(|e| format!("Could not produce a block: {:?}", e))
+            .and_then(|(block, state)| {
+                state
+                    .get_beacon_proposer_index(block.slot, RelativeEpoch::Current, &self.chain.spec)
+                    .map(|proposer_index| (block, proposer_index))
+                    .map_err(|e| format!("Could not find the block proposer: {:?}", e))
+            });
+        let (produced_block, proposer_index) = match produced {
+            Ok(produced) => produced,
+            Err(e) => {
+                // could not produce a block
+                let log_clone = self.log.clone();
+                warn!(self.log, "RPC Error"; "Error" => &e);
+                let f = sink
+                    .fail(RpcStatus::new(RpcStatusCode::Unknown, Some(e)))
+                    .map_err(move |e| warn!(log_clone, "failed to reply {:?}: {:?}", req, e));
+                return ctx.spawn(f);
+            }
+        };
+
         assert_eq!(
             produced_block.slot, requested_slot,
             "should produce at the requested slot"
@@ -76,6 +102,7 @@ impl<T: BeaconChainTypes> BeaconBlockService for BeaconBlockServiceInstance<T> {
 
         let mut resp = ProduceBeaconBlockResponse::new();
         resp.set_block(block);
+        resp.set_proposer_index(proposer_index as u64);
 
         let f = sink
             .success(resp)
@@ -155,11 +182,10 @@ impl<T: BeaconChainTypes> BeaconBlockService for BeaconBlockServiceInstance<T> {
                         );
 
                         resp.set_success(false);
+                        resp.set_retryable(true);
                         resp.set_msg(format!("failed_to_process: {:?}", e).as_bytes().to_vec());
                     }
                 }
-
-                resp.set_success(true);
             }
             Err(_) => {
                 resp.set_success(false);
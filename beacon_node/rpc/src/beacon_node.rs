@@ -5,6 +5,7 @@ use protos::services::{Empty, Fork, NodeInfoResponse};
 use protos::services_grpc::BeaconNodeService;
 use slog::{trace, warn};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Clone)]
 pub struct BeaconNodeServiceInstance<T: BeaconChainTypes> {
@@ -21,10 +22,11 @@ impl<T: BeaconChainTypes> BeaconNodeService for BeaconNodeServiceInstance<T> {
         let mut node_info = NodeInfoResponse::new();
         node_info.set_version(version::version());
 
-        // get the chain state
-        let state = &self.chain.head().beacon_state;
-        let state_fork = state.fork.clone();
-        let genesis_time = state.genesis_time;
+        // read the fields from the head state in place, as this is requested every slot
+        let (state_fork, genesis_time, head_slot) = self.chain.with_head(|head| {
+            let state = &head.beacon_state;
+            (state.fork.clone(), state.genesis_time, state.slot)
+        });
 
         // build the rpc fork struct
         let mut fork = Fork::new();
@@ -38,6 +40,13 @@ impl<T: BeaconChainTypes> BeaconNodeService for BeaconNodeServiceInstance<T> {
         node_info.set_genesis_time(genesis_time);
         node_info.set_genesis_slot(spec.genesis_slot.as_u64());
         node_info.set_network_id(u32::from(spec.network_id));
+        node_info.set_head_slot(head_slot.as_u64());
+        node_info.set_current_time_ms(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|now| now.as_millis() as u64)
+                .unwrap_or(0),
+        );
 
         // send the node_info the requester
         let error_log = self.log.clone();
@@ -2,12 +2,18 @@ use beacon_chain::{BeaconChain, BeaconChainTypes};
 use bls::PublicKey;
 use futures::Future;
 use grpcio::{RpcContext, RpcStatus, RpcStatusCode, UnarySink};
-use protos::services::{ActiveValidator, GetDutiesRequest, GetDutiesResponse, ValidatorDuty};
+use protos::services::{
+    ActiveValidator, GetDutiesRequest, GetDutiesResponse, GetValidatorBalancesRequest,
+    GetValidatorBalancesResponse, GetValidatorLivenessRequest, GetValidatorLivenessResponse,
+    GetValidatorStatusRequest, GetValidatorStatusResponse, ValidatorDuty, ValidatorStatus,
+};
 use protos::services_grpc::ValidatorService;
 use slog::{trace, warn};
 use ssz::Decode;
+use state_processing::common::get_attesting_indices;
+use std::collections::HashSet;
 use std::sync::Arc;
-use types::{Epoch, EthSpec, RelativeEpoch};
+use types::{BeaconState, BeaconStateError, Epoch, EthSpec, RelativeEpoch};
 
 #[derive(Clone)]
 pub struct ValidatorServiceInstance<T: BeaconChainTypes> {
@@ -165,6 +171,7 @@ impl<T: BeaconChainTypes> ValidatorService for ValidatorServiceInstance<T> {
             duty.set_attestation_slot(attestation_duties.slot.as_u64());
             duty.set_attestation_shard(attestation_duties.shard);
             duty.set_committee_len(attestation_duties.committee_len as u64);
+            duty.set_validator_index(val_index as u64);
 
             active_validator.set_duty(duty);
             resp_validators.push(active_validator);
@@ -175,4 +182,277 @@ impl<T: BeaconChainTypes> ValidatorService for ValidatorServiceInstance<T> {
             .map_err(move |e| println!("failed to reply {:?}: {:?}", req, e));
         ctx.spawn(f)
     }
+
+    /// For a list of validator public keys, this function returns the status of each validator
+    /// at the requested epoch, according to the validator registry of the head state.
+    fn get_validator_status(
+        &mut self,
+        ctx: RpcContext,
+        req: GetValidatorStatusRequest,
+        sink: UnarySink<GetValidatorStatusResponse>,
+    ) {
+        trace!(self.log, "RPC request"; "endpoint" => "GetValidatorStatus", "epoch" => req.get_epoch());
+
+        let epoch = Epoch::from(req.get_epoch());
+        let mut state = self.chain.head().beacon_state;
+        if let Err(e) = state.update_pubkey_cache() {
+            let log_clone = self.log.clone();
+            let f = sink
+                .fail(RpcStatus::new(
+                    RpcStatusCode::FailedPrecondition,
+                    Some(format!("Unable to build pubkey cache: {:?}", e)),
+                ))
+                .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+            return ctx.spawn(f);
+        }
+        let far_future_epoch = self.chain.spec.far_future_epoch;
+
+        let mut statuses = Vec::with_capacity(req.get_validators().get_public_keys().len());
+        for validator_pk in req.get_validators().get_public_keys() {
+            let public_key = match PublicKey::from_ssz_bytes(validator_pk) {
+                Ok(v) => v,
+                Err(_) => {
+                    let log_clone = self.log.clone();
+                    let f = sink
+                        .fail(RpcStatus::new(
+                            RpcStatusCode::InvalidArgument,
+                            Some("Invalid public_key".to_string()),
+                        ))
+                        .map_err(move |_| warn!(log_clone, "failed to reply {:?}", req));
+                    return ctx.spawn(f);
+                }
+            };
+
+            let validator = match state.get_validator_index(&public_key) {
+                Ok(index) => index.and_then(|index| state.validators.get(index)),
+                // the cache is not built, throw an error
+                Err(e) => {
+                    let log_clone = self.log.clone();
+                    let f = sink
+                        .fail(RpcStatus::new(
+                            RpcStatusCode::FailedPrecondition,
+                            Some(format!("Beacon state error {:?}", e)),
+                        ))
+                        .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+                    return ctx.spawn(f);
+                }
+            };
+
+            statuses.push(match validator {
+                None => ValidatorStatus::UNKNOWN,
+                Some(v) if v.is_exited_at(epoch) => ValidatorStatus::EXITED,
+                Some(v) if v.is_active_at(epoch) && v.exit_epoch != far_future_epoch => {
+                    ValidatorStatus::EXITING
+                }
+                Some(v) if v.is_active_at(epoch) => ValidatorStatus::ACTIVE,
+                Some(_) => ValidatorStatus::PENDING,
+            });
+        }
+
+        let mut resp = GetValidatorStatusResponse::new();
+        resp.set_statuses(statuses);
+
+        let log_clone = self.log.clone();
+        let f = sink
+            .success(resp)
+            .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+        ctx.spawn(f)
+    }
+
+    /// For a list of validator public keys, this function returns whether each validator has an
+    /// attestation targeting the requested epoch included in the head state. Only the current and
+    /// previous epochs of the head state are known.
+    fn get_validator_liveness(
+        &mut self,
+        ctx: RpcContext,
+        req: GetValidatorLivenessRequest,
+        sink: UnarySink<GetValidatorLivenessResponse>,
+    ) {
+        trace!(self.log, "RPC request"; "endpoint" => "GetValidatorLiveness", "epoch" => req.get_epoch());
+
+        match self.validator_liveness(&req) {
+            Ok(is_live) => {
+                let mut resp = GetValidatorLivenessResponse::new();
+                resp.set_is_live(is_live);
+
+                let log_clone = self.log.clone();
+                let f = sink
+                    .success(resp)
+                    .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+                ctx.spawn(f)
+            }
+            Err(status) => {
+                let log_clone = self.log.clone();
+                let f = sink
+                    .fail(status)
+                    .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+                ctx.spawn(f)
+            }
+        }
+    }
+
+    /// For a list of validator public keys, this function returns the balance of each validator
+    /// in the head state, along with the current epoch of that state.
+    fn get_validator_balances(
+        &mut self,
+        ctx: RpcContext,
+        req: GetValidatorBalancesRequest,
+        sink: UnarySink<GetValidatorBalancesResponse>,
+    ) {
+        trace!(self.log, "RPC request"; "endpoint" => "GetValidatorBalances");
+
+        match self.validator_balances(&req) {
+            Ok((epoch, balances)) => {
+                let mut resp = GetValidatorBalancesResponse::new();
+                resp.set_epoch(epoch.as_u64());
+                resp.set_balances(balances);
+
+                let log_clone = self.log.clone();
+                let f = sink
+                    .success(resp)
+                    .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+                ctx.spawn(f)
+            }
+            Err(status) => {
+                let log_clone = self.log.clone();
+                let f = sink
+                    .fail(status)
+                    .map_err(move |e| warn!(log_clone, "Failed to reply {:?}: {:?}", req, e));
+                ctx.spawn(f)
+            }
+        }
+    }
+}
+
+impl<T: BeaconChainTypes> ValidatorServiceInstance<T> {
+    /// Returns whether each validator in `req` attested in the requested epoch.
+    ///
+    /// The head state's caches are built as it becomes the head, so it is read in place rather
+    /// than cloned. Should a cache be missing, only the caches needed for the epoch are built, on
+    /// a copy of the state.
+    fn validator_liveness(
+        &self,
+        req: &GetValidatorLivenessRequest,
+    ) -> Result<Vec<bool>, RpcStatus> {
+        let epoch = Epoch::from(req.get_epoch());
+        let public_keys = req
+            .get_validators()
+            .get_public_keys()
+            .iter()
+            .map(|validator_pk| {
+                PublicKey::from_ssz_bytes(validator_pk).map_err(|_| {
+                    RpcStatus::new(
+                        RpcStatusCode::InvalidArgument,
+                        Some("Invalid public_key".to_string()),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let state_error = |e: BeaconStateError| {
+            RpcStatus::new(
+                RpcStatusCode::FailedPrecondition,
+                Some(format!("Beacon state error {:?}", e)),
+            )
+        };
+        let relative_epoch = |state: &BeaconState<T::EthSpec>| match RelativeEpoch::from_epoch(
+            state.current_epoch(),
+            epoch,
+        ) {
+            Ok(relative_epoch) if relative_epoch != RelativeEpoch::Next => Ok(relative_epoch),
+            _ => Err(RpcStatus::new(
+                RpcStatusCode::FailedPrecondition,
+                Some("Liveness is only known for the current and previous epochs".to_string()),
+            )),
+        };
+
+        let result = self.chain.with_head(|head| {
+            let state = &head.beacon_state;
+            relative_epoch(state).map(|_| live_validators(state, epoch, &public_keys))
+        })?;
+
+        match result {
+            Err(BeaconStateError::CommitteeCacheUninitialized(_))
+            | Err(BeaconStateError::PubkeyCacheIncomplete { .. }) => {
+                let mut state = self.chain.head().beacon_state;
+                let relative_epoch = relative_epoch(&state)?;
+                state
+                    .build_committee_cache(relative_epoch, &self.chain.spec)
+                    .map_err(state_error)?;
+                state.update_pubkey_cache().map_err(state_error)?;
+                live_validators(&state, epoch, &public_keys).map_err(state_error)
+            }
+            result => result.map_err(state_error),
+        }
+    }
+
+    /// Returns the current epoch of the head state and the balance of each validator in `req`.
+    fn validator_balances(
+        &self,
+        req: &GetValidatorBalancesRequest,
+    ) -> Result<(Epoch, Vec<u64>), RpcStatus> {
+        let mut state = self.chain.head().beacon_state;
+
+        let state_error = |e: BeaconStateError| {
+            RpcStatus::new(
+                RpcStatusCode::FailedPrecondition,
+                Some(format!("Beacon state error {:?}", e)),
+            )
+        };
+        state.update_pubkey_cache().map_err(state_error)?;
+
+        let balances = req
+            .get_validators()
+            .get_public_keys()
+            .iter()
+            .map(|validator_pk| {
+                let public_key = PublicKey::from_ssz_bytes(validator_pk).map_err(|_| {
+                    RpcStatus::new(
+                        RpcStatusCode::InvalidArgument,
+                        Some("Invalid public_key".to_string()),
+                    )
+                })?;
+
+                Ok(state
+                    .get_validator_index(&public_key)
+                    .map_err(state_error)?
+                    .and_then(|index| state.balances.get(index).cloned())
+                    .unwrap_or(0))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok((state.current_epoch(), balances))
+    }
+}
+
+/// Returns whether each of `public_keys` has an attestation targeting `epoch` included in
+/// `state`, which must be the current or previous epoch of the state.
+fn live_validators<E: EthSpec>(
+    state: &BeaconState<E>,
+    epoch: Epoch,
+    public_keys: &[PublicKey],
+) -> Result<Vec<bool>, BeaconStateError> {
+    let attestations = if epoch == state.current_epoch() {
+        &state.current_epoch_attestations
+    } else {
+        &state.previous_epoch_attestations
+    };
+
+    let mut live_indices = HashSet::new();
+    for attestation in attestations.iter() {
+        live_indices.extend(get_attesting_indices(
+            state,
+            &attestation.data,
+            &attestation.aggregation_bits,
+        )?);
+    }
+
+    public_keys
+        .iter()
+        .map(|public_key| {
+            Ok(state
+                .get_validator_index(public_key)?
+                .map_or(false, |index| live_indices.contains(&index)))
+        })
+        .collect()
 }
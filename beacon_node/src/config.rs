@@ -1,5 +1,7 @@
 use clap::ArgMatches;
-use client::{BeaconChainStartMethod, ClientConfig, Eth1BackendMethod, Eth2Config};
+use client::{
+    verify_deposit_contract, BeaconChainStartMethod, ClientConfig, Eth1BackendMethod, Eth2Config,
+};
 use eth2_config::{read_from_file, write_to_file};
 use lighthouse_bootstrap::Bootstrapper;
 use rand::{distributions::Alphanumeric, Rng};
@@ -28,8 +30,12 @@ pub fn get_configs(cli_args: &ArgMatches, core_log: Logger) -> Result<Config> {
     let mut builder = ConfigBuilder::new(cli_args, core_log)?;
 
     if let Some(server) = cli_args.value_of("eth1-server") {
+        let deposit_contract = cli_args
+            .value_of("deposit-contract")
+            .ok_or("The --deposit-contract address is required with --eth1-server")?;
         builder.set_eth1_backend_method(Eth1BackendMethod::Web3 {
             server: server.into(),
+            deposit_contract: deposit_contract.into(),
         })
     } else {
         builder.set_eth1_backend_method(Eth1BackendMethod::Interop)
@@ -563,6 +569,21 @@ impl ConfigBuilder {
             return Err("Specification constant mismatch".into());
         }
 
+        if let Eth1BackendMethod::Web3 {
+            deposit_contract, ..
+        } = &self.client_config.eth1_backend_method
+        {
+            if cli_args.is_present("allow-unknown-deposit-contract") {
+                warn!(
+                    self.log,
+                    "Deposit contract not verified";
+                    "deposit_contract" => deposit_contract,
+                );
+            } else {
+                verify_deposit_contract(&self.eth2_config.spec_constants, deposit_contract)?;
+            }
+        }
+
         Ok((self.client_config, self.eth2_config, self.log))
     }
 }
@@ -203,6 +203,100 @@ fn main() {
                 .help("Specifies the server for a web3 connection to the Eth1 chain.")
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("deposit-contract")
+                .long("deposit-contract")
+                .value_name("ADDRESS")
+                .help("The address of the deposit contract on the Eth1 chain. Must be that of the network of the spec constants.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("allow-unknown-deposit-contract")
+                .long("allow-unknown-deposit-contract")
+                .help("Use the deposit contract address even if it is not that of the network of the spec constants (e.g., for a custom testnet).")
+                .requires("deposit-contract")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("eth1-follow-distance")
+                .long("eth1-follow-distance")
+                .value_name("BLOCKS")
+                .help("The number of blocks behind the Eth1 head below which blocks are cached and voted for.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-cache-len")
+                .long("eth1-cache-len")
+                .value_name("BLOCKS")
+                .help("The most Eth1 blocks kept in memory.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-min-confirmations")
+                .long("eth1-min-confirmations")
+                .value_name("BLOCKS")
+                .help("The fewest confirmations of an Eth1 block served from the cache.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-prefer-finalized")
+                .long("eth1-prefer-finalized")
+                .help("Read the latest deposit root and count at the finalized Eth1 block, falling back to the follow distance if the Eth1 node does not support the finalized tag.")
+                .requires("eth1-server")
+                .takes_value(false)
+        )
+        .arg(
+            Arg::with_name("eth1-memo-ttl")
+                .long("eth1-memo-ttl")
+                .value_name("MILLISECONDS")
+                .help("The time for which identical requests to the Eth1 node are answered from memory. Zero disables this.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-requests-per-second")
+                .long("eth1-requests-per-second")
+                .value_name("REQUESTS")
+                .help("The most requests sent to the Eth1 node each second, e.g. to stay within the limits of a hosted node.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-progress-log-interval")
+                .long("eth1-progress-log-interval")
+                .value_name("MILLIS")
+                .help("The least time in milliseconds between progress logs while the Eth1 cache warms up.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-update-near-proposals-of")
+                .long("eth1-update-near-proposals-of")
+                .value_name("VALIDATOR_INDICES")
+                .help("Comma-separated validator indices. If set, the Eth1 cache is only updated while one of these validators has a proposal near, and otherwise at each heartbeat.")
+                .requires("eth1-server")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-proposal-lookahead")
+                .long("eth1-proposal-lookahead")
+                .value_name("EPOCHS")
+                .help("The epochs after the current epoch in which a proposal is near.")
+                .requires("eth1-update-near-proposals-of")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("eth1-heartbeat-interval")
+                .long("eth1-heartbeat-interval")
+                .value_name("MILLIS")
+                .help("The time in milliseconds between updates of the Eth1 cache while no proposal is near.")
+                .requires("eth1-update-near-proposals-of")
+                .takes_value(true)
+        )
         /*
          * Database parameters.
          */
@@ -1,4 +1,4 @@
-use client::{error, notifier, Client, ClientConfig, Eth1BackendMethod, Eth2Config};
+use client::{error, notifier, Client, ClientConfig, Eth2Config};
 use futures::sync::oneshot;
 use futures::Future;
 use slog::{error, info};
@@ -54,10 +54,6 @@ pub fn run_beacon_node(
         };
     }
 
-    if let Eth1BackendMethod::Web3 { .. } = client_config.eth1_backend_method {
-        return Err("Starting from web3 backend is not supported for interop.".into());
-    }
-
     match (db_type.as_str(), spec_constants.as_str()) {
         ("disk", "minimal") => run_client!(DiskStore, MinimalEthSpec),
         ("disk", "mainnet") => run_client!(DiskStore, MainnetEthSpec),
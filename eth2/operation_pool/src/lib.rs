@@ -23,8 +23,8 @@ use state_processing::per_block_processing::{
 use std::collections::{btree_map::Entry, hash_map, BTreeMap, HashMap, HashSet};
 use std::marker::PhantomData;
 use types::{
-    typenum::Unsigned, Attestation, AttesterSlashing, BeaconState, ChainSpec, Deposit, EthSpec,
-    ProposerSlashing, Transfer, Validator, VoluntaryExit,
+    typenum::Unsigned, Attestation, AttestationData, AttesterSlashing, BeaconState, ChainSpec,
+    Deposit, EthSpec, ProposerSlashing, Transfer, Validator, VoluntaryExit,
 };
 
 #[derive(Default, Debug)]
@@ -144,6 +144,23 @@ impl<T: EthSpec> OperationPool<T> {
         maximum_cover(valid_attestations, T::MaxAttestations::to_usize())
     }
 
+    /// Returns the attestation for `data` with the most signers, for publication by an
+    /// aggregator.
+    pub fn get_aggregate_attestation(
+        &self,
+        data: &AttestationData,
+        state: &BeaconState<T>,
+        spec: &ChainSpec,
+    ) -> Option<Attestation<T>> {
+        let id = AttestationId::from_data(data, state, spec);
+        self.attestations
+            .read()
+            .get(&id)?
+            .iter()
+            .max_by_key(|attestation| attestation.aggregation_bits.num_set_bits())
+            .cloned()
+    }
+
     /// Remove attestations which are too old to be included in a block.
     pub fn prune_attestations(&self, finalized_state: &BeaconState<T>) {
         // We know we can include an attestation if:
@@ -0,0 +1,229 @@
+use super::{Attestation, ChainSpec, Domain, EthSpec, Fork, Slot};
+use crate::test_utils::TestRandom;
+use bls::{PublicKey, Signature};
+use eth2_hashing::hash;
+
+use serde_derive::{Deserialize, Serialize};
+use ssz::Encode;
+use ssz_derive::{Decode, Encode};
+use test_random_derive::TestRandom;
+use tree_hash::{SignedRoot, TreeHash};
+use tree_hash_derive::{SignedRoot, TreeHash};
+
+/// The number of validators in each committee which are expected to be selected to aggregate its
+/// attestations.
+pub const TARGET_AGGREGATORS_PER_COMMITTEE: u64 = 16;
+
+/// An aggregate of a committee's attestations, published by a validator selected to aggregate
+/// them.
+///
+/// The `selection_proof` is the aggregator's signature of the attestation slot, which proves its
+/// selection.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    Encode,
+    Decode,
+    TreeHash,
+    TestRandom,
+    SignedRoot,
+)]
+#[serde(bound = "T: EthSpec")]
+pub struct AggregateAndProof<T: EthSpec> {
+    pub aggregator_index: u64,
+    pub aggregate: Attestation<T>,
+    pub selection_proof: Signature,
+    #[signed_root(skip_hashing)]
+    pub signature: Signature,
+}
+
+impl<T: EthSpec> AggregateAndProof<T> {
+    /// Returns `true` if the `selection_proof` is the signature of `pubkey` over `slot`, the slot
+    /// of the aggregate, under the selection proof domain of the slot's epoch.
+    pub fn is_valid_selection_proof(
+        &self,
+        slot: Slot,
+        pubkey: &PublicKey,
+        fork: &Fork,
+        spec: &ChainSpec,
+    ) -> bool {
+        let epoch = slot.epoch(T::slots_per_epoch());
+        let domain = spec.get_domain(epoch, Domain::SelectionProof, fork);
+        self.selection_proof
+            .verify(&slot.tree_hash_root(), domain, pubkey)
+    }
+
+    /// Returns `true` if the `signature` is the signature of `pubkey` over the aggregate and
+    /// selection proof, under the aggregate and proof domain of the epoch of `slot`, the slot of
+    /// the aggregate.
+    pub fn is_valid_signature(
+        &self,
+        slot: Slot,
+        pubkey: &PublicKey,
+        fork: &Fork,
+        spec: &ChainSpec,
+    ) -> bool {
+        let epoch = slot.epoch(T::slots_per_epoch());
+        let domain = spec.get_domain(epoch, Domain::AggregateAndProof, fork);
+        self.signature.verify(&self.signed_root(), domain, pubkey)
+    }
+}
+
+/// Returns `true` if the validator whose `selection_proof` is given was selected to aggregate
+/// the attestations of a committee of `committee_len` validators.
+///
+/// About `TARGET_AGGREGATORS_PER_COMMITTEE` validators are selected from each committee. Every
+/// validator of a smaller committee is selected.
+pub fn is_aggregator(committee_len: usize, selection_proof: &Signature) -> bool {
+    let modulo = std::cmp::max(1, committee_len as u64 / TARGET_AGGREGATORS_PER_COMMITTEE);
+    let digest = hash(&selection_proof.as_ssz_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&digest[0..8]);
+    u64::from_le_bytes(bytes) % modulo == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{generate_deterministic_keypair, SeedableRng, XorShiftRng};
+    use crate::*;
+
+    ssz_tests!(AggregateAndProof<MainnetEthSpec>);
+
+    /// Returns the selection proof of the validator with `index` for `slot`.
+    fn selection_proof(index: usize, slot: u64) -> Signature {
+        let keypair = generate_deterministic_keypair(index);
+        Signature::new(&Slot::new(slot).tree_hash_root(), 0, &keypair.sk)
+    }
+
+    /// Returns the aggregate and proof of the validator with `index` for `slot`, signed with the
+    /// `selection_domain` and `domain` types under `fork`.
+    fn signed_aggregate_and_proof(
+        index: usize,
+        slot: Slot,
+        selection_domain: Domain,
+        domain: Domain,
+        fork: &Fork,
+    ) -> AggregateAndProof<MinimalEthSpec> {
+        let spec = ChainSpec::minimal();
+        let keypair = generate_deterministic_keypair(index);
+        let epoch = slot.epoch(MinimalEthSpec::slots_per_epoch());
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let mut aggregate_and_proof = AggregateAndProof {
+            aggregator_index: index as u64,
+            aggregate: Attestation::random_for_test(&mut rng),
+            selection_proof: Signature::new(
+                &slot.tree_hash_root(),
+                spec.get_domain(epoch, selection_domain, fork),
+                &keypair.sk,
+            ),
+            signature: Signature::empty_signature(),
+        };
+        aggregate_and_proof.signature = Signature::new(
+            &aggregate_and_proof.signed_root(),
+            spec.get_domain(epoch, domain, fork),
+            &keypair.sk,
+        );
+        aggregate_and_proof
+    }
+
+    #[test]
+    fn aggregate_and_proof_verifies_under_its_own_domains() {
+        let spec = ChainSpec::minimal();
+        let pubkey = generate_deterministic_keypair(3).pk;
+        let slots_per_epoch = MinimalEthSpec::slots_per_epoch();
+        // The fork takes effect in epoch 1, so the slots either side are signed under different
+        // fork versions.
+        let fork = Fork {
+            previous_version: [0, 0, 0, 0],
+            current_version: [1, 0, 0, 0],
+            epoch: Epoch::new(1),
+        };
+
+        for slot in &[Slot::new(slots_per_epoch - 1), Slot::new(slots_per_epoch)] {
+            let valid = signed_aggregate_and_proof(
+                3,
+                *slot,
+                Domain::SelectionProof,
+                Domain::AggregateAndProof,
+                &fork,
+            );
+            assert!(valid.is_valid_selection_proof(*slot, &pubkey, &fork, &spec));
+            assert!(valid.is_valid_signature(*slot, &pubkey, &fork, &spec));
+
+            // The proof and signature are swapped between the two domains.
+            let swapped = signed_aggregate_and_proof(
+                3,
+                *slot,
+                Domain::AggregateAndProof,
+                Domain::SelectionProof,
+                &fork,
+            );
+            assert!(!swapped.is_valid_selection_proof(*slot, &pubkey, &fork, &spec));
+            assert!(!swapped.is_valid_signature(*slot, &pubkey, &fork, &spec));
+
+            // Signed by another validator.
+            let other_pubkey = generate_deterministic_keypair(4).pk;
+            assert!(!valid.is_valid_selection_proof(*slot, &other_pubkey, &fork, &spec));
+            assert!(!valid.is_valid_signature(*slot, &other_pubkey, &fork, &spec));
+        }
+
+        // Signed under the fork version of the other side of the fork.
+        let before_fork = Slot::new(slots_per_epoch - 1);
+        let after_fork = Slot::new(slots_per_epoch);
+        let valid_before = signed_aggregate_and_proof(
+            3,
+            before_fork,
+            Domain::SelectionProof,
+            Domain::AggregateAndProof,
+            &fork,
+        );
+        let post_fork = Fork {
+            epoch: Epoch::new(0),
+            ..fork.clone()
+        };
+        assert!(!valid_before.is_valid_selection_proof(before_fork, &pubkey, &post_fork, &spec));
+        assert!(!valid_before.is_valid_signature(before_fork, &pubkey, &post_fork, &spec));
+        // The domains of the epoch of a different slot do not verify.
+        assert!(!valid_before.is_valid_signature(after_fork, &pubkey, &fork, &spec));
+    }
+
+    #[test]
+    fn every_member_of_a_small_committee_aggregates() {
+        for index in 0..8 {
+            let proof = selection_proof(index, 1);
+            assert!(is_aggregator(1, &proof));
+            assert!(is_aggregator(
+                TARGET_AGGREGATORS_PER_COMMITTEE as usize * 2 - 1,
+                &proof
+            ));
+        }
+    }
+
+    #[test]
+    fn aggregators_selected_by_the_proof_hash() {
+        let committee_len = TARGET_AGGREGATORS_PER_COMMITTEE as usize * 4;
+        let proofs: Vec<Signature> = (0..256).map(|slot| selection_proof(0, slot)).collect();
+
+        for proof in &proofs {
+            let digest = hash(&proof.as_ssz_bytes());
+            let expected = digest[0..8]
+                .iter()
+                .rev()
+                .fold(0u64, |acc, byte| (acc << 8) | u64::from(*byte))
+                % 4
+                == 0;
+            assert_eq!(is_aggregator(committee_len, proof), expected);
+        }
+
+        // About a quarter of the validators are selected.
+        let selected = proofs
+            .iter()
+            .filter(|proof| is_aggregator(committee_len, proof))
+            .count();
+        assert!(selected > 32 && selected < 96, "selected {}", selected);
+    }
+}
@@ -13,6 +13,8 @@ pub enum Domain {
     Deposit,
     VoluntaryExit,
     Transfer,
+    SelectionProof,
+    AggregateAndProof,
 }
 
 /// Holds all the "constants" for a BeaconChain.
@@ -90,6 +92,8 @@ pub struct ChainSpec {
     domain_deposit: u32,
     domain_voluntary_exit: u32,
     domain_transfer: u32,
+    domain_selection_proof: u32,
+    domain_aggregate_and_proof: u32,
 
     pub boot_nodes: Vec<String>,
     pub network_id: u8,
@@ -107,6 +111,8 @@ impl ChainSpec {
             Domain::Deposit => self.domain_deposit,
             Domain::VoluntaryExit => self.domain_voluntary_exit,
             Domain::Transfer => self.domain_transfer,
+            Domain::SelectionProof => self.domain_selection_proof,
+            Domain::AggregateAndProof => self.domain_aggregate_and_proof,
         };
 
         let mut bytes: Vec<u8> = int_to_bytes4(domain_constant);
@@ -185,6 +191,8 @@ impl ChainSpec {
             domain_deposit: 3,
             domain_voluntary_exit: 4,
             domain_transfer: 5,
+            domain_selection_proof: 6,
+            domain_aggregate_and_proof: 7,
 
             /*
              * Network specific
@@ -269,5 +277,39 @@ mod tests {
         test_domain(Domain::Deposit, spec.domain_deposit, &spec);
         test_domain(Domain::VoluntaryExit, spec.domain_voluntary_exit, &spec);
         test_domain(Domain::Transfer, spec.domain_transfer, &spec);
+        test_domain(Domain::SelectionProof, spec.domain_selection_proof, &spec);
+        test_domain(
+            Domain::AggregateAndProof,
+            spec.domain_aggregate_and_proof,
+            &spec,
+        );
+    }
+
+    #[test]
+    fn aggregation_domains_match_known_values() {
+        let spec = ChainSpec::mainnet();
+        let fork = Fork {
+            previous_version: [0, 0, 0, 0],
+            current_version: [1, 2, 3, 4],
+            epoch: Epoch::new(2),
+        };
+
+        // The domain type occupies the low four bytes, and the fork version the high four.
+        assert_eq!(
+            spec.get_domain(Epoch::new(1), Domain::SelectionProof, &fork),
+            0x0000_0000_0000_0006
+        );
+        assert_eq!(
+            spec.get_domain(Epoch::new(1), Domain::AggregateAndProof, &fork),
+            0x0000_0000_0000_0007
+        );
+        assert_eq!(
+            spec.get_domain(Epoch::new(2), Domain::SelectionProof, &fork),
+            0x0403_0201_0000_0006
+        );
+        assert_eq!(
+            spec.get_domain(Epoch::new(2), Domain::AggregateAndProof, &fork),
+            0x0403_0201_0000_0007
+        );
     }
 }
@@ -0,0 +1,417 @@
+use super::beacon_node_attestation::BeaconNodeAttestation;
+use super::selection_proofs::SelectionProofCache;
+use super::{validate_attestation_data, Error, InvalidAttestationData};
+use crate::block_producer::{PublishOutcome, ValidatorEvent};
+use crate::publish_retry::publish_with_retries;
+use crate::signer::Signer;
+use crate::skip_reason::SkipReason;
+use core::marker::PhantomData;
+use slog::{crit, debug, error, info, warn};
+use std::sync::Arc;
+use tree_hash::SignedRoot;
+use types::aggregate_and_proof::is_aggregator;
+use types::{
+    AggregateAndProof, Attestation, AttestationDuty, ChainSpec, Domain, EthSpec, Fork, Signature,
+};
+
+/// Publishes the aggregate of a committee's attestations, if the validator is selected as one of
+/// the committee's aggregators.
+///
+/// A validator is selected by the hash of its signature over the duty slot, its selection proof,
+/// so about `TARGET_AGGREGATORS_PER_COMMITTEE` members of each committee aggregate. The proof is
+/// published with the aggregate so that others may check the selection.
+pub struct AggregationProducer<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> {
+    /// The current fork.
+    pub fork: Fork,
+    /// The attestation duty whose committee is aggregated.
+    pub duty: AttestationDuty,
+    /// The index of the validator, which identifies the aggregator.
+    pub validator_index: u64,
+    /// The chain specification.
+    pub spec: Arc<ChainSpec>,
+    /// The beacon node to connect to.
+    pub beacon_node: Arc<B>,
+    /// The signer of the selection proof and aggregate.
+    pub signer: &'a S,
+    /// Used for calculating epoch.
+    pub slots_per_epoch: u64,
+    /// Mere vessel for E.
+    pub _phantom: PhantomData<E>,
+    /// The number of times publication is retried if the beacon node fails.
+    pub max_publish_retries: u64,
+    /// If true, the aggregate is logged rather than signed and published.
+    pub dry_run: bool,
+    /// The selection proofs signed for each validator.
+    pub selection_proofs: Arc<SelectionProofCache>,
+}
+
+impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AggregationProducer<'a, B, S, E> {
+    /// Handle outputs and results from aggregation.
+    pub fn handle_produce_aggregate(&mut self, log: slog::Logger) -> Option<SkipReason> {
+        let result = self.produce_aggregate(&log);
+        match &result {
+            Ok(ValidatorEvent::AggregateProduced(slot)) => info!(
+                log,
+                "Aggregate produced";
+                "validator" => format!("{}", self.signer),
+                "slot" => slot,
+                "shard" => self.duty.shard,
+            ),
+            Ok(ValidatorEvent::NotAggregator(_slot)) => {}
+            Err(e) => error!(log, "Aggregation error"; "Error" => format!("{:?}", e)),
+            Ok(ValidatorEvent::NoAggregate(slot)) => {
+                warn!(log, "No attestations to aggregate"; "slot" => slot, "shard" => self.duty.shard)
+            }
+            Ok(ValidatorEvent::SignerRejection(_slot)) => {
+                error!(log, "Aggregation error"; "Error" => "Signer could not sign the aggregate".to_string())
+            }
+            Ok(ValidatorEvent::PublishAttestationFailed) => {
+                error!(log, "Aggregation error"; "Error" => "Beacon node was unable to publish an aggregate".to_string())
+            }
+            Ok(ValidatorEvent::InvalidAttestation) => {
+                error!(log, "Aggregation error"; "Error" => "The signed aggregate was invalid".to_string())
+            }
+            Ok(ValidatorEvent::AttestationExpired(_slot)) => {
+                error!(log, "Aggregation error"; "Error" => "The aggregate was too old to be included in a block".to_string())
+            }
+            // the withheld aggregate is logged as it is produced
+            Ok(ValidatorEvent::DryRun(_slot)) => {}
+            Ok(ValidatorEvent::InconsistentAttestationData(e)) => {
+                crit!(log, "Refused to sign an invalid aggregate from the beacon node"; "Error" => format!("{:?}", e), "slot" => self.duty.slot)
+            }
+            Ok(v) => warn!(log, "Unknown result for aggregation"; "Error" => format!("{:?}",v)),
+        }
+        match &result {
+            Ok(event) => SkipReason::from_event(event),
+            Err(_) => Some(SkipReason::BeaconNodeError),
+        }
+    }
+
+    /// Signs a selection proof for the duty slot and, if it selects the validator, requests the
+    /// committee's aggregate from the node, then signs and publishes it with the proof.
+    ///
+    /// The aggregate contains attestations signed by others, so it is not recorded by slashing
+    /// protection. Only its consistency with the duty is checked.
+    pub fn produce_aggregate(&mut self, log: &slog::Logger) -> Result<ValidatorEvent, Error> {
+        let slot = self.duty.slot;
+        let epoch = slot.epoch(self.slots_per_epoch);
+
+        let selection_proof =
+            self.selection_proofs
+                .get_or_sign(self.signer, slot, &self.fork, &self.spec);
+        let selection_proof = match selection_proof {
+            Some(selection_proof) => selection_proof,
+            None => return Ok(ValidatorEvent::SignerRejection(slot)),
+        };
+        if !is_aggregator(self.duty.committee_len, &selection_proof) {
+            debug!(
+                log,
+                "Not selected to aggregate";
+                "validator" => format!("{}", self.signer),
+                "slot" => slot,
+            );
+            return Ok(ValidatorEvent::NotAggregator(slot));
+        }
+
+        let aggregate = match self
+            .beacon_node
+            .produce_aggregate_attestation(slot, self.duty.shard)?
+        {
+            Some(aggregate) => aggregate,
+            None => return Ok(ValidatorEvent::NoAggregate(slot)),
+        };
+        if let Err(e) = self.validate_aggregate(&aggregate) {
+            return Ok(ValidatorEvent::InconsistentAttestationData(e));
+        }
+        if self.dry_run {
+            info!(
+                log,
+                "Dry run: aggregate not signed or published";
+                "validator" => format!("{}", self.signer),
+                "slot" => slot,
+                "shard" => self.duty.shard,
+                "attesters" => aggregate.aggregation_bits.num_set_bits(),
+            );
+            return Ok(ValidatorEvent::DryRun(slot));
+        }
+
+        let mut aggregate_and_proof = AggregateAndProof {
+            aggregator_index: self.validator_index,
+            aggregate,
+            selection_proof,
+            signature: Signature::empty_signature(),
+        };
+        let domain = self
+            .spec
+            .get_domain(epoch, Domain::AggregateAndProof, &self.fork);
+        aggregate_and_proof.signature = match self
+            .signer
+            .sign_message(&aggregate_and_proof.signed_root(), domain)
+        {
+            Some(signature) => signature,
+            None => return Ok(ValidatorEvent::SignerRejection(slot)),
+        };
+
+        let outcome = publish_with_retries(
+            || {
+                self.beacon_node
+                    .publish_aggregate_and_proof(aggregate_and_proof.clone())
+            },
+            self.max_publish_retries,
+            "aggregate",
+            log,
+        );
+        match outcome {
+            Ok(PublishOutcome::Valid) => Ok(ValidatorEvent::AggregateProduced(slot)),
+            Ok(PublishOutcome::InvalidAttestation(_string)) => {
+                Ok(ValidatorEvent::InvalidAttestation)
+            }
+            Ok(PublishOutcome::AttestationTooOld(_string)) => {
+                Ok(ValidatorEvent::AttestationExpired(slot))
+            }
+            Err(_) | Ok(_) => Ok(ValidatorEvent::PublishAttestationFailed),
+        }
+    }
+
+    /// Checks that the `aggregate` returned by the beacon node is for the committee and epoch of
+    /// `self.duty`.
+    fn validate_aggregate(&self, aggregate: &Attestation<E>) -> Result<(), InvalidAttestationData> {
+        validate_attestation_data(&self.duty, self.slots_per_epoch, &aggregate.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_producer::BeaconNodeError;
+    use ssz::{Decode, Encode};
+    use std::sync::Mutex;
+    use types::{
+        AggregateSignature, AttestationData, BitList, Checkpoint, Crosslink, Epoch, Hash256,
+        Keypair, MinimalEthSpec, Slot,
+    };
+
+    type E = MinimalEthSpec;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Offers `aggregate` for every committee and accepts every published aggregate.
+    struct TestBeaconNode {
+        aggregate: Option<Attestation<E>>,
+        published: Mutex<Vec<AggregateAndProof<E>>>,
+    }
+
+    impl BeaconNodeAttestation for TestBeaconNode {
+        fn produce_attestation_data(
+            &self,
+            _slot: Slot,
+            _shard: u64,
+        ) -> Result<AttestationData, BeaconNodeError> {
+            panic!("No attestations should be produced")
+        }
+
+        fn publish_attestation<T: EthSpec>(
+            &self,
+            _attestation: Attestation<T>,
+        ) -> Result<PublishOutcome, BeaconNodeError> {
+            panic!("No attestations should be produced")
+        }
+
+        fn head_slot(&self) -> Result<Slot, BeaconNodeError> {
+            Ok(Slot::new(0))
+        }
+
+        fn produce_aggregate_attestation<T: EthSpec>(
+            &self,
+            _slot: Slot,
+            _shard: u64,
+        ) -> Result<Option<Attestation<T>>, BeaconNodeError> {
+            self.aggregate
+                .as_ref()
+                .map(|aggregate| Attestation::from_ssz_bytes(&aggregate.as_ssz_bytes()))
+                .transpose()
+                .map_err(|_| BeaconNodeError::DecodeFailure)
+        }
+
+        fn publish_aggregate_and_proof<T: EthSpec>(
+            &self,
+            aggregate_and_proof: AggregateAndProof<T>,
+        ) -> Result<PublishOutcome, BeaconNodeError> {
+            let aggregate_and_proof =
+                AggregateAndProof::from_ssz_bytes(&aggregate_and_proof.as_ssz_bytes())
+                    .map_err(|_| BeaconNodeError::DecodeFailure)?;
+            self.published.lock().unwrap().push(aggregate_and_proof);
+            Ok(PublishOutcome::Valid)
+        }
+    }
+
+    const COMMITTEE_LEN: usize = 4;
+
+    fn duty() -> AttestationDuty {
+        AttestationDuty {
+            slot: Slot::new(3),
+            shard: 2,
+            committee_index: 1,
+            committee_len: COMMITTEE_LEN,
+        }
+    }
+
+    fn aggregate(shard: u64) -> Attestation<E> {
+        let mut aggregation_bits = BitList::with_capacity(COMMITTEE_LEN).unwrap();
+        aggregation_bits.set(0, true).unwrap();
+        aggregation_bits.set(1, true).unwrap();
+        Attestation {
+            aggregation_bits,
+            data: AttestationData {
+                beacon_block_root: Hash256::zero(),
+                source: Checkpoint::default(),
+                target: Checkpoint::default(),
+                crosslink: Crosslink {
+                    shard,
+                    ..Crosslink::default()
+                },
+            },
+            custody_bits: BitList::with_capacity(COMMITTEE_LEN).unwrap(),
+            signature: AggregateSignature::new(),
+        }
+    }
+
+    fn producer(
+        keypair: &Keypair,
+        beacon_node: Arc<TestBeaconNode>,
+    ) -> AggregationProducer<TestBeaconNode, Keypair, E> {
+        AggregationProducer {
+            fork: Fork::genesis(Epoch::new(0)),
+            duty: duty(),
+            validator_index: 7,
+            spec: Arc::new(ChainSpec::minimal()),
+            beacon_node,
+            signer: keypair,
+            slots_per_epoch: E::slots_per_epoch(),
+            _phantom: PhantomData,
+            max_publish_retries: 3,
+            dry_run: false,
+            selection_proofs: Arc::new(SelectionProofCache::new(true, E::slots_per_epoch())),
+        }
+    }
+
+    fn test_node(aggregate: Option<Attestation<E>>) -> Arc<TestBeaconNode> {
+        Arc::new(TestBeaconNode {
+            aggregate,
+            published: Mutex::new(vec![]),
+        })
+    }
+
+    #[test]
+    fn aggregate_signed_under_aggregation_domains() {
+        // Every member of a committee smaller than the aggregator target is selected.
+        let keypair = Keypair::random();
+        let beacon_node = test_node(Some(aggregate(2)));
+        let mut aggregation_producer = producer(&keypair, beacon_node.clone());
+
+        assert_eq!(
+            aggregation_producer.produce_aggregate(&null_logger()),
+            Ok(ValidatorEvent::AggregateProduced(Slot::new(3)))
+        );
+
+        let published = beacon_node.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let aggregate_and_proof = &published[0];
+        assert_eq!(aggregate_and_proof.aggregator_index, 7);
+        assert_eq!(aggregate_and_proof.aggregate, aggregate(2));
+
+        let spec = ChainSpec::minimal();
+        let fork = Fork::genesis(Epoch::new(0));
+        let slot = Slot::new(3);
+        assert!(aggregate_and_proof.is_valid_selection_proof(slot, &keypair.pk, &fork, &spec));
+        assert!(aggregate_and_proof.is_valid_signature(slot, &keypair.pk, &fork, &spec));
+    }
+
+    #[test]
+    fn aggregate_signed_under_the_fork_of_its_epoch() {
+        let keypair = Keypair::random();
+        let spec = ChainSpec::minimal();
+        let slot = Epoch::new(1).start_slot(E::slots_per_epoch());
+        let mut aggregate = aggregate(2);
+        aggregate.data.target.epoch = Epoch::new(1);
+        let beacon_node = test_node(Some(aggregate));
+        let fork = Fork {
+            previous_version: [0, 0, 0, 0],
+            current_version: [1, 0, 0, 0],
+            epoch: Epoch::new(1),
+        };
+
+        let mut aggregation_producer = producer(&keypair, beacon_node.clone());
+        aggregation_producer.duty.slot = slot;
+        aggregation_producer.fork = fork.clone();
+        assert_eq!(
+            aggregation_producer.produce_aggregate(&null_logger()),
+            Ok(ValidatorEvent::AggregateProduced(slot))
+        );
+
+        let published = beacon_node.published.lock().unwrap();
+        let aggregate_and_proof = &published[0];
+        assert!(aggregate_and_proof.is_valid_selection_proof(slot, &keypair.pk, &fork, &spec));
+        assert!(aggregate_and_proof.is_valid_signature(slot, &keypair.pk, &fork, &spec));
+
+        // Neither verifies under the fork version before the fork.
+        let genesis_fork = Fork::genesis(Epoch::new(0));
+        assert!(!aggregate_and_proof.is_valid_selection_proof(
+            slot,
+            &keypair.pk,
+            &genesis_fork,
+            &spec
+        ));
+        assert!(!aggregate_and_proof.is_valid_signature(slot, &keypair.pk, &genesis_fork, &spec));
+    }
+
+    #[test]
+    fn aggregate_for_another_committee_not_signed() {
+        let keypair = Keypair::random();
+        let beacon_node = test_node(Some(aggregate(5)));
+
+        assert_eq!(
+            producer(&keypair, beacon_node.clone()).produce_aggregate(&null_logger()),
+            Ok(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::WrongShard {
+                    expected: 2,
+                    found: 5,
+                }
+            ))
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn missing_aggregate_skipped() {
+        let keypair = Keypair::random();
+        let beacon_node = test_node(None);
+
+        assert_eq!(
+            producer(&keypair, beacon_node.clone()).handle_produce_aggregate(null_logger()),
+            Some(SkipReason::BeaconNodeUnableToProduce)
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn unselected_validator_does_not_aggregate() {
+        // In a large committee, only a fraction of the members are selected.
+        let beacon_node = test_node(Some(aggregate(2)));
+        let mut selected = 0;
+        for _ in 0..32 {
+            let keypair = Keypair::random();
+            let mut aggregation_producer = producer(&keypair, beacon_node.clone());
+            aggregation_producer.duty.committee_len = 1024;
+            match aggregation_producer.produce_aggregate(&null_logger()) {
+                Ok(ValidatorEvent::AggregateProduced(_)) => selected += 1,
+                Ok(ValidatorEvent::NotAggregator(_)) => {}
+                other => panic!("unexpected aggregation result {:?}", other),
+            }
+        }
+        assert!(selected < 32);
+        assert_eq!(beacon_node.published.lock().unwrap().len(), selected);
+    }
+}
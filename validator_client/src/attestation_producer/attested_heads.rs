@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use types::{PublicKey, Slot};
+
+/// The highest head slot of the beacon node at which each validator has signed an attestation.
+///
+/// A node which has re-synced from scratch, or fallen back to another node, may offer attestation
+/// data for a head earlier than one the validator has already voted for. The validator has seen a
+/// later chain, so such data is refused rather than signed.
+#[derive(Default)]
+pub struct AttestedHeads {
+    heads: Mutex<HashMap<PublicKey, Slot>>,
+}
+
+impl AttestedHeads {
+    /// Returns the highest head slot at which `pubkey` has attested, if any.
+    pub fn highest(&self, pubkey: &PublicKey) -> Option<Slot> {
+        self.heads
+            .lock()
+            .ok()
+            .and_then(|heads| heads.get(pubkey).copied())
+    }
+
+    /// Records that `pubkey` signed an attestation whilst the node's head was at `head_slot`.
+    pub fn record(&self, pubkey: &PublicKey, head_slot: Slot) {
+        if let Ok(mut heads) = self.heads.lock() {
+            let highest = heads.entry(pubkey.clone()).or_insert(head_slot);
+            if head_slot > *highest {
+                *highest = head_slot;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::Keypair;
+
+    #[test]
+    fn highest_head_slot_kept() {
+        let heads = AttestedHeads::default();
+        let pubkey = Keypair::random().pk;
+        assert_eq!(heads.highest(&pubkey), None);
+
+        heads.record(&pubkey, Slot::new(5));
+        heads.record(&pubkey, Slot::new(3));
+        assert_eq!(heads.highest(&pubkey), Some(Slot::new(5)));
+        heads.record(&pubkey, Slot::new(7));
+        assert_eq!(heads.highest(&pubkey), Some(Slot::new(7)));
+        assert_eq!(heads.highest(&Keypair::random().pk), None);
+    }
+}
@@ -1,6 +1,6 @@
 //TODO: generalise these enums to the crate
 use crate::block_producer::{BeaconNodeError, PublishOutcome};
-use types::{Attestation, AttestationData, EthSpec, Slot};
+use types::{AggregateAndProof, Attestation, AttestationData, EthSpec, Slot};
 
 /// Defines the methods required to produce and publish attestations on a Beacon Node. Abstracts the
 /// actual beacon node.
@@ -20,4 +20,21 @@ pub trait BeaconNodeAttestation: Send + Sync {
         &self,
         attestation: Attestation<T>,
     ) -> Result<PublishOutcome, BeaconNodeError>;
+
+    /// Request the slot of the node's head.
+    fn head_slot(&self) -> Result<Slot, BeaconNodeError>;
+
+    /// Request the aggregate of the attestations the node has seen for the committee of `shard`
+    /// at `slot`. Returns `None` if the node has seen no such attestations.
+    fn produce_aggregate_attestation<T: EthSpec>(
+        &self,
+        slot: Slot,
+        shard: u64,
+    ) -> Result<Option<Attestation<T>>, BeaconNodeError>;
+
+    /// Request that the node publishes an aggregate on behalf of a selected aggregator.
+    fn publish_aggregate_and_proof<T: EthSpec>(
+        &self,
+        aggregate_and_proof: AggregateAndProof<T>,
+    ) -> Result<PublishOutcome, BeaconNodeError>;
 }
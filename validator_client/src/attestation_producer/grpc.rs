@@ -1,14 +1,43 @@
 use super::beacon_node_attestation::BeaconNodeAttestation;
+use crate::audit_log::{audited, AuditLog, RequestOptions};
 use crate::block_producer::{BeaconNodeError, PublishOutcome};
-use protos::services_grpc::AttestationServiceClient;
+use protos::services_grpc::{AttestationServiceClient, BeaconNodeServiceClient};
 use ssz::{Decode, Encode};
+use std::sync::Arc;
 
 use protos::services::{
-    Attestation as GrpcAttestation, ProduceAttestationDataRequest, PublishAttestationRequest,
+    AggregateAndProof as GrpcAggregateAndProof, Attestation as GrpcAttestation, Empty,
+    ProduceAggregateAttestationRequest, ProduceAttestationDataRequest,
+    PublishAggregateAndProofRequest, PublishAttestationRequest, PublishAttestationResponse,
 };
-use types::{Attestation, AttestationData, EthSpec, Slot};
+use types::{AggregateAndProof, Attestation, AttestationData, EthSpec, Slot};
 
-impl BeaconNodeAttestation for AttestationServiceClient {
+/// Wraps the gRPC-generated service so that each request may be recorded in an audit log.
+pub struct AttestationGrpcClient {
+    client: Arc<AttestationServiceClient>,
+    /// Used to query the node's head slot.
+    beacon_node_client: BeaconNodeServiceClient,
+    audit_log: Option<Arc<AuditLog>>,
+    options: RequestOptions,
+}
+
+impl AttestationGrpcClient {
+    pub fn new(
+        client: Arc<AttestationServiceClient>,
+        beacon_node_client: BeaconNodeServiceClient,
+        audit_log: Option<Arc<AuditLog>>,
+        options: RequestOptions,
+    ) -> Self {
+        Self {
+            client,
+            beacon_node_client,
+            audit_log,
+            options,
+        }
+    }
+}
+
+impl BeaconNodeAttestation for AttestationGrpcClient {
     fn produce_attestation_data(
         &self,
         slot: Slot,
@@ -18,9 +47,14 @@ impl BeaconNodeAttestation for AttestationServiceClient {
         req.set_slot(slot.as_u64());
         req.set_shard(shard);
 
-        let reply = self
-            .produce_attestation_data(&req)
-            .map_err(|err| BeaconNodeError::RemoteFailure(format!("{:?}", err)))?;
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "AttestationService/ProduceAttestationData",
+            &req,
+            |req, opt| self.client.produce_attestation_data_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
 
         let attestation_data =
             AttestationData::from_ssz_bytes(reply.get_attestation_data().get_ssz())
@@ -41,17 +75,334 @@ impl BeaconNodeAttestation for AttestationServiceClient {
 
         req.set_attestation(grpc_attestation);
 
-        let reply = self
-            .publish_attestation(&req)
-            .map_err(|err| BeaconNodeError::RemoteFailure(format!("{:?}", err)))?;
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "AttestationService/PublishAttestation",
+            &req,
+            |req, opt| self.client.publish_attestation_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
 
-        if reply.get_success() {
-            Ok(PublishOutcome::Valid)
-        } else {
-            // TODO: distinguish between different errors
+        publish_outcome(&reply)
+    }
+
+    fn head_slot(&self) -> Result<Slot, BeaconNodeError> {
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "BeaconNodeService/Info",
+            &Empty::new(),
+            |req, opt| self.beacon_node_client.info_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
+
+        Ok(Slot::from(reply.get_head_slot()))
+    }
+
+    fn produce_aggregate_attestation<T: EthSpec>(
+        &self,
+        slot: Slot,
+        shard: u64,
+    ) -> Result<Option<Attestation<T>>, BeaconNodeError> {
+        let mut req = ProduceAggregateAttestationRequest::new();
+        req.set_slot(slot.as_u64());
+        req.set_shard(shard);
+
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "AttestationService/ProduceAggregateAttestation",
+            &req,
+            |req, opt| self.client.produce_aggregate_attestation_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
+
+        if !reply.has_aggregate() {
+            return Ok(None);
+        }
+        Attestation::from_ssz_bytes(reply.get_aggregate().get_ssz())
+            .map(Some)
+            .map_err(|_| BeaconNodeError::DecodeFailure)
+    }
+
+    fn publish_aggregate_and_proof<T: EthSpec>(
+        &self,
+        aggregate_and_proof: AggregateAndProof<T>,
+    ) -> Result<PublishOutcome, BeaconNodeError> {
+        let mut grpc_aggregate_and_proof = GrpcAggregateAndProof::new();
+        grpc_aggregate_and_proof.set_ssz(aggregate_and_proof.as_ssz_bytes());
+
+        let mut req = PublishAggregateAndProofRequest::new();
+        req.set_aggregate_and_proof(grpc_aggregate_and_proof);
+
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "AttestationService/PublishAggregateAndProof",
+            &req,
+            |req, opt| self.client.publish_aggregate_and_proof_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
+
+        publish_outcome(&reply)
+    }
+}
+
+/// Interprets the node's reply to the publication of an attestation or aggregate.
+fn publish_outcome(reply: &PublishAttestationResponse) -> Result<PublishOutcome, BeaconNodeError> {
+    let msg = String::from_utf8_lossy(reply.get_msg()).into_owned();
+    if reply.get_success() {
+        Ok(PublishOutcome::Valid)
+    } else if reply.get_too_old() {
+        Ok(PublishOutcome::AttestationTooOld(msg))
+    } else if reply.get_retryable() {
+        Err(BeaconNodeError::RemoteFailure(msg))
+    } else {
+        Ok(PublishOutcome::InvalidAttestation(msg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use grpcio::{
+        ChannelBuilder, EnvBuilder, Environment, RpcContext, RpcStatus, RpcStatusCode, Server,
+        ServerBuilder, UnarySink,
+    };
+    use protos::services::{ProduceAggregateAttestationResponse, ProduceAttestationDataResponse};
+    use protos::services_grpc::{create_attestation_service, AttestationService};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use types::{
+        AggregateSignature, BitList, Checkpoint, Crosslink, Hash256, MinimalEthSpec, Signature,
+    };
+
+    /// Records the SSZ and authorization header of each published attestation or aggregate,
+    /// answering with `reply`, or refusing the request as invalid if it is `None`. Offers
+    /// `aggregate` as the aggregate of every committee.
+    #[derive(Clone)]
+    struct MockAttestationService {
+        published: Arc<Mutex<Vec<(Vec<u8>, Option<Vec<u8>>)>>>,
+        reply: Option<PublishAttestationResponse>,
+        aggregate: Option<Attestation<MinimalEthSpec>>,
+    }
+
+    impl MockAttestationService {
+        fn record_and_reply(
+            &self,
+            ctx: RpcContext,
+            ssz: &[u8],
+            sink: UnarySink<PublishAttestationResponse>,
+        ) {
+            let authorization = ctx
+                .request_headers()
+                .iter()
+                .find(|(key, _)| *key == "authorization")
+                .map(|(_, value)| value.to_vec());
+            self.published
+                .lock()
+                .unwrap()
+                .push((ssz.to_vec(), authorization));
+            let reply = match &self.reply {
+                Some(reply) => sink.success(reply.clone()),
+                None => sink.fail(RpcStatus::new(RpcStatusCode::InvalidArgument, None)),
+            };
+            ctx.spawn(reply.map_err(|_| ()))
+        }
+    }
+
+    impl AttestationService for MockAttestationService {
+        fn produce_attestation_data(
+            &mut self,
+            ctx: RpcContext,
+            _req: ProduceAttestationDataRequest,
+            sink: UnarySink<ProduceAttestationDataResponse>,
+        ) {
+            let reply = sink.fail(RpcStatus::new(RpcStatusCode::Unimplemented, None));
+            ctx.spawn(reply.map_err(|_| ()))
+        }
+
+        fn publish_attestation(
+            &mut self,
+            ctx: RpcContext,
+            req: PublishAttestationRequest,
+            sink: UnarySink<PublishAttestationResponse>,
+        ) {
+            self.record_and_reply(ctx, req.get_attestation().get_ssz(), sink)
+        }
+
+        fn produce_aggregate_attestation(
+            &mut self,
+            ctx: RpcContext,
+            _req: ProduceAggregateAttestationRequest,
+            sink: UnarySink<ProduceAggregateAttestationResponse>,
+        ) {
+            let mut resp = ProduceAggregateAttestationResponse::new();
+            if let Some(aggregate) = &self.aggregate {
+                let mut grpc_aggregate = GrpcAttestation::new();
+                grpc_aggregate.set_ssz(aggregate.as_ssz_bytes());
+                resp.set_aggregate(grpc_aggregate);
+            }
+            ctx.spawn(sink.success(resp).map_err(|_| ()))
+        }
+
+        fn publish_aggregate_and_proof(
+            &mut self,
+            ctx: RpcContext,
+            req: PublishAggregateAndProofRequest,
+            sink: UnarySink<PublishAttestationResponse>,
+        ) {
+            self.record_and_reply(ctx, req.get_aggregate_and_proof().get_ssz(), sink)
+        }
+    }
+
+    /// Returns a client sending the API token `secret` to `node`, and the server of `node`.
+    fn mock_client(
+        env: &Arc<Environment>,
+        node: MockAttestationService,
+    ) -> (Server, AttestationGrpcClient) {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_attestation_service(node))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let address = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+        let channel = ChannelBuilder::new(env.clone()).connect(&address);
+        let options = RequestOptions {
+            timeout: Duration::from_secs(5),
+            api_token: Some("secret".to_string()),
+        };
+        let client = AttestationGrpcClient::new(
+            Arc::new(AttestationServiceClient::new(channel.clone())),
+            BeaconNodeServiceClient::new(channel),
+            None,
+            options,
+        );
+        (server, client)
+    }
+
+    fn reply(success: bool, retryable: bool, msg: &str) -> PublishAttestationResponse {
+        let mut reply = PublishAttestationResponse::new();
+        reply.set_success(success);
+        reply.set_retryable(retryable);
+        reply.set_msg(msg.as_bytes().to_vec());
+        reply
+    }
+
+    fn attestation() -> Attestation<MinimalEthSpec> {
+        Attestation {
+            aggregation_bits: BitList::with_capacity(4).unwrap(),
+            data: AttestationData {
+                beacon_block_root: Hash256::zero(),
+                source: Checkpoint::default(),
+                target: Checkpoint::default(),
+                crosslink: Crosslink::default(),
+            },
+            custody_bits: BitList::with_capacity(4).unwrap(),
+            signature: AggregateSignature::new(),
+        }
+    }
+
+    #[test]
+    fn publish_sends_attestation_and_distinguishes_failures() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let attestation = attestation();
+        let publish = |reply| {
+            let published = Arc::new(Mutex::new(vec![]));
+            let node = MockAttestationService {
+                published: published.clone(),
+                reply,
+                aggregate: None,
+            };
+            let (_server, client) = mock_client(&env, node);
+            let outcome = client.publish_attestation(attestation.clone());
+            let published = published.lock().unwrap().clone();
+            (outcome, published)
+        };
+
+        let (outcome, published) = publish(Some(reply(true, false, "")));
+        assert_eq!(outcome, Ok(PublishOutcome::Valid));
+        assert_eq!(
+            published,
+            vec![(attestation.as_ssz_bytes(), Some(b"Bearer secret".to_vec()))]
+        );
+
+        // A rejection carries the node's reason, whilst a failure to process the attestation is
+        // an error, so it is retried.
+        let (outcome, _) = publish(Some(reply(
+            false,
+            false,
+            "InvalidAttestation: BadSignature",
+        )));
+        assert_eq!(
+            outcome,
             Ok(PublishOutcome::InvalidAttestation(
-                "Publish failed".to_string(),
+                "InvalidAttestation: BadSignature".to_string()
             ))
+        );
+        let (outcome, _) = publish(Some(reply(false, true, "There was a beacon chain error")));
+        let error = outcome.unwrap_err();
+        assert!(error.is_retryable());
+        assert_eq!(
+            error,
+            BeaconNodeError::RemoteFailure("There was a beacon chain error".to_string())
+        );
+
+        let (outcome, _) = publish(None);
+        let error = outcome.unwrap_err();
+        assert!(!error.is_retryable());
+        match error {
+            BeaconNodeError::BadRequest(_) => {}
+            other => panic!("expected a bad request, got {:?}", other),
         }
     }
+
+    #[test]
+    fn aggregate_produced_and_published() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let mut aggregate = attestation();
+        aggregate.aggregation_bits.set(1, true).unwrap();
+        aggregate.aggregation_bits.set(3, true).unwrap();
+        let published = Arc::new(Mutex::new(vec![]));
+        let mut node = MockAttestationService {
+            published: published.clone(),
+            reply: Some(reply(true, false, "")),
+            aggregate: None,
+        };
+
+        // The node has seen no attestations for the committee.
+        let (_server, client) = mock_client(&env, node.clone());
+        assert_eq!(
+            client.produce_aggregate_attestation::<MinimalEthSpec>(Slot::new(3), 2),
+            Ok(None)
+        );
+
+        node.aggregate = Some(aggregate.clone());
+        let (_server, client) = mock_client(&env, node);
+        assert_eq!(
+            client.produce_aggregate_attestation(Slot::new(3), 2),
+            Ok(Some(aggregate.clone()))
+        );
+
+        let aggregate_and_proof = AggregateAndProof {
+            aggregator_index: 7,
+            aggregate,
+            selection_proof: Signature::empty_signature(),
+            signature: Signature::empty_signature(),
+        };
+        assert_eq!(
+            client.publish_aggregate_and_proof(aggregate_and_proof.clone()),
+            Ok(PublishOutcome::Valid)
+        );
+        assert_eq!(
+            *published.lock().unwrap(),
+            vec![(
+                aggregate_and_proof.as_ssz_bytes(),
+                Some(b"Bearer secret".to_vec())
+            )]
+        );
+    }
 }
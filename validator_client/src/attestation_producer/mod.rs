@@ -1,18 +1,30 @@
+mod aggregation;
+mod attested_heads;
 mod beacon_node_attestation;
 mod grpc;
+mod selection_proofs;
+
+pub use self::aggregation::AggregationProducer;
+pub use self::attested_heads::AttestedHeads;
+pub use self::beacon_node_attestation::BeaconNodeAttestation;
+pub use self::grpc::AttestationGrpcClient;
+pub use self::selection_proofs::SelectionProofCache;
 
 use std::sync::Arc;
 use types::{ChainSpec, Domain, EthSpec, Fork};
 //TODO: Move these higher up in the crate
 use super::block_producer::{BeaconNodeError, PublishOutcome, ValidatorEvent};
+use crate::observer::ProductionObserver;
+use crate::publish_retry::publish_with_retries;
 use crate::signer::Signer;
-use beacon_node_attestation::BeaconNodeAttestation;
+use crate::skip_reason::SkipReason;
+use crate::slashing_protection::SlashingDatabase;
 use core::marker::PhantomData;
-use slog::{error, info, warn};
+use slog::{crit, error, info, warn};
 use tree_hash::TreeHash;
 use types::{
     AggregateSignature, Attestation, AttestationData, AttestationDataAndCustodyBit,
-    AttestationDuty, BitList,
+    AttestationDuty, BitList, Epoch, Slot,
 };
 
 //TODO: Group these errors at a crate level
@@ -27,6 +39,20 @@ impl From<BeaconNodeError> for Error {
     }
 }
 
+/// The reason attestation data returned by the beacon node may not be signed.
+#[derive(Debug, PartialEq)]
+pub enum InvalidAttestationData {
+    /// The data is for a different committee to the duty. Committees are identified by their
+    /// shard.
+    WrongShard { expected: u64, found: u64 },
+    /// The data targets an epoch other than that of the duty's slot.
+    WrongTargetEpoch { expected: Epoch, found: Epoch },
+    /// The source checkpoint is later than the target.
+    SourceAfterTarget { source: Epoch, target: Epoch },
+    /// The node's head is earlier than one at which the validator has already attested.
+    HeadRegressed { highest: Slot, found: Slot },
+}
+
 /// This struct contains the logic for requesting and signing beacon attestations for a validator. The
 /// validator can abstractly sign via the Signer trait object.
 pub struct AttestationProducer<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> {
@@ -44,12 +70,30 @@ pub struct AttestationProducer<'a, B: BeaconNodeAttestation, S: Signer, E: EthSp
     pub slots_per_epoch: u64,
     /// Mere vessel for E.
     pub _phantom: PhantomData<E>,
+    /// Notified of the outcome of attestation production.
+    pub observer: Arc<dyn ProductionObserver<E>>,
+    /// Consulted before any attestation is signed.
+    pub slashing_protection: Arc<SlashingDatabase>,
+    /// The number of times publication is retried if the beacon node fails.
+    pub max_publish_retries: u64,
+    /// If true, the attestation is logged rather than signed and published.
+    pub dry_run: bool,
+    /// If set, the attestation data is re-fetched once if the node's head advances by more than
+    /// this many slots whilst it is being fetched.
+    pub freshness_tolerance: Option<u64>,
+    /// The highest head slot at which each validator has attested.
+    pub attested_heads: Arc<AttestedHeads>,
+    /// If set, the attestation is withheld whilst the node's head is more than this many slots
+    /// behind the duty slot. A syncing node's head has not been built upon by the rest of the
+    /// network, so may be abandoned.
+    pub syncing_distance: Option<u64>,
 }
 
 impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a, B, S, E> {
     /// Handle outputs and results from attestation production.
-    pub fn handle_produce_attestation(&mut self, log: slog::Logger) {
-        match self.produce_attestation() {
+    pub fn handle_produce_attestation(&mut self, log: slog::Logger) -> Option<SkipReason> {
+        let result = self.produce_attestation(&log);
+        match &result {
             Ok(ValidatorEvent::AttestationProduced(slot)) => info!(
                 log,
                 "Attestation produced";
@@ -61,8 +105,16 @@ impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a
             Ok(ValidatorEvent::IndexedAttestationNotProduced(_slot)) => error!(log, "Attestation production error"; "Error" => "Rejected the attestation as it could have been slashed".to_string()),
             Ok(ValidatorEvent::PublishAttestationFailed) => error!(log, "Attestation production error"; "Error" => "Beacon node was unable to publish an attestation".to_string()),
             Ok(ValidatorEvent::InvalidAttestation) => error!(log, "Attestation production error"; "Error" => "The signed attestation was invalid".to_string()),
+            Ok(ValidatorEvent::AttestationExpired(_slot)) => error!(log, "Attestation production error"; "Error" => "The attestation was too old to be included in a block".to_string()),
+            // the withheld attestation is logged as it is produced
+            Ok(ValidatorEvent::DryRun(_slot)) | Ok(ValidatorEvent::NodeSyncing(_slot)) => {}
+            Ok(ValidatorEvent::InconsistentAttestationData(e)) => crit!(log, "Refused to sign invalid attestation data from the beacon node"; "Error" => format!("{:?}", e), "slot" => self.duty.slot),
             Ok(v) => warn!(log, "Unknown result for attestation production"; "Error" => format!("{:?}",v)),
         }
+        match &result {
+            Ok(event) => SkipReason::from_event(event),
+            Err(_) => Some(SkipReason::BeaconNodeError),
+        }
     }
 
     /// Produce an attestation, sign it and send it back
@@ -71,29 +123,76 @@ impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a
     ///
     /// Ensures the message is not slashable.
     ///
-    /// !!! UNSAFE !!!
-    ///
-    /// The slash-protection code is not yet implemented. There is zero protection against
-    /// slashing.
-    pub fn produce_attestation(&mut self) -> Result<ValidatorEvent, Error> {
+    /// The attestation data returned by the beacon node is signed verbatim, as it represents the
+    /// node's canonical view of the chain. For example, if the duty slot was skipped, the
+    /// `beacon_block_root` will be that of the most recent block prior to the slot. The data is
+    /// only checked for consistency with the duty, never recomputed.
+    pub fn produce_attestation(&mut self, log: &slog::Logger) -> Result<ValidatorEvent, Error> {
         let epoch = self.duty.slot.epoch(self.slots_per_epoch);
 
-        let attestation = self
-            .beacon_node
-            .produce_attestation_data(self.duty.slot, self.duty.shard)?;
+        let attestation = self.fetch_attestation_data(log)?;
+        if let Err(e) = self.validate_attestation_data(&attestation) {
+            return Ok(ValidatorEvent::InconsistentAttestationData(e));
+        }
+        let head_slot = match self.check_head_slot(log) {
+            Ok(head_slot) => head_slot,
+            Err(e) => return Ok(ValidatorEvent::InconsistentAttestationData(e)),
+        };
+        if let (Some(distance), Some(head_slot)) = (self.syncing_distance, head_slot) {
+            if head_slot + distance < self.duty.slot {
+                warn!(
+                    log,
+                    "Attestation withheld whilst the beacon node is syncing";
+                    "validator" => format!("{}", self.signer),
+                    "slot" => self.duty.slot,
+                    "head_slot" => head_slot,
+                );
+                return Ok(ValidatorEvent::NodeSyncing(self.duty.slot));
+            }
+        }
+        if self.dry_run {
+            info!(
+                log,
+                "Dry run: attestation not signed or published";
+                "validator" => format!("{}", self.signer),
+                "slot" => self.duty.slot,
+                "shard" => attestation.crosslink.shard,
+                "source_epoch" => attestation.source.epoch,
+                "target_epoch" => attestation.target.epoch,
+                "beacon_block_root" => format!("{:?}", attestation.beacon_block_root),
+            );
+            return Ok(ValidatorEvent::DryRun(self.duty.slot));
+        }
         if self.safe_to_produce(&attestation) {
             let domain = self.spec.get_domain(epoch, Domain::Attestation, &self.fork);
             if let Some(attestation) = self.sign_attestation(attestation, self.duty, domain) {
-                match self.beacon_node.publish_attestation(attestation) {
+                if let Some(head_slot) = head_slot {
+                    self.attested_heads
+                        .record(&self.signer.to_public(), head_slot);
+                }
+                let outcome = publish_with_retries(
+                    || self.beacon_node.publish_attestation(attestation.clone()),
+                    self.max_publish_retries,
+                    "attestation",
+                    log,
+                );
+                match outcome {
                     Ok(PublishOutcome::InvalidAttestation(_string)) => {
                         Ok(ValidatorEvent::InvalidAttestation)
                     }
+                    Ok(PublishOutcome::AttestationTooOld(_string)) => {
+                        Ok(ValidatorEvent::AttestationExpired(self.duty.slot))
+                    }
                     Ok(PublishOutcome::Valid) => {
+                        self.observer
+                            .on_attestation_produced(&self.signer.to_public(), &attestation);
                         Ok(ValidatorEvent::AttestationProduced(self.duty.slot))
                     }
                     Err(_) | Ok(_) => Ok(ValidatorEvent::PublishAttestationFailed),
                 }
             } else {
+                self.observer
+                    .on_signing_failed(&self.signer.to_public(), self.duty.slot);
                 Ok(ValidatorEvent::SignerRejection(self.duty.slot))
             }
         } else {
@@ -103,6 +202,83 @@ impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a
         }
     }
 
+    /// Requests the attestation data for `self.duty`.
+    ///
+    /// If `self.freshness_tolerance` is set, the node's head slot is queried before and after the
+    /// data is fetched. If the head advanced by more than the tolerance, the data may be for a
+    /// stale head, so it is fetched once more. The data is used unchecked if the head slot cannot
+    /// be queried.
+    fn fetch_attestation_data(&self, log: &slog::Logger) -> Result<AttestationData, Error> {
+        let produce = || {
+            self.beacon_node
+                .produce_attestation_data(self.duty.slot, self.duty.shard)
+        };
+        let tolerance = match self.freshness_tolerance {
+            Some(tolerance) => tolerance,
+            None => return produce().map_err(Error::from),
+        };
+
+        let fetched_at = self.beacon_node.head_slot();
+        let attestation = produce()?;
+        match (fetched_at, self.beacon_node.head_slot()) {
+            (Ok(fetched_at), Ok(head_slot)) if head_slot > fetched_at + tolerance => {
+                info!(
+                    log,
+                    "Re-fetching attestation data after the head advanced";
+                    "validator" => format!("{}", self.signer),
+                    "slot" => self.duty.slot,
+                    "fetched_at_head_slot" => fetched_at,
+                    "head_slot" => head_slot,
+                );
+                produce().map_err(Error::from)
+            }
+            (Err(e), _) | (_, Err(e)) => {
+                warn!(
+                    log,
+                    "Unable to check the freshness of attestation data";
+                    "error" => format!("{:?}", e),
+                    "slot" => self.duty.slot,
+                );
+                Ok(attestation)
+            }
+            _ => Ok(attestation),
+        }
+    }
+
+    /// Returns the node's head slot, unless it is earlier than a head at which this validator has
+    /// already attested. The data is signed unchecked, with `None` returned, if the head slot
+    /// cannot be queried.
+    fn check_head_slot(&self, log: &slog::Logger) -> Result<Option<Slot>, InvalidAttestationData> {
+        let head_slot = match self.beacon_node.head_slot() {
+            Ok(head_slot) => head_slot,
+            Err(e) => {
+                warn!(
+                    log,
+                    "Unable to check that the head has not regressed";
+                    "error" => format!("{:?}", e),
+                    "slot" => self.duty.slot,
+                );
+                return Ok(None);
+            }
+        };
+        match self.attested_heads.highest(&self.signer.to_public()) {
+            Some(highest) if head_slot < highest => Err(InvalidAttestationData::HeadRegressed {
+                highest,
+                found: head_slot,
+            }),
+            _ => Ok(Some(head_slot)),
+        }
+    }
+
+    /// Checks that the `attestation` returned by the beacon node is for the shard and epoch of
+    /// `self.duty`, and that its source is no later than its target.
+    fn validate_attestation_data(
+        &self,
+        attestation: &AttestationData,
+    ) -> Result<(), InvalidAttestationData> {
+        validate_attestation_data(&self.duty, self.slots_per_epoch, attestation)
+    }
+
     /// Consumes an attestation, returning the attestation signed by the validators private key.
     ///
     /// Important: this function will not check to ensure the attestation is not slashable. This must be
@@ -113,8 +289,6 @@ impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a
         duties: AttestationDuty,
         domain: u64,
     ) -> Option<Attestation<E>> {
-        self.store_produce(&attestation);
-
         // build the aggregate signature
         let aggregate_signature = {
             let message = AttestationDataAndCustodyBit {
@@ -142,22 +316,556 @@ impl<'a, B: BeaconNodeAttestation, S: Signer, E: EthSpec> AttestationProducer<'a
         })
     }
 
-    /// Returns `true` if signing an attestation is safe (non-slashable).
-    ///
-    /// !!! UNSAFE !!!
-    ///
-    /// Important: this function is presently stubbed-out. It provides ZERO SAFETY.
-    fn safe_to_produce(&self, _attestation: &AttestationData) -> bool {
-        //TODO: Implement slash protection
-        true
+    /// Returns `true` if signing an attestation is safe (non-slashable), recording the vote so
+    /// that no conflicting attestation may be signed in the future.
+    fn safe_to_produce(&self, attestation: &AttestationData) -> bool {
+        self.slashing_protection
+            .check_and_insert_attestation(
+                &self.signer.to_public(),
+                attestation.source.epoch,
+                attestation.target.epoch,
+            )
+            .is_ok()
     }
+}
 
-    /// Record that an attestation was produced so that slashable votes may not be made in the future.
-    ///
-    /// !!! UNSAFE !!!
-    ///
-    /// Important: this function is presently stubbed-out. It provides ZERO SAFETY.
-    fn store_produce(&mut self, _attestation: &AttestationData) {
-        // TODO: Implement slash protection
+/// Checks that `attestation` is for the shard and epoch of `duty`, and that its source is no later
+/// than its target.
+fn validate_attestation_data(
+    duty: &AttestationDuty,
+    slots_per_epoch: u64,
+    attestation: &AttestationData,
+) -> Result<(), InvalidAttestationData> {
+    let epoch = duty.slot.epoch(slots_per_epoch);
+    if attestation.crosslink.shard != duty.shard {
+        Err(InvalidAttestationData::WrongShard {
+            expected: duty.shard,
+            found: attestation.crosslink.shard,
+        })
+    } else if attestation.target.epoch != epoch {
+        Err(InvalidAttestationData::WrongTargetEpoch {
+            expected: epoch,
+            found: attestation.target.epoch,
+        })
+    } else if attestation.source.epoch > attestation.target.epoch {
+        Err(InvalidAttestationData::SourceAfterTarget {
+            source: attestation.source.epoch,
+            target: attestation.target.epoch,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skip_reason::SkipCounters;
+    use ssz::{Decode, Encode};
+    use std::sync::Mutex;
+    use types::{
+        AggregateAndProof, BeaconBlock, Checkpoint, Crosslink, Epoch, Hash256, Keypair,
+        MinimalEthSpec, PublicKey, Slot,
+    };
+
+    type E = MinimalEthSpec;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Returns `data` for the requested shard, unless another `shard` is set, and accepts every
+    /// published attestation.
+    struct TestBeaconNode {
+        data: AttestationData,
+        shard: Option<u64>,
+        published: Mutex<Vec<Attestation<E>>>,
+        /// The number of times attestation data was requested.
+        produce_count: Mutex<usize>,
+        /// The head slots reported, in order. The last is repeated once the others are used.
+        head_slots: Mutex<Vec<Slot>>,
+        /// The outcome of each publication.
+        publish_outcome: PublishOutcome,
+    }
+
+    impl TestBeaconNode {
+        fn new(data: AttestationData) -> Arc<Self> {
+            Arc::new(Self {
+                data,
+                shard: None,
+                published: Mutex::new(vec![]),
+                produce_count: Mutex::new(0),
+                head_slots: Mutex::new(vec![Slot::new(0)]),
+                publish_outcome: PublishOutcome::Valid,
+            })
+        }
+    }
+
+    fn attestation_data(target_epoch: u64, beacon_block_root: Hash256) -> AttestationData {
+        AttestationData {
+            beacon_block_root,
+            source: Checkpoint::default(),
+            target: Checkpoint {
+                epoch: Epoch::new(target_epoch),
+                root: beacon_block_root,
+            },
+            crosslink: Crosslink::default(),
+        }
+    }
+
+    fn producer(
+        keypair: &Keypair,
+        beacon_node: Arc<TestBeaconNode>,
+        observer: Arc<RecordingObserver>,
+    ) -> AttestationProducer<TestBeaconNode, Keypair, E> {
+        AttestationProducer {
+            fork: Fork::genesis(Epoch::new(0)),
+            duty: AttestationDuty {
+                slot: Slot::new(3),
+                shard: 2,
+                committee_index: 1,
+                committee_len: 4,
+            },
+            spec: Arc::new(ChainSpec::minimal()),
+            beacon_node,
+            signer: keypair,
+            slots_per_epoch: E::slots_per_epoch(),
+            _phantom: PhantomData,
+            observer,
+            slashing_protection: Arc::new(SlashingDatabase::in_memory()),
+            max_publish_retries: 3,
+            dry_run: false,
+            freshness_tolerance: None,
+            attested_heads: Arc::new(AttestedHeads::default()),
+            syncing_distance: None,
+        }
+    }
+
+    impl BeaconNodeAttestation for TestBeaconNode {
+        fn produce_attestation_data(
+            &self,
+            _slot: Slot,
+            shard: u64,
+        ) -> Result<AttestationData, BeaconNodeError> {
+            *self.produce_count.lock().unwrap() += 1;
+            let mut data = self.data.clone();
+            data.crosslink.shard = self.shard.unwrap_or(shard);
+            Ok(data)
+        }
+
+        fn publish_attestation<T: EthSpec>(
+            &self,
+            attestation: Attestation<T>,
+        ) -> Result<PublishOutcome, BeaconNodeError> {
+            let attestation = Attestation::from_ssz_bytes(&attestation.as_ssz_bytes())
+                .map_err(|_| BeaconNodeError::DecodeFailure)?;
+            self.published.lock().unwrap().push(attestation);
+            Ok(self.publish_outcome.clone())
+        }
+
+        fn head_slot(&self) -> Result<Slot, BeaconNodeError> {
+            let mut head_slots = self.head_slots.lock().unwrap();
+            if head_slots.len() > 1 {
+                Ok(head_slots.remove(0))
+            } else {
+                Ok(head_slots[0])
+            }
+        }
+
+        fn produce_aggregate_attestation<T: EthSpec>(
+            &self,
+            _slot: Slot,
+            _shard: u64,
+        ) -> Result<Option<Attestation<T>>, BeaconNodeError> {
+            panic!("No aggregates should be produced")
+        }
+
+        fn publish_aggregate_and_proof<T: EthSpec>(
+            &self,
+            _aggregate_and_proof: AggregateAndProof<T>,
+        ) -> Result<PublishOutcome, BeaconNodeError> {
+            panic!("No aggregates should be produced")
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        attestations: Mutex<Vec<(PublicKey, Attestation<E>)>>,
+    }
+
+    impl ProductionObserver<E> for RecordingObserver {
+        fn on_block_produced(&self, _validator: &PublicKey, _block: &BeaconBlock<E>) {
+            panic!("No blocks should be produced")
+        }
+
+        fn on_attestation_produced(&self, validator: &PublicKey, attestation: &Attestation<E>) {
+            self.attestations
+                .lock()
+                .unwrap()
+                .push((validator.clone(), attestation.clone()));
+        }
+    }
+
+    #[test]
+    fn observer_notified_of_produced_attestation() {
+        let keypair = Keypair::random();
+        let beacon_node = TestBeaconNode::new(attestation_data(0, Hash256::zero()));
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(Slot::new(3)))
+        );
+
+        let attestations = observer.attestations.lock().unwrap();
+        assert_eq!(attestations.len(), 1);
+        assert_eq!(attestations[0].0, keypair.pk);
+        assert_eq!(attestations[0].1, beacon_node.published.lock().unwrap()[0]);
+        assert_eq!(attestations[0].1.data.crosslink.shard, 2);
+    }
+
+    #[test]
+    fn skipped_slot_attestation_uses_node_head_root() {
+        let keypair = Keypair::random();
+        // The duty slot was skipped, so the node reports the root of an earlier head.
+        let earlier_head_root = Hash256::from_low_u64_be(42);
+        let data = attestation_data(0, earlier_head_root);
+        let beacon_node = TestBeaconNode::new(data.clone());
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer);
+
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(Slot::new(3)))
+        );
+
+        let published = beacon_node.published.lock().unwrap();
+        let mut expected = data;
+        expected.crosslink.shard = 2;
+        assert_eq!(published[0].data, expected);
+        assert_eq!(published[0].data.beacon_block_root, earlier_head_root);
+    }
+
+    #[test]
+    fn inconsistent_attestation_data_not_signed() {
+        let keypair = Keypair::random();
+        // The duty is in epoch 0, but the node returns data targeting epoch 1.
+        let beacon_node = TestBeaconNode::new(attestation_data(1, Hash256::zero()));
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer);
+
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::WrongTargetEpoch {
+                    expected: Epoch::new(0),
+                    found: Epoch::new(1),
+                }
+            ))
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn dry_run_neither_signs_nor_publishes() {
+        let keypair = Keypair::random();
+        let beacon_node = TestBeaconNode::new(attestation_data(0, Hash256::zero()));
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+        attestation_producer.slashing_protection = slashing_protection.clone();
+        attestation_producer.dry_run = true;
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::DryRun(Slot::new(3)))
+        );
+        assert_eq!(
+            attestation_producer.handle_produce_attestation(null_logger()),
+            None
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+        assert!(observer.attestations.lock().unwrap().is_empty());
+
+        // Nothing was recorded by slashing protection, so the attestation may be signed for real.
+        attestation_producer.dry_run = false;
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(Slot::new(3)))
+        );
+    }
+
+    #[test]
+    fn attestation_data_for_another_shard_not_signed() {
+        let keypair = Keypair::random();
+        let beacon_node = Arc::new(TestBeaconNode {
+            data: attestation_data(0, Hash256::zero()),
+            shard: Some(5),
+            published: Mutex::new(vec![]),
+            produce_count: Mutex::new(0),
+            head_slots: Mutex::new(vec![Slot::new(0)]),
+            publish_outcome: PublishOutcome::Valid,
+        });
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer);
+
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::WrongShard {
+                    expected: 2,
+                    found: 5,
+                }
+            ))
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn attestation_data_with_source_after_target_not_signed() {
+        let keypair = Keypair::random();
+        let mut data = attestation_data(0, Hash256::zero());
+        data.source.epoch = Epoch::new(1);
+        let beacon_node = TestBeaconNode::new(data);
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+        let mut attestation_producer = producer(&keypair, beacon_node.clone(), observer);
+        attestation_producer.slashing_protection = slashing_protection.clone();
+
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::SourceAfterTarget {
+                    source: Epoch::new(1),
+                    target: Epoch::new(0),
+                }
+            ))
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+        // The refused vote is not recorded by slashing protection.
+        assert!(slashing_protection
+            .check_and_insert_attestation(&keypair.pk, Epoch::new(0), Epoch::new(0))
+            .is_ok());
+    }
+
+    #[test]
+    fn attestation_data_refetched_once_if_head_advances() {
+        let keypair = Keypair::random();
+        let observer = Arc::new(RecordingObserver::default());
+        let produce = |head_slots: Vec<u64>, tolerance: Option<u64>| {
+            let beacon_node = TestBeaconNode::new(attestation_data(0, Hash256::zero()));
+            *beacon_node.head_slots.lock().unwrap() =
+                head_slots.into_iter().map(Slot::new).collect();
+            let mut attestation_producer =
+                producer(&keypair, beacon_node.clone(), observer.clone());
+            attestation_producer.freshness_tolerance = tolerance;
+            assert_eq!(
+                attestation_producer.produce_attestation(&null_logger()),
+                Ok(ValidatorEvent::AttestationProduced(Slot::new(3)))
+            );
+            let produce_count = *beacon_node.produce_count.lock().unwrap();
+            produce_count
+        };
+
+        // The node advances between fetching and checking the data.
+        assert_eq!(produce(vec![2, 3], Some(0)), 2);
+        // ...but only by the tolerance.
+        assert_eq!(produce(vec![2, 3], Some(1)), 1);
+        // The head advances again after the re-fetch, which is not repeated.
+        assert_eq!(produce(vec![1, 2, 3, 4], Some(0)), 2);
+        // The head is not checked unless a tolerance is set.
+        assert_eq!(produce(vec![2, 3], None), 1);
+    }
+
+    #[test]
+    fn attestation_rejected_as_too_old_counted_as_expired() {
+        let keypair = Keypair::random();
+        let beacon_node = Arc::new(TestBeaconNode {
+            data: attestation_data(0, Hash256::zero()),
+            shard: None,
+            published: Mutex::new(vec![]),
+            produce_count: Mutex::new(0),
+            head_slots: Mutex::new(vec![Slot::new(0)]),
+            publish_outcome: PublishOutcome::AttestationTooOld("too old".into()),
+        });
+        let observer = Arc::new(RecordingObserver::default());
+        let skip_counters = SkipCounters::default();
+
+        let reason = producer(&keypair, beacon_node, observer.clone())
+            .handle_produce_attestation(null_logger());
+        assert_eq!(reason, Some(SkipReason::Expired));
+
+        skip_counters.record(
+            "attestation",
+            "validator",
+            Slot::new(3),
+            reason.unwrap(),
+            &null_logger(),
+        );
+        assert_eq!(skip_counters.count(SkipReason::Expired), 1);
+        assert_eq!(skip_counters.count(SkipReason::PublishRejected), 0);
+        assert!(observer.attestations.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_produce_attestation_reports_skip_reason() {
+        let keypair = Keypair::random();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let observer = Arc::new(RecordingObserver::default());
+
+        let beacon_node = TestBeaconNode::new(attestation_data(0, Hash256::zero()));
+        let mut attestation_producer = producer(&keypair, beacon_node, observer.clone());
+        assert_eq!(
+            attestation_producer.handle_produce_attestation(log.clone()),
+            None
+        );
+
+        let beacon_node = TestBeaconNode::new(attestation_data(1, Hash256::zero()));
+        assert_eq!(
+            producer(&keypair, beacon_node, observer).handle_produce_attestation(log),
+            Some(SkipReason::InconsistentData)
+        );
+    }
+
+    #[test]
+    fn attestation_withheld_whilst_node_syncing() {
+        let keypair = Keypair::random();
+        let observer = Arc::new(RecordingObserver::default());
+        let producer_at = |head_slot: u64, syncing_distance: Option<u64>| {
+            let beacon_node = TestBeaconNode::new(attestation_data(1, Hash256::zero()));
+            *beacon_node.head_slots.lock().unwrap() = vec![Slot::new(head_slot)];
+            let mut attestation_producer =
+                producer(&keypair, beacon_node.clone(), observer.clone());
+            attestation_producer.duty.slot = Slot::new(12);
+            attestation_producer.syncing_distance = syncing_distance;
+            (attestation_producer, beacon_node)
+        };
+
+        let (mut attestation_producer, beacon_node) = producer_at(7, Some(4));
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::NodeSyncing(Slot::new(12)))
+        );
+        assert_eq!(
+            attestation_producer.handle_produce_attestation(null_logger()),
+            Some(SkipReason::NodeSyncing)
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+
+        // A head within the distance is attested to.
+        let (mut attestation_producer, _) = producer_at(8, Some(4));
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(Slot::new(12)))
+        );
+
+        // Unless configured, the node's head is attested to however far behind it is.
+        let (mut attestation_producer, _) = producer_at(0, None);
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(Slot::new(12)))
+        );
+        assert_eq!(observer.attestations.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn attestation_refused_if_node_head_regresses() {
+        let keypair = Keypair::random();
+        let observer = Arc::new(RecordingObserver::default());
+        let attested_heads = Arc::new(AttestedHeads::default());
+        let slot = |epoch: u64| Epoch::new(epoch).start_slot(E::slots_per_epoch());
+
+        let producer_at = |target_epoch: u64, head_slot: u64| {
+            let beacon_node = TestBeaconNode::new(attestation_data(target_epoch, Hash256::zero()));
+            *beacon_node.head_slots.lock().unwrap() = vec![Slot::new(head_slot)];
+            let mut attestation_producer =
+                producer(&keypair, beacon_node.clone(), observer.clone());
+            attestation_producer.duty.slot = slot(target_epoch);
+            attestation_producer.attested_heads = attested_heads.clone();
+            (attestation_producer, beacon_node)
+        };
+
+        let (mut attestation_producer, _) = producer_at(1, 8);
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(slot(1)))
+        );
+        assert_eq!(attested_heads.highest(&keypair.pk), Some(Slot::new(8)));
+
+        // The node re-synced, and offers data for a head earlier than that already attested to.
+        let (mut attestation_producer, beacon_node) = producer_at(2, 5);
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::HeadRegressed {
+                    highest: Slot::new(8),
+                    found: Slot::new(5),
+                }
+            ))
+        );
+        assert_eq!(
+            attestation_producer.handle_produce_attestation(null_logger()),
+            Some(SkipReason::InconsistentData)
+        );
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+        assert_eq!(attested_heads.highest(&keypair.pk), Some(Slot::new(8)));
+
+        // Attestations resume once the node has caught up.
+        let (mut attestation_producer, _) = producer_at(2, 16);
+        assert_eq!(
+            attestation_producer.produce_attestation(&null_logger()),
+            Ok(ValidatorEvent::AttestationProduced(slot(2)))
+        );
+        assert_eq!(attested_heads.highest(&keypair.pk), Some(Slot::new(16)));
+        assert_eq!(observer.attestations.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn surrounding_and_surrounded_votes_not_signed() {
+        let keypair = Keypair::random();
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+        let slot = |epoch: u64| Epoch::new(epoch).start_slot(E::slots_per_epoch());
+
+        let produce = |source_epoch: u64, target_epoch: u64| {
+            let mut data = attestation_data(target_epoch, Hash256::zero());
+            data.source.epoch = Epoch::new(source_epoch);
+            let beacon_node = TestBeaconNode::new(data);
+
+            let mut attestation_producer =
+                producer(&keypair, beacon_node.clone(), observer.clone());
+            attestation_producer.duty.slot = slot(target_epoch);
+            attestation_producer.slashing_protection = slashing_protection.clone();
+            let event = attestation_producer
+                .produce_attestation(&null_logger())
+                .unwrap();
+            let published = beacon_node.published.lock().unwrap().len();
+            (event, published)
+        };
+
+        assert_eq!(
+            produce(1, 4),
+            (ValidatorEvent::AttestationProduced(slot(4)), 1)
+        );
+
+        // Surrounded by the vote from epoch 1 to 4.
+        assert_eq!(
+            produce(2, 3),
+            (ValidatorEvent::IndexedAttestationNotProduced(slot(3)), 0)
+        );
+
+        // Surrounds the vote from epoch 1 to 4.
+        assert_eq!(
+            produce(0, 5),
+            (ValidatorEvent::IndexedAttestationNotProduced(slot(5)), 0)
+        );
+
+        assert_eq!(observer.attestations.lock().unwrap().len(), 1);
     }
 }
@@ -0,0 +1,199 @@
+use crate::signer::Signer;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tree_hash::TreeHash;
+use types::{ChainSpec, Domain, Fork, PublicKey, Signature, Slot};
+
+/// The selection proofs signed by each validator for the slots of its attestation duties.
+///
+/// A selection proof is a signature over the slot, under a domain which depends only on the fork
+/// and the slot's epoch. It is needed once to decide whether the validator aggregates and again to
+/// publish the aggregate, so it is signed once and kept until its epoch has passed. The proofs are
+/// discarded if the fork changes.
+pub struct SelectionProofCache {
+    /// If false, every proof is signed afresh.
+    enabled: bool,
+    slots_per_epoch: u64,
+    proofs: Mutex<CachedProofs>,
+}
+
+#[derive(Default)]
+struct CachedProofs {
+    /// The fork under which the proofs were signed.
+    fork: Option<Fork>,
+    proofs: HashMap<(PublicKey, Slot), Signature>,
+}
+
+impl SelectionProofCache {
+    pub fn new(enabled: bool, slots_per_epoch: u64) -> Self {
+        Self {
+            enabled,
+            slots_per_epoch,
+            proofs: Mutex::new(CachedProofs::default()),
+        }
+    }
+
+    /// Returns the selection proof of `signer` for `slot`, signing it unless it is cached for
+    /// `fork`. Returns `None` if the signer refuses to sign.
+    pub fn get_or_sign<S: Signer>(
+        &self,
+        signer: &S,
+        slot: Slot,
+        fork: &Fork,
+        spec: &ChainSpec,
+    ) -> Option<Signature> {
+        let pubkey = signer.to_public();
+        if self.enabled {
+            if let Some(proof) = self.cached(&pubkey, slot, fork) {
+                return Some(proof);
+            }
+        }
+
+        let epoch = slot.epoch(self.slots_per_epoch);
+        let domain = spec.get_domain(epoch, Domain::SelectionProof, fork);
+        let proof = signer.sign_message(&slot.tree_hash_root(), domain)?;
+
+        if self.enabled {
+            if let Ok(mut cached) = self.proofs.lock() {
+                if cached.fork.as_ref() != Some(fork) {
+                    cached.fork = Some(fork.clone());
+                    cached.proofs.clear();
+                }
+                let slots_per_epoch = self.slots_per_epoch;
+                cached
+                    .proofs
+                    .retain(|(_, proof_slot), _| proof_slot.epoch(slots_per_epoch) + 1 >= epoch);
+                cached.proofs.insert((pubkey, slot), proof.clone());
+            }
+        }
+        Some(proof)
+    }
+
+    /// Returns the proof of `pubkey` for `slot`, if it was signed under `fork`.
+    fn cached(&self, pubkey: &PublicKey, slot: Slot, fork: &Fork) -> Option<Signature> {
+        let cached = self.proofs.lock().ok()?;
+        if cached.fork.as_ref() == Some(fork) {
+            cached.proofs.get(&(pubkey.clone(), slot)).cloned()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use types::{Epoch, Keypair};
+
+    const SLOTS_PER_EPOCH: u64 = 8;
+
+    /// Signs with `keypair`, counting each signature.
+    #[derive(Clone)]
+    struct CountingSigner {
+        keypair: Keypair,
+        signatures: Arc<AtomicUsize>,
+    }
+
+    impl fmt::Display for CountingSigner {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            fmt::Display::fmt(&self.keypair, f)
+        }
+    }
+
+    impl Signer for CountingSigner {
+        fn sign_message(&self, message: &[u8], domain: u64) -> Option<Signature> {
+            self.signatures.fetch_add(1, Ordering::SeqCst);
+            self.keypair.sign_message(message, domain)
+        }
+
+        fn to_public(&self) -> PublicKey {
+            self.keypair.pk.clone()
+        }
+    }
+
+    fn signer() -> CountingSigner {
+        CountingSigner {
+            keypair: Keypair::random(),
+            signatures: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[test]
+    fn cached_proof_matches_fresh_proof() {
+        let spec = ChainSpec::minimal();
+        let fork = Fork::genesis(Epoch::new(0));
+        let signer = signer();
+        let cache = SelectionProofCache::new(true, SLOTS_PER_EPOCH);
+        let uncached = SelectionProofCache::new(false, SLOTS_PER_EPOCH);
+
+        let fresh = uncached.get_or_sign(&signer.keypair, Slot::new(3), &fork, &spec);
+        let first = cache.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        let second = cache.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        assert!(fresh.is_some());
+        assert_eq!(first, fresh);
+        assert_eq!(second, fresh);
+        assert_eq!(signer.signatures.load(Ordering::SeqCst), 1);
+
+        // Each slot has its own proof.
+        let other_slot = cache.get_or_sign(&signer, Slot::new(4), &fork, &spec);
+        assert_ne!(other_slot, fresh);
+        assert_eq!(signer.signatures.load(Ordering::SeqCst), 2);
+
+        // Without the cache, each request is signed.
+        uncached.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        uncached.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        assert_eq!(signer.signatures.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn proofs_resigned_after_fork_change() {
+        let spec = ChainSpec::minimal();
+        let fork = Fork::genesis(Epoch::new(0));
+        let next_fork = Fork {
+            previous_version: fork.current_version,
+            current_version: [1, 0, 0, 0],
+            epoch: Epoch::new(0),
+        };
+        let signer = signer();
+        let cache = SelectionProofCache::new(true, SLOTS_PER_EPOCH);
+
+        let before = cache.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        let after = cache.get_or_sign(&signer, Slot::new(3), &next_fork, &spec);
+        assert_eq!(signer.signatures.load(Ordering::SeqCst), 2);
+        assert_ne!(before, after);
+        assert_eq!(
+            after,
+            SelectionProofCache::new(false, SLOTS_PER_EPOCH).get_or_sign(
+                &signer.keypair,
+                Slot::new(3),
+                &next_fork,
+                &spec
+            )
+        );
+    }
+
+    #[test]
+    fn proofs_of_past_epochs_discarded() {
+        let spec = ChainSpec::minimal();
+        let fork = Fork::genesis(Epoch::new(0));
+        let signer = signer();
+        let cache = SelectionProofCache::new(true, SLOTS_PER_EPOCH);
+
+        cache.get_or_sign(&signer, Slot::new(3), &fork, &spec);
+        cache.get_or_sign(&signer, Slot::new(SLOTS_PER_EPOCH), &fork, &spec);
+        assert!(cache
+            .cached(&signer.to_public(), Slot::new(3), &fork)
+            .is_some());
+
+        cache.get_or_sign(&signer, Slot::new(SLOTS_PER_EPOCH * 2), &fork, &spec);
+        assert!(cache
+            .cached(&signer.to_public(), Slot::new(3), &fork)
+            .is_none());
+        assert!(cache
+            .cached(&signer.to_public(), Slot::new(SLOTS_PER_EPOCH), &fork)
+            .is_some());
+    }
+}
@@ -0,0 +1,256 @@
+use grpcio::{CallOption, MetadataBuilder};
+use protobuf::Message;
+use serde_json::json;
+use slog::warn;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// An append-only record of every request made to the beacon node.
+///
+/// Request and response bodies are stored as SHA-256 hashes, so calls can be correlated with the
+/// beacon node's logs without writing messages to disk. Once the file would exceed `max_bytes`
+/// it is moved to `<path>.1` (replacing any previous one) and a new file is started.
+pub struct AuditLog {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Mutex<File>,
+    log: slog::Logger,
+}
+
+impl AuditLog {
+    /// Opens the audit log at `path`, appending to it if it already exists.
+    pub fn open(path: &Path, max_bytes: u64, log: slog::Logger) -> Result<Self, String> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            file: Mutex::new(open_append(path)?),
+            log,
+        })
+    }
+
+    /// Appends a record of a call to `method`. `response` is the encoded response, or a
+    /// description of the error if the call failed.
+    pub fn record(
+        &self,
+        method: &str,
+        request: &[u8],
+        response: Result<&[u8], &str>,
+        latency: Duration,
+    ) -> Result<(), String> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| format!("System time is before the UNIX epoch: {:?}", e))?;
+
+        let entry = match response {
+            Ok(response) => json!({
+                "timestamp_ms": timestamp.as_millis() as u64,
+                "method": method,
+                "request_hash": hex_hash(request),
+                "status": "ok",
+                "response_hash": hex_hash(response),
+                "latency_ms": latency.as_millis() as u64,
+            }),
+            Err(error) => json!({
+                "timestamp_ms": timestamp.as_millis() as u64,
+                "method": method,
+                "request_hash": hex_hash(request),
+                "status": "error",
+                "error": error,
+                "latency_ms": latency.as_millis() as u64,
+            }),
+        };
+        let line = format!("{}\n", entry);
+
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| "Audit log lock poisoned".to_string())?;
+
+        let len = file
+            .metadata()
+            .map_err(|e| format!("Unable to read audit log metadata: {:?}", e))?
+            .len();
+        if len > 0 && len + line.len() as u64 > self.max_bytes {
+            fs::rename(&self.path, rotated_path(&self.path))
+                .map_err(|e| format!("Unable to rotate audit log: {:?}", e))?;
+            *file = open_append(&self.path)?;
+        }
+
+        file.write_all(line.as_bytes())
+            .map_err(|e| format!("Unable to write audit log: {:?}", e))
+    }
+}
+
+/// The options with which every request is made to the beacon node.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestOptions {
+    /// Requests which have not completed within this time fail.
+    pub timeout: Duration,
+    /// If set, sent with each request as `authorization: Bearer <token>`.
+    pub api_token: Option<String>,
+}
+
+impl RequestOptions {
+    fn call_option(&self) -> CallOption {
+        let call_option = CallOption::default().timeout(self.timeout);
+        match &self.api_token {
+            Some(token) => {
+                let mut headers = MetadataBuilder::new();
+                match headers.add_str("authorization", &format!("Bearer {}", token)) {
+                    Ok(_) => call_option.headers(headers.build()),
+                    // the token is validated when the config is loaded
+                    Err(_) => call_option,
+                }
+            }
+            None => call_option,
+        }
+    }
+}
+
+/// Performs the gRPC `call` with `req` and the call options given by `options`, recording it in
+/// the `audit_log` if one is provided.
+///
+/// A failure to write the audit log is logged but does not affect the call.
+pub fn audited<Req, Resp, F>(
+    audit_log: Option<&AuditLog>,
+    options: &RequestOptions,
+    method: &str,
+    req: &Req,
+    call: F,
+) -> grpcio::Result<Resp>
+where
+    Req: Message,
+    Resp: Message,
+    F: FnOnce(&Req, CallOption) -> grpcio::Result<Resp>,
+{
+    let audit_log = match audit_log {
+        Some(audit_log) => audit_log,
+        None => return call(req, options.call_option()),
+    };
+
+    let started = Instant::now();
+    let result = call(req, options.call_option());
+    let latency = started.elapsed();
+
+    let request = req.write_to_bytes().unwrap_or_default();
+    let response = match &result {
+        Ok(resp) => Ok(resp.write_to_bytes().unwrap_or_default()),
+        Err(e) => Err(format!("{:?}", e)),
+    };
+
+    if let Err(e) = audit_log.record(
+        method,
+        &request,
+        response.as_ref().map(Vec::as_slice).map_err(String::as_str),
+        latency,
+    ) {
+        warn!(audit_log.log, "Unable to record beacon node request"; "error" => e);
+    }
+
+    result
+}
+
+fn open_append(path: &Path) -> Result<File, String> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| format!("Unable to open audit log: {:?}", e))
+}
+
+/// Returns the path to which a full audit log at `path` is moved.
+fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_os_string();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+fn hex_hash(bytes: &[u8]) -> String {
+    eth2_hashing::hash(bytes)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protos::services::{Empty, NodeInfoResponse};
+    use tempfile::tempdir;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    fn options() -> RequestOptions {
+        RequestOptions {
+            timeout: Duration::from_secs(1),
+            api_token: None,
+        }
+    }
+
+    fn read_entries(path: &Path) -> Vec<serde_json::Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn requests_and_responses_recorded() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let audit_log = AuditLog::open(&path, 1 << 20, null_logger()).unwrap();
+
+        let mut response = NodeInfoResponse::new();
+        response.set_version("test".into());
+        let expected_response_hash = hex_hash(&response.write_to_bytes().unwrap());
+
+        let result = audited(
+            Some(&audit_log),
+            &options(),
+            "info",
+            &Empty::new(),
+            |_, _| Ok(response.clone()),
+        );
+        assert_eq!(result.unwrap(), response);
+
+        let result: grpcio::Result<NodeInfoResponse> = audited(
+            Some(&audit_log),
+            &options(),
+            "info",
+            &Empty::new(),
+            |_, _| Err(grpcio::Error::RemoteStopped),
+        );
+        assert!(result.is_err());
+
+        let entries = read_entries(&path);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["method"], "info");
+        assert_eq!(entries[0]["request_hash"], hex_hash(&[]));
+        assert_eq!(entries[0]["status"], "ok");
+        assert_eq!(entries[0]["response_hash"], expected_response_hash);
+        assert_eq!(entries[1]["status"], "error");
+        assert!(entries[1].get("response_hash").is_none());
+    }
+
+    #[test]
+    fn rotates_when_full() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let audit_log = AuditLog::open(&path, 300, null_logger()).unwrap();
+
+        for _ in 0..3 {
+            audit_log
+                .record("info", &[], Ok(&[][..]), Duration::from_millis(1))
+                .unwrap();
+        }
+
+        assert!(fs::metadata(&path).unwrap().len() <= 300);
+        assert!(!read_entries(&rotated_path(&path)).is_empty());
+    }
+}
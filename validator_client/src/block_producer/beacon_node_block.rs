@@ -1,8 +1,52 @@
+use grpcio::{RpcStatus, RpcStatusCode};
 use types::{BeaconBlock, EthSpec, Signature, Slot};
 #[derive(Debug, PartialEq, Clone)]
 pub enum BeaconNodeError {
     RemoteFailure(String),
     DecodeFailure,
+    /// The beacon node did not respond within the request timeout.
+    Timeout(String),
+    /// The beacon node refused the configured API token, or requires one.
+    Unauthorized(String),
+    /// The beacon node refused the request as malformed, so would refuse it again.
+    BadRequest(String),
+}
+
+impl BeaconNodeError {
+    /// Returns `true` if sending the same request again may succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            BeaconNodeError::BadRequest(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl From<grpcio::Error> for BeaconNodeError {
+    fn from(e: grpcio::Error) -> BeaconNodeError {
+        match &e {
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: RpcStatusCode::DeadlineExceeded,
+                ..
+            }) => BeaconNodeError::Timeout(format!("{:?}", e)),
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: RpcStatusCode::Unauthenticated,
+                ..
+            }) => BeaconNodeError::Unauthorized(format!("{:?}", e)),
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: RpcStatusCode::InvalidArgument,
+                ..
+            }) => BeaconNodeError::BadRequest(format!("{:?}", e)),
+            _ => BeaconNodeError::RemoteFailure(format!("{:?}", e)),
+        }
+    }
+}
+
+/// A block produced by the beacon node, with the index of the validator which is to propose it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ProducedBlock<T: EthSpec> {
+    pub block: BeaconBlock<T>,
+    pub proposer_index: u64,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -10,19 +54,22 @@ pub enum PublishOutcome {
     Valid,
     InvalidBlock(String),
     InvalidAttestation(String),
+    /// The attestation was rejected as it can no longer be included in a block.
+    AttestationTooOld(String),
 }
 
 /// Defines the methods required to produce and publish blocks on a Beacon Node. Abstracts the
 /// actual beacon node.
 pub trait BeaconNodeBlock: Send + Sync {
-    /// Request that the node produces a block.
+    /// Request that the node produces a block, including `graffiti`.
     ///
     /// Returns Ok(None) if the Beacon Node is unable to produce at the given slot.
     fn produce_beacon_block<T: EthSpec>(
         &self,
         slot: Slot,
         randao_reveal: &Signature,
-    ) -> Result<Option<BeaconBlock<T>>, BeaconNodeError>;
+        graffiti: &[u8; 32],
+    ) -> Result<Option<ProducedBlock<T>>, BeaconNodeError>;
 
     /// Request that the node publishes a block.
     ///
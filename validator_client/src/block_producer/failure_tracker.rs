@@ -0,0 +1,84 @@
+use slog::crit;
+use std::sync::atomic::{AtomicU64, Ordering};
+use types::Slot;
+
+/// Counts consecutive failed block proposals across all validators.
+///
+/// Block and attestation production run independently, so a failing proposal path (e.g., a beacon
+/// node which cannot produce blocks) does not prevent attestations. This makes such a failure
+/// loud, rather than letting it pass as a series of individual errors.
+pub struct BlockFailureTracker {
+    consecutive_failures: AtomicU64,
+    /// The number of consecutive failures at which to alert. Zero disables alerting.
+    alert_threshold: u64,
+}
+
+impl BlockFailureTracker {
+    pub fn new(alert_threshold: u64) -> Self {
+        Self {
+            consecutive_failures: AtomicU64::new(0),
+            alert_threshold,
+        }
+    }
+
+    /// Returns the number of block proposals which have failed since the last success.
+    pub fn consecutive_failures(&self) -> u64 {
+        self.consecutive_failures.load(Ordering::SeqCst)
+    }
+
+    /// Records the outcome of a block proposal at `slot`, returning `true` if an alert was raised.
+    pub fn record(&self, slot: Slot, produced: bool, log: &slog::Logger) -> bool {
+        if produced {
+            self.consecutive_failures.store(0, Ordering::SeqCst);
+            return false;
+        }
+
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if self.alert_threshold > 0 && failures >= self.alert_threshold {
+            crit!(
+                log,
+                "Block proposals are repeatedly failing";
+                "consecutive_failures" => failures,
+                "slot" => slot,
+                "msg" => "attestations are still being produced",
+            );
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    #[test]
+    fn alerts_after_consecutive_failures() {
+        let log = null_logger();
+        let tracker = BlockFailureTracker::new(3);
+
+        assert!(!tracker.record(Slot::new(1), false, &log));
+        assert!(!tracker.record(Slot::new(2), false, &log));
+        assert!(tracker.record(Slot::new(3), false, &log));
+        assert!(tracker.record(Slot::new(4), false, &log));
+
+        // A successful proposal resets the count.
+        assert!(!tracker.record(Slot::new(5), true, &log));
+        assert!(!tracker.record(Slot::new(6), false, &log));
+    }
+
+    #[test]
+    fn zero_threshold_never_alerts() {
+        let log = null_logger();
+        let tracker = BlockFailureTracker::new(0);
+
+        for slot in 0..10 {
+            assert!(!tracker.record(Slot::new(slot), false, &log));
+        }
+    }
+}
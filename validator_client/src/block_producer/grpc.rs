@@ -1,4 +1,5 @@
 use super::beacon_node_block::*;
+use crate::audit_log::{audited, AuditLog, RequestOptions};
 use protos::services::{
     BeaconBlock as GrpcBeaconBlock, ProduceBeaconBlockRequest, PublishBeaconBlockRequest,
 };
@@ -12,14 +13,38 @@ use types::{BeaconBlock, EthSpec, Signature, Slot};
 /// implemented upon it.
 pub struct BeaconBlockGrpcClient {
     client: Arc<BeaconBlockServiceClient>,
+    audit_log: Option<Arc<AuditLog>>,
+    options: RequestOptions,
 }
 
 impl BeaconBlockGrpcClient {
-    pub fn new(client: Arc<BeaconBlockServiceClient>) -> Self {
-        Self { client }
+    pub fn new(
+        client: Arc<BeaconBlockServiceClient>,
+        audit_log: Option<Arc<AuditLog>>,
+        options: RequestOptions,
+    ) -> Self {
+        Self {
+            client,
+            audit_log,
+            options,
+        }
     }
 }
 
+/// Builds the request for a block at `slot`. All 32 bytes of `graffiti` are always sent, since an
+/// empty graffiti field asks the node to use its own default.
+fn produce_block_request(
+    slot: Slot,
+    randao_reveal: &Signature,
+    graffiti: &[u8; 32],
+) -> ProduceBeaconBlockRequest {
+    let mut req = ProduceBeaconBlockRequest::new();
+    req.set_slot(slot.as_u64());
+    req.set_randao_reveal(randao_reveal.as_ssz_bytes());
+    req.set_graffiti(graffiti.to_vec());
+    req
+}
+
 impl BeaconNodeBlock for BeaconBlockGrpcClient {
     /// Request a Beacon Node (BN) to produce a new block at the supplied slot.
     ///
@@ -29,17 +54,19 @@ impl BeaconNodeBlock for BeaconBlockGrpcClient {
         &self,
         slot: Slot,
         randao_reveal: &Signature,
-    ) -> Result<Option<BeaconBlock<T>>, BeaconNodeError> {
+        graffiti: &[u8; 32],
+    ) -> Result<Option<ProducedBlock<T>>, BeaconNodeError> {
         // request a beacon block from the node
-        let mut req = ProduceBeaconBlockRequest::new();
-        req.set_slot(slot.as_u64());
-        req.set_randao_reveal(randao_reveal.as_ssz_bytes());
+        let req = produce_block_request(slot, randao_reveal, graffiti);
 
-        //TODO: Determine if we want an explicit timeout
-        let reply = self
-            .client
-            .produce_beacon_block(&req)
-            .map_err(|err| BeaconNodeError::RemoteFailure(format!("{:?}", err)))?;
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "BeaconBlockService/ProduceBeaconBlock",
+            &req,
+            |req, opt| self.client.produce_beacon_block_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
 
         // format the reply
         if reply.has_block() {
@@ -49,7 +76,10 @@ impl BeaconNodeBlock for BeaconBlockGrpcClient {
             let block =
                 BeaconBlock::from_ssz_bytes(&ssz).map_err(|_| BeaconNodeError::DecodeFailure)?;
 
-            Ok(Some(block))
+            Ok(Some(ProducedBlock {
+                block,
+                proposer_index: reply.get_proposer_index(),
+            }))
         } else {
             Ok(None)
         }
@@ -72,16 +102,38 @@ impl BeaconNodeBlock for BeaconBlockGrpcClient {
 
         req.set_block(grpc_block);
 
-        let reply = self
-            .client
-            .publish_beacon_block(&req)
-            .map_err(|err| BeaconNodeError::RemoteFailure(format!("{:?}", err)))?;
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "BeaconBlockService/PublishBeaconBlock",
+            &req,
+            |req, opt| self.client.publish_beacon_block_opt(req, opt),
+        )
+        .map_err(BeaconNodeError::from)?;
 
+        let msg = String::from_utf8_lossy(reply.get_msg()).into_owned();
         if reply.get_success() {
             Ok(PublishOutcome::Valid)
+        } else if reply.get_retryable() {
+            Err(BeaconNodeError::RemoteFailure(msg))
         } else {
-            // TODO: distinguish between different errors
-            Ok(PublishOutcome::InvalidBlock("Publish failed".to_string()))
+            Ok(PublishOutcome::InvalidBlock(msg))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_graffiti_sent_in_full() {
+        let randao_reveal = Signature::empty_signature();
+
+        let req = produce_block_request(Slot::new(1), &randao_reveal, &[0; 32]);
+        assert_eq!(req.get_graffiti(), &[0; 32][..]);
+
+        let req = produce_block_request(Slot::new(1), &randao_reveal, &[7; 32]);
+        assert_eq!(req.get_graffiti(), &[7; 32][..]);
+    }
+}
@@ -1,15 +1,23 @@
 mod beacon_node_block;
+mod failure_tracker;
 mod grpc;
 
-use self::beacon_node_block::BeaconNodeBlock;
-pub use self::beacon_node_block::{BeaconNodeError, PublishOutcome};
+pub use self::beacon_node_block::{
+    BeaconNodeBlock, BeaconNodeError, ProducedBlock, PublishOutcome,
+};
+pub use self::failure_tracker::BlockFailureTracker;
 pub use self::grpc::BeaconBlockGrpcClient;
+use crate::attestation_producer::InvalidAttestationData;
+use crate::observer::ProductionObserver;
+use crate::publish_retry::publish_with_retries;
 use crate::signer::Signer;
+use crate::skip_reason::SkipReason;
+use crate::slashing_protection::SlashingDatabase;
 use core::marker::PhantomData;
-use slog::{error, info, trace, warn};
+use slog::{crit, error, info, trace, warn};
 use std::sync::Arc;
 use tree_hash::{SignedRoot, TreeHash};
-use types::{BeaconBlock, ChainSpec, Domain, EthSpec, Fork, Slot};
+use types::{BeaconBlock, ChainSpec, Domain, EthSpec, Fork, Hash256, Signature, Slot};
 
 #[derive(Debug, PartialEq)]
 pub enum Error {
@@ -28,12 +36,45 @@ pub enum ValidatorEvent {
     IndexedAttestationNotProduced(Slot),
     /// The Beacon Node was unable to produce a block at that slot.
     BeaconNodeUnableToProduceBlock(Slot),
+    /// Beacon node rejected the signed block.
+    BlockRejected(Slot),
     /// The signer failed to sign the message.
     SignerRejection(Slot),
     /// Publishing an attestation failed.
     PublishAttestationFailed,
     /// Beacon node rejected the attestation.
     InvalidAttestation,
+    /// Beacon node rejected the attestation as too old to be included in a block.
+    AttestationExpired(Slot),
+    /// The beacon node returned attestation data which does not match the duty.
+    InconsistentAttestationData(InvalidAttestationData),
+    /// The beacon node returned a block which does not match the duty.
+    InconsistentBlock(InvalidBlock),
+    /// A block or attestation was produced, but neither signed nor published.
+    DryRun(Slot),
+    /// An aggregate was signed and published by a selected aggregator.
+    AggregateProduced(Slot),
+    /// The validator's selection proof did not select it to aggregate its committee.
+    NotAggregator(Slot),
+    /// The beacon node had no attestations for the aggregator's committee.
+    NoAggregate(Slot),
+    /// An attestation was withheld as the beacon node's head was too far behind its slot.
+    NodeSyncing(Slot),
+}
+
+/// The reason a block returned by the beacon node may not be signed.
+#[derive(Debug, PartialEq)]
+pub enum InvalidBlock {
+    /// The block is for a different slot to the duty.
+    WrongSlot { expected: Slot, found: Slot },
+    /// The block does not contain this validator's RANDAO reveal, so was built for another
+    /// proposer. Blocks do not otherwise identify their proposer.
+    WrongProposer,
+    /// The beacon node reports that the block is to be proposed by a validator other than the
+    /// one whose duty it is.
+    WrongProposerIndex { expected: u64, found: u64 },
+    /// The block has a zero parent root, which only the genesis block may have.
+    ZeroParentRoot,
 }
 
 /// This struct contains the logic for requesting and signing beacon blocks for a validator. The
@@ -43,6 +84,9 @@ pub struct BlockProducer<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> {
     pub fork: Fork,
     /// The current slot to produce a block for.
     pub slot: Slot,
+    /// The index of the validator, if known from its duties. A block which the beacon node
+    /// reports is for another proposer is refused.
+    pub validator_index: Option<u64>,
     /// The current epoch.
     pub spec: Arc<ChainSpec>,
     /// The beacon node to connect to.
@@ -55,12 +99,25 @@ pub struct BlockProducer<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> {
     pub _phantom: PhantomData<E>,
     /// The logger, for logging
     pub log: slog::Logger,
+    /// Notified of the outcome of block production.
+    pub observer: Arc<dyn ProductionObserver<E>>,
+    /// Consulted before any block is signed.
+    pub slashing_protection: Arc<SlashingDatabase>,
+    /// The graffiti to include in the block.
+    pub graffiti: [u8; 32],
+    /// The number of times publication is retried if the beacon node fails.
+    pub max_publish_retries: u64,
+    /// If true, the block is logged rather than signed and published.
+    pub dry_run: bool,
 }
 
 impl<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> BlockProducer<'a, B, S, E> {
     /// Handle outputs and results from block production.
-    pub fn handle_produce_block(&mut self) {
-        match self.produce_block() {
+    ///
+    /// Returns the reason the block was skipped, or `None` if a block was produced.
+    pub fn handle_produce_block(&mut self) -> Option<SkipReason> {
+        let result = self.produce_block();
+        match &result {
             Ok(ValidatorEvent::BlockProduced(slot)) => info!(
                 self.log,
                 "Block produced";
@@ -71,8 +128,16 @@ impl<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> BlockProducer<'a, B, S, E> {
             Ok(ValidatorEvent::SignerRejection(_slot)) => error!(self.log, "Block production error"; "Error" => "Signer Could not sign the block".to_string()),
             Ok(ValidatorEvent::SlashableBlockNotProduced(_slot)) => error!(self.log, "Block production error"; "Error" => "Rejected the block as it could have been slashed".to_string()),
             Ok(ValidatorEvent::BeaconNodeUnableToProduceBlock(_slot)) => error!(self.log, "Block production error"; "Error" => "Beacon node was unable to produce a block".to_string()),
+            Ok(ValidatorEvent::BlockRejected(_slot)) => error!(self.log, "Block production error"; "Error" => "Beacon node rejected the signed block".to_string()),
+            // the withheld block is logged as it is produced
+            Ok(ValidatorEvent::DryRun(_slot)) => {}
+            Ok(ValidatorEvent::InconsistentBlock(e)) => crit!(self.log, "Refused to sign an invalid block from the beacon node"; "Error" => format!("{:?}", e), "slot" => self.slot),
             Ok(v) => warn!(self.log, "Unknown result for block production"; "Error" => format!("{:?}",v)),
         }
+        match &result {
+            Ok(event) => SkipReason::from_event(event),
+            Err(_) => Some(SkipReason::BeaconNodeError),
+        }
     }
 
     /// Produce a block at some slot.
@@ -81,39 +146,74 @@ impl<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> BlockProducer<'a, B, S, E> {
     ///
     /// Ensures the message is not slashable.
     ///
-    /// !!! UNSAFE !!!
-    ///
-    /// The slash-protection code is not yet implemented. There is zero protection against
-    /// slashing.
+    /// In a dry run, only the RANDAO reveal is signed, since the beacon node requires it to build
+    /// the block and it cannot be slashed.
     pub fn produce_block(&mut self) -> Result<ValidatorEvent, Error> {
         let epoch = self.slot.epoch(self.slots_per_epoch);
         trace!(self.log, "Producing block"; "epoch" => epoch);
 
-        let message = epoch.tree_hash_root();
-        let randao_reveal = match self.signer.sign_message(
-            &message,
-            self.spec.get_domain(epoch, Domain::Randao, &self.fork),
-        ) {
+        let randao_reveal = match self.randao_reveal() {
             None => {
-                warn!(self.log, "Signing rejected"; "message" => format!("{:?}", message));
+                warn!(self.log, "Signing rejected"; "message" => "randao reveal", "epoch" => epoch);
+                self.observer
+                    .on_signing_failed(&self.signer.to_public(), self.slot);
                 return Ok(ValidatorEvent::SignerRejection(self.slot));
             }
             Some(signature) => signature,
         };
 
-        if let Some(block) = self
+        if let Some(ProducedBlock {
+            block,
+            proposer_index,
+        }) = self
             .beacon_node
-            .produce_beacon_block(self.slot, &randao_reveal)?
+            .produce_beacon_block(self.slot, &randao_reveal, &self.graffiti)?
         {
+            if let Err(e) = self.validate_block(&block, proposer_index, &randao_reveal) {
+                return Ok(ValidatorEvent::InconsistentBlock(e));
+            }
+            if self.dry_run {
+                info!(
+                    self.log,
+                    "Dry run: block not signed or published";
+                    "validator" => format!("{}", self.signer),
+                    "slot" => block.slot,
+                    "parent_root" => format!("{:?}", block.parent_root),
+                    "signing_root" => hex::encode(block.signed_root()),
+                );
+                return Ok(ValidatorEvent::DryRun(self.slot));
+            }
             if self.safe_to_produce(&block) {
                 let slot = block.slot;
                 let domain = self
                     .spec
                     .get_domain(epoch, Domain::BeaconProposer, &self.fork);
                 if let Some(block) = self.sign_block(block, domain) {
-                    self.beacon_node.publish_beacon_block(block)?;
-                    Ok(ValidatorEvent::BlockProduced(slot))
+                    let outcome = publish_with_retries(
+                        || self.beacon_node.publish_beacon_block(block.clone()),
+                        self.max_publish_retries,
+                        "block",
+                        &self.log,
+                    )?;
+                    match outcome {
+                        PublishOutcome::Valid => {
+                            self.observer
+                                .on_block_produced(&self.signer.to_public(), &block);
+                            Ok(ValidatorEvent::BlockProduced(slot))
+                        }
+                        rejection => {
+                            warn!(
+                                self.log,
+                                "Beacon node rejected block";
+                                "slot" => slot,
+                                "outcome" => format!("{:?}", rejection),
+                            );
+                            Ok(ValidatorEvent::BlockRejected(slot))
+                        }
+                    }
                 } else {
+                    self.observer
+                        .on_signing_failed(&self.signer.to_public(), self.slot);
                     Ok(ValidatorEvent::SignerRejection(self.slot))
                 }
             } else {
@@ -124,13 +224,50 @@ impl<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> BlockProducer<'a, B, S, E> {
         }
     }
 
+    /// Signs the RANDAO reveal for the epoch of the block being produced.
+    ///
+    /// The domain is computed from the epoch of `self.slot`, so the correct fork version is used
+    /// for blocks either side of a fork boundary.
+    fn randao_reveal(&self) -> Option<Signature> {
+        let epoch = self.slot.epoch(self.slots_per_epoch);
+        let message = epoch.tree_hash_root();
+        let domain = self.spec.get_domain(epoch, Domain::Randao, &self.fork);
+
+        self.signer.sign_message(&message, domain)
+    }
+
+    /// Checks that a `block` returned by the beacon node, for `proposer_index`, is the one
+    /// requested for this duty.
+    fn validate_block(
+        &self,
+        block: &BeaconBlock<E>,
+        proposer_index: u64,
+        randao_reveal: &Signature,
+    ) -> Result<(), InvalidBlock> {
+        if block.slot != self.slot {
+            Err(InvalidBlock::WrongSlot {
+                expected: self.slot,
+                found: block.slot,
+            })
+        } else if let Some(expected) = self.validator_index.filter(|i| *i != proposer_index) {
+            Err(InvalidBlock::WrongProposerIndex {
+                expected,
+                found: proposer_index,
+            })
+        } else if block.body.randao_reveal != *randao_reveal {
+            Err(InvalidBlock::WrongProposer)
+        } else if block.parent_root == Hash256::zero() {
+            Err(InvalidBlock::ZeroParentRoot)
+        } else {
+            Ok(())
+        }
+    }
+
     /// Consumes a block, returning that block signed by the validators private key.
     ///
     /// Important: this function will not check to ensure the block is not slashable. This must be
     /// done upstream.
     fn sign_block(&mut self, mut block: BeaconBlock<E>, domain: u64) -> Option<BeaconBlock<E>> {
-        self.store_produce(&block);
-
         match self.signer.sign_message(&block.signed_root()[..], domain) {
             None => None,
             Some(signature) => {
@@ -140,25 +277,12 @@ impl<'a, B: BeaconNodeBlock, S: Signer, E: EthSpec> BlockProducer<'a, B, S, E> {
         }
     }
 
-    /// Returns `true` if signing a block is safe (non-slashable).
-    ///
-    /// !!! UNSAFE !!!
-    ///
-    /// Important: this function is presently stubbed-out. It provides ZERO SAFETY.
-    fn safe_to_produce(&self, _block: &BeaconBlock<E>) -> bool {
-        // TODO: ensure the producer doesn't produce slashable blocks.
-        // https://github.com/sigp/lighthouse/issues/160
-        true
-    }
-
-    /// Record that a block was produced so that slashable votes may not be made in the future.
-    ///
-    /// !!! UNSAFE !!!
-    ///
-    /// Important: this function is presently stubbed-out. It provides ZERO SAFETY.
-    fn store_produce(&mut self, _block: &BeaconBlock<E>) {
-        // TODO: record this block production to prevent future slashings.
-        // https://github.com/sigp/lighthouse/issues/160
+    /// Returns `true` if signing a block is safe (non-slashable), recording the proposal so that
+    /// no conflicting block may be signed in the future.
+    fn safe_to_produce(&self, block: &BeaconBlock<E>) -> bool {
+        self.slashing_protection
+            .check_and_insert_block_proposal(&self.signer.to_public(), block.slot)
+            .is_ok()
     }
 }
 
@@ -168,6 +292,525 @@ impl From<BeaconNodeError> for Error {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote_signer::RemoteSigner;
+    use crate::signer::ValidatorSigner;
+    use ssz::{Decode, Encode};
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use types::{Attestation, Epoch, Keypair, MinimalEthSpec, PublicKey};
+
+    type E = MinimalEthSpec;
+
+    /// A way in which the test beacon node corrupts the blocks it produces.
+    #[derive(Clone, Copy)]
+    enum Corruption {
+        WrongSlot,
+        WrongRandaoReveal,
+        WrongProposerIndex,
+        ZeroParentRoot,
+    }
+
+    /// The index of the validator for which the test beacon node produces blocks.
+    const PROPOSER_INDEX: u64 = 7;
+
+    /// Returns an empty block at the requested slot, unless configured to `corrupt` it, and
+    /// accepts every published block, after failing the first `publish_failures` attempts to
+    /// publish. If `reject` is set, published blocks are rejected as invalid instead.
+    struct TestBeaconNode {
+        published: Mutex<Vec<BeaconBlock<E>>>,
+        publish_failures: Mutex<usize>,
+        reject: Mutex<bool>,
+        randao_reveals: Mutex<Vec<Signature>>,
+        corrupt: Mutex<Option<Corruption>>,
+    }
+
+    impl BeaconNodeBlock for TestBeaconNode {
+        fn produce_beacon_block<T: EthSpec>(
+            &self,
+            slot: Slot,
+            randao_reveal: &Signature,
+            graffiti: &[u8; 32],
+        ) -> Result<Option<ProducedBlock<T>>, BeaconNodeError> {
+            self.randao_reveals
+                .lock()
+                .unwrap()
+                .push(randao_reveal.clone());
+            let mut block = BeaconBlock::empty(&ChainSpec::minimal());
+            block.slot = slot;
+            block.parent_root = Hash256::from_low_u64_be(1);
+            block.body.randao_reveal = randao_reveal.clone();
+            block.body.graffiti = *graffiti;
+            let mut proposer_index = PROPOSER_INDEX;
+            match *self.corrupt.lock().unwrap() {
+                Some(Corruption::WrongSlot) => block.slot += 1,
+                Some(Corruption::WrongRandaoReveal) => {
+                    block.body.randao_reveal = Signature::empty_signature()
+                }
+                Some(Corruption::WrongProposerIndex) => proposer_index += 1,
+                Some(Corruption::ZeroParentRoot) => block.parent_root = Hash256::zero(),
+                None => {}
+            }
+            Ok(Some(ProducedBlock {
+                block,
+                proposer_index,
+            }))
+        }
+
+        fn publish_beacon_block<T: EthSpec>(
+            &self,
+            block: BeaconBlock<T>,
+        ) -> Result<PublishOutcome, BeaconNodeError> {
+            let mut publish_failures = self.publish_failures.lock().unwrap();
+            if *publish_failures > 0 {
+                *publish_failures -= 1;
+                return Err(BeaconNodeError::RemoteFailure("unavailable".into()));
+            }
+            if *self.reject.lock().unwrap() {
+                return Ok(PublishOutcome::InvalidBlock("invalid signature".into()));
+            }
+            let block = BeaconBlock::from_ssz_bytes(&block.as_ssz_bytes())
+                .map_err(|_| BeaconNodeError::DecodeFailure)?;
+            self.published.lock().unwrap().push(block);
+            Ok(PublishOutcome::Valid)
+        }
+    }
+
+    /// A signer which refuses to sign anything.
+    #[derive(Clone)]
+    struct RejectingSigner(PublicKey);
+
+    impl std::fmt::Display for RejectingSigner {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl Signer for RejectingSigner {
+        fn sign_message(&self, _message: &[u8], _domain: u64) -> Option<Signature> {
+            None
+        }
+
+        fn to_public(&self) -> PublicKey {
+            self.0.clone()
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        blocks: Mutex<Vec<(PublicKey, BeaconBlock<E>)>>,
+        signing_failures: Mutex<Vec<(PublicKey, Slot)>>,
+    }
+
+    impl ProductionObserver<E> for RecordingObserver {
+        fn on_block_produced(&self, validator: &PublicKey, block: &BeaconBlock<E>) {
+            self.blocks
+                .lock()
+                .unwrap()
+                .push((validator.clone(), block.clone()));
+        }
+
+        fn on_attestation_produced(&self, _validator: &PublicKey, _attestation: &Attestation<E>) {
+            panic!("No attestations should be produced")
+        }
+
+        fn on_signing_failed(&self, validator: &PublicKey, slot: Slot) {
+            self.signing_failures
+                .lock()
+                .unwrap()
+                .push((validator.clone(), slot));
+        }
+    }
+
+    fn producer<'a, S: Signer>(
+        signer: &'a S,
+        beacon_node: Arc<TestBeaconNode>,
+        observer: Arc<RecordingObserver>,
+    ) -> BlockProducer<'a, TestBeaconNode, S, E> {
+        BlockProducer {
+            fork: Fork::genesis(Epoch::new(0)),
+            slot: Slot::new(9),
+            validator_index: Some(PROPOSER_INDEX),
+            spec: Arc::new(ChainSpec::minimal()),
+            beacon_node,
+            signer,
+            slots_per_epoch: E::slots_per_epoch(),
+            _phantom: PhantomData,
+            log: slog::Logger::root(slog::Discard, slog::o!()),
+            observer,
+            slashing_protection: Arc::new(SlashingDatabase::in_memory()),
+            graffiti: [0; 32],
+            max_publish_retries: 3,
+            dry_run: false,
+        }
+    }
+
+    fn beacon_node() -> Arc<TestBeaconNode> {
+        Arc::new(TestBeaconNode {
+            published: Mutex::new(vec![]),
+            publish_failures: Mutex::new(0),
+            reject: Mutex::new(false),
+            randao_reveals: Mutex::new(vec![]),
+            corrupt: Mutex::new(None),
+        })
+    }
+
+    #[test]
+    fn observer_notified_of_produced_block() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+        );
+
+        let blocks = observer.blocks.lock().unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0, keypair.pk);
+        assert_eq!(blocks[0].1, beacon_node.published.lock().unwrap()[0]);
+        assert!(observer.signing_failures.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn rejected_block_not_reported_as_produced() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        *beacon_node.reject.lock().unwrap() = true;
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockRejected(Slot::new(9)))
+        );
+        assert!(observer.blocks.lock().unwrap().is_empty());
+
+        let mut block_producer = producer(&keypair, beacon_node, observer.clone());
+        assert_eq!(
+            block_producer.handle_produce_block(),
+            Some(SkipReason::PublishRejected)
+        );
+        assert!(observer.blocks.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn randao_domain_follows_block_epoch_across_fork() {
+        let keypair = Keypair::random();
+        let fork = Fork {
+            previous_version: [0, 0, 0, 1],
+            current_version: [0, 0, 0, 2],
+            epoch: Epoch::new(2),
+        };
+
+        // The `DOMAIN_RANDAO` constant from the spec, followed by the fork version.
+        let expected_domain = |version: [u8; 4]| {
+            let mut bytes = [0; 8];
+            bytes[..4].copy_from_slice(&1_u32.to_le_bytes());
+            bytes[4..].copy_from_slice(&version);
+            u64::from_le_bytes(bytes)
+        };
+
+        let mut block_producer = producer(
+            &keypair,
+            beacon_node(),
+            Arc::new(RecordingObserver::default()),
+        );
+        block_producer.fork = fork.clone();
+
+        // The last slot before the fork uses the previous fork version.
+        let last_slot_before_fork = Epoch::new(2).start_slot(E::slots_per_epoch()) - 1;
+        block_producer.slot = last_slot_before_fork;
+        let reveal = block_producer.randao_reveal().unwrap();
+        let message = Epoch::new(1).tree_hash_root();
+        assert!(reveal.verify(
+            &message,
+            expected_domain(fork.previous_version),
+            &keypair.pk
+        ));
+        assert!(!reveal.verify(&message, expected_domain(fork.current_version), &keypair.pk));
+
+        // The first slot of the fork epoch uses the current fork version.
+        block_producer.slot = Epoch::new(2).start_slot(E::slots_per_epoch());
+        let reveal = block_producer.randao_reveal().unwrap();
+        let message = Epoch::new(2).tree_hash_root();
+        assert!(reveal.verify(&message, expected_domain(fork.current_version), &keypair.pk));
+        assert!(!reveal.verify(
+            &message,
+            expected_domain(fork.previous_version),
+            &keypair.pk
+        ));
+    }
+
+    #[test]
+    fn randao_reveal_included_in_block_request() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let spec = ChainSpec::minimal();
+
+        let mut block_producer = producer(
+            &keypair,
+            beacon_node.clone(),
+            Arc::new(RecordingObserver::default()),
+        );
+        // Slot 9 is in epoch 1 of the minimal spec.
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+        );
+
+        let epoch = Epoch::new(1);
+        let domain = spec.get_domain(epoch, Domain::Randao, &Fork::genesis(Epoch::new(0)));
+        let reveals = beacon_node.randao_reveals.lock().unwrap();
+        assert_eq!(reveals.len(), 1);
+        assert!(reveals[0].verify(&epoch.tree_hash_root(), domain, &keypair.pk));
+        assert!(!reveals[0].verify(&Epoch::new(0).tree_hash_root(), domain, &keypair.pk));
+
+        // The block itself is signed under a different domain.
+        let proposer_domain =
+            spec.get_domain(epoch, Domain::BeaconProposer, &Fork::genesis(Epoch::new(0)));
+        assert!(!reveals[0].verify(&epoch.tree_hash_root(), proposer_domain, &keypair.pk));
+    }
+
+    #[test]
+    fn invalid_blocks_not_signed() {
+        let keypair = Keypair::random();
+        let cases = vec![
+            (
+                Corruption::WrongSlot,
+                InvalidBlock::WrongSlot {
+                    expected: Slot::new(9),
+                    found: Slot::new(10),
+                },
+            ),
+            (Corruption::WrongRandaoReveal, InvalidBlock::WrongProposer),
+            (
+                Corruption::WrongProposerIndex,
+                InvalidBlock::WrongProposerIndex {
+                    expected: PROPOSER_INDEX,
+                    found: PROPOSER_INDEX + 1,
+                },
+            ),
+            (Corruption::ZeroParentRoot, InvalidBlock::ZeroParentRoot),
+        ];
+
+        for (corruption, expected) in cases {
+            let beacon_node = beacon_node();
+            *beacon_node.corrupt.lock().unwrap() = Some(corruption);
+            let observer = Arc::new(RecordingObserver::default());
+            let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+            let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+            block_producer.slashing_protection = slashing_protection.clone();
+            assert_eq!(
+                block_producer.produce_block(),
+                Ok(ValidatorEvent::InconsistentBlock(expected))
+            );
+            assert!(beacon_node.published.lock().unwrap().is_empty());
+            assert!(observer.blocks.lock().unwrap().is_empty());
+
+            // The refused block is not recorded, so a valid block may still be signed.
+            *beacon_node.corrupt.lock().unwrap() = None;
+            assert_eq!(
+                block_producer.produce_block(),
+                Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+            );
+        }
+    }
+
+    #[test]
+    fn dry_run_neither_signs_nor_publishes() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+        let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+        block_producer.slashing_protection = slashing_protection.clone();
+        block_producer.dry_run = true;
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::DryRun(Slot::new(9)))
+        );
+        assert_eq!(block_producer.handle_produce_block(), None);
+
+        // The block was requested, but nothing was published.
+        assert_eq!(beacon_node.randao_reveals.lock().unwrap().len(), 2);
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+        assert!(observer.blocks.lock().unwrap().is_empty());
+
+        // Nothing was recorded by slashing protection, so the block may be signed for real.
+        block_producer.dry_run = false;
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+        );
+    }
+
+    #[test]
+    fn observer_notified_of_signing_failure() {
+        let signer = RejectingSigner(Keypair::random().pk);
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut block_producer = producer(&signer, beacon_node.clone(), observer.clone());
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::SignerRejection(Slot::new(9)))
+        );
+
+        assert_eq!(
+            *observer.signing_failures.lock().unwrap(),
+            vec![(signer.0.clone(), Slot::new(9))]
+        );
+        assert!(observer.blocks.lock().unwrap().is_empty());
+        assert!(beacon_node.published.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn handle_produce_block_reports_outcome() {
+        let keypair = Keypair::random();
+        let observer = Arc::new(RecordingObserver::default());
+        assert_eq!(
+            producer(&keypair, beacon_node(), observer.clone()).handle_produce_block(),
+            None
+        );
+
+        let signer = RejectingSigner(keypair.pk.clone());
+        assert_eq!(
+            producer(&signer, beacon_node(), observer).handle_produce_block(),
+            Some(SkipReason::SignerRejection)
+        );
+    }
+
+    #[test]
+    fn consecutive_proposals_sign_distinct_blocks() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+
+        // A producer is built for each duty, so nothing is shared between the two slots.
+        for slot in &[Slot::new(9), Slot::new(10)] {
+            let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+            block_producer.slot = *slot;
+            assert_eq!(
+                block_producer.produce_block(),
+                Ok(ValidatorEvent::BlockProduced(*slot))
+            );
+        }
+
+        let published = beacon_node.published.lock().unwrap();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].slot, Slot::new(9));
+        assert_eq!(published[1].slot, Slot::new(10));
+        assert_ne!(published[0].signature, published[1].signature);
+        assert_eq!(observer.blocks.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn double_proposal_not_signed() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+        // Two producers for the same slot, e.g. after a restart.
+        for expected in &[
+            ValidatorEvent::BlockProduced(Slot::new(9)),
+            ValidatorEvent::SlashableBlockNotProduced(Slot::new(9)),
+        ] {
+            let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+            block_producer.slashing_protection = slashing_protection.clone();
+            assert_eq!(block_producer.produce_block().as_ref(), Ok(expected));
+        }
+
+        assert_eq!(beacon_node.published.lock().unwrap().len(), 1);
+        assert_eq!(observer.blocks.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn local_fallback_signs_under_slashing_protection_when_remote_signer_fails() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+        let slashing_protection = Arc::new(SlashingDatabase::in_memory());
+
+        // Nothing listens at the remote signer's address, so every remote request fails.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let remote =
+            RemoteSigner::new(keypair.pk.clone(), &url, Duration::from_secs(1), log).unwrap();
+        let signer = ValidatorSigner::RemoteWithFallback(remote, keypair.clone());
+
+        // The local key signs the first proposal, and slashing protection refuses the second.
+        for expected in &[
+            ValidatorEvent::BlockProduced(Slot::new(9)),
+            ValidatorEvent::SlashableBlockNotProduced(Slot::new(9)),
+        ] {
+            let mut block_producer = producer(&signer, beacon_node.clone(), observer.clone());
+            block_producer.slashing_protection = slashing_protection.clone();
+            assert_eq!(block_producer.produce_block().as_ref(), Ok(expected));
+        }
+
+        let published = beacon_node.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let domain = ChainSpec::minimal().get_domain(
+            Slot::new(9).epoch(E::slots_per_epoch()),
+            Domain::BeaconProposer,
+            &Fork::genesis(Epoch::new(0)),
+        );
+        assert!(published[0].signature.verify(
+            &published[0].signed_root()[..],
+            domain,
+            &keypair.pk
+        ));
+    }
+
+    #[test]
+    fn publication_retried_after_transient_failures() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        *beacon_node.publish_failures.lock().unwrap() = 2;
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut block_producer = producer(&keypair, beacon_node.clone(), observer.clone());
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+        );
+
+        // The block signed before the first attempt is the one published.
+        let published = beacon_node.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        assert_eq!(observer.blocks.lock().unwrap()[0].1, published[0]);
+    }
+
+    #[test]
+    fn graffiti_included_in_block_request() {
+        let keypair = Keypair::random();
+        let beacon_node = beacon_node();
+        let observer = Arc::new(RecordingObserver::default());
+
+        let mut block_producer = producer(&keypair, beacon_node.clone(), observer);
+        block_producer.graffiti = [7; 32];
+        assert_eq!(
+            block_producer.produce_block(),
+            Ok(ValidatorEvent::BlockProduced(Slot::new(9)))
+        );
+
+        assert_eq!(
+            beacon_node.published.lock().unwrap()[0].body.graffiti,
+            [7; 32]
+        );
+    }
+}
+
 /* Old tests - Re-work for new logic
 #[cfg(test)]
 mod tests {
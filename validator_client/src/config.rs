@@ -1,16 +1,21 @@
+use crate::keystore::{load_keystore, PASSWORD_FILE_EXTENSION};
+use crate::message_sink::DEFAULT_MESSAGE_SINK_MAX_BYTES;
 use bincode;
 use bls::Keypair;
 use clap::ArgMatches;
 use serde_derive::{Deserialize, Serialize};
 use slog::{error, info, o, warn, Drain};
+use ssz::Decode;
 use std::fs::{self, File, OpenOptions};
 use std::io::{Error, ErrorKind};
 use std::ops::Range;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use types::{
     test_utils::{generate_deterministic_keypair, load_keypairs_from_yaml},
-    EthSpec, MainnetEthSpec,
+    Epoch, EthSpec, MainnetEthSpec, PublicKey,
 };
 
 pub const DEFAULT_SERVER: &str = "localhost";
@@ -33,6 +38,228 @@ impl Default for KeySource {
     }
 }
 
+/// The storage used to record signed messages for slashing protection.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SlashingProtectionKind {
+    /// A single JSON file, re-written on each signing.
+    File,
+    /// An SQLite database, using transactions for each check-and-insert.
+    Sqlite,
+}
+
+impl Default for SlashingProtectionKind {
+    fn default() -> Self {
+        SlashingProtectionKind::Sqlite
+    }
+}
+
+impl SlashingProtectionKind {
+    /// The name of the slashing protection file within the data directory.
+    pub fn filename(self) -> &'static str {
+        match self {
+            SlashingProtectionKind::File => "slashing_protection.json",
+            SlashingProtectionKind::Sqlite => "slashing_protection.sqlite",
+        }
+    }
+}
+
+/// The level at which the one-line summary of each slot is logged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SlotSummaryLevel {
+    Off,
+    Info,
+    Debug,
+}
+
+impl Default for SlotSummaryLevel {
+    fn default() -> Self {
+        SlotSummaryLevel::Info
+    }
+}
+
+/// The response to a local clock which differs from the beacon node's at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ClockSkewAction {
+    Warn,
+    /// Refuse to start.
+    Reject,
+}
+
+impl Default for ClockSkewAction {
+    fn default() -> Self {
+        ClockSkewAction::Warn
+    }
+}
+
+/// The response to a beacon node whose clock is at least a slot ahead of the local clock.
+///
+/// Duties are halted by default, since trusting the node would sign messages for a slot which
+/// has not yet started on the local clock.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum NodeClockAheadAction {
+    /// Use the beacon node's slot rather than the local slot.
+    Trust,
+    /// Perform no duties until the local clock catches up.
+    Halt,
+}
+
+impl Default for NodeClockAheadAction {
+    fn default() -> Self {
+        NodeClockAheadAction::Halt
+    }
+}
+
+/// A fork which is not known to the spec, used for shadow-fork testing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ShadowFork {
+    pub version: [u8; 4],
+    pub epoch: Epoch,
+}
+
+impl FromStr for ShadowFork {
+    type Err = &'static str;
+
+    /// Parses a fork in the form `0x<version>@<epoch>`, e.g., `0x00000002@1024`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '@');
+        let version = parts.next().unwrap_or("");
+        let epoch = parts
+            .next()
+            .ok_or("Shadow fork must be in the form VERSION@EPOCH")?;
+
+        let version = version.trim_start_matches("0x");
+        if version.len() != 8 || !version.is_ascii() {
+            return Err("Shadow fork version must be four hex-encoded bytes");
+        }
+        let mut bytes = [0; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&version[i * 2..i * 2 + 2], 16)
+                .map_err(|_| "Unable to parse shadow fork version")?;
+        }
+
+        let epoch = epoch
+            .parse::<u64>()
+            .map_err(|_| "Unable to parse shadow fork epoch")?;
+
+        Ok(Self {
+            version: bytes,
+            epoch: Epoch::new(epoch),
+        })
+    }
+}
+
+/// A point within a slot, as a fraction of the slot's duration.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SlotFraction {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+/// Attestations are produced a third of the way through the slot, as in the honest validator
+/// spec.
+pub const ATTESTATION_TIME: SlotFraction = SlotFraction {
+    numerator: 1,
+    denominator: 3,
+};
+
+/// Aggregates are produced two thirds of the way through the slot, as in the honest validator
+/// spec.
+pub const DEFAULT_AGGREGATION_TIME: SlotFraction = SlotFraction {
+    numerator: 2,
+    denominator: 3,
+};
+
+impl SlotFraction {
+    /// Returns the time into a slot of `slot_duration` at which this point is reached.
+    pub fn of(self, slot_duration: Duration) -> Duration {
+        slot_duration * self.numerator / self.denominator
+    }
+
+    /// Returns `true` if this point is later in the slot than `other`.
+    pub fn is_after(self, other: SlotFraction) -> bool {
+        u64::from(self.numerator) * u64::from(other.denominator)
+            > u64::from(other.numerator) * u64::from(self.denominator)
+    }
+}
+
+impl FromStr for SlotFraction {
+    type Err = &'static str;
+
+    /// Parses a fraction of a slot in the form `<numerator>/<denominator>`, e.g., `2/3`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        let numerator = parts
+            .next()
+            .unwrap_or("")
+            .parse::<u32>()
+            .map_err(|_| "Unable to parse slot fraction numerator")?;
+        let denominator = parts
+            .next()
+            .ok_or("Slot fraction must be in the form NUMERATOR/DENOMINATOR")?
+            .parse::<u32>()
+            .map_err(|_| "Unable to parse slot fraction denominator")?;
+
+        if numerator >= denominator {
+            return Err("Slot fraction must be less than one");
+        }
+        Ok(Self {
+            numerator,
+            denominator,
+        })
+    }
+}
+
+/// A validator whose signing is delegated to a remote signer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RemoteSignerConfig {
+    pub public_key: PublicKey,
+    /// The base URL of the signing service.
+    pub url: String,
+    /// If set, the validator's local key signs whenever the remote signer fails. The same key is
+    /// then usable from two places, so this must be enabled explicitly for each validator.
+    #[serde(default)]
+    pub local_fallback: bool,
+    /// If set, the time to wait for this validator's remote signer to respond, in place of
+    /// `remote_signer_timeout_ms`. Clamped to the configured bounds.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+impl FromStr for RemoteSignerConfig {
+    type Err = &'static str;
+
+    /// Parses a remote signer in the form `0x<public key>@<url>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '@');
+        let public_key = parts.next().unwrap_or("");
+        let url = parts
+            .next()
+            .filter(|url| !url.is_empty())
+            .ok_or("Remote signer must be in the form PUBKEY@URL")?;
+
+        Ok(Self {
+            public_key: parse_remote_signer_public_key(public_key)?,
+            url: url.to_string(),
+            local_fallback: false,
+            timeout_ms: None,
+        })
+    }
+}
+
+/// Parses the `0x`-prefixed, hex-encoded public key of a remote signer.
+fn parse_remote_signer_public_key(public_key: &str) -> Result<PublicKey, &'static str> {
+    let public_key = public_key.trim_start_matches("0x");
+    if public_key.len() % 2 != 0 || !public_key.is_ascii() {
+        return Err("Remote signer public key must be hex-encoded");
+    }
+    let bytes = (0..public_key.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&public_key[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| "Unable to parse remote signer public key")?;
+    PublicKey::from_ssz_bytes(&bytes).map_err(|_| "Remote signer public key is invalid")
+}
+
 /// Stores the core configuration for this validator instance.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -49,12 +276,198 @@ pub struct Config {
     pub server_grpc_port: u16,
     /// The HTTP port on the server, for the REST API.
     pub server_http_port: u16,
+    /// Further Beacon Nodes, as `HOST:PORT` gRPC addresses, used when `server` fails.
+    pub fallback_servers: Vec<String>,
+    /// If set, a file of further Beacon Node addresses, one per line, which replace
+    /// `fallback_servers` whenever the file changes.
+    pub fallback_servers_file: Option<PathBuf>,
+    /// The Beacon Nodes, as `HOST:PORT` gRPC addresses in order of preference, from which
+    /// aggregates are requested and to which they are published. If empty, aggregates use the
+    /// same nodes as attestations.
+    pub aggregate_servers: Vec<String>,
     /// The number of slots per epoch.
     pub slots_per_epoch: u64,
+    /// The storage used for slashing protection.
+    pub slashing_protection: SlashingProtectionKind,
+    /// The number of validators whose slashing protection checks may run concurrently.
+    pub slashing_protection_parallelism: usize,
+    /// If set, each message accepted by slashing protection is also appended to this file.
+    pub slashing_protection_replica: Option<PathBuf>,
+    /// The number of seconds to wait for keypairs to appear in the data directory at startup.
+    pub keys_wait_timeout_secs: u64,
+    /// The number of failed attempts to connect to the beacon node before exiting. Zero retries
+    /// forever.
+    pub max_connect_attempts: u64,
+    /// If set, a file containing the last processed slot is written here each slot.
+    pub heartbeat_file: Option<PathBuf>,
+    /// If set, the validator client's counters are written here each slot, in the Prometheus text
+    /// format.
+    pub metrics_file: Option<PathBuf>,
+    /// If set, a span for each phase of the work of each slot is appended here as JSON, in the
+    /// form of an OpenTelemetry span. Off by default.
+    pub trace_file: Option<PathBuf>,
+    /// If set, each produced block and attestation is appended here as SSZ, for forwarding to a
+    /// message queue.
+    pub message_sink_file: Option<PathBuf>,
+    /// If set, each produced block and attestation is published to `message_sink_subject` on the
+    /// NATS server at this address.
+    pub message_sink_nats: Option<String>,
+    /// The NATS subject to which produced messages are published.
+    pub message_sink_subject: String,
+    /// The total size of the produced messages which may wait to be sent, in bytes. Once
+    /// exceeded, the oldest are dropped.
+    pub message_sink_max_bytes: usize,
+    /// If set, known duties are cached here so they survive a restart.
+    pub duties_cache_path: Option<PathBuf>,
+    /// The number of consecutive failed block proposals after which a critical alert is logged.
+    /// Zero disables the alert.
+    pub block_failure_alert_threshold: u64,
+    /// The time to wait after the start of each slot before performing duties, giving the beacon
+    /// node time to process the slot.
+    pub slot_processing_delay_ms: u64,
+    /// The graffiti included in proposed blocks.
+    pub graffiti: [u8; 32],
+    /// The maximum number of duties requests which may be sent to the beacon node at once.
+    pub max_concurrent_duties_requests: usize,
+    /// The maximum number of blocks and attestations which may be produced and signed at once.
+    /// Further duties are queued.
+    pub max_concurrent_signings: usize,
+    /// The number of times publishing a block or attestation is retried if the beacon node fails.
+    pub max_publish_retries: u64,
+    /// The level at which a summary of each processed slot is logged.
+    pub slot_summary: SlotSummaryLevel,
+    /// Forks, in addition to the beacon node's, used when computing signing domains.
+    pub shadow_forks: Vec<ShadowFork>,
+    /// Validators whose signing is delegated to a remote signer.
+    pub remote_signers: Vec<RemoteSignerConfig>,
+    /// The time to wait for a remote signer to respond.
+    pub remote_signer_timeout_ms: u64,
+    /// The least time a validator's own remote signer timeout may be. Lower values, e.g. from a
+    /// typo, are raised to it.
+    pub min_remote_signer_timeout_ms: u64,
+    /// The greatest time a validator's own remote signer timeout may be. Higher values are
+    /// lowered to it.
+    pub max_remote_signer_timeout_ms: u64,
+    /// The time to wait for the beacon node to respond to each request.
+    pub request_timeout_ms: u64,
+    /// If set, sent to the beacon nodes with every request as a bearer token.
+    #[serde(skip)]
+    pub api_token: Option<String>,
+    /// If set, the beacon nodes are connected to over TLS, trusting only the PEM-encoded
+    /// certificate authority in this file.
+    pub tls_ca_file: Option<PathBuf>,
+    /// If set, every request to the beacon node is recorded in this file.
+    pub audit_log_file: Option<PathBuf>,
+    /// The size at which the audit log is rotated.
+    pub audit_log_max_bytes: u64,
+    /// The number of worker threads in the validator service runtime. Defaults to one per core.
+    pub runtime_worker_threads: Option<usize>,
+    /// If set, the runtime worker threads are pinned to these CPU cores (Linux only).
+    pub cpu_affinity: Option<Vec<usize>>,
+    /// If true, validators do not sign until they have been observed to be offline on the
+    /// network, guarding against another instance signing with the same keys.
+    pub doppelganger_protection: bool,
+    /// If true, the balance of each validator is checked each epoch and any decrease is warned
+    /// of. Off by default, since it adds a request to the beacon node each epoch.
+    pub balance_warnings: bool,
+    /// If true, the change in each validator's balance between epochs is logged as its reward or
+    /// penalty, and the totals are exported with the metrics. Enables the balance checks.
+    pub reward_logging: bool,
+    /// The greatest difference between the local time and the beacon node's time which is
+    /// accepted at startup.
+    pub max_clock_skew_ms: u64,
+    /// The response to a clock skew greater than `max_clock_skew_ms`.
+    pub clock_skew_action: ClockSkewAction,
+    /// The response to a beacon node whose clock is ahead of the local clock.
+    pub node_clock_ahead: NodeClockAheadAction,
+    /// If set, attestation data is re-fetched once if the beacon node's head advances by more
+    /// than this many slots whilst it is fetched.
+    pub attestation_freshness_tolerance: Option<u64>,
+    /// The number of slots the beacon node's head may be behind the current slot before the node
+    /// is considered to be syncing, and its report that a validator has no duties is not trusted.
+    pub syncing_distance: u64,
+    /// If true, attestations are produced whilst the beacon node is syncing. Otherwise they are
+    /// withheld until its head is within `syncing_distance` of the duty slot.
+    pub attest_while_syncing: bool,
+    /// If true, duties are fetched and blocks and attestations requested from the beacon node,
+    /// but they are logged rather than signed and published.
+    pub dry_run: bool,
+    /// If true, each aggregation selection proof is signed once and kept until its epoch has
+    /// passed, rather than signed whenever it is needed.
+    pub selection_proof_cache: bool,
+    /// The point in each slot at which aggregates are produced. Always after the attestation
+    /// time, so that the committee's attestations have reached the beacon node.
+    pub aggregation_time: SlotFraction,
 }
 
 const DEFAULT_PRIVATE_KEY_FILENAME: &str = "private.key";
 
+/// The interval at which the data directory is polled whilst waiting for keypairs.
+const KEYS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Returns the number of CPU cores currently online.
+fn available_cores() -> usize {
+    let cores = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_ONLN) };
+    if cores < 1 {
+        1
+    } else {
+        cores as usize
+    }
+}
+
+/// Returns an error if `cores` is empty or names a core that is not less than `available`.
+fn validate_cpu_affinity(cores: &[usize], available: usize) -> Result<(), &'static str> {
+    if cores.is_empty() {
+        Err("CPU affinity must contain at least one core")
+    } else if cores.iter().any(|core| *core >= available) {
+        Err("CPU affinity contains a core which is not available")
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns `address` if it is of the form `HOST:PORT`.
+fn parse_grpc_address(address: &str) -> Result<String, &'static str> {
+    let mut parts = address.rsplitn(2, ':');
+    match (parts.next().map(str::parse::<u16>), parts.next()) {
+        (Some(Ok(_)), Some(host)) if !host.is_empty() => Ok(address.to_string()),
+        _ => Err("Unable to parse fallback server, expected HOST:PORT"),
+    }
+}
+
+/// Reads the gRPC address of each fallback Beacon Node from the file at `path`, one per line.
+/// Blank lines and those starting with `#` are ignored.
+pub fn read_fallback_servers(path: &Path) -> Result<Vec<String>, String> {
+    fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {:?}: {:?}", path, e))?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| parse_grpc_address(line).map_err(|e| format!("{}: {}", e, line)))
+        .collect()
+}
+
+/// Returns `true` if `path` is a JSON file with a neighbouring password file, other than a
+/// slashing protection database.
+fn is_keystore_path(path: &Path) -> bool {
+    let is_slashing_protection = path.file_name().map_or(false, |name| {
+        name == SlashingProtectionKind::File.filename()
+    });
+
+    path.extension().map_or(false, |ext| ext == "json")
+        && !is_slashing_protection
+        && path.with_extension(PASSWORD_FILE_EXTENSION).is_file()
+}
+
+/// Returns the UTF-8 bytes of `graffiti`, truncated or zero-padded to 32 bytes.
+fn graffiti_from_str(graffiti: &str) -> [u8; 32] {
+    let bytes = graffiti.as_bytes();
+    let len = bytes.len().min(32);
+    let mut padded = [0; 32];
+    padded[..len].copy_from_slice(&bytes[..len]);
+    padded
+}
+
 impl Default for Config {
     /// Build a new configuration from defaults.
     fn default() -> Self {
@@ -69,12 +482,124 @@ impl Default for Config {
             server_http_port: DEFAULT_SERVER_GRPC_PORT
                 .parse::<u16>()
                 .expect("HTTP port constant should be valid"),
+            fallback_servers: vec![],
+            fallback_servers_file: None,
+            aggregate_servers: vec![],
             slots_per_epoch: MainnetEthSpec::slots_per_epoch(),
+            slashing_protection: <_>::default(),
+            slashing_protection_parallelism: 4,
+            slashing_protection_replica: None,
+            keys_wait_timeout_secs: 0,
+            max_connect_attempts: 0,
+            heartbeat_file: None,
+            trace_file: None,
+            metrics_file: None,
+            message_sink_file: None,
+            message_sink_nats: None,
+            message_sink_subject: "lighthouse.validator.produced".to_string(),
+            message_sink_max_bytes: DEFAULT_MESSAGE_SINK_MAX_BYTES,
+            duties_cache_path: None,
+            block_failure_alert_threshold: 3,
+            slot_processing_delay_ms: 100,
+            graffiti: [0; 32],
+            max_concurrent_duties_requests: 4,
+            max_concurrent_signings: 16,
+            max_publish_retries: 3,
+            slot_summary: <_>::default(),
+            shadow_forks: vec![],
+            remote_signers: vec![],
+            remote_signer_timeout_ms: 2_000,
+            min_remote_signer_timeout_ms: 100,
+            max_remote_signer_timeout_ms: 6_000,
+            request_timeout_ms: 5_000,
+            tls_ca_file: None,
+            api_token: None,
+            audit_log_file: None,
+            audit_log_max_bytes: 100 * 1024 * 1024,
+            runtime_worker_threads: None,
+            cpu_affinity: None,
+            doppelganger_protection: false,
+            balance_warnings: false,
+            reward_logging: false,
+            max_clock_skew_ms: 2_000,
+            clock_skew_action: <_>::default(),
+            node_clock_ahead: <_>::default(),
+            attestation_freshness_tolerance: None,
+            syncing_distance: 16,
+            attest_while_syncing: false,
+            dry_run: false,
+            selection_proof_cache: true,
+            aggregation_time: DEFAULT_AGGREGATION_TIME,
         }
     }
 }
 
 impl Config {
+    /// Returns the slot processing delay, or an error unless it is shorter than a slot.
+    pub fn slot_processing_delay(&self, milliseconds_per_slot: u64) -> Result<Duration, String> {
+        if self.slot_processing_delay_ms < milliseconds_per_slot {
+            Ok(Duration::from_millis(self.slot_processing_delay_ms))
+        } else {
+            Err(format!(
+                "Slot processing delay of {}ms must be shorter than the {}ms slot duration",
+                self.slot_processing_delay_ms, milliseconds_per_slot
+            ))
+        }
+    }
+
+    /// Returns the time to wait for the remote signer of `remote`: its own timeout, clamped to
+    /// the configured bounds with a warning, or else the default.
+    pub fn remote_signer_timeout(
+        &self,
+        remote: &RemoteSignerConfig,
+        log: &slog::Logger,
+    ) -> Duration {
+        let timeout_ms = match remote.timeout_ms {
+            Some(timeout_ms) => {
+                let clamped = timeout_ms
+                    .max(self.min_remote_signer_timeout_ms)
+                    .min(self.max_remote_signer_timeout_ms);
+                if clamped != timeout_ms {
+                    warn!(
+                        log,
+                        "Remote signer timeout out of bounds, clamping it";
+                        "validator" => remote.public_key.as_hex_string(),
+                        "timeout_ms" => timeout_ms,
+                        "clamped_ms" => clamped,
+                    );
+                }
+                clamped
+            }
+            None => self.remote_signer_timeout_ms,
+        };
+        Duration::from_millis(timeout_ms)
+    }
+
+    /// Returns the gRPC address of each Beacon Node, in order of preference.
+    pub fn grpc_servers(&self) -> Vec<String> {
+        std::iter::once(format!("{}:{}", self.server, self.server_grpc_port))
+            .chain(self.fallback_servers.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the certificate authority trusted for TLS connections to the beacon nodes, or
+    /// `None` if TLS is not enabled.
+    pub fn tls_root_cert(&self) -> Result<Option<Vec<u8>>, String> {
+        let path = match &self.tls_ca_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let pem = fs::read(path)
+            .map_err(|e| format!("Unable to read TLS CA file {:?}: {:?}", path, e))?;
+        if !String::from_utf8_lossy(&pem).contains("-----BEGIN CERTIFICATE-----") {
+            return Err(format!(
+                "TLS CA file {:?} contains no PEM certificate",
+                path
+            ));
+        }
+        Ok(Some(pem))
+    }
+
     /// Returns the full path for the client data directory (not just the name of the directory).
     pub fn full_data_dir(&self) -> Option<PathBuf> {
         dirs::home_dir().map(|path| path.join(&self.data_dir))
@@ -87,6 +612,12 @@ impl Config {
         Some(path)
     }
 
+    /// Returns the full path of the slashing protection file within the data directory.
+    pub fn slashing_protection_path(&self) -> Option<PathBuf> {
+        self.full_data_dir()
+            .map(|path| path.join(self.slashing_protection.filename()))
+    }
+
     /// Apply the following arguments to `self`, replacing values if they are specified in `args`.
     ///
     /// Returns an error if arguments are obviously invalid. May succeed even if some values are
@@ -109,6 +640,311 @@ impl Config {
             self.server = srv.to_string();
         };
 
+        if let Some(fallback_servers) = args.values_of("fallback-server") {
+            self.fallback_servers = fallback_servers
+                .map(parse_grpc_address)
+                .collect::<Result<Vec<_>, _>>()?;
+        };
+
+        if let Some(aggregate_servers) = args.values_of("aggregate-server") {
+            self.aggregate_servers = aggregate_servers
+                .map(parse_grpc_address)
+                .collect::<Result<Vec<_>, _>>()?;
+        };
+
+        if let Some(path) = args.value_of("fallback-servers-file") {
+            let path = PathBuf::from(path);
+            self.fallback_servers = read_fallback_servers(&path).map_err(|e| {
+                error!(log, "Unable to read the fallback servers file"; "error" => e);
+                "Unable to read the fallback servers file"
+            })?;
+            self.fallback_servers_file = Some(path);
+        };
+
+        match args.value_of("slashing-protection") {
+            Some("file") => self.slashing_protection = SlashingProtectionKind::File,
+            Some("sqlite") => self.slashing_protection = SlashingProtectionKind::Sqlite,
+            Some(_) => return Err("Unknown slashing protection backend"),
+            None => {}
+        };
+
+        if let Some(parallelism) = args.value_of("slashing-protection-parallelism") {
+            self.slashing_protection_parallelism = parallelism
+                .parse::<usize>()
+                .map_err(|_| "Unable to parse slashing protection parallelism")?;
+        };
+
+        if let Some(replica) = args.value_of("slashing-protection-replica") {
+            self.slashing_protection_replica = Some(PathBuf::from(replica));
+        };
+
+        if let Some(timeout) = args.value_of("keys-wait-timeout") {
+            self.keys_wait_timeout_secs = timeout
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse keys wait timeout")?;
+        };
+
+        if let Some(attempts) = args.value_of("max-connect-attempts") {
+            self.max_connect_attempts = attempts
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse max connect attempts")?;
+        };
+
+        if let Some(heartbeat_file) = args.value_of("heartbeat-file") {
+            self.heartbeat_file = Some(PathBuf::from(heartbeat_file));
+        };
+
+        if let Some(metrics_file) = args.value_of("metrics-file") {
+            self.metrics_file = Some(PathBuf::from(metrics_file));
+        };
+
+        if let Some(trace_file) = args.value_of("trace-file") {
+            self.trace_file = Some(PathBuf::from(trace_file));
+        };
+
+        if let Some(message_sink_file) = args.value_of("message-sink-file") {
+            self.message_sink_file = Some(PathBuf::from(message_sink_file));
+        };
+
+        if let Some(address) = args.value_of("message-sink-nats") {
+            self.message_sink_nats = Some(address.to_string());
+        };
+
+        if self.message_sink_file.is_some() && self.message_sink_nats.is_some() {
+            return Err("Only one of the message sink file and NATS server may be set");
+        }
+
+        if let Some(subject) = args.value_of("message-sink-subject") {
+            self.message_sink_subject = subject.to_string();
+        };
+
+        if let Some(max_bytes) = args.value_of("message-sink-max-bytes") {
+            self.message_sink_max_bytes = max_bytes
+                .parse::<usize>()
+                .map_err(|_| "Unable to parse message sink max bytes")?;
+        };
+
+        if let Some(duties_cache) = args.value_of("duties-cache") {
+            self.duties_cache_path = Some(PathBuf::from(duties_cache));
+        };
+
+        if let Some(threshold) = args.value_of("block-failure-alert-threshold") {
+            self.block_failure_alert_threshold = threshold
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse block failure alert threshold")?;
+        };
+
+        if let Some(delay) = args.value_of("slot-processing-delay") {
+            self.slot_processing_delay_ms = delay
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse slot processing delay")?;
+        };
+
+        if let Some(graffiti) = args.value_of("graffiti") {
+            self.graffiti = graffiti_from_str(graffiti);
+        };
+
+        if let Some(retries) = args.value_of("max-publish-retries") {
+            self.max_publish_retries = retries
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse max publish retries")?;
+        };
+
+        if let Some(limit) = args.value_of("duties-concurrency") {
+            self.max_concurrent_duties_requests = limit
+                .parse::<usize>()
+                .map_err(|_| "Unable to parse duties concurrency")?;
+        };
+
+        if let Some(limit) = args.value_of("signing-concurrency") {
+            self.max_concurrent_signings = match limit.parse::<usize>() {
+                Ok(0) => return Err("Signing concurrency must be at least one"),
+                Ok(limit) => limit,
+                Err(_) => return Err("Unable to parse signing concurrency"),
+            };
+        };
+
+        match args.value_of("slot-summary") {
+            Some("off") => self.slot_summary = SlotSummaryLevel::Off,
+            Some("info") => self.slot_summary = SlotSummaryLevel::Info,
+            Some("debug") => self.slot_summary = SlotSummaryLevel::Debug,
+            Some(_) => return Err("Unknown slot summary level"),
+            None => {}
+        };
+
+        if let Some(shadow_forks) = args.values_of("shadow-fork") {
+            self.shadow_forks = shadow_forks
+                .map(str::parse::<ShadowFork>)
+                .collect::<Result<Vec<_>, _>>()?;
+        };
+
+        if let Some(remote_signers) = args.values_of("remote-signer") {
+            self.remote_signers = remote_signers
+                .map(str::parse::<RemoteSignerConfig>)
+                .collect::<Result<Vec<_>, _>>()?;
+        };
+
+        if let Some(fallbacks) = args.values_of("remote-signer-fallback") {
+            for fallback in fallbacks {
+                let public_key = parse_remote_signer_public_key(fallback)?;
+                self.remote_signers
+                    .iter_mut()
+                    .find(|remote| remote.public_key == public_key)
+                    .ok_or("Remote signer fallback must name a validator with a remote signer")?
+                    .local_fallback = true;
+            }
+        };
+
+        if let Some(timeout) = args.value_of("remote-signer-timeout") {
+            self.remote_signer_timeout_ms = timeout
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse remote signer timeout")?;
+        };
+
+        if let Some(timeout) = args.value_of("min-remote-signer-timeout") {
+            self.min_remote_signer_timeout_ms = timeout
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse minimum remote signer timeout")?;
+        };
+
+        if let Some(timeout) = args.value_of("max-remote-signer-timeout") {
+            self.max_remote_signer_timeout_ms = timeout
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse maximum remote signer timeout")?;
+        };
+
+        if self.min_remote_signer_timeout_ms > self.max_remote_signer_timeout_ms {
+            return Err("The minimum remote signer timeout exceeds the maximum");
+        }
+
+        if let Some(timeouts) = args.values_of("remote-signer-validator-timeout") {
+            for timeout in timeouts {
+                let mut parts = timeout.splitn(2, '=');
+                let public_key = parse_remote_signer_public_key(parts.next().unwrap_or(""))?;
+                let timeout_ms = parts.next().and_then(|ms| ms.parse::<u64>().ok());
+                let timeout_ms = timeout_ms
+                    .ok_or("Remote signer validator timeout must be in the form PUBKEY=MS")?;
+                self.remote_signers
+                    .iter_mut()
+                    .find(|remote| remote.public_key == public_key)
+                    .ok_or("Remote signer timeout must name a validator with a remote signer")?
+                    .timeout_ms = Some(timeout_ms);
+            }
+        };
+
+        if let Some(timeout) = args.value_of("request-timeout") {
+            self.request_timeout_ms = timeout
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse request timeout")?;
+        };
+
+        if let Some(token) = args.value_of("api-token") {
+            if token.is_empty() || !token.chars().all(|c| c.is_ascii_graphic()) {
+                return Err("API token must be non-empty printable ASCII without spaces");
+            }
+            self.api_token = Some(token.to_string());
+        };
+
+        if let Some(path) = args.value_of("tls-ca-file") {
+            self.tls_ca_file = Some(PathBuf::from(path));
+        };
+
+        if let Some(audit_log_file) = args.value_of("audit-log") {
+            self.audit_log_file = Some(PathBuf::from(audit_log_file));
+        };
+
+        if let Some(max_bytes) = args.value_of("audit-log-max-bytes") {
+            self.audit_log_max_bytes = max_bytes
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse audit log max bytes")?;
+        };
+
+        if let Some(threads) = args.value_of("worker-threads") {
+            let threads = threads
+                .parse::<usize>()
+                .map_err(|_| "Unable to parse worker threads")?;
+            if threads == 0 {
+                return Err("Worker threads must be greater than zero");
+            }
+            self.runtime_worker_threads = Some(threads);
+        };
+
+        if let Some(affinity) = args.value_of("cpu-affinity") {
+            let cores = affinity
+                .split(',')
+                .map(|core| core.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|_| "Unable to parse CPU affinity")?;
+            validate_cpu_affinity(&cores, available_cores())?;
+            self.cpu_affinity = Some(cores);
+        };
+
+        if args.is_present("enable-doppelganger-protection") {
+            self.doppelganger_protection = true;
+        };
+
+        if args.is_present("balance-warnings") {
+            self.balance_warnings = true;
+        };
+
+        if args.is_present("log-rewards") {
+            self.reward_logging = true;
+        };
+
+        if let Some(skew) = args.value_of("max-clock-skew") {
+            self.max_clock_skew_ms = skew
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse max clock skew")?;
+        };
+
+        match args.value_of("clock-skew-action") {
+            Some("warn") => self.clock_skew_action = ClockSkewAction::Warn,
+            Some("reject") => self.clock_skew_action = ClockSkewAction::Reject,
+            Some(_) => return Err("Unknown clock skew action"),
+            None => {}
+        };
+
+        match args.value_of("node-clock-ahead") {
+            Some("trust") => self.node_clock_ahead = NodeClockAheadAction::Trust,
+            Some("halt") => self.node_clock_ahead = NodeClockAheadAction::Halt,
+            Some(_) => return Err("Unknown node clock ahead action"),
+            None => {}
+        };
+
+        if let Some(tolerance) = args.value_of("attestation-freshness-tolerance") {
+            self.attestation_freshness_tolerance = Some(
+                tolerance
+                    .parse::<u64>()
+                    .map_err(|_| "Unable to parse attestation freshness tolerance")?,
+            );
+        };
+
+        if let Some(distance) = args.value_of("syncing-distance") {
+            self.syncing_distance = distance
+                .parse::<u64>()
+                .map_err(|_| "Unable to parse syncing distance")?;
+        };
+
+        if args.is_present("attest-while-syncing") {
+            self.attest_while_syncing = true;
+        };
+
+        if args.is_present("dry-run") {
+            self.dry_run = true;
+        };
+
+        if args.is_present("no-selection-proof-cache") {
+            self.selection_proof_cache = false;
+        };
+
+        if let Some(aggregation_time) = args.value_of("aggregation-time") {
+            let aggregation_time = aggregation_time.parse::<SlotFraction>()?;
+            if !aggregation_time.is_after(ATTESTATION_TIME) {
+                return Err("Aggregation time must be later than a third of the slot");
+            }
+            self.aggregation_time = aggregation_time;
+        };
+
         Ok(())
     }
 
@@ -187,6 +1023,11 @@ impl Config {
         }
     }
 
+    /// Loads a keypair from each validator directory and each EIP-2335 keystore (`*.json`) in the
+    /// data directory. Keys which cannot be loaded are logged and skipped.
+    ///
+    /// Only JSON files with a neighbouring password file are keystores, so the slashing protection
+    /// database and any other JSON in the data directory are ignored.
     pub fn fetch_keys_from_disk(&self, log: &slog::Logger) -> Result<Vec<Keypair>, String> {
         Ok(
             fs::read_dir(&self.full_data_dir().expect("Data dir must exist"))
@@ -207,6 +1048,20 @@ impl Config {
                                 None
                             }
                         }
+                    } else if is_keystore_path(&path) {
+                        // An EIP-2335 keystore, with its password in a neighbouring file.
+                        match load_keystore(&path) {
+                            Ok(keypair) => Some(keypair),
+                            Err(e) => {
+                                error!(
+                                    log,
+                                    "Failed to decrypt a validator keystore";
+                                    "error" => format!("{:?}", e),
+                                    "path" => path.to_str(),
+                                );
+                                None
+                            }
+                        }
                     } else {
                         None
                     }
@@ -215,6 +1070,39 @@ impl Config {
         )
     }
 
+    /// Loads the keypairs from disk, waiting up to `self.keys_wait_timeout_secs` for the data
+    /// directory to exist and contain at least one keypair.
+    ///
+    /// Returns the result of the final attempt if no keypairs appear before the timeout.
+    pub fn wait_for_keys_from_disk(&self, log: &slog::Logger) -> Result<Vec<Keypair>, String> {
+        let deadline = Instant::now() + Duration::from_secs(self.keys_wait_timeout_secs);
+        let mut waiting = false;
+
+        loop {
+            let result = self.fetch_keys_from_disk(log);
+            let found_keys = result
+                .as_ref()
+                .map(|keypairs| !keypairs.is_empty())
+                .unwrap_or(false);
+
+            if found_keys || Instant::now() >= deadline {
+                return result;
+            }
+
+            if !waiting {
+                info!(
+                    log,
+                    "Waiting for validator keys";
+                    "datadir" => format!("{:?}", self.full_data_dir()),
+                    "timeout_seconds" => self.keys_wait_timeout_secs,
+                );
+                waiting = true;
+            }
+
+            std::thread::sleep(KEYS_POLL_INTERVAL);
+        }
+    }
+
     pub fn fetch_testing_keypairs(
         &self,
         range: std::ops::Range<usize>,
@@ -227,7 +1115,7 @@ impl Config {
     #[allow(dead_code)]
     pub fn fetch_keys(&self, log: &slog::Logger) -> Result<Vec<Keypair>, String> {
         let keypairs = match &self.key_source {
-            KeySource::Disk => self.fetch_keys_from_disk(log)?,
+            KeySource::Disk => self.wait_for_keys_from_disk(log)?,
             KeySource::TestingKeypairRange(range) => {
                 warn!(
                     log,
@@ -277,3 +1165,282 @@ impl Config {
         Ok(key_path)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, o!())
+    }
+
+    #[test]
+    fn undecryptable_keystore_skipped() {
+        let dir = tempdir().unwrap();
+
+        let mut config = Config::default();
+        config.data_dir = dir.path().join("validators");
+        let keypair = Keypair::random();
+        config.save_key(&keypair).unwrap();
+        fs::write(config.data_dir.join("broken.json"), "{}").unwrap();
+        fs::write(config.data_dir.join("broken.pass"), "password").unwrap();
+
+        let keypairs = config.fetch_keys_from_disk(&null_logger()).unwrap();
+        assert_eq!(keypairs.len(), 1);
+        assert_eq!(keypairs[0].pk, keypair.pk);
+    }
+
+    /// Counts the log records at `Error` level or above.
+    struct ErrorCounter(Arc<AtomicUsize>);
+
+    impl Drain for ErrorCounter {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            if record.level().is_at_least(slog::Level::Error) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn other_json_in_data_dir_not_loaded_as_keystore() {
+        let dir = tempdir().unwrap();
+
+        let mut config = Config::default();
+        config.data_dir = dir.path().join("validators");
+        config.slashing_protection = SlashingProtectionKind::File;
+        let keypair = Keypair::random();
+        config.save_key(&keypair).unwrap();
+        fs::write(
+            config
+                .data_dir
+                .join(SlashingProtectionKind::File.filename()),
+            "{}",
+        )
+        .unwrap();
+        fs::write(config.data_dir.join("notes.json"), "{}").unwrap();
+
+        let errors = Arc::new(AtomicUsize::new(0));
+        let log = slog::Logger::root(ErrorCounter(errors.clone()), o!());
+        let keypairs = config.fetch_keys_from_disk(&log).unwrap();
+        assert_eq!(keypairs.len(), 1);
+        assert_eq!(keypairs[0].pk, keypair.pk);
+        assert_eq!(errors.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn fetch_keys_waits_for_keys_dir() {
+        let dir = tempdir().unwrap();
+
+        let mut config = Config::default();
+        config.data_dir = dir.path().join("validators");
+        config.keys_wait_timeout_secs = 10;
+
+        let writer_config = config.clone();
+        let writer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(750));
+            writer_config.save_key(&Keypair::random()).unwrap();
+        });
+
+        let keypairs = config.fetch_keys(&null_logger()).unwrap();
+        assert_eq!(keypairs.len(), 1);
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn fetch_keys_gives_up_after_timeout() {
+        let dir = tempdir().unwrap();
+
+        let mut config = Config::default();
+        config.data_dir = dir.path().join("validators");
+        config.keys_wait_timeout_secs = 1;
+
+        assert!(config.fetch_keys(&null_logger()).is_err());
+    }
+
+    #[test]
+    fn cpu_affinity_validated_against_available_cores() {
+        assert!(validate_cpu_affinity(&[0, 3], 4).is_ok());
+        assert!(validate_cpu_affinity(&[0, 4], 4).is_err());
+        assert!(validate_cpu_affinity(&[], 4).is_err());
+    }
+
+    #[test]
+    fn slot_processing_delay_must_be_shorter_than_a_slot() {
+        let mut config = Config::default();
+        assert_eq!(
+            config.slot_processing_delay(6_000),
+            Ok(Duration::from_millis(100))
+        );
+
+        config.slot_processing_delay_ms = 6_000;
+        assert!(config.slot_processing_delay(6_000).is_err());
+        assert_eq!(
+            config.slot_processing_delay(12_000),
+            Ok(Duration::from_millis(6_000))
+        );
+    }
+
+    #[test]
+    fn graffiti_padded_and_truncated() {
+        let mut expected = [0; 32];
+        expected[..10].copy_from_slice(b"lighthouse");
+        assert_eq!(graffiti_from_str("lighthouse"), expected);
+
+        assert_eq!(graffiti_from_str(""), [0; 32]);
+        assert_eq!(graffiti_from_str(&"a".repeat(40)), [b'a'; 32]);
+    }
+
+    #[test]
+    fn fallback_servers_follow_primary() {
+        let mut config = Config::default();
+        config.server = "10.0.0.1".to_string();
+        config.server_grpc_port = 5051;
+        config.fallback_servers = vec!["10.0.0.2:5052".to_string()];
+
+        assert_eq!(
+            config.grpc_servers(),
+            vec!["10.0.0.1:5051".to_string(), "10.0.0.2:5052".to_string()]
+        );
+
+        assert_eq!(
+            parse_grpc_address("10.0.0.2:5052"),
+            Ok("10.0.0.2:5052".to_string())
+        );
+        assert!(parse_grpc_address("10.0.0.2").is_err());
+        assert!(parse_grpc_address(":5052").is_err());
+        assert!(parse_grpc_address("10.0.0.2:port").is_err());
+    }
+
+    #[test]
+    fn tls_root_cert_must_be_pem() {
+        let mut config = Config::default();
+        assert_eq!(config.tls_root_cert(), Ok(None));
+
+        let ca_file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/tls/ca.pem");
+        config.tls_ca_file = Some(ca_file.clone());
+        assert_eq!(
+            config.tls_root_cert(),
+            Ok(Some(fs::read(&ca_file).unwrap()))
+        );
+
+        // a private key is not a certificate authority
+        config.tls_ca_file = Some(ca_file.with_file_name("server.key"));
+        assert!(config.tls_root_cert().is_err());
+        config.tls_ca_file = Some(ca_file.with_file_name("missing.pem"));
+        assert!(config.tls_root_cert().is_err());
+    }
+
+    #[test]
+    fn fallback_servers_read_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fallback_servers");
+
+        fs::write(
+            &path,
+            "# standby nodes\n10.0.0.2:5052\n\n  10.0.0.3:5053  \n",
+        )
+        .unwrap();
+        assert_eq!(
+            read_fallback_servers(&path),
+            Ok(vec![
+                "10.0.0.2:5052".to_string(),
+                "10.0.0.3:5053".to_string()
+            ])
+        );
+
+        fs::write(&path, "10.0.0.2:5052\n10.0.0.3\n").unwrap();
+        assert!(read_fallback_servers(&path).is_err());
+        assert!(read_fallback_servers(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn parse_remote_signer() {
+        let public_key = Keypair::random().pk;
+        let parsed = format!("{}@http://localhost:9000", public_key.as_hex_string())
+            .parse::<RemoteSignerConfig>()
+            .unwrap();
+        assert_eq!(
+            parsed,
+            RemoteSignerConfig {
+                public_key,
+                url: "http://localhost:9000".to_string(),
+                local_fallback: false,
+                timeout_ms: None,
+            }
+        );
+
+        assert!("0x00@http://localhost:9000"
+            .parse::<RemoteSignerConfig>()
+            .is_err());
+        assert!(format!("{}@", Keypair::random().pk.as_hex_string())
+            .parse::<RemoteSignerConfig>()
+            .is_err());
+    }
+
+    #[test]
+    fn remote_signer_timeouts_clamped() {
+        let config = Config::default();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let timeout = |timeout_ms| {
+            let remote = RemoteSignerConfig {
+                public_key: Keypair::random().pk,
+                url: "http://localhost:9000".to_string(),
+                local_fallback: false,
+                timeout_ms,
+            };
+            config.remote_signer_timeout(&remote, &log)
+        };
+
+        assert_eq!(timeout(None), Duration::from_millis(2_000));
+        assert_eq!(timeout(Some(500)), Duration::from_millis(500));
+        // e.g., seconds given in place of milliseconds
+        assert_eq!(timeout(Some(3)), Duration::from_millis(100));
+        assert_eq!(timeout(Some(30_000_000)), Duration::from_millis(6_000));
+    }
+
+    #[test]
+    fn parse_shadow_fork() {
+        assert_eq!(
+            "0x0000000a@1024".parse::<ShadowFork>(),
+            Ok(ShadowFork {
+                version: [0, 0, 0, 10],
+                epoch: Epoch::new(1024),
+            })
+        );
+        assert!("0x0000000a".parse::<ShadowFork>().is_err());
+        assert!("0x000a@1024".parse::<ShadowFork>().is_err());
+        assert!("0x0000000g@1024".parse::<ShadowFork>().is_err());
+    }
+
+    #[test]
+    fn parse_slot_fraction() {
+        assert_eq!(
+            "3/4".parse::<SlotFraction>(),
+            Ok(SlotFraction {
+                numerator: 3,
+                denominator: 4,
+            })
+        );
+        assert!("3".parse::<SlotFraction>().is_err());
+        assert!("4/4".parse::<SlotFraction>().is_err());
+        assert!("1/0".parse::<SlotFraction>().is_err());
+        assert!("a/3".parse::<SlotFraction>().is_err());
+
+        let fraction = |s: &str| s.parse::<SlotFraction>().unwrap();
+        assert!(DEFAULT_AGGREGATION_TIME.is_after(ATTESTATION_TIME));
+        assert!(!fraction("2/6").is_after(ATTESTATION_TIME));
+        assert!(fraction("34/100").is_after(ATTESTATION_TIME));
+        assert_eq!(
+            DEFAULT_AGGREGATION_TIME.of(Duration::from_secs(12)),
+            Duration::from_secs(8)
+        );
+    }
+}
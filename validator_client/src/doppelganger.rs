@@ -0,0 +1,282 @@
+use slog::{crit, info};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use types::{Epoch, PublicKey};
+
+/// The number of epochs in which a validator must be seen not to attest before it may sign.
+pub const DOPPELGANGER_DETECTION_EPOCHS: u64 = 2;
+
+/// Whether a validator has attested in the previous and current epochs, as seen by the beacon
+/// node's head state.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct Liveness {
+    pub previous_epoch: bool,
+    pub current_epoch: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum State {
+    /// Liveness is yet to be checked for `remaining_epochs` epochs, the first of which is
+    /// `next_epoch`. `next_epoch` is set to the epoch after the current epoch once it is known.
+    Observing {
+        next_epoch: Option<Epoch>,
+        remaining_epochs: u64,
+    },
+    /// The validator attested whilst being observed, so it is active elsewhere.
+    Detected,
+}
+
+/// Prevents a newly loaded validator from signing until the beacon node has shown it not to have
+/// attested for `DOPPELGANGER_DETECTION_EPOCHS` epochs. If it did attest, the same key is already
+/// in use elsewhere, and signing would risk a slashable double vote.
+///
+/// An attestation may be included up to an epoch after its slot, so an epoch only counts as
+/// observed at the last slot of the epoch after it. Until then, liveness in both the previous and
+/// current epochs is checked every slot, so that a doppelganger is detected as soon as one of its
+/// attestations is included.
+///
+/// Validators which were never registered, or which have completed the observation, may sign.
+#[derive(Default)]
+pub struct DoppelgangerProtection {
+    states: RwLock<HashMap<PublicKey, State>>,
+}
+
+impl DoppelgangerProtection {
+    /// Prevents `pubkey` from signing until it has been observed.
+    pub fn register(&self, pubkey: PublicKey) {
+        if let Ok(mut states) = self.states.write() {
+            states.insert(
+                pubkey,
+                State::Observing {
+                    next_epoch: None,
+                    remaining_epochs: DOPPELGANGER_DETECTION_EPOCHS,
+                },
+            );
+        }
+    }
+
+    /// Returns `true` if `pubkey` may sign.
+    pub fn may_sign(&self, pubkey: &PublicKey) -> bool {
+        self.states
+            .read()
+            .map(|states| !states.contains_key(pubkey))
+            .unwrap_or(false)
+    }
+
+    /// Returns the validators whose liveness in `current_epoch` and the epoch before it should be
+    /// checked.
+    ///
+    /// The epoch of the first call and those before it are never checked, since the validator may
+    /// have attested in them before this client was restarted.
+    pub fn liveness_checks(&self, current_epoch: Epoch) -> Vec<PublicKey> {
+        let mut states = match self.states.write() {
+            Ok(states) => states,
+            Err(_) => return vec![],
+        };
+
+        states
+            .iter_mut()
+            .filter_map(|(pubkey, state)| match state {
+                State::Observing { next_epoch, .. } => {
+                    if *next_epoch.get_or_insert(current_epoch + 1) <= current_epoch {
+                        Some(pubkey.clone())
+                    } else {
+                        None
+                    }
+                }
+                State::Detected => None,
+            })
+            .collect()
+    }
+
+    /// Records the liveness of each validator returned by `liveness_checks`. `epoch_ending` is set
+    /// at the last slot of `current_epoch`, by which time every attestation from the previous
+    /// epoch has been included.
+    pub fn record_liveness(
+        &self,
+        current_epoch: Epoch,
+        epoch_ending: bool,
+        liveness: &[(PublicKey, Liveness)],
+        log: &slog::Logger,
+    ) {
+        let mut states = match self.states.write() {
+            Ok(states) => states,
+            Err(_) => return,
+        };
+        let previous_epoch = current_epoch - 1;
+
+        for (pubkey, live) in liveness {
+            let state = match states.get_mut(pubkey) {
+                Some(state) => state,
+                None => continue,
+            };
+            let (next_epoch, remaining_epochs) = match *state {
+                State::Observing {
+                    next_epoch: Some(next_epoch),
+                    remaining_epochs,
+                } => (next_epoch, remaining_epochs),
+                _ => continue,
+            };
+
+            let live_epoch = if live.current_epoch && current_epoch >= next_epoch {
+                Some(current_epoch)
+            } else if live.previous_epoch && previous_epoch >= next_epoch {
+                Some(previous_epoch)
+            } else {
+                None
+            };
+
+            if let Some(epoch) = live_epoch {
+                crit!(
+                    log,
+                    "Doppelganger detected";
+                    "msg" => "the validator is active elsewhere and will not sign. Stop the \
+                              other validator client, then restart this one",
+                    "validator" => pubkey.as_hex_string(),
+                    "epoch" => epoch,
+                );
+                *state = State::Detected;
+            } else if epoch_ending && previous_epoch >= next_epoch {
+                *state = State::Observing {
+                    next_epoch: Some(current_epoch),
+                    remaining_epochs: remaining_epochs.saturating_sub(1),
+                };
+            }
+        }
+
+        states.retain(|pubkey, state| match state {
+            State::Observing {
+                remaining_epochs: 0,
+                ..
+            } => {
+                info!(
+                    log,
+                    "Doppelganger protection complete";
+                    "validator" => pubkey.as_hex_string(),
+                );
+                false
+            }
+            State::Observing {
+                remaining_epochs, ..
+            } => {
+                if epoch_ending {
+                    info!(
+                        log,
+                        "Validator in doppelganger protection";
+                        "validator" => pubkey.as_hex_string(),
+                        "epochs_remaining" => *remaining_epochs,
+                    );
+                }
+                true
+            }
+            State::Detected => true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::Keypair;
+
+    const SLOTS_PER_EPOCH: u64 = 8;
+
+    /// Checks liveness at every slot of `epoch`, as returned by `live` for each checked epoch.
+    fn observe_epoch<F: Fn(Epoch) -> bool>(
+        protection: &DoppelgangerProtection,
+        epoch: Epoch,
+        live: F,
+    ) {
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        for slot in 0..SLOTS_PER_EPOCH {
+            let liveness: Vec<(PublicKey, Liveness)> = protection
+                .liveness_checks(epoch)
+                .into_iter()
+                .map(|pubkey| {
+                    let liveness = Liveness {
+                        previous_epoch: live(epoch - 1),
+                        current_epoch: live(epoch),
+                    };
+                    (pubkey, liveness)
+                })
+                .collect();
+            protection.record_liveness(epoch, slot == SLOTS_PER_EPOCH - 1, &liveness, &log);
+        }
+    }
+
+    #[test]
+    fn epochs_before_registration_not_checked() {
+        let protection = DoppelgangerProtection::default();
+        let pubkey = Keypair::random().pk;
+        protection.register(pubkey.clone());
+
+        // Liveness in epochs 9 and 10 is not checked, as the validator may have attested in them
+        // before the restart.
+        assert!(protection.liveness_checks(Epoch::new(10)).is_empty());
+        assert_eq!(
+            protection.liveness_checks(Epoch::new(11)),
+            vec![pubkey.clone()]
+        );
+
+        // Epochs 11 and 12 are each observed by the end of the epoch after them.
+        for epoch in 10..13 {
+            observe_epoch(&protection, Epoch::new(epoch), |_| false);
+            assert!(!protection.may_sign(&pubkey));
+        }
+        observe_epoch(&protection, Epoch::new(13), |_| false);
+        assert!(protection.may_sign(&pubkey));
+        assert!(protection.liveness_checks(Epoch::new(14)).is_empty());
+    }
+
+    #[test]
+    fn attestation_in_registration_epoch_not_a_doppelganger() {
+        let protection = DoppelgangerProtection::default();
+        let pubkey = Keypair::random().pk;
+        let registration_epoch = Epoch::new(10);
+        protection.register(pubkey.clone());
+
+        // This client attested in the registration epoch before it was restarted.
+        let mut epoch = registration_epoch;
+        while !protection.may_sign(&pubkey) {
+            assert!(epoch <= registration_epoch + 1 + DOPPELGANGER_DETECTION_EPOCHS);
+            observe_epoch(&protection, epoch, |epoch| epoch == registration_epoch);
+            epoch += 1;
+        }
+        assert_eq!(
+            epoch,
+            registration_epoch + 2 + DOPPELGANGER_DETECTION_EPOCHS
+        );
+    }
+
+    #[test]
+    fn attestation_included_in_next_epoch_detected() {
+        let protection = DoppelgangerProtection::default();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let pubkey = Keypair::random().pk;
+        protection.register(pubkey.clone());
+
+        observe_epoch(&protection, Epoch::new(10), |_| false);
+
+        // The other client's attestation from the last slot of epoch 11 is not included until
+        // midway through epoch 12.
+        observe_epoch(&protection, Epoch::new(11), |_| false);
+        let not_live = Liveness::default();
+        protection.record_liveness(Epoch::new(12), false, &[(pubkey.clone(), not_live)], &log);
+        let previous_live = Liveness {
+            previous_epoch: true,
+            current_epoch: false,
+        };
+        protection.record_liveness(
+            Epoch::new(12),
+            false,
+            &[(pubkey.clone(), previous_live)],
+            &log,
+        );
+        assert!(protection.liveness_checks(Epoch::new(12)).is_empty());
+
+        for epoch in 12..20 {
+            observe_epoch(&protection, Epoch::new(epoch), |_| false);
+        }
+        assert!(!protection.may_sign(&pubkey));
+    }
+}
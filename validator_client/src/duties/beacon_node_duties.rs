@@ -1,9 +1,52 @@
 use super::EpochDuties;
-use types::{Epoch, PublicKey};
+use grpcio::{RpcStatus, RpcStatusCode};
+use types::{Epoch, PublicKey, Slot};
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum BeaconNodeDutiesError {
     RemoteFailure(String),
+    /// The beacon node did not respond within the request timeout.
+    Timeout(String),
+    /// The beacon node refused the configured API token, or requires one.
+    Unauthorized(String),
+}
+
+impl From<grpcio::Error> for BeaconNodeDutiesError {
+    fn from(e: grpcio::Error) -> BeaconNodeDutiesError {
+        match &e {
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: RpcStatusCode::DeadlineExceeded,
+                ..
+            }) => BeaconNodeDutiesError::Timeout(format!("{:?}", e)),
+            grpcio::Error::RpcFailure(RpcStatus {
+                status: RpcStatusCode::Unauthenticated,
+                ..
+            }) => BeaconNodeDutiesError::Unauthorized(format!("{:?}", e)),
+            _ => BeaconNodeDutiesError::RemoteFailure(format!("{:?}", e)),
+        }
+    }
+}
+
+/// The stage of a validator's lifecycle, as reported by a Beacon Node.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ValidatorStatus {
+    /// Not yet active. This includes validators whose deposit has not yet been processed.
+    Pending,
+    Active,
+    /// Active, but due to exit.
+    Exiting,
+    Exited,
+}
+
+impl ValidatorStatus {
+    /// Returns `true` if the validator is required to perform duties. An exiting validator
+    /// remains active until its exit epoch.
+    pub fn is_active(self) -> bool {
+        match self {
+            ValidatorStatus::Active | ValidatorStatus::Exiting => true,
+            ValidatorStatus::Pending | ValidatorStatus::Exited => false,
+        }
+    }
 }
 
 /// Defines the methods required to obtain a validators shuffling from a Beacon Node.
@@ -17,4 +60,29 @@ pub trait BeaconNodeDuties: Send + Sync {
         epoch: Epoch,
         pub_keys: &[PublicKey],
     ) -> Result<EpochDuties, BeaconNodeDutiesError>;
+
+    /// Gets the status of each validator at `epoch`, in the same order as `pub_keys`.
+    fn request_validator_status(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<ValidatorStatus>, BeaconNodeDutiesError>;
+
+    /// Gets whether each validator has an attestation included for `epoch`, in the same order as
+    /// `pub_keys`.
+    fn request_validator_liveness(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<bool>, BeaconNodeDutiesError>;
+
+    /// Gets the balance of each validator in the Beacon Node's head state, in the same order as
+    /// `pub_keys`, along with the current epoch of that state.
+    fn request_validator_balances(
+        &self,
+        pub_keys: &[PublicKey],
+    ) -> Result<(Epoch, Vec<u64>), BeaconNodeDutiesError>;
+
+    /// Gets the slot of the Beacon Node's head block.
+    fn request_head_slot(&self) -> Result<Slot, BeaconNodeDutiesError>;
 }
@@ -0,0 +1,76 @@
+use super::epoch_duties::{EpochDuties, EpochDutiesMap, EpochDuty};
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use types::{Epoch, PublicKey};
+
+/// The duty of a single validator, as stored in the cache file.
+#[derive(Serialize, Deserialize)]
+struct CachedDuty {
+    pubkey: PublicKey,
+    duty: Option<EpochDuty>,
+}
+
+/// The duties of all validators for one epoch, as stored in the cache file.
+///
+/// Duties are stored as lists rather than maps, since JSON object keys must be strings.
+#[derive(Serialize, Deserialize)]
+struct CachedEpochDuties {
+    epoch: Epoch,
+    duties: Vec<CachedDuty>,
+}
+
+/// Writes all duties in `duties_map` to `path`, replacing any previous contents.
+pub fn save_duties(path: &Path, duties_map: &EpochDutiesMap) -> Result<(), String> {
+    let cached: Vec<CachedEpochDuties> = duties_map
+        .iter()
+        .map(|(epoch, duties)| CachedEpochDuties {
+            epoch: *epoch,
+            duties: duties
+                .iter()
+                .map(|(pubkey, duty)| CachedDuty {
+                    pubkey: pubkey.clone(),
+                    duty: duty.clone(),
+                })
+                .collect(),
+        })
+        .collect();
+    let json = serde_json::to_string(&cached)
+        .map_err(|e| format!("Unable to serialize duties: {:?}", e))?;
+
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, json).map_err(|e| format!("Unable to write duties cache: {:?}", e))?;
+    fs::rename(&temp_path, path).map_err(|e| format!("Unable to replace duties cache: {:?}", e))
+}
+
+/// Reads the duties stored at `path`, discarding those for epochs prior to `current_epoch`.
+///
+/// Returns an empty map if `path` does not exist.
+pub fn load_duties(
+    path: &Path,
+    slots_per_epoch: u64,
+    current_epoch: Epoch,
+) -> Result<EpochDutiesMap, String> {
+    let mut duties_map = EpochDutiesMap::new(slots_per_epoch);
+    if !path.exists() {
+        return Ok(duties_map);
+    }
+
+    let json =
+        fs::read_to_string(path).map_err(|e| format!("Unable to read duties cache: {:?}", e))?;
+    let cached: Vec<CachedEpochDuties> = serde_json::from_str(&json)
+        .map_err(|e| format!("Unable to parse duties cache: {:?}", e))?;
+
+    for epoch_duties in cached {
+        if epoch_duties.epoch < current_epoch {
+            continue;
+        }
+        let duties: EpochDuties = epoch_duties
+            .duties
+            .into_iter()
+            .map(|cached| (cached.pubkey, cached.duty))
+            .collect();
+        duties_map.insert(epoch_duties.epoch, duties);
+    }
+    Ok(duties_map)
+}
@@ -1,3 +1,4 @@
+use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
@@ -11,8 +12,10 @@ use types::{AttestationDuty, Epoch, PublicKey, Slot};
 pub struct WorkInfo {
     /// Validator needs to produce a block.
     pub produce_block: bool,
-    /// Validator needs to produce an attestation. This supplies the required attestation data.
-    pub attestation_duty: Option<AttestationDuty>,
+    /// The index of the validator, if known.
+    pub validator_index: Option<u64>,
+    /// Validator needs to produce an attestation for each of these duties.
+    pub attestation_duties: Vec<AttestationDuty>,
 }
 
 /// The information required for a validator to propose and attest during some epoch.
@@ -20,10 +23,16 @@ pub struct WorkInfo {
 /// Generally obtained from a Beacon Node, this information contains the validators canonical index
 /// (their sequence in the global validator induction process) and the "shuffling" for that index
 /// for some epoch.
-#[derive(Debug, PartialEq, Clone, Copy, Default)]
+///
+/// A validator may be assigned more than one attestation duty in an epoch, so all of them are
+/// stored.
+#[derive(Debug, PartialEq, Clone, Default, Serialize, Deserialize)]
 pub struct EpochDuty {
     pub block_production_slot: Option<Slot>,
-    pub attestation_duty: AttestationDuty,
+    pub attestation_duties: Vec<AttestationDuty>,
+    /// The index of the validator, unless it was cached by an earlier version.
+    #[serde(default)]
+    pub validator_index: Option<u64>,
 }
 
 impl EpochDuty {
@@ -35,16 +44,19 @@ impl EpochDuty {
             _ => false,
         };
 
-        // if the validator is required to attest to a shard, create the data
-        let mut attestation_duty = None;
-        if self.attestation_duty.slot == slot {
-            attestation_duty = Some(self.attestation_duty)
-        }
+        // if the validator is required to attest to a shard, collect each duty for this slot
+        let attestation_duties: Vec<AttestationDuty> = self
+            .attestation_duties
+            .iter()
+            .filter(|duty| duty.slot == slot)
+            .cloned()
+            .collect();
 
-        if produce_block | attestation_duty.is_some() {
+        if produce_block || !attestation_duties.is_empty() {
             return Some(WorkInfo {
                 produce_block,
-                attestation_duty,
+                validator_index: self.validator_index,
+                attestation_duties,
             });
         }
         None
@@ -57,10 +69,16 @@ impl fmt::Display for EpochDuty {
         if let Some(block_slot) = self.block_production_slot {
             display_block = block_slot.to_string();
         }
+        let display_attestations = self
+            .attestation_duties
+            .iter()
+            .map(|duty| format!("(slot: {}, shard: {})", duty.slot, duty.shard))
+            .collect::<Vec<_>>()
+            .join(", ");
         write!(
             f,
-            "produce block slot: {}, attestation slot: {}, attestation shard: {}",
-            display_block, self.attestation_duty.slot, self.attestation_duty.shard
+            "produce block slot: {}, attestations: [{}]",
+            display_block, display_attestations
         )
     }
 }
@@ -130,4 +148,53 @@ impl EpochDutiesMap {
     }
 }
 
-// TODO: add tests.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attestation_duty(slot: u64, shard: u64) -> AttestationDuty {
+        AttestationDuty {
+            slot: Slot::new(slot),
+            shard,
+            committee_index: 0,
+            committee_len: 1,
+        }
+    }
+
+    #[test]
+    fn multiple_attestation_duties_in_one_epoch() {
+        let slots_per_epoch = 8;
+        let pubkey = PublicKey::default();
+
+        let duty = EpochDuty {
+            block_production_slot: Some(Slot::new(3)),
+            attestation_duties: vec![attestation_duty(2, 0), attestation_duty(5, 1)],
+            validator_index: None,
+        };
+
+        let mut duties = EpochDuties::new();
+        duties.insert(pubkey.clone(), Some(duty));
+
+        let mut map = EpochDutiesMap::new(slots_per_epoch);
+        map.insert(Epoch::new(0), duties);
+
+        let work_at = |slot: u64| match map.is_work_slot(Slot::new(slot), &pubkey) {
+            Ok(work) => work,
+            Err(_) => panic!("validator and epoch should be known"),
+        };
+
+        let work = work_at(2).expect("should attest at slot 2");
+        assert!(!work.produce_block);
+        assert_eq!(work.attestation_duties, vec![attestation_duty(2, 0)]);
+
+        let work = work_at(3).expect("should propose at slot 3");
+        assert!(work.produce_block);
+        assert!(work.attestation_duties.is_empty());
+
+        let work = work_at(5).expect("should attest at slot 5");
+        assert!(!work.produce_block);
+        assert_eq!(work.attestation_duties, vec![attestation_duty(5, 1)]);
+
+        assert!(work_at(4).is_none());
+    }
+}
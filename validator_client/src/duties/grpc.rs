@@ -1,15 +1,42 @@
-use super::beacon_node_duties::{BeaconNodeDuties, BeaconNodeDutiesError};
+use super::beacon_node_duties::{BeaconNodeDuties, BeaconNodeDutiesError, ValidatorStatus};
 use super::epoch_duties::{EpochDuties, EpochDuty};
-// to use if we manually specify a timeout
-//use grpcio::CallOption;
-use protos::services::{GetDutiesRequest, Validators};
-use protos::services_grpc::ValidatorServiceClient;
+use crate::audit_log::{audited, AuditLog, RequestOptions};
+use protos::services::{
+    Empty, GetDutiesRequest, GetValidatorBalancesRequest, GetValidatorLivenessRequest,
+    GetValidatorStatusRequest, ValidatorStatus as ProtoValidatorStatus, Validators,
+};
+use protos::services_grpc::{BeaconNodeServiceClient, ValidatorServiceClient};
 use ssz::ssz_encode;
 use std::collections::HashMap;
-// use std::time::Duration;
+use std::sync::Arc;
 use types::{AttestationDuty, Epoch, PublicKey, Slot};
 
-impl BeaconNodeDuties for ValidatorServiceClient {
+/// Wraps the gRPC-generated service so that each request may be recorded in an audit log.
+pub struct ValidatorGrpcClient {
+    client: ValidatorServiceClient,
+    /// Used to query the node's head slot.
+    beacon_node_client: BeaconNodeServiceClient,
+    audit_log: Option<Arc<AuditLog>>,
+    options: RequestOptions,
+}
+
+impl ValidatorGrpcClient {
+    pub fn new(
+        client: ValidatorServiceClient,
+        beacon_node_client: BeaconNodeServiceClient,
+        audit_log: Option<Arc<AuditLog>>,
+        options: RequestOptions,
+    ) -> Self {
+        Self {
+            client,
+            beacon_node_client,
+            audit_log,
+            options,
+        }
+    }
+}
+
+impl BeaconNodeDuties for ValidatorGrpcClient {
     /// Requests all duties (block signing and committee attesting) from the Beacon Node (BN).
     fn request_duties(
         &self,
@@ -24,13 +51,15 @@ impl BeaconNodeDuties for ValidatorServiceClient {
         validators.set_public_keys(pub_keys.iter().map(|v| ssz_encode(v)).collect());
         req.set_validators(validators);
 
-        // set a timeout for requests
-        // let call_opt = CallOption::default().timeout(Duration::from_secs(2));
-
         // send the request, get the duties reply
-        let reply = self
-            .get_validator_duties(&req)
-            .map_err(|err| BeaconNodeDutiesError::RemoteFailure(format!("{:?}", err)))?;
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "ValidatorService/GetValidatorDuties",
+            &req,
+            |req, opt| self.client.get_validator_duties_opt(req, opt),
+        )
+        .map_err(BeaconNodeDutiesError::from)?;
 
         let mut epoch_duties: HashMap<PublicKey, Option<EpochDuty>> = HashMap::new();
         for (index, validator_duty) in reply.get_active_validators().iter().enumerate() {
@@ -58,10 +87,133 @@ impl BeaconNodeDuties for ValidatorServiceClient {
 
             let epoch_duty = EpochDuty {
                 block_production_slot,
-                attestation_duty,
+                attestation_duties: vec![attestation_duty],
+                validator_index: Some(active_duty.get_validator_index()),
             };
             epoch_duties.insert(pub_keys[index].clone(), Some(epoch_duty));
         }
         Ok(epoch_duties)
     }
+
+    /// Requests the status of each validator from the Beacon Node (BN).
+    fn request_validator_status(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<ValidatorStatus>, BeaconNodeDutiesError> {
+        let mut req = GetValidatorStatusRequest::new();
+        req.set_epoch(epoch.as_u64());
+        let mut validators = Validators::new();
+        validators.set_public_keys(pub_keys.iter().map(|v| ssz_encode(v)).collect());
+        req.set_validators(validators);
+
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "ValidatorService/GetValidatorStatus",
+            &req,
+            |req, opt| self.client.get_validator_status_opt(req, opt),
+        )
+        .map_err(BeaconNodeDutiesError::from)?;
+
+        if reply.get_statuses().len() != pub_keys.len() {
+            return Err(BeaconNodeDutiesError::RemoteFailure(format!(
+                "Beacon node returned {} statuses for {} validators",
+                reply.get_statuses().len(),
+                pub_keys.len()
+            )));
+        }
+
+        Ok(reply
+            .get_statuses()
+            .iter()
+            .map(|status| match status {
+                ProtoValidatorStatus::UNKNOWN | ProtoValidatorStatus::PENDING => {
+                    ValidatorStatus::Pending
+                }
+                ProtoValidatorStatus::ACTIVE => ValidatorStatus::Active,
+                ProtoValidatorStatus::EXITING => ValidatorStatus::Exiting,
+                ProtoValidatorStatus::EXITED => ValidatorStatus::Exited,
+            })
+            .collect())
+    }
+
+    /// Requests the liveness of each validator from the Beacon Node (BN).
+    fn request_validator_liveness(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<bool>, BeaconNodeDutiesError> {
+        let mut req = GetValidatorLivenessRequest::new();
+        req.set_epoch(epoch.as_u64());
+        let mut validators = Validators::new();
+        validators.set_public_keys(pub_keys.iter().map(|v| ssz_encode(v)).collect());
+        req.set_validators(validators);
+
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "ValidatorService/GetValidatorLiveness",
+            &req,
+            |req, opt| self.client.get_validator_liveness_opt(req, opt),
+        )
+        .map_err(BeaconNodeDutiesError::from)?;
+
+        if reply.get_is_live().len() != pub_keys.len() {
+            return Err(BeaconNodeDutiesError::RemoteFailure(format!(
+                "Beacon node returned liveness for {} of {} validators",
+                reply.get_is_live().len(),
+                pub_keys.len()
+            )));
+        }
+
+        Ok(reply.get_is_live().to_vec())
+    }
+
+    /// Requests the balance of each validator from the Beacon Node (BN).
+    fn request_validator_balances(
+        &self,
+        pub_keys: &[PublicKey],
+    ) -> Result<(Epoch, Vec<u64>), BeaconNodeDutiesError> {
+        let mut req = GetValidatorBalancesRequest::new();
+        let mut validators = Validators::new();
+        validators.set_public_keys(pub_keys.iter().map(|v| ssz_encode(v)).collect());
+        req.set_validators(validators);
+
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "ValidatorService/GetValidatorBalances",
+            &req,
+            |req, opt| self.client.get_validator_balances_opt(req, opt),
+        )
+        .map_err(BeaconNodeDutiesError::from)?;
+
+        if reply.get_balances().len() != pub_keys.len() {
+            return Err(BeaconNodeDutiesError::RemoteFailure(format!(
+                "Beacon node returned {} balances for {} validators",
+                reply.get_balances().len(),
+                pub_keys.len()
+            )));
+        }
+
+        Ok((
+            Epoch::from(reply.get_epoch()),
+            reply.get_balances().to_vec(),
+        ))
+    }
+
+    /// Requests the head slot from the Beacon Node (BN).
+    fn request_head_slot(&self) -> Result<Slot, BeaconNodeDutiesError> {
+        let reply = audited(
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.options,
+            "BeaconNodeService/Info",
+            &Empty::new(),
+            |req, opt| self.beacon_node_client.info_opt(req, opt),
+        )
+        .map_err(BeaconNodeDutiesError::from)?;
+
+        Ok(Slot::from(reply.get_head_slot()))
+    }
 }
@@ -1,21 +1,35 @@
 mod beacon_node_duties;
+mod cache;
 mod epoch_duties;
 mod grpc;
-// TODO: reintroduce tests
-//#[cfg(test)]
-//mod test_node;
+mod schedule;
+#[cfg(test)]
+pub mod test_node;
 
-pub use self::beacon_node_duties::{BeaconNodeDuties, BeaconNodeDutiesError};
-use self::epoch_duties::{EpochDuties, EpochDutiesMapError};
-pub use self::epoch_duties::{EpochDutiesMap, WorkInfo};
+pub use self::beacon_node_duties::{BeaconNodeDuties, BeaconNodeDutiesError, ValidatorStatus};
+pub use self::cache::load_duties;
+use self::cache::save_duties;
+use self::epoch_duties::EpochDutiesMapError;
+pub use self::epoch_duties::{EpochDuties, EpochDutiesMap, EpochDuty, WorkInfo};
+pub use self::grpc::ValidatorGrpcClient;
 use super::signer::Signer;
-use futures::Async;
-use slog::{debug, error, info};
+use crate::doppelganger::{DoppelgangerProtection, Liveness};
+use crate::skip_reason::SkipReason;
+use slog::{debug, error, info, warn};
+use std::collections::HashMap;
 use std::fmt::Display;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::sync::RwLock;
 use types::{Epoch, PublicKey, Slot};
 
+/// The number of epochs prior to the current epoch for which duties are retained.
+const RETAINED_PAST_EPOCHS: u64 = 2;
+
+/// The maximum number of validators whose duties are requested from the Beacon Node in a single
+/// request.
+pub const DUTIES_BATCH_SIZE: usize = 64;
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum UpdateOutcome {
     /// The `EpochDuties` were not updated during this poll.
@@ -27,12 +41,39 @@ pub enum UpdateOutcome {
     DutiesChanged(Epoch, EpochDuties),
 }
 
+/// The validators whose duties for an epoch differ from those previously known.
+#[derive(Debug, Default, PartialEq)]
+pub struct DutiesChangeSummary {
+    /// Validators whose block production slot changed.
+    pub proposals: Vec<PublicKey>,
+    /// Validators whose attestation duties changed.
+    pub attestations: Vec<PublicKey>,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Error {
     DutiesMapPoisoned,
     BeaconNodeDutiesError(BeaconNodeDutiesError),
     UnknownEpoch,
     UnknownValidator,
+    /// The beacon node did not return duties for every requested validator. This may happen
+    /// transiently whilst the node is syncing, so it is not treated as "no duties".
+    IncompleteDuties(Epoch),
+    /// The beacon node's head is too far behind the current slot for its report that a validator
+    /// has no duties to be trusted.
+    NodeSyncing {
+        head_slot: Slot,
+        current_slot: Slot,
+    },
+    /// A thread requesting duties from the Beacon Node panicked.
+    DutiesRequestPanicked,
+}
+
+/// The sums of the increases and decreases of a validator's balance.
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub struct RewardTotals {
+    pub rewards_gwei: u64,
+    pub penalties_gwei: u64,
 }
 
 /// A polling state machine which ensures the latest `EpochDuties` are obtained from the Beacon
@@ -44,15 +85,51 @@ pub struct DutiesManager<U: BeaconNodeDuties, S: Signer> {
     /// A list of all signer objects known to the validator service.
     pub signers: Arc<Vec<S>>,
     pub beacon_node: Arc<U>,
+    /// The maximum number of validators included in each request to the Beacon Node.
+    pub batch_size: usize,
+    /// The maximum number of requests to the Beacon Node which may be in flight at once.
+    pub max_concurrent_requests: usize,
+    /// If set, the duties are written here each time they change.
+    pub cache_path: Option<PathBuf>,
+    /// The most recently reported status of each validator. Validators which are known not to be
+    /// active are given no work.
+    pub validator_statuses: RwLock<HashMap<PublicKey, ValidatorStatus>>,
+    /// Validators in doppelganger protection are given no work.
+    pub doppelganger: DoppelgangerProtection,
+    /// If true, the balance of each validator is checked once per epoch.
+    pub monitor_balances: bool,
+    /// The most recently reported balance of each validator, with the epoch of the state it was
+    /// taken from.
+    pub validator_balances: RwLock<HashMap<PublicKey, (Epoch, u64)>>,
+    /// If true, each change in a validator's balance between epochs is logged and added to its
+    /// `validator_rewards`. Only effective if `monitor_balances` is set.
+    pub log_rewards: bool,
+    /// The total rewards and penalties of each validator since the balance checks began.
+    pub validator_rewards: RwLock<HashMap<PublicKey, RewardTotals>>,
+    /// The index of each validator, as most recently reported with its duties.
+    pub validator_indices: RwLock<HashMap<PublicKey, u64>>,
+    /// The number of slots the beacon node's head may be behind the current slot before the node
+    /// is considered to be syncing.
+    pub syncing_distance: u64,
 }
 
-impl<U: BeaconNodeDuties, S: Signer + Display> DutiesManager<U, S> {
+impl<U: BeaconNodeDuties + 'static, S: Signer + Display> DutiesManager<U, S> {
     /// Check the Beacon Node for `EpochDuties`.
     ///
     /// be a wall-clock (e.g., system time, remote server time, etc.).
-    fn update(&self, epoch: Epoch) -> Result<UpdateOutcome, Error> {
+    fn update(&self, epoch: Epoch, current_slot: Slot) -> Result<UpdateOutcome, Error> {
         let public_keys: Vec<PublicKey> = self.signers.iter().map(Signer::to_public).collect();
-        let duties = self.beacon_node.request_duties(epoch, &public_keys)?;
+        let duties = self.request_duties(epoch, &public_keys)?;
+        // An inactive validator is reported explicitly, so a missing validator indicates a
+        // partial response. Keep any previously known duties rather than overwriting them.
+        if !public_keys.iter().all(|pubkey| duties.contains_key(pubkey)) {
+            return Err(Error::IncompleteDuties(epoch));
+        }
+        // A syncing node reports active validators as having no duties, so check that it is
+        // synced before accepting that any validator has none.
+        if duties.values().any(Option::is_none) {
+            self.check_synced(current_slot)?;
+        }
         {
             // If these duties were known, check to see if they're updates or identical.
             if let Some(known_duties) = self.duties_map.read()?.get(&epoch) {
@@ -72,19 +149,387 @@ impl<U: BeaconNodeDuties, S: Signer + Display> DutiesManager<U, S> {
         Ok(UpdateOutcome::DutiesChanged(epoch, duties))
     }
 
-    /// A future wrapping around `update()`. This will perform logic based upon the update
-    /// process and complete once the update has completed.
-    pub fn run_update(&self, epoch: Epoch, log: slog::Logger) -> Result<Async<()>, ()> {
-        match self.update(epoch) {
-            Err(error) => error!(log, "Epoch duties poll error"; "error" => format!("{:?}", error)),
-            Ok(UpdateOutcome::NoChange(epoch)) => debug!(log, "No change in duties"; "epoch" => epoch),
-            Ok(UpdateOutcome::DutiesChanged(epoch, duties)) => info!(log, "Duties changed (potential re-org)"; "epoch" => epoch, "duties" => format!("{:?}", duties)),
+    /// Returns an error if the node's head is more than `self.syncing_distance` slots behind
+    /// `current_slot`.
+    fn check_synced(&self, current_slot: Slot) -> Result<(), Error> {
+        let head_slot = self.beacon_node.request_head_slot()?;
+        if head_slot + self.syncing_distance < current_slot {
+            Err(Error::NodeSyncing {
+                head_slot,
+                current_slot,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Requests the duties for `public_keys` in batches of `batch_size`, with at most
+    /// `max_concurrent_requests` batches in flight at once.
+    ///
+    /// Fails if any batch fails, since the merged duties would otherwise be incomplete.
+    fn request_duties(
+        &self,
+        epoch: Epoch,
+        public_keys: &[PublicKey],
+    ) -> Result<EpochDuties, Error> {
+        let batches: Vec<Vec<PublicKey>> = public_keys
+            .chunks(self.batch_size.max(1))
+            .map(<[PublicKey]>::to_vec)
+            .collect();
+
+        let mut duties = EpochDuties::new();
+        for concurrent_batches in batches.chunks(self.max_concurrent_requests.max(1)) {
+            let requests: Vec<_> = concurrent_batches
+                .iter()
+                .cloned()
+                .map(|batch| {
+                    let beacon_node = self.beacon_node.clone();
+                    std::thread::spawn(move || beacon_node.request_duties(epoch, &batch))
+                })
+                .collect();
+
+            for request in requests {
+                let batch_duties = request.join().map_err(|_| Error::DutiesRequestPanicked)??;
+                duties.extend(batch_duties);
+            }
+        }
+        Ok(duties)
+    }
+
+    /// Updates the duties for `epoch`, the epoch of `current_slot`, logging each validator whose
+    /// duties have changed.
+    ///
+    /// Returns the validators whose duties changed, which is empty if the update failed.
+    pub fn run_update(
+        &self,
+        epoch: Epoch,
+        current_slot: Slot,
+        log: slog::Logger,
+    ) -> DutiesChangeSummary {
+        let previous = self
+            .duties_map
+            .read()
+            .ok()
+            .and_then(|duties_map| duties_map.get(&epoch).cloned());
+
+        match self.update(epoch, current_slot) {
+            Err(error) => {
+                error!(log, "Epoch duties poll error"; "error" => format!("{:?}", error));
+                DutiesChangeSummary::default()
+            }
+            Ok(UpdateOutcome::NoChange(epoch)) => {
+                debug!(log, "No change in duties"; "epoch" => epoch);
+                DutiesChangeSummary::default()
+            }
+            Ok(UpdateOutcome::DutiesChanged(epoch, duties)) => {
+                info!(log, "Duties changed (potential re-org)"; "epoch" => epoch);
+                self.update_validator_indices(epoch, &duties, &log);
+                self.save_cache(&log);
+                self.log_duty_changes(epoch, previous.as_ref(), &duties, &log)
+            }
             Ok(UpdateOutcome::NewDuties(epoch, duties)) => {
                 info!(log, "New duties obtained"; "epoch" => epoch);
-                print_duties(&log, duties);
+                self.update_validator_indices(epoch, &duties, &log);
+                self.save_cache(&log);
+                self.log_duty_changes(epoch, None, &duties, &log)
+            }
+        }
+    }
+
+    /// Records the index of each validator reported with its `duties` for `epoch`.
+    ///
+    /// If a known validator is reported with a different index, as after a testnet is reset,
+    /// everything learnt for its previous index is discarded: its duties for other epochs, its
+    /// status and its balance. Each is requested again for the new index.
+    fn update_validator_indices(&self, epoch: Epoch, duties: &EpochDuties, log: &slog::Logger) {
+        let mut indices = match self.validator_indices.write() {
+            Ok(indices) => indices,
+            Err(_) => {
+                error!(log, "Validator indices poisoned");
+                return;
+            }
+        };
+
+        for signer in self.signers.iter() {
+            let pubkey = signer.to_public();
+            let index = match duties
+                .get(&pubkey)
+                .and_then(Option::as_ref)
+                .and_then(|duty| duty.validator_index)
+            {
+                Some(index) => index,
+                None => continue,
+            };
+            match indices.insert(pubkey.clone(), index) {
+                Some(previous) if previous != index => {
+                    warn!(
+                        log,
+                        "Validator index changed";
+                        "validator" => format!("{}", signer),
+                        "epoch" => epoch,
+                        "previous_index" => previous,
+                        "index" => index,
+                    );
+                    if let Ok(mut duties_map) = self.duties_map.write() {
+                        for (_, duties) in duties_map.iter_mut().filter(|(e, _)| **e != epoch) {
+                            duties.remove(&pubkey);
+                        }
+                    }
+                    if let Ok(mut statuses) = self.validator_statuses.write() {
+                        statuses.remove(&pubkey);
+                    }
+                    if let Ok(mut balances) = self.validator_balances.write() {
+                        balances.remove(&pubkey);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Writes the known duties to `self.cache_path`, if set. Failures are logged, since the
+    /// duties can always be requested again.
+    fn save_cache(&self, log: &slog::Logger) {
+        let path = match &self.cache_path {
+            Some(path) => path,
+            None => return,
+        };
+        let result = self
+            .duties_map
+            .read()
+            .map_err(|_| "Duties map poisoned".to_string())
+            .and_then(|duties_map| save_duties(path, &duties_map));
+        if let Err(e) = result {
+            warn!(
+                log,
+                "Unable to cache duties";
+                "error" => e,
+                "path" => format!("{:?}", path),
+            );
+        }
+    }
+
+    /// Logs each validator whose duties in `duties` differ from those in `previous`.
+    fn log_duty_changes(
+        &self,
+        epoch: Epoch,
+        previous: Option<&EpochDuties>,
+        duties: &EpochDuties,
+        log: &slog::Logger,
+    ) -> DutiesChangeSummary {
+        let duty_of = |duties: Option<&EpochDuties>, pubkey: &PublicKey| {
+            duties
+                .and_then(|duties| duties.get(pubkey))
+                .and_then(Option::as_ref)
+                .cloned()
+                .unwrap_or_default()
+        };
+
+        let mut summary = DutiesChangeSummary::default();
+        for signer in self.signers.iter() {
+            let pubkey = signer.to_public();
+            let old = duty_of(previous, &pubkey);
+            let new = duty_of(Some(duties), &pubkey);
+
+            if old.block_production_slot != new.block_production_slot {
+                info!(
+                    log,
+                    "Block proposal duty updated";
+                    "validator" => format!("{}", signer),
+                    "epoch" => epoch,
+                    "slot" => new
+                        .block_production_slot
+                        .map_or_else(|| "none".to_string(), |slot| slot.to_string()),
+                );
+                summary.proposals.push(pubkey.clone());
+            }
+            if old.attestation_duties != new.attestation_duties {
+                info!(
+                    log,
+                    "Attestation committee changed";
+                    "validator" => format!("{}", signer),
+                    "epoch" => epoch,
+                    "duty" => format!("{}", new),
+                );
+                summary.attestations.push(pubkey);
+            }
+        }
+        summary
+    }
+
+    /// Requests the status of every validator from the Beacon Node, logging each validator whose
+    /// status has changed. On failure, the previously known statuses are retained.
+    pub fn refresh_validator_status(&self, epoch: Epoch, log: &slog::Logger) {
+        let public_keys: Vec<PublicKey> = self.signers.iter().map(Signer::to_public).collect();
+        let statuses = match self
+            .beacon_node
+            .request_validator_status(epoch, &public_keys)
+        {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                warn!(log, "Unable to refresh validator status"; "error" => format!("{:?}", e));
+                return;
             }
         };
-        Ok(Async::Ready(()))
+
+        let mut known_statuses = match self.validator_statuses.write() {
+            Ok(known_statuses) => known_statuses,
+            Err(_) => {
+                error!(log, "Validator statuses poisoned");
+                return;
+            }
+        };
+
+        for ((signer, pubkey), status) in self.signers.iter().zip(public_keys).zip(statuses) {
+            let previous = known_statuses.insert(pubkey, status);
+            // An active validator is unremarkable, unless it was previously inactive.
+            if previous != Some(status) && (previous.is_some() || !status.is_active()) {
+                info!(
+                    log,
+                    "Validator status changed";
+                    "validator" => format!("{}", signer),
+                    "previous" => previous.map_or("Unknown".to_string(), |s| format!("{:?}", s)),
+                    "status" => format!("{:?}", status),
+                    "epoch" => epoch,
+                );
+            }
+        }
+    }
+
+    /// Checks whether any validator in doppelganger protection attested in `current_epoch` or the
+    /// epoch before it. This is called every slot, with `epoch_ending` set at the last slot of
+    /// `current_epoch`. On failure, the check is made at a later slot instead.
+    pub fn check_doppelgangers(
+        &self,
+        current_epoch: Epoch,
+        epoch_ending: bool,
+        log: &slog::Logger,
+    ) {
+        let public_keys = self.doppelganger.liveness_checks(current_epoch);
+        if public_keys.is_empty() {
+            return;
+        }
+
+        let previous = self
+            .beacon_node
+            .request_validator_liveness(current_epoch - 1, &public_keys);
+        let current = self
+            .beacon_node
+            .request_validator_liveness(current_epoch, &public_keys);
+
+        match previous.and_then(|previous| current.map(|current| (previous, current))) {
+            Ok((previous, current)) => {
+                let liveness: Vec<(PublicKey, Liveness)> = public_keys
+                    .into_iter()
+                    .zip(previous.into_iter().zip(current))
+                    .map(|(pubkey, (previous_epoch, current_epoch))| {
+                        let liveness = Liveness {
+                            previous_epoch,
+                            current_epoch,
+                        };
+                        (pubkey, liveness)
+                    })
+                    .collect();
+                self.doppelganger
+                    .record_liveness(current_epoch, epoch_ending, &liveness, log);
+            }
+            Err(e) => warn!(
+                log,
+                "Unable to check for doppelgangers";
+                "error" => format!("{:?}", e),
+            ),
+        }
+    }
+
+    /// Requests the balance of every validator from the Beacon Node if `monitor_balances` is set,
+    /// warning of each validator whose balance has decreased since an earlier epoch. A decrease
+    /// indicates missed duties or penalties, even when every duty appeared to succeed.
+    pub fn check_balances(&self, log: &slog::Logger) {
+        if !self.monitor_balances {
+            return;
+        }
+
+        let public_keys: Vec<PublicKey> = self.signers.iter().map(Signer::to_public).collect();
+        let (epoch, balances) = match self.beacon_node.request_validator_balances(&public_keys) {
+            Ok(balances) => balances,
+            Err(e) => {
+                warn!(log, "Unable to check validator balances"; "error" => format!("{:?}", e));
+                return;
+            }
+        };
+
+        let mut known_balances = match self.validator_balances.write() {
+            Ok(known_balances) => known_balances,
+            Err(_) => {
+                error!(log, "Validator balances poisoned");
+                return;
+            }
+        };
+
+        for ((signer, pubkey), balance) in self.signers.iter().zip(public_keys).zip(balances) {
+            match known_balances.get(&pubkey) {
+                // Balances are only compared between epochs, once the head has moved on.
+                Some((previous_epoch, _)) if *previous_epoch >= epoch => continue,
+                Some((previous_epoch, previous)) if balance < *previous => warn!(
+                    log,
+                    "Validator balance decreased";
+                    "validator" => format!("{}", signer),
+                    "epoch" => epoch,
+                    "previous_epoch" => previous_epoch,
+                    "balance_gwei" => balance,
+                    "decrease_gwei" => previous - balance,
+                ),
+                _ => debug!(
+                    log,
+                    "Validator balance";
+                    "validator" => format!("{}", signer),
+                    "epoch" => epoch,
+                    "balance_gwei" => balance,
+                ),
+            }
+            if let (true, Some(&previous)) = (self.log_rewards, known_balances.get(&pubkey)) {
+                self.record_reward(signer, &pubkey, previous, (epoch, balance), log);
+            }
+            known_balances.insert(pubkey, (epoch, balance));
+        }
+    }
+
+    /// Adds the change in the balance of `signer` from `previous` to `current`, each an epoch and
+    /// a balance, to its reward totals and logs it.
+    fn record_reward(
+        &self,
+        signer: &S,
+        pubkey: &PublicKey,
+        previous: (Epoch, u64),
+        current: (Epoch, u64),
+        log: &slog::Logger,
+    ) {
+        let mut rewards = match self.validator_rewards.write() {
+            Ok(rewards) => rewards,
+            Err(_) => {
+                error!(log, "Validator rewards poisoned");
+                return;
+            }
+        };
+
+        let (reward, penalty) = (
+            current.1.saturating_sub(previous.1),
+            previous.1.saturating_sub(current.1),
+        );
+        let totals = rewards.entry(pubkey.clone()).or_default();
+        totals.rewards_gwei += reward;
+        totals.penalties_gwei += penalty;
+        info!(
+            log,
+            "Validator reward";
+            "validator" => format!("{}", signer),
+            "epoch" => current.0,
+            "previous_epoch" => previous.0,
+            "reward_gwei" => reward,
+            "penalty_gwei" => penalty,
+            "total_reward_gwei" => totals.rewards_gwei,
+            "total_penalty_gwei" => totals.penalties_gwei,
+        );
     }
 
     /// Returns a list of (index, WorkInfo) indicating all the validators that have work to perform
@@ -94,9 +539,14 @@ impl<U: BeaconNodeDuties, S: Signer + Display> DutiesManager<U, S> {
 
         // if the map is poisoned, return None
         let duties = self.duties_map.read().ok()?;
+        let statuses = self.validator_statuses.read().ok()?;
 
         for (index, validator_signer) in self.signers.iter().enumerate() {
-            match duties.is_work_slot(slot, &validator_signer.to_public()) {
+            let pubkey = validator_signer.to_public();
+            if self.blocked_reason(&pubkey, &statuses).is_some() {
+                continue;
+            }
+            match duties.is_work_slot(slot, &pubkey) {
                 Ok(Some(work_type)) => current_work.push((index, work_type)),
                 Ok(None) => {} // No work for this validator
                 //TODO: This should really log an error, as we shouldn't end up with an err here.
@@ -108,6 +558,74 @@ impl<U: BeaconNodeDuties, S: Signer + Display> DutiesManager<U, S> {
         }
         Some(current_work)
     }
+
+    /// Returns the indices of the signers which have work at `slot` but may not perform it, with
+    /// the reason.
+    pub fn blocked_work(&self, slot: Slot) -> Vec<(usize, SkipReason)> {
+        let (duties, statuses) = match (self.duties_map.read(), self.validator_statuses.read()) {
+            (Ok(duties), Ok(statuses)) => (duties, statuses),
+            _ => return vec![],
+        };
+
+        self.signers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, signer)| {
+                let pubkey = signer.to_public();
+                match duties.is_work_slot(slot, &pubkey) {
+                    Ok(Some(_)) => self
+                        .blocked_reason(&pubkey, &statuses)
+                        .map(|reason| (index, reason)),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the reason `pubkey` may not perform its duties, if any.
+    fn blocked_reason(
+        &self,
+        pubkey: &PublicKey,
+        statuses: &HashMap<PublicKey, ValidatorStatus>,
+    ) -> Option<SkipReason> {
+        // A validator whose status is unknown may still perform the duties it was given.
+        if statuses
+            .get(pubkey)
+            .map_or(false, |status| !status.is_active())
+        {
+            Some(SkipReason::Inactive)
+        } else if !self.doppelganger.may_sign(pubkey) {
+            Some(SkipReason::Doppelganger)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the indices of the signers whose duties at `slot` are unknown, and so cannot be
+    /// performed.
+    pub fn unknown_duties(&self, slot: Slot) -> Vec<usize> {
+        let duties = match self.duties_map.read() {
+            Ok(duties) => duties,
+            Err(_) => return (0..self.signers.len()).collect(),
+        };
+
+        self.signers
+            .iter()
+            .enumerate()
+            .filter(|(_, signer)| duties.is_work_slot(slot, &signer.to_public()).is_err())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Removes the duties for epochs more than `RETAINED_PAST_EPOCHS` before `current_epoch`,
+    /// returning the number of epochs removed.
+    pub fn prune_duties(&self, current_epoch: Epoch) -> Result<usize, Error> {
+        let oldest_retained = current_epoch.saturating_sub(RETAINED_PAST_EPOCHS);
+        let mut duties_map = self.duties_map.write()?;
+        let known_epochs = duties_map.len();
+        duties_map.retain(|epoch, _| *epoch >= oldest_retained);
+        Ok(known_epochs - duties_map.len())
+    }
 }
 
 //TODO: Use error_chain to handle errors
@@ -132,79 +650,655 @@ impl From<EpochDutiesMapError> for Error {
     }
 }
 
-fn print_duties(log: &slog::Logger, duties: EpochDuties) {
-    for (pk, duty) in duties.iter() {
-        if let Some(display_duty) = duty {
-            info!(log, "Validator: {}",pk; "Duty" => format!("{}",display_duty));
-        } else {
-            info!(log, "Validator: {}",pk; "Duty" => "None");
-        }
-    }
-}
-
-/* TODO: Modify tests for new Duties Manager form
 #[cfg(test)]
 mod tests {
+    use super::epoch_duties::EpochDuty;
     use super::test_node::TestBeaconNode;
     use super::*;
+    use crate::doppelganger::DOPPELGANGER_DETECTION_EPOCHS;
     use bls::Keypair;
-    use slot_clock::TestingSlotClock;
-    use types::Slot;
+    use types::AttestationDuty;
 
-    // TODO: implement more thorough testing.
-    // https://github.com/sigp/lighthouse/issues/160
-    //
-    // These tests should serve as a good example for future tests.
+    const SLOTS_PER_EPOCH: u64 = 8;
 
+    fn manager(keypairs: Vec<Keypair>) -> DutiesManager<TestBeaconNode, Keypair> {
+        DutiesManager {
+            duties_map: RwLock::new(EpochDutiesMap::new(SLOTS_PER_EPOCH)),
+            signers: Arc::new(keypairs),
+            beacon_node: Arc::new(TestBeaconNode::default()),
+            batch_size: DUTIES_BATCH_SIZE,
+            max_concurrent_requests: 4,
+            cache_path: None,
+            validator_statuses: <_>::default(),
+            doppelganger: <_>::default(),
+            monitor_balances: false,
+            validator_balances: <_>::default(),
+            log_rewards: false,
+            validator_rewards: <_>::default(),
+            validator_indices: <_>::default(),
+            syncing_distance: 0,
+        }
+    }
+
+    fn duties_for(keypair: &Keypair, block_production_slot: Option<u64>) -> EpochDuties {
+        let duty = EpochDuty {
+            block_production_slot: block_production_slot.map(Slot::new),
+            attestation_duties: vec![AttestationDuty {
+                slot: Slot::new(1),
+                shard: 0,
+                committee_index: 0,
+                committee_len: 1,
+            }],
+            validator_index: None,
+        };
+        let mut duties = EpochDuties::new();
+        duties.insert(keypair.pk.clone(), Some(duty));
+        duties
+    }
 
     #[test]
-    pub fn polling() {
-        let spec = Arc::new(ChainSpec::mainnet());
-        let duties_map = Arc::new(EpochDutiesMap::new(T::slots_per_epoch()));
+    fn polling() {
         let keypair = Keypair::random();
-        let slot_clock = Arc::new(TestingSlotClock::new(0));
-        let beacon_node = Arc::new(TestBeaconNode::default());
+        let manager = manager(vec![keypair.clone()]);
+        let epoch = Epoch::new(0);
+
+        // Get the duties for the first time...
+        let duties = duties_for(&keypair, Some(2));
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        assert_eq!(
+            manager.update(epoch, Slot::new(0)),
+            Ok(UpdateOutcome::NewDuties(epoch, duties))
+        );
+
+        // Get the same duties again...
+        assert_eq!(
+            manager.update(epoch, Slot::new(0)),
+            Ok(UpdateOutcome::NoChange(epoch))
+        );
+
+        // Return new duties.
+        let duties = duties_for(&keypair, Some(3));
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        assert_eq!(
+            manager.update(epoch, Slot::new(0)),
+            Ok(UpdateOutcome::DutiesChanged(epoch, duties))
+        );
+    }
 
+    #[test]
+    fn only_active_validators_given_work() {
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::random()).collect();
+        let manager = manager(keypairs.clone());
+        let epoch = Epoch::new(0);
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+
+        let mut duties = EpochDuties::new();
+        for keypair in &keypairs {
+            duties.extend(duties_for(keypair, None));
+        }
+        manager.beacon_node.set_next_duties_result(Ok(duties));
+        manager.update(epoch, Slot::new(0)).unwrap();
+
+        let statuses = [
+            ValidatorStatus::Active,
+            ValidatorStatus::Pending,
+            ValidatorStatus::Exiting,
+            ValidatorStatus::Exited,
+        ];
+        for (keypair, status) in keypairs.iter().zip(statuses.iter()) {
+            manager
+                .beacon_node
+                .validator_statuses
+                .write()
+                .unwrap()
+                .insert(keypair.pk.clone(), *status);
+        }
+        manager.refresh_validator_status(epoch, &log);
+
+        let work_indices = |manager: &DutiesManager<TestBeaconNode, Keypair>| -> Vec<usize> {
+            manager
+                .get_current_work(Slot::new(1))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect()
+        };
+        assert_eq!(work_indices(&manager), vec![0, 2]);
+        assert_eq!(
+            manager.blocked_work(Slot::new(1)),
+            vec![(1, SkipReason::Inactive), (3, SkipReason::Inactive)]
+        );
+
+        // The pending validator becomes active.
+        manager
+            .beacon_node
+            .validator_statuses
+            .write()
+            .unwrap()
+            .insert(keypairs[1].pk.clone(), ValidatorStatus::Active);
+        manager.refresh_validator_status(epoch, &log);
+        assert_eq!(work_indices(&manager), vec![0, 1, 2]);
+        assert_eq!(
+            manager.blocked_work(Slot::new(1)),
+            vec![(3, SkipReason::Inactive)]
+        );
+        assert!(manager.blocked_work(Slot::new(2)).is_empty());
+    }
+
+    #[test]
+    fn validators_seen_attesting_elsewhere_never_sign() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::random()).collect();
+        let manager = manager(keypairs.clone());
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+
+        let mut duties = EpochDuties::new();
+        for keypair in &keypairs {
+            duties.extend(duties_for(keypair, None));
+            manager.doppelganger.register(keypair.pk.clone());
+        }
+        manager.beacon_node.set_next_duties_result(Ok(duties));
+        manager.update(Epoch::new(0), Slot::new(0)).unwrap();
+
+        // The first validator is attesting from another client.
+        manager
+            .beacon_node
+            .live_validators
+            .write()
+            .unwrap()
+            .insert(keypairs[0].pk.clone());
+
+        let work_indices = |manager: &DutiesManager<TestBeaconNode, Keypair>| -> Vec<usize> {
+            manager
+                .get_current_work(Slot::new(1))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect()
+        };
+
+        let observe_epoch = |epoch: u64| {
+            for slot in 0..SLOTS_PER_EPOCH {
+                manager.check_doppelgangers(Epoch::new(epoch), slot == SLOTS_PER_EPOCH - 1, &log);
+            }
+        };
+
+        // Epoch 0 is never checked, so epochs 1 and 2 are observed by the end of epoch 3.
+        for epoch in 0..=DOPPELGANGER_DETECTION_EPOCHS {
+            observe_epoch(epoch);
+            assert!(work_indices(&manager).is_empty());
+            assert_eq!(
+                manager.blocked_work(Slot::new(1)),
+                vec![(0, SkipReason::Doppelganger), (1, SkipReason::Doppelganger)]
+            );
+        }
+
+        observe_epoch(DOPPELGANGER_DETECTION_EPOCHS + 1);
+        assert_eq!(work_indices(&manager), vec![1]);
+        assert_eq!(
+            manager.blocked_work(Slot::new(1)),
+            vec![(0, SkipReason::Doppelganger)]
+        );
+
+        // Once detected, the validator stays blocked even if it goes offline elsewhere.
+        manager.beacon_node.live_validators.write().unwrap().clear();
+        observe_epoch(DOPPELGANGER_DETECTION_EPOCHS + 2);
+        assert_eq!(work_indices(&manager), vec![1]);
+    }
+
+    #[test]
+    fn late_included_attestation_detects_doppelganger() {
+        let keypairs: Vec<Keypair> = (0..2).map(|_| Keypair::random()).collect();
+        let manager = manager(keypairs.clone());
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+
+        let mut duties = EpochDuties::new();
+        for keypair in &keypairs {
+            duties.extend(duties_for(keypair, None));
+            manager.doppelganger.register(keypair.pk.clone());
+        }
+        manager.beacon_node.set_next_duties_result(Ok(duties));
+        manager.update(Epoch::new(0), Slot::new(0)).unwrap();
+
+        let work_indices = |manager: &DutiesManager<TestBeaconNode, Keypair>| -> Vec<usize> {
+            manager
+                .get_current_work(Slot::new(1))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(index, _)| index)
+                .collect()
+        };
+
+        for epoch in 0..2 {
+            for slot in 0..SLOTS_PER_EPOCH {
+                manager.check_doppelgangers(Epoch::new(epoch), slot == SLOTS_PER_EPOCH - 1, &log);
+            }
+        }
+
+        // The first validator attested in the last slot of epoch 1 from another client, but the
+        // attestation is only included a few slots into epoch 2.
+        for slot in 0..SLOTS_PER_EPOCH {
+            if slot == 3 {
+                manager
+                    .beacon_node
+                    .live_in_epochs
+                    .write()
+                    .unwrap()
+                    .insert((keypairs[0].pk.clone(), Epoch::new(1)));
+            }
+            manager.check_doppelgangers(Epoch::new(2), slot == SLOTS_PER_EPOCH - 1, &log);
+        }
+
+        for slot in 0..SLOTS_PER_EPOCH {
+            manager.check_doppelgangers(Epoch::new(3), slot == SLOTS_PER_EPOCH - 1, &log);
+        }
+        assert_eq!(work_indices(&manager), vec![1]);
+    }
+
+    #[test]
+    fn empty_duties_are_not_treated_as_no_duties() {
+        let keypair = Keypair::random();
+        let manager = manager(vec![keypair.clone()]);
+        let epoch = Epoch::new(0);
+
+        let duties = duties_for(&keypair, Some(2));
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        manager.update(epoch, Slot::new(0)).unwrap();
+
+        // A syncing node returns an empty list of duties.
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(EpochDuties::new()));
+        assert_eq!(
+            manager.update(epoch, Slot::new(0)),
+            Err(Error::IncompleteDuties(epoch))
+        );
+
+        // The previously known duties are retained.
+        assert_eq!(
+            manager.duties_map.read().unwrap().get(&epoch),
+            Some(&duties)
+        );
+        assert!(manager.get_current_work(Slot::new(2)).is_some());
+    }
+
+    #[test]
+    fn no_duties_from_syncing_node_not_accepted() {
+        let (active, inactive) = (Keypair::random(), Keypair::random());
+        let manager = DutiesManager {
+            syncing_distance: 4,
+            ..manager(vec![active.clone(), inactive.clone()])
+        };
+        let epoch = Epoch::new(2);
+        let current_slot = epoch.start_slot(SLOTS_PER_EPOCH) + 1;
+
+        let mut duties = duties_for(&active, Some(18));
+        duties.insert(inactive.pk.clone(), None);
+        *manager.beacon_node.head_slot.write().unwrap() = current_slot;
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        manager.update(epoch, current_slot).unwrap();
+
+        // The node falls behind, and reports that neither validator has any duties.
+        *manager.beacon_node.head_slot.write().unwrap() = Slot::new(12);
+        let mut no_duties = EpochDuties::new();
+        no_duties.insert(active.pk.clone(), None);
+        no_duties.insert(inactive.pk.clone(), None);
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(no_duties.clone()));
+        assert_eq!(
+            manager.update(epoch, current_slot),
+            Err(Error::NodeSyncing {
+                head_slot: Slot::new(12),
+                current_slot,
+            })
+        );
+        assert_eq!(
+            manager.duties_map.read().unwrap().get(&epoch),
+            Some(&duties)
+        );
+
+        // Once the node is within the syncing distance, the validators are accepted to have no
+        // duties.
+        *manager.beacon_node.head_slot.write().unwrap() = Slot::new(13);
+        assert_eq!(
+            manager.update(epoch, current_slot),
+            Ok(UpdateOutcome::DutiesChanged(epoch, no_duties))
+        );
+    }
+
+    #[test]
+    fn unknown_duties_counts_validators_without_duties() {
+        let known = Keypair::random();
+        let manager = manager(vec![known.clone(), Keypair::random()]);
+        let slot = Slot::new(1);
+
+        // No duties are known for the epoch.
+        assert_eq!(manager.unknown_duties(slot), vec![0, 1]);
+
+        manager
+            .duties_map
+            .write()
+            .unwrap()
+            .insert(slot.epoch(SLOTS_PER_EPOCH), duties_for(&known, None));
+        assert_eq!(manager.unknown_duties(slot), vec![1]);
+    }
+
+    #[test]
+    fn prune_duties_removes_expired_epochs() {
+        let keypair = Keypair::random();
+        let manager = manager(vec![keypair.clone()]);
+        {
+            let mut duties_map = manager.duties_map.write().unwrap();
+            for epoch in 0..6 {
+                duties_map.insert(Epoch::new(epoch), duties_for(&keypair, None));
+            }
+        }
+
+        assert_eq!(manager.prune_duties(Epoch::new(5)), Ok(3));
+
+        let mut epochs: Vec<Epoch> = manager.duties_map.read().unwrap().keys().cloned().collect();
+        epochs.sort();
+        assert_eq!(epochs, vec![Epoch::new(3), Epoch::new(4), Epoch::new(5)]);
+
+        // Nothing is removed near genesis.
+        assert_eq!(manager.prune_duties(Epoch::new(1)), Ok(0));
+    }
+
+    #[test]
+    fn run_update_reports_only_changed_duties() {
+        let unchanged = Keypair::random();
+        let proposer = Keypair::random();
+        let manager = manager(vec![unchanged.clone(), proposer.clone()]);
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let epoch = Epoch::new(0);
+
+        let mut duties = duties_for(&unchanged, None);
+        duties.extend(duties_for(&proposer, None));
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        // Both validators have newly known attestation duties.
+        assert_eq!(
+            manager.run_update(epoch, Slot::new(0), log.clone()),
+            DutiesChangeSummary {
+                proposals: vec![],
+                attestations: vec![unchanged.pk.clone(), proposer.pk.clone()],
+            }
+        );
+
+        // Only the proposer's duties change.
+        duties.extend(duties_for(&proposer, Some(4)));
+        manager.beacon_node.set_next_duties_result(Ok(duties));
+        assert_eq!(
+            manager.run_update(epoch, Slot::new(0), log.clone()),
+            DutiesChangeSummary {
+                proposals: vec![proposer.pk.clone()],
+                attestations: vec![],
+            }
+        );
+
+        // Nothing changes.
+        assert_eq!(
+            manager.run_update(epoch, Slot::new(0), log),
+            DutiesChangeSummary::default()
+        );
+    }
+
+    #[test]
+    fn duties_requested_in_concurrent_batches() {
+        let keypairs: Vec<Keypair> = (0..20).map(|_| Keypair::random()).collect();
         let manager = DutiesManager {
-            spec: spec.clone(),
-            pubkey: keypair.pk.clone(),
-            duties_map: duties_map.clone(),
-            slot_clock: slot_clock.clone(),
-            beacon_node: beacon_node.clone(),
+            duties_map: RwLock::new(EpochDutiesMap::new(SLOTS_PER_EPOCH)),
+            signers: Arc::new(keypairs.clone()),
+            beacon_node: Arc::new(TestBeaconNode {
+                request_delay: Some(std::time::Duration::from_millis(20)),
+                ..TestBeaconNode::default()
+            }),
+            batch_size: 3,
+            max_concurrent_requests: 2,
+            cache_path: None,
+            validator_statuses: <_>::default(),
+            doppelganger: <_>::default(),
+            monitor_balances: false,
+            validator_balances: <_>::default(),
+            log_rewards: false,
+            validator_rewards: <_>::default(),
+            validator_indices: <_>::default(),
+            syncing_distance: 0,
         };
+        let epoch = Epoch::new(0);
+
+        let mut duties = EpochDuties::new();
+        for keypair in &keypairs {
+            duties.extend(duties_for(keypair, None));
+        }
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
 
-        // Configure response from the BeaconNode.
-        let duties = EpochDuties {
-            validator_index: 0,
-            block_production_slot: Some(Slot::new(10)),
+        assert_eq!(
+            manager.update(epoch, Slot::new(0)),
+            Ok(UpdateOutcome::NewDuties(epoch, duties))
+        );
+        assert_eq!(*manager.beacon_node.request_count.lock().unwrap(), 7);
+        assert_eq!(*manager.beacon_node.max_active_requests.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn duties_restored_from_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("duties.json");
+        let keypair = Keypair::random();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+
+        let mut manager = manager(vec![keypair.clone()]);
+        manager.cache_path = Some(path.clone());
+        manager
+            .duties_map
+            .write()
+            .unwrap()
+            .insert(Epoch::new(0), duties_for(&keypair, Some(2)));
+        let duties = duties_for(&keypair, Some(10));
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties.clone()));
+        manager.run_update(Epoch::new(1), Slot::new(SLOTS_PER_EPOCH), log);
+
+        // Duties for epochs prior to the current epoch are discarded.
+        let duties_map = load_duties(&path, SLOTS_PER_EPOCH, Epoch::new(1)).unwrap();
+        assert_eq!(duties_map.keys().collect::<Vec<_>>(), vec![&Epoch::new(1)]);
+        assert_eq!(duties_map.get(&Epoch::new(1)), Some(&duties));
+
+        let restored = DutiesManager {
+            duties_map: RwLock::new(duties_map),
+            ..manager
         };
-        beacon_node.set_next_shuffling_result(Ok(Some(duties)));
+        assert!(restored.get_current_work(Slot::new(10)).is_some());
+    }
 
-        // Get the duties for the first time...
+    /// Records the level and message of each log record.
+    struct CaptureDrain(Arc<std::sync::Mutex<Vec<(slog::Level, String)>>>);
+
+    impl slog::Drain for CaptureDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.msg().to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn decreasing_balance_is_warned() {
+        let (losing, gaining) = (Keypair::random(), Keypair::random());
+        let manager = DutiesManager {
+            monitor_balances: true,
+            ..manager(vec![losing.clone(), gaining.clone()])
+        };
+        let records = Arc::new(std::sync::Mutex::new(vec![]));
+        let log = slog::Logger::root(CaptureDrain(records.clone()), slog::o!());
+        let warnings = || {
+            records
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(level, _)| *level == slog::Level::Warning)
+                .map(|(_, msg)| msg.clone())
+                .collect::<Vec<_>>()
+        };
+        let set_balances = |epoch: u64, losing_balance: u64, gaining_balance: u64| {
+            *manager.beacon_node.head_epoch.write().unwrap() = Epoch::new(epoch);
+            let mut balances = manager.beacon_node.validator_balances.write().unwrap();
+            balances.insert(losing.pk.clone(), losing_balance);
+            balances.insert(gaining.pk.clone(), gaining_balance);
+        };
+
+        set_balances(1, 32_000_000_000, 32_000_000_000);
+        manager.check_balances(&log);
+        assert!(warnings().is_empty());
+
+        // A decrease within the same epoch is not compared.
+        set_balances(1, 31_990_000_000, 32_000_000_000);
+        manager.check_balances(&log);
+        assert!(warnings().is_empty());
+
+        set_balances(2, 31_990_000_000, 32_010_000_000);
+        manager.check_balances(&log);
+        assert_eq!(warnings(), vec!["Validator balance decreased".to_string()]);
         assert_eq!(
-            manager.poll(),
-            Ok(PollOutcome::NewDuties(Epoch::new(0), duties))
+            manager.validator_balances.read().unwrap().get(&losing.pk),
+            Some(&(Epoch::new(2), 31_990_000_000))
         );
-        // Get the same duties again...
-        assert_eq!(manager.poll(), Ok(PollOutcome::NoChange(Epoch::new(0))));
+    }
 
-        // Return new duties.
-        let duties = EpochDuties {
-            validator_index: 0,
-            block_production_slot: Some(Slot::new(11)),
+    #[test]
+    fn balance_changes_recorded_as_rewards() {
+        let keypair = Keypair::random();
+        let manager = DutiesManager {
+            monitor_balances: true,
+            log_rewards: true,
+            ..manager(vec![keypair.clone()])
+        };
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let check = |epoch: u64, balance: u64| {
+            *manager.beacon_node.head_epoch.write().unwrap() = Epoch::new(epoch);
+            manager
+                .beacon_node
+                .validator_balances
+                .write()
+                .unwrap()
+                .insert(keypair.pk.clone(), balance);
+            manager.check_balances(&log);
+            manager
+                .validator_rewards
+                .read()
+                .unwrap()
+                .get(&keypair.pk)
+                .copied()
         };
-        beacon_node.set_next_shuffling_result(Ok(Some(duties)));
+
+        // Nothing is earned until there is a balance to compare with.
+        assert_eq!(check(1, 32_000_000_000), None);
+        assert_eq!(check(1, 32_000_000_500), None);
+
+        let totals = |rewards_gwei, penalties_gwei| {
+            Some(RewardTotals {
+                rewards_gwei,
+                penalties_gwei,
+            })
+        };
+        assert_eq!(check(2, 32_000_001_000), totals(1_000, 0));
+        assert_eq!(check(3, 32_000_000_400), totals(1_000, 600));
+        assert_eq!(check(5, 32_000_002_400), totals(3_000, 600));
+    }
+
+    #[test]
+    fn balances_not_requested_unless_enabled() {
+        let keypair = Keypair::random();
+        let manager = manager(vec![keypair.clone()]);
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+
+        manager
+            .beacon_node
+            .validator_balances
+            .write()
+            .unwrap()
+            .insert(keypair.pk.clone(), 32_000_000_000);
+        manager.check_balances(&log);
+        assert!(manager.validator_balances.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn changed_validator_index_discards_index_state() {
+        let keypair = Keypair::random();
+        let manager = manager(vec![keypair.clone()]);
+        let records = Arc::new(std::sync::Mutex::new(vec![]));
+        let log = slog::Logger::root(CaptureDrain(records.clone()), slog::o!());
+        let duties_with_index = |index| {
+            let mut duties = duties_for(&keypair, None);
+            if let Some(Some(duty)) = duties.get_mut(&keypair.pk) {
+                duty.validator_index = Some(index);
+            }
+            duties
+        };
+
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties_with_index(3)));
+        manager.run_update(Epoch::new(0), Slot::new(0), log.clone());
         assert_eq!(
-            manager.poll(),
-            Ok(PollOutcome::DutiesChanged(Epoch::new(0), duties))
+            manager.validator_indices.read().unwrap().get(&keypair.pk),
+            Some(&3)
         );
 
-        // Return no duties.
-        beacon_node.set_next_shuffling_result(Ok(None));
+        // Duties for a later epoch, a status and a balance are known for index 3.
+        manager
+            .duties_map
+            .write()
+            .unwrap()
+            .insert(Epoch::new(2), duties_with_index(3));
+        manager
+            .validator_statuses
+            .write()
+            .unwrap()
+            .insert(keypair.pk.clone(), ValidatorStatus::Active);
+        manager
+            .validator_balances
+            .write()
+            .unwrap()
+            .insert(keypair.pk.clone(), (Epoch::new(0), 32_000_000_000));
+
+        // The testnet is reset, and the validator is given another index.
+        manager
+            .beacon_node
+            .set_next_duties_result(Ok(duties_with_index(5)));
+        manager.run_update(Epoch::new(1), Slot::new(SLOTS_PER_EPOCH), log);
+
         assert_eq!(
-            manager.poll(),
-            Ok(PollOutcome::UnknownValidatorOrEpoch(Epoch::new(0)))
+            manager.validator_indices.read().unwrap().get(&keypair.pk),
+            Some(&5)
         );
+        let warned =
+            records.lock().unwrap().iter().any(|(level, msg)| {
+                *level == slog::Level::Warning && msg == "Validator index changed"
+            });
+        assert!(warned);
+        let duties_map = manager.duties_map.read().unwrap();
+        assert_eq!(duties_map.get(&Epoch::new(1)), Some(&duties_with_index(5)));
+        assert_eq!(duties_map.get(&Epoch::new(2)), Some(&EpochDuties::new()));
+        assert!(manager.validator_statuses.read().unwrap().is_empty());
+        assert!(manager.validator_balances.read().unwrap().is_empty());
     }
 }
-*/
@@ -0,0 +1,197 @@
+use super::{BeaconNodeDuties, DutiesManager, Error};
+use crate::signer::Signer;
+use serde_derive::Serialize;
+use std::fmt::Display;
+use types::{Epoch, PublicKey, Slot};
+
+/// The kind of work a validator is scheduled to perform.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DutyKind {
+    Proposal,
+    Attestation,
+}
+
+/// A single upcoming duty for one validator.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct ScheduledDuty {
+    /// The hex-encoded public key of the validator.
+    pub validator: String,
+    pub duty: DutyKind,
+    pub slot: Slot,
+    /// The shard to attest to, for attestation duties.
+    pub shard: Option<u64>,
+    /// The start of `slot`, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+impl<U: BeaconNodeDuties, S: Signer + Display> DutiesManager<U, S> {
+    /// Requests the duties of all known validators for `epochs` epochs from `start_epoch` and
+    /// returns them in slot order.
+    ///
+    /// The duties are not stored or acted upon. `slot_timestamp` returns the start time of a slot,
+    /// in seconds since the UNIX epoch.
+    pub fn schedule<F>(
+        &self,
+        start_epoch: Epoch,
+        epochs: u64,
+        slot_timestamp: F,
+    ) -> Result<Vec<ScheduledDuty>, Error>
+    where
+        F: Fn(Slot) -> u64,
+    {
+        let public_keys: Vec<PublicKey> = self.signers.iter().map(Signer::to_public).collect();
+        let mut schedule = vec![];
+
+        for epoch in (0..epochs).map(|i| start_epoch + i) {
+            let duties = self.beacon_node.request_duties(epoch, &public_keys)?;
+
+            for pubkey in &public_keys {
+                let duty = match duties.get(pubkey) {
+                    Some(Some(duty)) => duty,
+                    Some(None) => continue,
+                    None => return Err(Error::IncompleteDuties(epoch)),
+                };
+                let validator = pubkey.as_hex_string();
+
+                if let Some(slot) = duty.block_production_slot {
+                    schedule.push(ScheduledDuty {
+                        validator: validator.clone(),
+                        duty: DutyKind::Proposal,
+                        slot,
+                        shard: None,
+                        timestamp: slot_timestamp(slot),
+                    });
+                }
+
+                for attestation_duty in &duty.attestation_duties {
+                    schedule.push(ScheduledDuty {
+                        validator: validator.clone(),
+                        duty: DutyKind::Attestation,
+                        slot: attestation_duty.slot,
+                        shard: Some(attestation_duty.shard),
+                        timestamp: slot_timestamp(attestation_duty.slot),
+                    });
+                }
+            }
+        }
+
+        schedule.sort_by_key(|duty| duty.slot);
+
+        Ok(schedule)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::epoch_duties::{EpochDuties, EpochDutiesMap, EpochDuty};
+    use super::super::{BeaconNodeDutiesError, ValidatorStatus, DUTIES_BATCH_SIZE};
+    use super::*;
+    use bls::Keypair;
+    use std::sync::{Arc, RwLock};
+    use types::AttestationDuty;
+
+    const SLOTS_PER_EPOCH: u64 = 8;
+
+    /// Assigns the first validator to propose in the third slot of every epoch, and every
+    /// validator to attest in the last slot.
+    struct EpochNode;
+
+    impl BeaconNodeDuties for EpochNode {
+        fn request_duties(
+            &self,
+            epoch: Epoch,
+            pub_keys: &[PublicKey],
+        ) -> Result<EpochDuties, BeaconNodeDutiesError> {
+            let start_slot = epoch.start_slot(SLOTS_PER_EPOCH);
+            Ok(pub_keys
+                .iter()
+                .enumerate()
+                .map(|(i, pubkey)| {
+                    let duty = EpochDuty {
+                        block_production_slot: if i == 0 { Some(start_slot + 2) } else { None },
+                        attestation_duties: vec![AttestationDuty {
+                            slot: start_slot + SLOTS_PER_EPOCH - 1,
+                            shard: i as u64,
+                            committee_index: 0,
+                            committee_len: 1,
+                        }],
+                        validator_index: None,
+                    };
+                    (pubkey.clone(), Some(duty))
+                })
+                .collect())
+        }
+
+        fn request_validator_status(
+            &self,
+            _epoch: Epoch,
+            pub_keys: &[PublicKey],
+        ) -> Result<Vec<ValidatorStatus>, BeaconNodeDutiesError> {
+            Ok(vec![ValidatorStatus::Active; pub_keys.len()])
+        }
+
+        fn request_validator_liveness(
+            &self,
+            _epoch: Epoch,
+            pub_keys: &[PublicKey],
+        ) -> Result<Vec<bool>, BeaconNodeDutiesError> {
+            Ok(vec![false; pub_keys.len()])
+        }
+
+        fn request_validator_balances(
+            &self,
+            pub_keys: &[PublicKey],
+        ) -> Result<(Epoch, Vec<u64>), BeaconNodeDutiesError> {
+            Ok((Epoch::new(0), vec![0; pub_keys.len()]))
+        }
+
+        fn request_head_slot(&self) -> Result<Slot, BeaconNodeDutiesError> {
+            Ok(Slot::new(0))
+        }
+    }
+
+    #[test]
+    fn schedule_contains_proposal_slots() {
+        let keypairs = vec![Keypair::random(), Keypair::random()];
+        let manager = DutiesManager {
+            duties_map: RwLock::new(EpochDutiesMap::new(SLOTS_PER_EPOCH)),
+            signers: Arc::new(keypairs.clone()),
+            beacon_node: Arc::new(EpochNode),
+            batch_size: DUTIES_BATCH_SIZE,
+            max_concurrent_requests: 1,
+            cache_path: None,
+            validator_statuses: <_>::default(),
+            doppelganger: <_>::default(),
+            monitor_balances: false,
+            validator_balances: <_>::default(),
+            log_rewards: false,
+            validator_rewards: <_>::default(),
+            validator_indices: <_>::default(),
+            syncing_distance: 0,
+        };
+
+        let schedule = manager
+            .schedule(Epoch::new(3), 2, |slot| 1000 + slot.as_u64() * 6)
+            .unwrap();
+
+        let proposals: Vec<_> = schedule
+            .iter()
+            .filter(|duty| duty.duty == DutyKind::Proposal)
+            .collect();
+        assert_eq!(proposals.len(), 2);
+        assert_eq!(proposals[0].slot, Slot::new(26));
+        assert_eq!(proposals[1].slot, Slot::new(34));
+        assert_eq!(proposals[0].timestamp, 1000 + 26 * 6);
+        assert!(proposals
+            .iter()
+            .all(|duty| duty.validator == keypairs[0].pk.as_hex_string()));
+
+        // Two attestations for each epoch, one per validator.
+        assert_eq!(schedule.len(), 6);
+        assert!(schedule.windows(2).all(|pair| pair[0].slot <= pair[1].slot));
+
+        // The duties are not stored.
+        assert!(manager.duties_map.read().unwrap().is_empty());
+    }
+}
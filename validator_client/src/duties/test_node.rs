@@ -1,32 +1,122 @@
-use super::traits::{BeaconNode, BeaconNodeError};
+use super::beacon_node_duties::{BeaconNodeDuties, BeaconNodeDutiesError, ValidatorStatus};
 use super::EpochDuties;
-use bls::PublicKey;
-use std::sync::RwLock;
-use types::Epoch;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use types::{Epoch, PublicKey, Slot};
 
-type ShufflingResult = Result<Option<EpochDuties>, BeaconNodeError>;
+type DutiesResult = Result<EpochDuties, BeaconNodeDutiesError>;
 
 /// A test-only struct used to simulate a Beacon Node.
 #[derive(Default)]
 pub struct TestBeaconNode {
-    pub request_shuffling_input: RwLock<Option<(Epoch, PublicKey)>>,
-    pub request_shuffling_result: RwLock<Option<ShufflingResult>>,
+    pub request_duties_input: RwLock<Option<(Epoch, Vec<PublicKey>)>>,
+    pub request_duties_result: RwLock<Option<DutiesResult>>,
+    /// If set, each request takes this long to complete.
+    pub request_delay: Option<Duration>,
+    /// The number of requests which are currently being handled.
+    pub active_requests: Mutex<usize>,
+    /// The greatest number of requests handled at the same time.
+    pub max_active_requests: Mutex<usize>,
+    /// The total number of requests received.
+    pub request_count: Mutex<usize>,
+    /// The status reported for each validator. Validators which are not present are active.
+    pub validator_statuses: RwLock<HashMap<PublicKey, ValidatorStatus>>,
+    /// The validators reported to have attested in every epoch.
+    pub live_validators: RwLock<HashSet<PublicKey>>,
+    /// The validators reported to have attested in a single epoch.
+    pub live_in_epochs: RwLock<HashSet<(PublicKey, Epoch)>>,
+    /// The current epoch of the head state.
+    pub head_epoch: RwLock<Epoch>,
+    /// The balance reported for each validator. Validators which are not present have a balance
+    /// of zero.
+    pub validator_balances: RwLock<HashMap<PublicKey, u64>>,
+    /// The slot of the head block.
+    pub head_slot: RwLock<Slot>,
 }
 
 impl TestBeaconNode {
-    /// Set the result to be returned when `request_shuffling` is called.
-    pub fn set_next_shuffling_result(&self, result: ShufflingResult) {
-        *self.request_shuffling_result.write().unwrap() = Some(result);
+    /// Set the result to be returned when `request_duties` is called.
+    pub fn set_next_duties_result(&self, result: DutiesResult) {
+        *self.request_duties_result.write().unwrap() = Some(result);
     }
 }
 
-impl BeaconNode for TestBeaconNode {
-    /// Returns the value specified by the `set_next_shuffling_result`.
-    fn request_shuffling(&self, epoch: Epoch, public_key: &PublicKey) -> ShufflingResult {
-        *self.request_shuffling_input.write().unwrap() = Some((epoch, public_key.clone()));
-        match *self.request_shuffling_result.read().unwrap() {
+impl BeaconNodeDuties for TestBeaconNode {
+    /// Returns the value specified by the `set_next_duties_result`.
+    fn request_duties(&self, epoch: Epoch, pub_keys: &[PublicKey]) -> DutiesResult {
+        *self.request_duties_input.write().unwrap() = Some((epoch, pub_keys.to_vec()));
+        *self.request_count.lock().unwrap() += 1;
+        {
+            let mut active = self.active_requests.lock().unwrap();
+            *active += 1;
+            let mut max_active = self.max_active_requests.lock().unwrap();
+            *max_active = (*max_active).max(*active);
+        }
+
+        if let Some(delay) = self.request_delay {
+            std::thread::sleep(delay);
+        }
+
+        *self.active_requests.lock().unwrap() -= 1;
+        match *self.request_duties_result.read().unwrap() {
             Some(ref r) => r.clone(),
-            None => panic!("TestBeaconNode: produce_result == None"),
+            None => panic!("TestBeaconNode: request_duties_result == None"),
         }
     }
+
+    /// Returns the statuses specified in `validator_statuses`.
+    fn request_validator_status(
+        &self,
+        _epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<ValidatorStatus>, BeaconNodeDutiesError> {
+        let statuses = self.validator_statuses.read().unwrap();
+        Ok(pub_keys
+            .iter()
+            .map(|pubkey| {
+                statuses
+                    .get(pubkey)
+                    .cloned()
+                    .unwrap_or(ValidatorStatus::Active)
+            })
+            .collect())
+    }
+
+    /// Returns `true` for the validators in `live_validators` or `live_in_epochs`.
+    fn request_validator_liveness(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<bool>, BeaconNodeDutiesError> {
+        let live_validators = self.live_validators.read().unwrap();
+        let live_in_epochs = self.live_in_epochs.read().unwrap();
+        Ok(pub_keys
+            .iter()
+            .map(|pubkey| {
+                live_validators.contains(pubkey)
+                    || live_in_epochs.contains(&(pubkey.clone(), epoch))
+            })
+            .collect())
+    }
+
+    /// Returns `head_epoch` and the balances in `validator_balances`.
+    fn request_validator_balances(
+        &self,
+        pub_keys: &[PublicKey],
+    ) -> Result<(Epoch, Vec<u64>), BeaconNodeDutiesError> {
+        let balances = self.validator_balances.read().unwrap();
+        Ok((
+            *self.head_epoch.read().unwrap(),
+            pub_keys
+                .iter()
+                .map(|pubkey| balances.get(pubkey).cloned().unwrap_or(0))
+                .collect(),
+        ))
+    }
+
+    /// Returns `head_slot`.
+    fn request_head_slot(&self) -> Result<Slot, BeaconNodeDutiesError> {
+        Ok(*self.head_slot.read().unwrap())
+    }
 }
@@ -0,0 +1,510 @@
+use crate::attestation_producer::BeaconNodeAttestation;
+use crate::block_producer::{BeaconNodeBlock, BeaconNodeError, ProducedBlock, PublishOutcome};
+use crate::duties::{BeaconNodeDuties, BeaconNodeDutiesError, EpochDuties, ValidatorStatus};
+use slog::{info, warn};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
+use types::{
+    AggregateAndProof, Attestation, AttestationData, BeaconBlock, Epoch, EthSpec, PublicKey,
+    Signature, Slot,
+};
+
+/// The recent history of requests made to a single beacon node.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NodeHealth {
+    /// When a request to the node last succeeded.
+    pub last_success: Option<Instant>,
+    /// The number of requests which have failed since the last success.
+    pub consecutive_failures: u64,
+}
+
+struct CandidateNode<C> {
+    /// The address of the node, used when logging.
+    name: String,
+    client: C,
+    health: Mutex<NodeHealth>,
+}
+
+/// The nodes of a `BeaconNodeFallback` at some point in time, unaffected by any later call to
+/// `replace_nodes`.
+pub struct Nodes<C>(Arc<Vec<CandidateNode<C>>>);
+
+impl<C> Nodes<C> {
+    /// Returns each node's name and client, in the order they were configured.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &C)> {
+        self.0.iter().map(|node| (node.name.as_str(), &node.client))
+    }
+}
+
+/// A set of clients, one per beacon node, with each request sent to the healthiest node first.
+///
+/// A request which fails because the node could not be reached, or returned an error, is retried
+/// on the next node. Nodes with the fewest consecutive failures are tried first, and nodes with
+/// equal failures are tried in the order they were configured.
+///
+/// The nodes may be replaced whilst running. Requests already in progress finish on the nodes
+/// they started with.
+pub struct BeaconNodeFallback<C> {
+    nodes: RwLock<Arc<Vec<CandidateNode<C>>>>,
+    log: slog::Logger,
+}
+
+impl<C> BeaconNodeFallback<C> {
+    /// Creates a fallback over `nodes`, given as `(name, client)` pairs in order of preference.
+    pub fn new(nodes: Vec<(String, C)>, log: slog::Logger) -> Self {
+        let nodes = candidates(nodes, &[]);
+        Self {
+            nodes: RwLock::new(Arc::new(nodes)),
+            log,
+        }
+    }
+
+    /// Replaces every node with `nodes`, given as `(name, client)` pairs in order of preference.
+    /// A node with the same name as one being replaced keeps its health.
+    pub fn replace_nodes(&self, nodes: Vec<(String, C)>) {
+        let mut current = self.nodes.write().expect("nodes lock poisoned");
+        let names: Vec<&str> = nodes.iter().map(|(name, _)| name.as_str()).collect();
+        info!(
+            self.log,
+            "Beacon nodes replaced";
+            "nodes" => names.join(", "),
+        );
+        *current = Arc::new(candidates(nodes, &current));
+    }
+
+    /// Returns the current nodes.
+    pub fn nodes(&self) -> Nodes<C> {
+        Nodes(self.snapshot())
+    }
+
+    /// Returns the health of the node at `index`, in the order the nodes were configured.
+    pub fn health(&self, index: usize) -> Option<NodeHealth> {
+        self.snapshot()
+            .get(index)
+            .map(|node| *node.health.lock().expect("node health lock poisoned"))
+    }
+
+    fn snapshot(&self) -> Arc<Vec<CandidateNode<C>>> {
+        self.nodes.read().expect("nodes lock poisoned").clone()
+    }
+
+    /// Calls `request` on each node, healthiest first, until it succeeds.
+    ///
+    /// An error for which `fails_over` returns `false` is returned immediately, as another node
+    /// would return the same. If every node fails, `all_failed` is called with a description of
+    /// each node's error.
+    pub fn first_success<T, Err, F, G, H>(
+        &self,
+        request: F,
+        fails_over: G,
+        all_failed: H,
+    ) -> Result<T, Err>
+    where
+        Err: Debug,
+        F: Fn(&C) -> Result<T, Err>,
+        G: Fn(&Err) -> bool,
+        H: FnOnce(String) -> Err,
+    {
+        let mut errors = vec![];
+        let nodes = self.snapshot();
+
+        for node in by_health(&nodes) {
+            match request(&node.client) {
+                Ok(value) => {
+                    let mut health = node.health.lock().expect("node health lock poisoned");
+                    health.last_success = Some(Instant::now());
+                    health.consecutive_failures = 0;
+                    return Ok(value);
+                }
+                Err(e) if fails_over(&e) => {
+                    let health = {
+                        let mut health = node.health.lock().expect("node health lock poisoned");
+                        health.consecutive_failures += 1;
+                        *health
+                    };
+                    warn!(
+                        self.log,
+                        "Beacon node request failed";
+                        "node" => &node.name,
+                        "error" => format!("{:?}", e),
+                        "consecutive_failures" => health.consecutive_failures,
+                        "secs_since_success" => health
+                            .last_success
+                            .map(|at| at.elapsed().as_secs().to_string())
+                            .unwrap_or_else(|| "never".to_string()),
+                    );
+                    errors.push(format!("{}: {:?}", node.name, e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(all_failed(format!(
+            "All beacon nodes failed: {}",
+            errors.join("; ")
+        )))
+    }
+}
+
+/// Returns a candidate for each of `nodes`, with the health of any node in `previous` of the same
+/// name.
+fn candidates<C>(nodes: Vec<(String, C)>, previous: &[CandidateNode<C>]) -> Vec<CandidateNode<C>> {
+    nodes
+        .into_iter()
+        .map(|(name, client)| {
+            let health = previous
+                .iter()
+                .find(|node| node.name == name)
+                .map(|node| *node.health.lock().expect("node health lock poisoned"))
+                .unwrap_or_default();
+            CandidateNode {
+                name,
+                client,
+                health: Mutex::new(health),
+            }
+        })
+        .collect()
+}
+
+/// Returns the nodes ordered by the fewest consecutive failures, then by configured order.
+fn by_health<C>(nodes: &[CandidateNode<C>]) -> Vec<&CandidateNode<C>> {
+    let mut nodes = nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let failures = node
+                .health
+                .lock()
+                .expect("node health lock poisoned")
+                .consecutive_failures;
+            (failures, index, node)
+        })
+        .collect::<Vec<_>>();
+    nodes.sort_by_key(|(failures, index, _)| (*failures, *index));
+    nodes.into_iter().map(|(_, _, node)| node).collect()
+}
+
+fn duties_fail_over(e: &BeaconNodeDutiesError) -> bool {
+    match e {
+        BeaconNodeDutiesError::RemoteFailure(_)
+        | BeaconNodeDutiesError::Timeout(_)
+        | BeaconNodeDutiesError::Unauthorized(_) => true,
+    }
+}
+
+/// A node which cannot be reached, or refuses the API token, fails over, whereas a response which
+/// cannot be decoded is returned, as it indicates a bug rather than an unhealthy node.
+fn node_fail_over(e: &BeaconNodeError) -> bool {
+    match e {
+        BeaconNodeError::RemoteFailure(_)
+        | BeaconNodeError::Timeout(_)
+        | BeaconNodeError::Unauthorized(_) => true,
+        BeaconNodeError::DecodeFailure | BeaconNodeError::BadRequest(_) => false,
+    }
+}
+
+impl<C: BeaconNodeDuties> BeaconNodeDuties for BeaconNodeFallback<C> {
+    fn request_duties(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<EpochDuties, BeaconNodeDutiesError> {
+        self.first_success(
+            |client| client.request_duties(epoch, pub_keys),
+            duties_fail_over,
+            BeaconNodeDutiesError::RemoteFailure,
+        )
+    }
+
+    fn request_validator_status(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<ValidatorStatus>, BeaconNodeDutiesError> {
+        self.first_success(
+            |client| client.request_validator_status(epoch, pub_keys),
+            duties_fail_over,
+            BeaconNodeDutiesError::RemoteFailure,
+        )
+    }
+
+    fn request_validator_liveness(
+        &self,
+        epoch: Epoch,
+        pub_keys: &[PublicKey],
+    ) -> Result<Vec<bool>, BeaconNodeDutiesError> {
+        self.first_success(
+            |client| client.request_validator_liveness(epoch, pub_keys),
+            duties_fail_over,
+            BeaconNodeDutiesError::RemoteFailure,
+        )
+    }
+
+    fn request_validator_balances(
+        &self,
+        pub_keys: &[PublicKey],
+    ) -> Result<(Epoch, Vec<u64>), BeaconNodeDutiesError> {
+        self.first_success(
+            |client| client.request_validator_balances(pub_keys),
+            duties_fail_over,
+            BeaconNodeDutiesError::RemoteFailure,
+        )
+    }
+
+    fn request_head_slot(&self) -> Result<Slot, BeaconNodeDutiesError> {
+        self.first_success(
+            |client| client.request_head_slot(),
+            duties_fail_over,
+            BeaconNodeDutiesError::RemoteFailure,
+        )
+    }
+}
+
+impl<C: BeaconNodeBlock> BeaconNodeBlock for BeaconNodeFallback<C> {
+    fn produce_beacon_block<T: EthSpec>(
+        &self,
+        slot: Slot,
+        randao_reveal: &Signature,
+        graffiti: &[u8; 32],
+    ) -> Result<Option<ProducedBlock<T>>, BeaconNodeError> {
+        self.first_success(
+            |client| client.produce_beacon_block(slot, randao_reveal, graffiti),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+
+    fn publish_beacon_block<T: EthSpec>(
+        &self,
+        block: BeaconBlock<T>,
+    ) -> Result<PublishOutcome, BeaconNodeError> {
+        self.first_success(
+            |client| client.publish_beacon_block(block.clone()),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+}
+
+impl<C: BeaconNodeAttestation> BeaconNodeAttestation for BeaconNodeFallback<C> {
+    fn produce_attestation_data(
+        &self,
+        slot: Slot,
+        shard: u64,
+    ) -> Result<AttestationData, BeaconNodeError> {
+        self.first_success(
+            |client| client.produce_attestation_data(slot, shard),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+
+    fn publish_attestation<T: EthSpec>(
+        &self,
+        attestation: Attestation<T>,
+    ) -> Result<PublishOutcome, BeaconNodeError> {
+        self.first_success(
+            |client| client.publish_attestation(attestation.clone()),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+
+    fn head_slot(&self) -> Result<Slot, BeaconNodeError> {
+        self.first_success(
+            |client| client.head_slot(),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+
+    fn produce_aggregate_attestation<T: EthSpec>(
+        &self,
+        slot: Slot,
+        shard: u64,
+    ) -> Result<Option<Attestation<T>>, BeaconNodeError> {
+        self.first_success(
+            |client| client.produce_aggregate_attestation(slot, shard),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+
+    fn publish_aggregate_and_proof<T: EthSpec>(
+        &self,
+        aggregate_and_proof: AggregateAndProof<T>,
+    ) -> Result<PublishOutcome, BeaconNodeError> {
+        self.first_success(
+            |client| client.publish_aggregate_and_proof(aggregate_and_proof.clone()),
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::duties::test_node::TestBeaconNode;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Returns a fallback over two nodes, whose `request_duties` return `primary` and `secondary`.
+    fn fallback(
+        primary: Result<EpochDuties, BeaconNodeDutiesError>,
+        secondary: Result<EpochDuties, BeaconNodeDutiesError>,
+    ) -> BeaconNodeFallback<TestBeaconNode> {
+        let nodes = vec![primary, secondary]
+            .into_iter()
+            .enumerate()
+            .map(|(index, result)| {
+                let node = TestBeaconNode::default();
+                node.set_next_duties_result(result);
+                (format!("node-{}", index), node)
+            })
+            .collect();
+        BeaconNodeFallback::new(nodes, null_logger())
+    }
+
+    fn request_count(fallback: &BeaconNodeFallback<TestBeaconNode>, index: usize) -> usize {
+        let nodes = fallback.nodes();
+        let (_, node) = nodes.iter().nth(index).unwrap();
+        *node.request_count.lock().unwrap()
+    }
+
+    fn syncing() -> Result<EpochDuties, BeaconNodeDutiesError> {
+        Err(BeaconNodeDutiesError::RemoteFailure("syncing".into()))
+    }
+
+    #[test]
+    fn failing_node_fails_over_and_is_deprioritised() {
+        let fallback = fallback(syncing(), Ok(EpochDuties::new()));
+
+        assert_eq!(
+            fallback.request_duties(Epoch::new(0), &[]),
+            Ok(EpochDuties::new())
+        );
+        let primary_health = fallback.health(0).unwrap();
+        assert_eq!(primary_health.consecutive_failures, 1);
+        assert_eq!(primary_health.last_success, None);
+        assert!(fallback.health(1).unwrap().last_success.is_some());
+
+        // The secondary is now preferred, so the failing primary is not asked again.
+        assert_eq!(
+            fallback.request_duties(Epoch::new(0), &[]),
+            Ok(EpochDuties::new())
+        );
+        assert_eq!(request_count(&fallback, 0), 1);
+        assert_eq!(request_count(&fallback, 1), 2);
+    }
+
+    #[test]
+    fn error_aggregated_when_all_nodes_fail() {
+        let fallback = fallback(syncing(), syncing());
+
+        match fallback.request_duties(Epoch::new(0), &[]) {
+            Err(BeaconNodeDutiesError::RemoteFailure(e)) => {
+                assert!(e.starts_with("All beacon nodes failed"));
+                assert!(e.contains("node-0: RemoteFailure(\"syncing\")"));
+                assert!(e.contains("node-1: RemoteFailure(\"syncing\")"));
+            }
+            other => panic!("expected an aggregated failure, got {:?}", other),
+        }
+        assert_eq!(fallback.health(0).unwrap().consecutive_failures, 1);
+        assert_eq!(fallback.health(1).unwrap().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn decode_failure_does_not_fail_over() {
+        let fallback = BeaconNodeFallback::new(
+            vec![("node-0".to_string(), ()), ("node-1".to_string(), ())],
+            null_logger(),
+        );
+        let requests = Mutex::new(0);
+
+        let result: Result<(), _> = fallback.first_success(
+            |_| {
+                *requests.lock().unwrap() += 1;
+                Err(BeaconNodeError::DecodeFailure)
+            },
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        );
+
+        assert_eq!(result, Err(BeaconNodeError::DecodeFailure));
+        assert_eq!(*requests.lock().unwrap(), 1);
+        assert_eq!(fallback.health(0).unwrap().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn timeouts_and_refused_tokens_distinguished_and_fail_over() {
+        use grpcio::{RpcStatus, RpcStatusCode};
+        let failure = |code| grpcio::Error::RpcFailure(RpcStatus::new(code, None));
+
+        match BeaconNodeError::from(failure(RpcStatusCode::DeadlineExceeded)) {
+            e @ BeaconNodeError::Timeout(_) => assert!(node_fail_over(&e)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        match BeaconNodeDutiesError::from(failure(RpcStatusCode::DeadlineExceeded)) {
+            e @ BeaconNodeDutiesError::Timeout(_) => assert!(duties_fail_over(&e)),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+
+        match BeaconNodeDutiesError::from(failure(RpcStatusCode::Unauthenticated)) {
+            e @ BeaconNodeDutiesError::Unauthorized(_) => assert!(duties_fail_over(&e)),
+            other => panic!("expected an unauthorized error, got {:?}", other),
+        }
+
+        match BeaconNodeError::from(failure(RpcStatusCode::Unavailable)) {
+            BeaconNodeError::RemoteFailure(_) => {}
+            other => panic!("expected a remote failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nodes_replaced_mid_run() {
+        let fallback = fallback(syncing(), Ok(EpochDuties::new()));
+        fallback.request_duties(Epoch::new(0), &[]).unwrap();
+
+        // The replacement is made whilst a request is in progress, which finishes on the original
+        // nodes.
+        let requests = Mutex::new(vec![]);
+        let result: Result<(), _> = fallback.first_success(
+            |client| {
+                requests
+                    .lock()
+                    .unwrap()
+                    .push(*client.request_count.lock().unwrap());
+                if requests.lock().unwrap().len() == 1 {
+                    let replacement = TestBeaconNode::default();
+                    replacement.set_next_duties_result(Ok(EpochDuties::new()));
+                    let failing = TestBeaconNode::default();
+                    failing.set_next_duties_result(syncing());
+                    fallback.replace_nodes(vec![
+                        ("node-2".to_string(), replacement),
+                        ("node-0".to_string(), failing),
+                    ]);
+                }
+                Err(BeaconNodeError::RemoteFailure("unavailable".into()))
+            },
+            node_fail_over,
+            BeaconNodeError::RemoteFailure,
+        );
+        assert!(result.is_err());
+        assert_eq!(*requests.lock().unwrap(), vec![1, 1]);
+
+        // Later requests use the new nodes. The node which kept its name kept the health it had
+        // when replaced.
+        assert_eq!(fallback.health(1).unwrap().consecutive_failures, 1);
+        assert_eq!(
+            fallback.request_duties(Epoch::new(0), &[]),
+            Ok(EpochDuties::new())
+        );
+        assert_eq!(request_count(&fallback, 0), 1);
+        assert_eq!(request_count(&fallback, 1), 0);
+        assert_eq!(fallback.nodes().iter().count(), 2);
+    }
+}
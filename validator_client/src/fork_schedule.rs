@@ -0,0 +1,144 @@
+use crate::config::ShadowFork;
+use types::{Epoch, Fork};
+
+/// The fork reported by the beacon node, extended by any shadow forks from the configuration.
+///
+/// Shadow forks are used for testing upgrades which are not yet known to the spec. The fork used
+/// to compute signing domains at some epoch is the latest one scheduled at or before that epoch.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForkSchedule {
+    base: Fork,
+    shadow_forks: Vec<ShadowFork>,
+}
+
+impl ForkSchedule {
+    /// Returns an error unless the epochs of `shadow_forks` are strictly increasing and all later
+    /// than the epoch of the `base` fork.
+    pub fn new(base: Fork, shadow_forks: Vec<ShadowFork>) -> Result<Self, String> {
+        let mut previous_epoch = base.epoch;
+        for shadow_fork in &shadow_forks {
+            if shadow_fork.epoch <= previous_epoch {
+                return Err(format!(
+                    "Shadow fork at epoch {} must be later than the fork at epoch {}",
+                    shadow_fork.epoch, previous_epoch
+                ));
+            }
+            previous_epoch = shadow_fork.epoch;
+        }
+
+        Ok(Self { base, shadow_forks })
+    }
+
+    /// Replaces the beacon node's fork, returning `true` if it differs from the previous fork.
+    ///
+    /// Shadow forks at or before the epoch of the new fork are superseded by it, so are dropped.
+    pub fn set_base(&mut self, base: Fork) -> bool {
+        if base == self.base {
+            return false;
+        }
+
+        self.shadow_forks
+            .retain(|shadow_fork| shadow_fork.epoch > base.epoch);
+        self.base = base;
+        true
+    }
+
+    /// Returns the fork to be used for signing messages in `epoch`.
+    pub fn fork_at(&self, epoch: Epoch) -> Fork {
+        self.shadow_forks
+            .iter()
+            .take_while(|shadow_fork| shadow_fork.epoch <= epoch)
+            .fold(self.base.clone(), |fork, shadow_fork| Fork {
+                previous_version: fork.current_version,
+                current_version: shadow_fork.version,
+                epoch: shadow_fork.epoch,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{ChainSpec, Domain};
+
+    fn base() -> Fork {
+        Fork {
+            previous_version: [0, 0, 0, 0],
+            current_version: [1, 0, 0, 0],
+            epoch: Epoch::new(2),
+        }
+    }
+
+    fn shadow_fork(version: u8, epoch: u64) -> ShadowFork {
+        ShadowFork {
+            version: [version, 0, 0, 0],
+            epoch: Epoch::new(epoch),
+        }
+    }
+
+    #[test]
+    fn domains_switch_at_shadow_fork_epoch() {
+        let spec = ChainSpec::minimal();
+        let schedule = ForkSchedule::new(base(), vec![shadow_fork(2, 10)]).unwrap();
+
+        let domain = |epoch: u64| {
+            let epoch = Epoch::new(epoch);
+            spec.get_domain(epoch, Domain::Attestation, &schedule.fork_at(epoch))
+        };
+        let expected = |version: [u8; 4]| {
+            let fork = Fork {
+                previous_version: version,
+                current_version: version,
+                epoch: Epoch::new(0),
+            };
+            spec.get_domain(Epoch::new(0), Domain::Attestation, &fork)
+        };
+
+        assert_eq!(domain(1), expected([0, 0, 0, 0]));
+        assert_eq!(domain(2), expected([1, 0, 0, 0]));
+        assert_eq!(domain(9), expected([1, 0, 0, 0]));
+        assert_eq!(domain(10), expected([2, 0, 0, 0]));
+        assert_eq!(domain(100), expected([2, 0, 0, 0]));
+    }
+
+    #[test]
+    fn later_shadow_forks_supersede_earlier_ones() {
+        let schedule =
+            ForkSchedule::new(base(), vec![shadow_fork(2, 10), shadow_fork(3, 20)]).unwrap();
+
+        assert_eq!(schedule.fork_at(Epoch::new(5)), base());
+        assert_eq!(
+            schedule.fork_at(Epoch::new(25)),
+            Fork {
+                previous_version: [2, 0, 0, 0],
+                current_version: [3, 0, 0, 0],
+                epoch: Epoch::new(20),
+            }
+        );
+    }
+
+    #[test]
+    fn shadow_fork_epochs_must_increase() {
+        assert!(ForkSchedule::new(base(), vec![shadow_fork(2, 2)]).is_err());
+        assert!(ForkSchedule::new(base(), vec![shadow_fork(2, 10), shadow_fork(3, 10)]).is_err());
+        assert!(ForkSchedule::new(base(), vec![shadow_fork(2, 10), shadow_fork(3, 5)]).is_err());
+    }
+
+    #[test]
+    fn fork_from_beacon_node_replaced_at_epoch_boundary() {
+        let mut schedule = ForkSchedule::new(base(), vec![shadow_fork(3, 10)]).unwrap();
+        let next = Fork {
+            previous_version: [1, 0, 0, 0],
+            current_version: [2, 0, 0, 0],
+            epoch: Epoch::new(10),
+        };
+
+        assert!(!schedule.set_base(base()));
+        assert!(schedule.set_base(next.clone()));
+        assert!(!schedule.set_base(next.clone()));
+
+        // The shadow fork at the same epoch is superseded by the beacon node's fork.
+        assert_eq!(schedule.fork_at(Epoch::new(10)), next);
+        assert_eq!(schedule.fork_at(Epoch::new(9)), next);
+    }
+}
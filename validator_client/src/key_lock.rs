@@ -0,0 +1,129 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use types::PublicKey;
+
+/// The extension of the lock file held for each validator.
+const LOCK_FILE_EXTENSION: &str = "lock";
+
+/// Prevents two validator clients from signing for the same validator, which would risk a
+/// slashable double vote.
+///
+/// The lock is an exclusive advisory lock (`flock`) on a `<pubkey>.lock` file, held for as long
+/// as the `KeyLock` is alive. The operating system releases it when the holder exits, however it
+/// exits, so a lock file left behind by a crashed process never blocks a restart. The file
+/// contains the process id of the holder, which is only used in error messages.
+///
+/// The file is not removed when the lock is released, since another process may already have
+/// opened it and be about to lock it.
+pub struct KeyLock {
+    _file: File,
+}
+
+impl KeyLock {
+    /// Acquires the lock for `pubkey` in `dir`, failing if it is held by another process.
+    pub fn acquire(dir: &Path, pubkey: &PublicKey) -> Result<Self, String> {
+        let path = dir
+            .join(pubkey.as_hex_string())
+            .with_extension(LOCK_FILE_EXTENSION);
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)
+            .map_err(|e| format!("Unable to open lock {:?}: {:?}", path, e))?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = std::io::Error::last_os_error();
+            return Err(if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                format!(
+                    "Validator {} is in use by another process (pid {})",
+                    pubkey,
+                    holder.trim()
+                )
+            } else {
+                format!("Unable to lock {:?}: {:?}", path, e)
+            });
+        }
+
+        file.set_len(0)
+            .and_then(|()| write!(file, "{}", std::process::id()))
+            .map_err(|e| format!("Unable to write lock {:?}: {:?}", path, e))?;
+
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+    use types::Keypair;
+
+    #[test]
+    fn second_lock_fails_until_released() {
+        let dir = tempfile::tempdir().unwrap();
+        let pubkey = Keypair::random().pk;
+
+        let lock = KeyLock::acquire(dir.path(), &pubkey).unwrap();
+        assert!(KeyLock::acquire(dir.path(), &pubkey).is_err());
+
+        drop(lock);
+        assert!(KeyLock::acquire(dir.path(), &pubkey).is_ok());
+    }
+
+    #[test]
+    fn racing_acquirers_over_stale_lock_get_one_lock() {
+        const ACQUIRERS: usize = 8;
+
+        let dir = tempfile::tempdir().unwrap();
+        let pubkey = Keypair::random().pk;
+        let path = dir
+            .path()
+            .join(pubkey.as_hex_string())
+            .with_extension(LOCK_FILE_EXTENSION);
+        fs::write(&path, i32::max_value().to_string()).unwrap();
+
+        // Each acquirer opens its own file description, as a separate process would.
+        let barrier = Arc::new(Barrier::new(ACQUIRERS));
+        let handles: Vec<_> = (0..ACQUIRERS)
+            .map(|_| {
+                let dir = dir.path().to_path_buf();
+                let pubkey = pubkey.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    barrier.wait();
+                    KeyLock::acquire(&dir, &pubkey)
+                })
+            })
+            .collect();
+        let locks: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+
+        assert_eq!(locks.iter().filter(|lock| lock.is_ok()).count(), 1);
+    }
+
+    #[test]
+    fn stale_lock_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let pubkey = Keypair::random().pk;
+        let path = dir
+            .path()
+            .join(pubkey.as_hex_string())
+            .with_extension(LOCK_FILE_EXTENSION);
+
+        // No process can have this id, as it exceeds the kernel's maximum.
+        fs::write(&path, i32::max_value().to_string()).unwrap();
+        let _lock = KeyLock::acquire(dir.path(), &pubkey).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+}
@@ -0,0 +1,349 @@
+use bls::{Keypair, PublicKey, SecretKey};
+use eth2_hashing::hash;
+use serde_derive::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::Path;
+use unicode_normalization::UnicodeNormalization;
+
+/// The extension of the file containing the password for a keystore, which is otherwise named
+/// the same as the keystore.
+pub const PASSWORD_FILE_EXTENSION: &str = "pass";
+
+/// The only keystore version supported.
+const KEYSTORE_VERSION: u64 = 4;
+
+/// The length of the key derived from the password.
+const DERIVED_KEY_LEN: usize = 32;
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The keystore or its password could not be read.
+    UnableToRead(String),
+    /// The keystore is not valid EIP-2335 JSON.
+    InvalidJson(String),
+    UnsupportedVersion(u64),
+    UnsupportedFunction(String),
+    InvalidParams(String),
+    InvalidHex(String),
+    /// The checksum did not match, which almost always means the password is wrong.
+    IncorrectPassword,
+    InvalidSecretKey,
+    /// The decrypted secret key does not match the public key stored in the keystore.
+    PublicKeyMismatch,
+}
+
+/// A cryptographic function applied to a keystore, with its parameters and its input or output.
+#[derive(Deserialize)]
+struct Module {
+    function: String,
+    params: Value,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct Crypto {
+    kdf: Module,
+    checksum: Module,
+    cipher: Module,
+}
+
+/// An EIP-2335 encrypted keystore.
+#[derive(Deserialize)]
+pub struct Keystore {
+    crypto: Crypto,
+    #[serde(default)]
+    pubkey: Option<String>,
+    version: u64,
+}
+
+#[derive(Deserialize)]
+struct ScryptParams {
+    dklen: usize,
+    n: u32,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+struct Pbkdf2Params {
+    dklen: usize,
+    c: u32,
+    prf: String,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+impl Keystore {
+    pub fn from_json(json: &str) -> Result<Self, Error> {
+        let keystore: Keystore =
+            serde_json::from_str(json).map_err(|e| Error::InvalidJson(format!("{:?}", e)))?;
+        if keystore.version != KEYSTORE_VERSION {
+            return Err(Error::UnsupportedVersion(keystore.version));
+        }
+        Ok(keystore)
+    }
+
+    /// Decrypts the keystore with `password`, returning the keypair it contains.
+    pub fn decrypt(&self, password: &str) -> Result<Keypair, Error> {
+        let derived_key = self.derive_key(&normalize_password(password))?;
+
+        let cipher_message = hex_decode(&self.crypto.cipher.message)?;
+        if self.crypto.checksum.function != "sha256" {
+            return Err(Error::UnsupportedFunction(
+                self.crypto.checksum.function.clone(),
+            ));
+        }
+        let mut checksum_input = derived_key[16..32].to_vec();
+        checksum_input.extend_from_slice(&cipher_message);
+        if hash(&checksum_input) != hex_decode(&self.crypto.checksum.message)? {
+            return Err(Error::IncorrectPassword);
+        }
+
+        let secret = self.decrypt_secret(&derived_key[0..16], cipher_message)?;
+        let secret_key = secret_key_from_bytes(&secret)?;
+        let keypair = Keypair {
+            pk: PublicKey::from_secret_key(&secret_key),
+            sk: secret_key,
+        };
+
+        if let Some(pubkey) = &self.pubkey {
+            if hex_decode(pubkey)? != ssz::Encode::as_ssz_bytes(&keypair.pk) {
+                return Err(Error::PublicKeyMismatch);
+            }
+        }
+        Ok(keypair)
+    }
+
+    /// Derives the decryption key from the normalized `password`.
+    fn derive_key(&self, password: &[u8]) -> Result<Vec<u8>, Error> {
+        let kdf = &self.crypto.kdf;
+        let invalid = |e: serde_json::Error| Error::InvalidParams(format!("{:?}", e));
+        let mut derived_key = vec![0; DERIVED_KEY_LEN];
+
+        match kdf.function.as_str() {
+            "scrypt" => {
+                let params: ScryptParams =
+                    serde_json::from_value(kdf.params.clone()).map_err(invalid)?;
+                check_dklen(params.dklen)?;
+                if !params.n.is_power_of_two() || params.n < 2 {
+                    return Err(Error::InvalidParams(
+                        "scrypt n must be a power of two".into(),
+                    ));
+                }
+                let scrypt_params =
+                    scrypt::ScryptParams::new(params.n.trailing_zeros() as u8, params.r, params.p)
+                        .map_err(|e| Error::InvalidParams(format!("{:?}", e)))?;
+                scrypt::scrypt(
+                    password,
+                    &hex_decode(&params.salt)?,
+                    &scrypt_params,
+                    &mut derived_key,
+                )
+                .map_err(|e| Error::InvalidParams(format!("{:?}", e)))?;
+            }
+            "pbkdf2" => {
+                let params: Pbkdf2Params =
+                    serde_json::from_value(kdf.params.clone()).map_err(invalid)?;
+                check_dklen(params.dklen)?;
+                if params.prf != "hmac-sha256" {
+                    return Err(Error::UnsupportedFunction(params.prf));
+                }
+                let iterations = NonZeroU32::new(params.c)
+                    .ok_or_else(|| Error::InvalidParams("pbkdf2 c must be non-zero".into()))?;
+                ring::pbkdf2::derive(
+                    ring::pbkdf2::PBKDF2_HMAC_SHA256,
+                    iterations,
+                    &hex_decode(&params.salt)?,
+                    password,
+                    &mut derived_key,
+                );
+            }
+            other => return Err(Error::UnsupportedFunction(other.to_string())),
+        }
+        Ok(derived_key)
+    }
+
+    /// Decrypts the secret key bytes using the first half of the derived key.
+    fn decrypt_secret(&self, key: &[u8], mut message: Vec<u8>) -> Result<Vec<u8>, Error> {
+        use aes_ctr::stream_cipher::generic_array::GenericArray;
+        use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+
+        let cipher = &self.crypto.cipher;
+        if cipher.function != "aes-128-ctr" {
+            return Err(Error::UnsupportedFunction(cipher.function.clone()));
+        }
+        let params: CipherParams = serde_json::from_value(cipher.params.clone())
+            .map_err(|e| Error::InvalidParams(format!("{:?}", e)))?;
+        let iv = hex_decode(&params.iv)?;
+        if iv.len() != 16 {
+            return Err(Error::InvalidParams(
+                "aes-128-ctr iv must be 16 bytes".into(),
+            ));
+        }
+
+        aes_ctr::Aes128Ctr::new(GenericArray::from_slice(key), GenericArray::from_slice(&iv))
+            .apply_keystream(&mut message);
+        Ok(message)
+    }
+}
+
+/// Reads the keystore at `path` and decrypts it with the password in the file of the same name
+/// with the `PASSWORD_FILE_EXTENSION` extension.
+pub fn load_keystore(path: &Path) -> Result<Keypair, Error> {
+    let password_path = path.with_extension(PASSWORD_FILE_EXTENSION);
+    let json = fs::read_to_string(path).map_err(|e| Error::UnableToRead(format!("{:?}", e)))?;
+    let password = fs::read_to_string(&password_path)
+        .map_err(|e| Error::UnableToRead(format!("{:?}: {:?}", password_path, e)))?;
+
+    // Trailing newlines are left by most editors and are never part of the password.
+    Keystore::from_json(&json)?.decrypt(password.trim_end_matches(|c| c == '\n' || c == '\r'))
+}
+
+/// Applies the EIP-2335 password processing: NFKD normalization, then removal of control codes.
+fn normalize_password(password: &str) -> Vec<u8> {
+    password
+        .nfkd()
+        .filter(|c| {
+            let c = *c as u32;
+            !(c < 0x20 || (0x7f..=0x9f).contains(&c))
+        })
+        .collect::<String>()
+        .into_bytes()
+}
+
+fn check_dklen(dklen: usize) -> Result<(), Error> {
+    if dklen == DERIVED_KEY_LEN {
+        Ok(())
+    } else {
+        Err(Error::InvalidParams(format!(
+            "dklen must be {}, not {}",
+            DERIVED_KEY_LEN, dklen
+        )))
+    }
+}
+
+/// Returns the secret key for the 32 `bytes` stored in a keystore.
+fn secret_key_from_bytes(bytes: &[u8]) -> Result<SecretKey, Error> {
+    if bytes.len() != 32 {
+        return Err(Error::InvalidSecretKey);
+    }
+    // Secret keys are big-endian, and are decoded from a 48-byte field.
+    let mut padded = vec![0; 16];
+    padded.extend_from_slice(bytes);
+    SecretKey::from_bytes(&padded).map_err(|_| Error::InvalidSecretKey)
+}
+
+fn hex_decode(s: &str) -> Result<Vec<u8>, Error> {
+    hex::decode(s.trim_start_matches("0x")).map_err(|_| Error::InvalidHex(s.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The EIP-2335 test password, which normalizes to "testpassword🔑".
+    const PASSWORD: &str = "𝔱𝔢𝔰𝔱𝔭𝔞𝔰𝔰𝔴𝔬𝔯𝔡🔑";
+
+    const SECRET: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    /// Returns a keystore for `SECRET`, encrypted with `PASSWORD` using `kdf`.
+    fn keystore_json(kdf: &str, checksum: &str, cipher_message: &str) -> String {
+        format!(
+            r#"{{
+                "crypto": {{
+                    "kdf": {},
+                    "checksum": {{"function": "sha256", "params": {{}}, "message": "{}"}},
+                    "cipher": {{
+                        "function": "aes-128-ctr",
+                        "params": {{"iv": "264daa3f303d7259501c93d997d84fe6"}},
+                        "message": "{}"
+                    }}
+                }},
+                "description": "test",
+                "path": "m/12381/60/0/0",
+                "uuid": "1d85ae20-35c5-4611-98e8-aa14a633906f",
+                "version": 4
+            }}"#,
+            kdf, checksum, cipher_message
+        )
+    }
+
+    fn pbkdf2_keystore() -> String {
+        keystore_json(
+            r#"{"function": "pbkdf2", "message": "", "params": {"dklen": 32, "c": 2,
+                "prf": "hmac-sha256",
+                "salt": "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"}}"#,
+            "a967f18d3d8e9a9fe08d744ce7a3e5d235cae979947da9d56aa0a05ea3dfa4ad",
+            "e1ec2106433a1a1b8d04610071c806586d5eb5b712c1d938eaafb223a7a7e53e",
+        )
+    }
+
+    fn scrypt_keystore() -> String {
+        keystore_json(
+            r#"{"function": "scrypt", "message": "", "params": {"dklen": 32, "n": 16, "p": 1,
+                "r": 8,
+                "salt": "d4e56740f876aef8c010b86a40d5f56745a118d0906a34e69aec8c0db1cb8fa3"}}"#,
+            "01fdeadd92f9333bcb830fb2c6de4c9f906c91cb84b77435d886722fa4c418f2",
+            "32da0474576fbb8f22eb7935f0b32e48d142e94431f0e93bf56f55961ce99520",
+        )
+    }
+
+    fn expected_public_key() -> PublicKey {
+        let secret_key = secret_key_from_bytes(&hex::decode(SECRET).unwrap()).unwrap();
+        PublicKey::from_secret_key(&secret_key)
+    }
+
+    #[test]
+    fn decrypt_pbkdf2_keystore() {
+        let keypair = Keystore::from_json(&pbkdf2_keystore())
+            .unwrap()
+            .decrypt(PASSWORD)
+            .unwrap();
+        assert_eq!(keypair.pk, expected_public_key());
+    }
+
+    #[test]
+    fn decrypt_scrypt_keystore() {
+        let keypair = Keystore::from_json(&scrypt_keystore())
+            .unwrap()
+            .decrypt(PASSWORD)
+            .unwrap();
+        assert_eq!(keypair.pk, expected_public_key());
+    }
+
+    #[test]
+    fn keystore_loaded_with_password_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("validator.json");
+        fs::write(&path, pbkdf2_keystore()).unwrap();
+        fs::write(
+            path.with_extension(PASSWORD_FILE_EXTENSION),
+            format!("{}\n", PASSWORD),
+        )
+        .unwrap();
+
+        assert_eq!(load_keystore(&path).unwrap().pk, expected_public_key());
+    }
+
+    #[test]
+    fn wrong_password_rejected() {
+        let keystore = Keystore::from_json(&scrypt_keystore()).unwrap();
+        assert_eq!(
+            keystore.decrypt("testpassword").map(|_| ()),
+            Err(Error::IncorrectPassword)
+        );
+    }
+
+    #[test]
+    fn control_codes_removed_from_password() {
+        assert_eq!(normalize_password("test\u{7f}pass\nword"), b"testpassword");
+        assert_eq!(normalize_password(PASSWORD), "testpassword🔑".as_bytes());
+    }
+}
@@ -1,4 +1,5 @@
 extern crate libc;
 pub mod config;
+mod keystore;
 
 pub use crate::config::Config;
@@ -1,23 +1,42 @@
 mod attestation_producer;
+mod audit_log;
 mod block_producer;
 mod config;
+mod doppelganger;
 mod duties;
 pub mod error;
+mod fallback;
+mod fork_schedule;
+mod key_lock;
+mod keystore;
+mod message_sink;
+mod observer;
+mod publish_retry;
+mod remote_signer;
 mod service;
 mod signer;
+mod signing_pool;
+mod skip_reason;
+mod slashing_protection;
+mod spans;
 
 use crate::config::{
     Config as ClientConfig, KeySource, DEFAULT_SERVER, DEFAULT_SERVER_GRPC_PORT,
     DEFAULT_SERVER_HTTP_PORT,
 };
+use crate::duties::ValidatorGrpcClient;
+use crate::message_sink::{FileSink, NatsSink, SinkObserver, MESSAGE_SINK_CAPACITY};
+use crate::observer::{NoOpObserver, ProductionObserver};
 use crate::service::Service as ValidatorService;
+use crate::slashing_protection::{interchange, SlashingDatabase};
 use clap::{App, Arg, ArgMatches, SubCommand};
 use eth2_config::Eth2Config;
 use lighthouse_bootstrap::Bootstrapper;
-use protos::services_grpc::ValidatorServiceClient;
 use slog::{crit, error, info, o, Drain, Level, Logger};
+use std::fs;
 use std::path::PathBuf;
-use types::{InteropEthSpec, Keypair, MainnetEthSpec, MinimalEthSpec};
+use std::sync::Arc;
+use types::{EthSpec, Hash256, InteropEthSpec, Keypair, MainnetEthSpec, MinimalEthSpec};
 
 pub const DEFAULT_SPEC: &str = "minimal";
 pub const DEFAULT_DATA_DIR: &str = ".lighthouse-validator";
@@ -79,6 +98,32 @@ fn main() {
                 .default_value(DEFAULT_SERVER)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("fallback-server")
+                .long("fallback-server")
+                .value_name("HOST:PORT")
+                .help("The gRPC address of a further BeaconNode, used when the server fails. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("aggregate-server")
+                .long("aggregate-server")
+                .value_name("HOST:PORT")
+                .help("The gRPC address of a BeaconNode to which aggregates are published, in place of the nodes used for attestations. May be repeated, in order of preference.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("fallback-servers-file")
+                .long("fallback-servers-file")
+                .value_name("FILE")
+                .help("Read the gRPC addresses of further BeaconNodes from FILE, one per line. The nodes are replaced without a restart whenever FILE changes.")
+                .takes_value(true)
+                .conflicts_with("fallback-server"),
+        )
         .arg(
             Arg::with_name("server-grpc-port")
                 .long("server-grpc-port")
@@ -97,6 +142,368 @@ fn main() {
                 .default_value(DEFAULT_SERVER_HTTP_PORT)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("slashing-protection")
+                .long("slashing-protection")
+                .value_name("BACKEND")
+                .help("The storage used to record signed messages for slashing protection.")
+                .takes_value(true)
+                .possible_values(&["file", "sqlite"])
+                .default_value("sqlite"),
+        )
+        .arg(
+            Arg::with_name("slashing-protection-parallelism")
+                .long("slashing-protection-parallelism")
+                .value_name("COUNT")
+                .help("The number of validators whose slashing protection checks may run concurrently. Ignored by the file backend.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slashing-protection-replica")
+                .long("slashing-protection-replica")
+                .value_name("FILE")
+                .help("Append a JSON record of each message accepted by slashing protection to this file, e.g., for a standby host.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("keys-wait-timeout")
+                .long("keys-wait-timeout")
+                .value_name("SECONDS")
+                .help("Seconds to wait for validator keys to appear in the data directory at startup.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-connect-attempts")
+                .long("max-connect-attempts")
+                .value_name("COUNT")
+                .help("Exit after this many failed attempts to connect to the beacon node (0 retries forever).")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("heartbeat-file")
+                .long("heartbeat-file")
+                .value_name("FILE")
+                .help("File re-written with the current slot and time after each processed slot.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("metrics-file")
+                .long("metrics-file")
+                .value_name("FILE")
+                .help("File re-written after each processed slot with the skipped duty, failure and balance counters, in the Prometheus text format (e.g., for the node exporter's textfile collector).")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trace-file")
+                .long("trace-file")
+                .value_name("FILE")
+                .help("Append a span for the duties fetch and each block, attestation and aggregate produced in each slot to FILE, as lines of JSON in the form of OpenTelemetry spans (e.g., for a collector's file receiver). Spans of the same slot share a trace id.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-sink-file")
+                .long("message-sink-file")
+                .value_name("FILE")
+                .help("File to which each produced block and attestation is appended as a line of JSON containing its SSZ. Messages are dropped rather than delay duties.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-sink-nats")
+                .long("message-sink-nats")
+                .value_name("HOST:PORT")
+                .help("NATS server to which each produced block and attestation is published as JSON containing its SSZ. Messages are dropped rather than delay duties.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-sink-subject")
+                .long("message-sink-subject")
+                .value_name("SUBJECT")
+                .help("The NATS subject to which produced messages are published. Defaults to lighthouse.validator.produced.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-sink-max-bytes")
+                .long("message-sink-max-bytes")
+                .value_name("BYTES")
+                .help("The total size of the produced messages which may wait to be sent to the message sink. Once exceeded, the oldest are dropped. Defaults to 16 MiB.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("duties-cache")
+                .long("duties-cache")
+                .value_name("FILE")
+                .help("File in which known duties are cached, so that they survive a restart.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("block-failure-alert-threshold")
+                .long("block-failure-alert-threshold")
+                .value_name("COUNT")
+                .help("Log a critical alert after this many consecutive failed block proposals. Zero disables the alert.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slot-processing-delay")
+                .long("slot-processing-delay")
+                .value_name("MILLISECONDS")
+                .help("The time to wait after the start of each slot before performing duties. Must be shorter than a slot.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-publish-retries")
+                .long("max-publish-retries")
+                .value_name("COUNT")
+                .help("The number of times publishing a block or attestation is retried if the beacon node fails.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("duties-concurrency")
+                .long("duties-concurrency")
+                .value_name("REQUESTS")
+                .help("The maximum number of duties requests sent to the beacon node at once.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("signing-concurrency")
+                .long("signing-concurrency")
+                .value_name("DUTIES")
+                .help("The maximum number of blocks and attestations produced at once. Further duties are queued.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("graffiti")
+                .long("graffiti")
+                .value_name("GRAFFITI")
+                .help("A string included in proposed blocks, truncated to 32 bytes.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("slot-summary")
+                .long("slot-summary")
+                .value_name("LEVEL")
+                .help("The log level of the one-line summary of each processed slot.")
+                .takes_value(true)
+                .possible_values(&["off", "info", "debug"])
+                .default_value("info"),
+        )
+        .arg(
+            Arg::with_name("shadow-fork")
+                .long("shadow-fork")
+                .value_name("VERSION@EPOCH")
+                .help("An additional fork, unknown to the spec, used when signing from EPOCH onwards. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("remote-signer")
+                .long("remote-signer")
+                .value_name("PUBKEY@URL")
+                .help("Delegate signing for the validator with PUBKEY to the remote signer at URL. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("remote-signer-fallback")
+                .long("remote-signer-fallback")
+                .value_name("PUBKEY")
+                .help("Sign with the local key of the validator with PUBKEY whenever its remote signer fails. Slashing protection still applies, but the key is then usable from two places. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("api-token")
+                .long("api-token")
+                .value_name("TOKEN")
+                .help("Send TOKEN to the beacon nodes with every request, as an authorization bearer token.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-ca-file")
+                .long("tls-ca-file")
+                .value_name("FILE")
+                .help("Connect to the beacon nodes over TLS, trusting only the PEM-encoded certificate authority in FILE.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .value_name("MILLISECONDS")
+                .help("The time to wait for the beacon node to respond to each request.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("remote-signer-timeout")
+                .long("remote-signer-timeout")
+                .value_name("MILLISECONDS")
+                .help("The time to wait for a remote signer to respond.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("remote-signer-validator-timeout")
+                .long("remote-signer-validator-timeout")
+                .value_name("PUBKEY=MILLISECONDS")
+                .help("The time to wait for the remote signer of the validator with the 0x-prefixed public key, in place of --remote-signer-timeout. Clamped to the minimum and maximum remote signer timeouts. May be repeated.")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("min-remote-signer-timeout")
+                .long("min-remote-signer-timeout")
+                .value_name("MILLISECONDS")
+                .help("The least time a validator's own remote signer timeout may be. Defaults to 100.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-remote-signer-timeout")
+                .long("max-remote-signer-timeout")
+                .value_name("MILLISECONDS")
+                .help("The greatest time a validator's own remote signer timeout may be. Defaults to 6000.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("audit-log")
+                .long("audit-log")
+                .value_name("FILE")
+                .help("Record every beacon node request and response (as hashes) in this file.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("audit-log-max-bytes")
+                .long("audit-log-max-bytes")
+                .value_name("BYTES")
+                .help("The size at which the audit log is rotated.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export-schedule")
+                .long("export-schedule")
+                .value_name("EPOCHS")
+                .help("Print the duties of all validators for this many epochs as JSON, then exit.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import-slashing-protection")
+                .long("import-slashing-protection")
+                .value_name("FILE")
+                .help("Merge an EIP-3076 slashing protection interchange file into the database, then exit.")
+                .takes_value(true)
+                .requires("genesis-validators-root"),
+        )
+        .arg(
+            Arg::with_name("export-slashing-protection")
+                .long("export-slashing-protection")
+                .value_name("FILE")
+                .help("Write the slashing protection database to an EIP-3076 interchange file, then exit.")
+                .takes_value(true)
+                .requires("genesis-validators-root"),
+        )
+        .arg(
+            Arg::with_name("genesis-validators-root")
+                .long("genesis-validators-root")
+                .value_name("HASH")
+                .help("The genesis validators root of the chain, as recorded in interchange files.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("worker-threads")
+                .long("worker-threads")
+                .value_name("COUNT")
+                .help("The number of worker threads used to process duties. Defaults to one per core.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cpu-affinity")
+                .long("cpu-affinity")
+                .value_name("CORES")
+                .help("Comma-separated list of CPU cores to which worker threads are pinned (Linux only).")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("enable-doppelganger-protection")
+                .long("enable-doppelganger-protection")
+                .help("Delay signing for a few epochs whilst checking that no other instance is signing with the same keys.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("balance-warnings")
+                .long("balance-warnings")
+                .help("Check validator balances each epoch and warn when one decreases.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("log-rewards")
+                .long("log-rewards")
+                .help("Check validator balances each epoch, logging each change as a reward or penalty and exporting the totals as metrics.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("max-clock-skew")
+                .long("max-clock-skew")
+                .value_name("MILLISECONDS")
+                .help("The greatest difference between the local time and the beacon node's time accepted at startup.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("clock-skew-action")
+                .long("clock-skew-action")
+                .value_name("ACTION")
+                .help("Whether to warn or refuse to start if the local clock differs from the beacon node's by more than --max-clock-skew.")
+                .takes_value(true)
+                .possible_values(&["warn", "reject"])
+                .default_value("warn"),
+        )
+        .arg(
+            Arg::with_name("node-clock-ahead")
+                .long("node-clock-ahead")
+                .value_name("ACTION")
+                .help("Whether to use the beacon node's slot or halt duties when the beacon node's clock is a slot or more ahead of the local clock.")
+                .takes_value(true)
+                .possible_values(&["trust", "halt"])
+                .default_value("halt"),
+        )
+        .arg(
+            Arg::with_name("attestation-freshness-tolerance")
+                .long("attestation-freshness-tolerance")
+                .value_name("SLOTS")
+                .help("Re-fetch attestation data once if the beacon node's head advances by more than SLOTS whilst it is fetched.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("syncing-distance")
+                .long("syncing-distance")
+                .value_name("SLOTS")
+                .help("Consider the beacon node to be syncing if its head is more than SLOTS behind the current slot, and do not accept that validators have no duties until it has caught up. Defaults to 16.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("attest-while-syncing")
+                .long("attest-while-syncing")
+                .help("Attest to the beacon node's head whilst it is syncing, rather than withholding attestations until it has caught up.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .help("Perform duties against the beacon node, but log blocks and attestations rather than signing and publishing them.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("no-selection-proof-cache")
+                .long("no-selection-proof-cache")
+                .help("Sign each aggregation selection proof whenever it is needed, rather than once per slot.")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::with_name("aggregation-time")
+                .long("aggregation-time")
+                .value_name("FRACTION")
+                .help("The point in each slot at which aggregates are produced, as a fraction of the slot such as 3/4. Must be later than a third of the slot. Defaults to 2/3.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("debug-level")
                 .long("debug-level")
@@ -191,20 +598,89 @@ fn main() {
         "datadir" => client_config.full_data_dir().expect("Unable to find datadir").to_str(),
     );
 
+    if let Some(epochs) = matches.value_of("export-schedule") {
+        let epochs = match epochs.parse::<u64>() {
+            Ok(epochs) => epochs,
+            Err(e) => {
+                crit!(log, "Unable to parse export schedule epochs"; "error" => e.to_string());
+                return;
+            }
+        };
+
+        let result = match eth2_config.spec_constants.as_str() {
+            "mainnet" => {
+                ValidatorService::<ValidatorGrpcClient, Keypair, MainnetEthSpec>::export_schedule(
+                    client_config,
+                    eth2_config,
+                    epochs,
+                    log.clone(),
+                )
+            }
+            "minimal" => {
+                ValidatorService::<ValidatorGrpcClient, Keypair, MinimalEthSpec>::export_schedule(
+                    client_config,
+                    eth2_config,
+                    epochs,
+                    log.clone(),
+                )
+            }
+            "interop" => {
+                ValidatorService::<ValidatorGrpcClient, Keypair, InteropEthSpec>::export_schedule(
+                    client_config,
+                    eth2_config,
+                    epochs,
+                    log.clone(),
+                )
+            }
+            other => {
+                crit!(log, "Unknown spec constants"; "title" => other);
+                return;
+            }
+        };
+
+        match result {
+            Ok(schedule) => println!("{}", schedule),
+            Err(e) => crit!(log, "Unable to export schedule"; "error" => e.to_string()),
+        }
+        return;
+    }
+
+    if matches.is_present("import-slashing-protection")
+        || matches.is_present("export-slashing-protection")
+    {
+        match run_interchange(&matches, &client_config, &log) {
+            Ok(()) => info!(log, "Slashing protection interchange complete"),
+            Err(e) => crit!(log, "Slashing protection interchange failed"; "error" => e),
+        }
+        return;
+    }
+
+    // forward produced blocks and attestations, if requested
+    let sink_observer = match sink_observer(&client_config, &log) {
+        Ok(sink_observer) => sink_observer,
+        Err(e) => {
+            crit!(log, "Unable to open message sink"; "error" => e);
+            return;
+        }
+    };
+
     let result = match eth2_config.spec_constants.as_str() {
-        "mainnet" => ValidatorService::<ValidatorServiceClient, Keypair, MainnetEthSpec>::start(
+        "mainnet" => ValidatorService::<ValidatorGrpcClient, Keypair, MainnetEthSpec>::start(
             client_config,
             eth2_config,
+            production_observer(&sink_observer),
             log.clone(),
         ),
-        "minimal" => ValidatorService::<ValidatorServiceClient, Keypair, MinimalEthSpec>::start(
+        "minimal" => ValidatorService::<ValidatorGrpcClient, Keypair, MinimalEthSpec>::start(
             client_config,
             eth2_config,
+            production_observer(&sink_observer),
             log.clone(),
         ),
-        "interop" => ValidatorService::<ValidatorServiceClient, Keypair, InteropEthSpec>::start(
+        "interop" => ValidatorService::<ValidatorGrpcClient, Keypair, InteropEthSpec>::start(
             client_config,
             eth2_config,
+            production_observer(&sink_observer),
             log.clone(),
         ),
         other => {
@@ -221,6 +697,72 @@ fn main() {
     }
 }
 
+/// Returns an observer which forwards each produced block and attestation to the configured
+/// message sink, if any.
+fn sink_observer(client_config: &ClientConfig, log: &Logger) -> Result<Option<Arc<SinkObserver>>> {
+    let observer = match (
+        &client_config.message_sink_file,
+        &client_config.message_sink_nats,
+    ) {
+        (Some(path), _) => SinkObserver::new(
+            FileSink::open(path)?,
+            MESSAGE_SINK_CAPACITY,
+            client_config.message_sink_max_bytes,
+            log.clone(),
+        ),
+        (None, Some(address)) => SinkObserver::new(
+            NatsSink::connect(address, &client_config.message_sink_subject)?,
+            MESSAGE_SINK_CAPACITY,
+            client_config.message_sink_max_bytes,
+            log.clone(),
+        ),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Arc::new(observer)))
+}
+
+/// Returns the observer notified of each produced block and attestation.
+fn production_observer<E: EthSpec>(
+    sink_observer: &Option<Arc<SinkObserver>>,
+) -> Arc<dyn ProductionObserver<E>> {
+    match sink_observer {
+        Some(sink_observer) => sink_observer.clone(),
+        None => Arc::new(NoOpObserver),
+    }
+}
+
+/// Imports and/or exports the slashing protection database, as requested on the CLI.
+fn run_interchange(
+    cli_args: &ArgMatches,
+    client_config: &ClientConfig,
+    log: &Logger,
+) -> Result<()> {
+    let genesis_validators_root = cli_args
+        .value_of("genesis-validators-root")
+        .ok_or("The genesis validators root is required")?
+        .trim_start_matches("0x")
+        .parse::<Hash256>()
+        .map_err(|e| format!("Unable to parse genesis validators root: {:?}", e))?;
+    let db = SlashingDatabase::open(client_config, log.clone())?;
+
+    if let Some(path) = cli_args.value_of("import-slashing-protection") {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read interchange file: {:?}", e))?;
+        interchange::import(&db, &json, genesis_validators_root)
+            .map_err(|e| format!("Unable to import interchange file: {:?}", e))?;
+    }
+
+    if let Some(path) = cli_args.value_of("export-slashing-protection") {
+        let exported = interchange::export(&db, genesis_validators_root)
+            .map_err(|e| format!("Unable to export interchange file: {:?}", e))?;
+        let json = serde_json::to_string_pretty(&exported)
+            .map_err(|e| format!("Unable to serialize interchange file: {:?}", e))?;
+        fs::write(path, json).map_err(|e| format!("Unable to write interchange file: {:?}", e))?;
+    }
+
+    Ok(())
+}
+
 /// Parses the CLI arguments and attempts to load the client and eth2 configuration.
 ///
 /// This is not a pure function, it reads from disk and may contact network servers.
@@ -0,0 +1,649 @@
+use crate::observer::ProductionObserver;
+use serde_derive::{Deserialize, Serialize};
+use slog::warn;
+use ssz::Encode;
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use types::{Attestation, BeaconBlock, EthSpec, PublicKey};
+
+/// The number of produced messages which may wait to be sent before further messages are dropped.
+pub const MESSAGE_SINK_CAPACITY: usize = 1024;
+
+/// The default total size of the messages which may wait to be sent, in bytes.
+pub const DEFAULT_MESSAGE_SINK_MAX_BYTES: usize = 16 * 1024 * 1024;
+
+/// The time to wait for the NATS server when connecting or publishing.
+const NATS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A block or attestation which was produced by one of the validators.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ProducedMessage {
+    Block {
+        validator: String,
+        /// The SSZ of the signed block, as hex.
+        ssz: String,
+    },
+    Attestation {
+        validator: String,
+        /// The SSZ of the signed attestation, as hex.
+        ssz: String,
+    },
+}
+
+/// A destination for produced messages, such as a message queue topic.
+///
+/// Messages are sent from a single thread in the order they were produced, so a slow sink
+/// delays only the messages behind it, never the validator's duties.
+pub trait MessageSink: Send + 'static {
+    fn send(&mut self, message: &ProducedMessage) -> Result<(), String>;
+}
+
+/// Appends each message to a file as a line of JSON, from which an external process may forward
+/// it to a message queue.
+pub struct FileSink {
+    file: File,
+}
+
+impl FileSink {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Unable to open message sink file: {:?}", e))?;
+        Ok(Self { file })
+    }
+}
+
+impl MessageSink for FileSink {
+    fn send(&mut self, message: &ProducedMessage) -> Result<(), String> {
+        let mut line = serde_json::to_string(message)
+            .map_err(|e| format!("Unable to serialize message: {:?}", e))?;
+        line.push('\n');
+
+        self.file
+            .write_all(line.as_bytes())
+            .map_err(|e| format!("Unable to write message: {:?}", e))
+    }
+}
+
+/// Publishes each message as JSON to a NATS subject, using the NATS text protocol.
+///
+/// If a publish fails, e.g. because the server closed the connection, the sink reconnects once and
+/// retries.
+pub struct NatsSink {
+    address: String,
+    subject: String,
+    stream: TcpStream,
+    /// Bytes received from the server which do not yet form a complete line.
+    received: Vec<u8>,
+}
+
+impl NatsSink {
+    pub fn connect(address: &str, subject: &str) -> Result<Self, String> {
+        if subject.is_empty() || subject.contains(char::is_whitespace) {
+            return Err(format!("Invalid NATS subject: {:?}", subject));
+        }
+        Ok(Self {
+            address: address.to_string(),
+            subject: subject.to_string(),
+            stream: nats_connect(address)?,
+            received: vec![],
+        })
+    }
+
+    /// Replies to each PING the server has sent, so that it does not close the connection as
+    /// stale. Fails if the server reported an error or closed the connection.
+    fn answer_pings(&mut self) -> Result<(), String> {
+        let mut buf = [0; 512];
+        self.stream
+            .set_nonblocking(true)
+            .map_err(|e| format!("Unable to poll NATS connection: {:?}", e))?;
+        let read = loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => break Err("NATS server closed the connection".to_string()),
+                Ok(n) => self.received.extend_from_slice(&buf[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(format!("Unable to read from NATS server: {:?}", e)),
+            }
+        };
+        self.stream
+            .set_nonblocking(false)
+            .map_err(|e| format!("Unable to poll NATS connection: {:?}", e))?;
+        read?;
+
+        while let Some(end) = self.received.windows(2).position(|w| w == b"\r\n") {
+            let line: Vec<u8> = self.received.drain(..end + 2).collect();
+            if line.starts_with(b"PING") {
+                self.stream
+                    .write_all(b"PONG\r\n")
+                    .map_err(|e| format!("Unable to write to NATS server: {:?}", e))?;
+            } else if line.starts_with(b"-ERR") {
+                return Err(format!(
+                    "NATS server error: {}",
+                    String::from_utf8_lossy(&line).trim_end()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn publish(&mut self, payload: &[u8]) -> Result<(), String> {
+        self.answer_pings()?;
+        let mut frame = format!("PUB {} {}\r\n", self.subject, payload.len()).into_bytes();
+        frame.extend_from_slice(payload);
+        frame.extend_from_slice(b"\r\n");
+        self.stream
+            .write_all(&frame)
+            .map_err(|e| format!("Unable to publish to NATS server: {:?}", e))
+    }
+}
+
+impl MessageSink for NatsSink {
+    fn send(&mut self, message: &ProducedMessage) -> Result<(), String> {
+        let payload = serde_json::to_vec(message)
+            .map_err(|e| format!("Unable to serialize message: {:?}", e))?;
+        if self.publish(&payload).is_ok() {
+            return Ok(());
+        }
+
+        self.stream = nats_connect(&self.address)?;
+        self.received.clear();
+        self.publish(&payload)
+    }
+}
+
+/// Connects to the NATS server at `address`, reading its INFO line and sending CONNECT.
+fn nats_connect(address: &str) -> Result<TcpStream, String> {
+    let mut stream = TcpStream::connect(address)
+        .map_err(|e| format!("Unable to connect to NATS server {}: {:?}", address, e))?;
+    stream
+        .set_read_timeout(Some(NATS_TIMEOUT))
+        .and_then(|()| stream.set_write_timeout(Some(NATS_TIMEOUT)))
+        .map_err(|e| format!("Unable to configure NATS connection: {:?}", e))?;
+
+    // read a byte at a time, so that nothing after the INFO line is consumed
+    let mut info = vec![];
+    let mut byte = [0; 1];
+    while !info.ends_with(b"\r\n") {
+        stream
+            .read_exact(&mut byte)
+            .map_err(|e| format!("Unable to read NATS server info: {:?}", e))?;
+        info.push(byte[0]);
+    }
+    if !info.starts_with(b"INFO") {
+        return Err(format!(
+            "Unexpected response from NATS server: {}",
+            String::from_utf8_lossy(&info).trim_end()
+        ));
+    }
+
+    stream
+        .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false}\r\n")
+        .map_err(|e| format!("Unable to write to NATS server: {:?}", e))?;
+    Ok(stream)
+}
+
+/// A `ProductionObserver` which SSZ-encodes each produced block and attestation and queues it to
+/// be sent to a `MessageSink`.
+///
+/// Delivery is best-effort: once `capacity` messages are waiting, further messages are dropped
+/// rather than delaying the producer. Once the waiting messages would exceed `max_bytes`, the
+/// oldest are dropped to make room, bounding the memory held whilst the sink is slow.
+pub struct SinkObserver {
+    queue: Arc<MessageQueue>,
+    /// The number of messages dropped because the queue was full.
+    dropped: AtomicU64,
+    log: slog::Logger,
+}
+
+impl SinkObserver {
+    /// Starts a thread which sends queued messages to `sink`. The thread stops once the observer
+    /// is dropped.
+    pub fn new<S: MessageSink>(
+        sink: S,
+        capacity: usize,
+        max_bytes: usize,
+        log: slog::Logger,
+    ) -> Self {
+        let queue = Arc::new(MessageQueue::new(capacity, max_bytes));
+
+        let sink_log = log.clone();
+        let sink_queue = queue.clone();
+        std::thread::Builder::new()
+            .name("message-sink".into())
+            .spawn(move || run_sink(sink, &sink_queue, &sink_log))
+            .expect("should spawn message sink thread");
+
+        Self {
+            queue,
+            dropped: AtomicU64::new(0),
+            log,
+        }
+    }
+
+    /// Returns the number of messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Returns the size of the messages waiting to be sent, in bytes.
+    pub fn queued_bytes(&self) -> u64 {
+        self.queue.bytes() as u64
+    }
+
+    fn enqueue(&self, message: ProducedMessage) {
+        let record_dropped = |count: u64| self.dropped.fetch_add(count, Ordering::SeqCst) + count;
+        match self.queue.push(message) {
+            Enqueued::Queued { evicted: 0 } => {}
+            Enqueued::Queued { evicted } => {
+                let dropped = record_dropped(evicted);
+                warn!(
+                    self.log,
+                    "Message sink over its size limit, dropping the oldest messages";
+                    "evicted" => evicted,
+                    "dropped" => dropped,
+                );
+            }
+            Enqueued::Full => {
+                let dropped = record_dropped(1);
+                warn!(self.log, "Message sink full, dropping message"; "dropped" => dropped);
+            }
+            Enqueued::TooLarge => {
+                let dropped = record_dropped(1);
+                warn!(self.log, "Message larger than the message sink size limit, dropping it"; "dropped" => dropped);
+            }
+            Enqueued::Closed => {
+                let dropped = record_dropped(1);
+                warn!(self.log, "Message sink stopped, dropping message"; "dropped" => dropped);
+            }
+        }
+    }
+}
+
+impl Drop for SinkObserver {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+impl<E: EthSpec> ProductionObserver<E> for SinkObserver {
+    fn on_block_produced(&self, validator: &PublicKey, block: &BeaconBlock<E>) {
+        self.enqueue(ProducedMessage::Block {
+            validator: validator.as_hex_string(),
+            ssz: format!("0x{}", hex::encode(block.as_ssz_bytes())),
+        });
+    }
+
+    fn on_attestation_produced(&self, validator: &PublicKey, attestation: &Attestation<E>) {
+        self.enqueue(ProducedMessage::Attestation {
+            validator: validator.as_hex_string(),
+            ssz: format!("0x{}", hex::encode(attestation.as_ssz_bytes())),
+        });
+    }
+
+    fn dropped_messages(&self) -> u64 {
+        self.dropped()
+    }
+
+    fn queued_message_bytes(&self) -> u64 {
+        self.queued_bytes()
+    }
+}
+
+/// The outcome of queueing a message.
+#[derive(Debug, PartialEq)]
+enum Enqueued {
+    /// The message was queued after evicting this many of the oldest messages.
+    Queued { evicted: u64 },
+    /// The queue holds its capacity of messages.
+    Full,
+    /// The message alone exceeds the queue's size limit.
+    TooLarge,
+    /// The sink thread has stopped.
+    Closed,
+}
+
+/// The messages waiting to be sent, bounded in number and total size.
+struct MessageQueue {
+    state: Mutex<QueueState>,
+    available: Condvar,
+    capacity: usize,
+    max_bytes: usize,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<ProducedMessage>,
+    /// The total size of `messages`.
+    bytes: usize,
+    closed: bool,
+}
+
+/// The bytes occupied by `message` whilst queued, excluding fixed overheads.
+fn message_size(message: &ProducedMessage) -> usize {
+    match message {
+        ProducedMessage::Block { validator, ssz }
+        | ProducedMessage::Attestation { validator, ssz } => validator.len() + ssz.len(),
+    }
+}
+
+impl MessageQueue {
+    fn new(capacity: usize, max_bytes: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            available: Condvar::new(),
+            capacity,
+            max_bytes,
+        }
+    }
+
+    fn push(&self, message: ProducedMessage) -> Enqueued {
+        let size = message_size(&message);
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Enqueued::Closed,
+        };
+        if state.closed {
+            return Enqueued::Closed;
+        }
+        if state.messages.len() >= self.capacity {
+            return Enqueued::Full;
+        }
+        if size > self.max_bytes {
+            return Enqueued::TooLarge;
+        }
+
+        let mut evicted = 0;
+        while state.bytes + size > self.max_bytes {
+            match state.messages.pop_front() {
+                Some(oldest) => {
+                    state.bytes -= message_size(&oldest);
+                    evicted += 1;
+                }
+                None => break,
+            }
+        }
+        state.bytes += size;
+        state.messages.push_back(message);
+        self.available.notify_one();
+        Enqueued::Queued { evicted }
+    }
+
+    /// Waits for the oldest message, returning `None` once the queue is closed and empty.
+    fn pop(&self) -> Option<ProducedMessage> {
+        let mut state = self.state.lock().ok()?;
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                state.bytes -= message_size(&message);
+                return Some(message);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.available.wait(state).ok()?;
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        self.state.lock().map(|state| state.bytes).unwrap_or(0)
+    }
+
+    /// Refuses further messages. The sink thread stops once the queued messages are sent.
+    fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+        }
+        self.available.notify_all();
+    }
+}
+
+/// Closes the queue when the sink thread ends, even if the sink panics.
+struct CloseOnExit<'a>(&'a MessageQueue);
+
+impl<'a> Drop for CloseOnExit<'a> {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Sends messages from `queue` to `sink` until the observer is dropped.
+fn run_sink<S: MessageSink>(mut sink: S, queue: &MessageQueue, log: &slog::Logger) {
+    let _close = CloseOnExit(queue);
+    while let Some(message) = queue.pop() {
+        if let Err(e) = sink.send(&message) {
+            warn!(log, "Unable to send produced message"; "error" => e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::mpsc::{channel, Sender};
+    use std::time::Duration;
+    use types::{
+        AggregateSignature, AttestationData, BitList, ChainSpec, Checkpoint, Crosslink, Hash256,
+        Keypair, MinimalEthSpec,
+    };
+
+    type E = MinimalEthSpec;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Forwards each message to a channel, once `release` is unlocked.
+    struct MockSink {
+        sent: Sender<ProducedMessage>,
+        release: Arc<Mutex<()>>,
+    }
+
+    impl MessageSink for MockSink {
+        fn send(&mut self, message: &ProducedMessage) -> Result<(), String> {
+            let _release = self.release.lock().unwrap();
+            self.sent.send(message.clone()).map_err(|e| e.to_string())
+        }
+    }
+
+    fn attestation() -> Attestation<E> {
+        Attestation {
+            aggregation_bits: BitList::with_capacity(1).unwrap(),
+            data: AttestationData {
+                beacon_block_root: Hash256::zero(),
+                source: Checkpoint::default(),
+                target: Checkpoint::default(),
+                crosslink: Crosslink::default(),
+            },
+            custody_bits: BitList::with_capacity(1).unwrap(),
+            signature: AggregateSignature::new(),
+        }
+    }
+
+    #[test]
+    fn produced_messages_sent_to_sink() {
+        let (sent, received) = channel();
+        let sink = MockSink {
+            sent,
+            release: <_>::default(),
+        };
+        let observer = SinkObserver::new(
+            sink,
+            MESSAGE_SINK_CAPACITY,
+            DEFAULT_MESSAGE_SINK_MAX_BYTES,
+            null_logger(),
+        );
+        let validator = Keypair::random().pk;
+        let block = BeaconBlock::<E>::empty(&ChainSpec::minimal());
+        let attestation = attestation();
+
+        ProductionObserver::<E>::on_block_produced(&observer, &validator, &block);
+        ProductionObserver::<E>::on_attestation_produced(&observer, &validator, &attestation);
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            ProducedMessage::Block {
+                validator: validator.as_hex_string(),
+                ssz: format!("0x{}", hex::encode(block.as_ssz_bytes())),
+            }
+        );
+        assert_eq!(
+            received.recv_timeout(timeout).unwrap(),
+            ProducedMessage::Attestation {
+                validator: validator.as_hex_string(),
+                ssz: format!("0x{}", hex::encode(attestation.as_ssz_bytes())),
+            }
+        );
+        assert_eq!(observer.dropped(), 0);
+    }
+
+    #[test]
+    fn messages_published_to_nats_subject() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"INFO {}\r\nPING\r\n").unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut lines = vec![];
+            for _ in 0..4 {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                lines.push(line.trim_end().to_string());
+            }
+            lines
+        });
+
+        let mut sink = NatsSink::connect(&address, "validator.produced").unwrap();
+        let message = ProducedMessage::Block {
+            validator: "0x00".into(),
+            ssz: "0x01".into(),
+        };
+        sink.send(&message).unwrap();
+
+        let lines = server.join().unwrap();
+        let payload = serde_json::to_string(&message).unwrap();
+        assert!(lines[0].starts_with("CONNECT "));
+        assert_eq!(lines[1], "PONG");
+        assert_eq!(
+            lines[2],
+            format!("PUB validator.produced {}", payload.len())
+        );
+        assert_eq!(lines[3], payload);
+
+        assert!(NatsSink::connect(&address, "two words").is_err());
+    }
+
+    #[test]
+    fn messages_dropped_rather_than_blocking_when_full() {
+        const CAPACITY: usize = 2;
+
+        let (sent, received) = channel();
+        let release = Arc::new(Mutex::new(()));
+        let sink = MockSink {
+            sent,
+            release: release.clone(),
+        };
+        // The sink cannot send until released, so the queue fills.
+        let held = release.lock().unwrap();
+        let observer = SinkObserver::new(
+            sink,
+            CAPACITY,
+            DEFAULT_MESSAGE_SINK_MAX_BYTES,
+            null_logger(),
+        );
+        let validator = Keypair::random().pk;
+        let block = BeaconBlock::<E>::empty(&ChainSpec::minimal());
+
+        // The first message is taken by the sink thread, then `CAPACITY` more are queued. Each
+        // call returns without waiting for the sink.
+        ProductionObserver::<E>::on_block_produced(&observer, &validator, &block);
+        std::thread::sleep(Duration::from_millis(100));
+        for _ in 0..CAPACITY + 2 {
+            ProductionObserver::<E>::on_block_produced(&observer, &validator, &block);
+        }
+        drop(held);
+
+        let delivered = (0..)
+            .map(|_| received.recv_timeout(Duration::from_millis(500)))
+            .take_while(Result::is_ok)
+            .count();
+        assert_eq!(delivered, CAPACITY + 1);
+        assert_eq!(observer.dropped(), 2);
+        assert_eq!(ProductionObserver::<E>::dropped_messages(&observer), 2);
+    }
+
+    #[test]
+    fn oldest_messages_evicted_beyond_max_bytes() {
+        let (sent, received) = channel();
+        let release = Arc::new(Mutex::new(()));
+        let sink = MockSink {
+            sent,
+            release: release.clone(),
+        };
+        let block = BeaconBlock::<E>::empty(&ChainSpec::minimal());
+        let message = |validator: &str| ProducedMessage::Block {
+            validator: validator.to_string(),
+            ssz: format!("0x{}", hex::encode(block.as_ssz_bytes())),
+        };
+        let validators: Vec<_> = (0..5).map(|_| Keypair::random().pk).collect();
+        let size = message_size(&message(&validators[0].as_hex_string()));
+
+        // Room for two and a half messages.
+        let held = release.lock().unwrap();
+        let observer = SinkObserver::new(sink, MESSAGE_SINK_CAPACITY, size * 5 / 2, null_logger());
+
+        // The first message is taken by the sink thread, then each message beyond the second
+        // evicts the oldest.
+        ProductionObserver::<E>::on_block_produced(&observer, &validators[0], &block);
+        std::thread::sleep(Duration::from_millis(100));
+        for validator in &validators[1..] {
+            ProductionObserver::<E>::on_block_produced(&observer, validator, &block);
+        }
+        assert_eq!(observer.queued_bytes(), 2 * size as u64);
+        assert_eq!(observer.dropped(), 2);
+        drop(held);
+
+        let delivered: Vec<_> = (0..)
+            .map(|_| received.recv_timeout(Duration::from_millis(500)))
+            .take_while(Result::is_ok)
+            .map(Result::unwrap)
+            .collect();
+        let expected: Vec<_> = [0, 3, 4]
+            .iter()
+            .map(|&i| message(&validators[i].as_hex_string()))
+            .collect();
+        assert_eq!(delivered, expected);
+        assert_eq!(observer.queued_bytes(), 0);
+        assert_eq!(ProductionObserver::<E>::queued_message_bytes(&observer), 0);
+    }
+
+    #[test]
+    fn message_larger_than_max_bytes_dropped() {
+        let queue = MessageQueue::new(MESSAGE_SINK_CAPACITY, 8);
+        let message = |ssz: &str| ProducedMessage::Attestation {
+            validator: "0x00".into(),
+            ssz: ssz.into(),
+        };
+
+        assert_eq!(queue.push(message("0x01")), Enqueued::Queued { evicted: 0 });
+        assert_eq!(queue.push(message("0x0102")), Enqueued::TooLarge);
+        assert_eq!(queue.bytes(), 8);
+
+        // Queued messages are still sent once the queue is closed.
+        queue.close();
+        assert_eq!(queue.push(message("0x02")), Enqueued::Closed);
+        assert_eq!(queue.pop(), Some(message("0x01")));
+        assert_eq!(queue.pop(), None);
+    }
+}
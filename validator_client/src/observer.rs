@@ -0,0 +1,32 @@
+use types::{Attestation, BeaconBlock, EthSpec, PublicKey, Slot};
+
+/// Receives notifications from the block and attestation producers.
+///
+/// Implement this trait to add custom behaviour (e.g., alerting or accounting) when a validator
+/// performs its duties. All methods default to doing nothing and are called from the producer
+/// threads, so implementations should return quickly.
+pub trait ProductionObserver<E: EthSpec>: Send + Sync {
+    /// Called once a block has been signed and published by `validator`.
+    fn on_block_produced(&self, _validator: &PublicKey, _block: &BeaconBlock<E>) {}
+
+    /// Called once an attestation has been signed and published by `validator`.
+    fn on_attestation_produced(&self, _validator: &PublicKey, _attestation: &Attestation<E>) {}
+
+    /// Called when the signer for `validator` refused to sign a message for `slot`.
+    fn on_signing_failed(&self, _validator: &PublicKey, _slot: Slot) {}
+
+    /// Returns the number of notifications which were dropped rather than delay the producers.
+    fn dropped_messages(&self) -> u64 {
+        0
+    }
+
+    /// Returns the size of the notifications waiting to be delivered, in bytes.
+    fn queued_message_bytes(&self) -> u64 {
+        0
+    }
+}
+
+/// A `ProductionObserver` which ignores all notifications.
+pub struct NoOpObserver;
+
+impl<E: EthSpec> ProductionObserver<E> for NoOpObserver {}
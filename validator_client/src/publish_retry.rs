@@ -0,0 +1,129 @@
+use crate::block_producer::{BeaconNodeError, PublishOutcome};
+use slog::{crit, warn};
+use std::time::Duration;
+
+/// The delay before the first retry, doubled after each further failure.
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Calls `publish` until it succeeds, retrying at most `max_retries` times.
+///
+/// Only retryable errors, which indicate the beacon node could not be reached or failed to handle
+/// the request, are retried. An outcome rejecting the message, or a request refused as malformed,
+/// is returned immediately, since the same message would be rejected again. `publish` must send an already-signed message, so that
+/// retrying never signs anything new.
+pub fn publish_with_retries<F>(
+    mut publish: F,
+    max_retries: u64,
+    message: &str,
+    log: &slog::Logger,
+) -> Result<PublishOutcome, BeaconNodeError>
+where
+    F: FnMut() -> Result<PublishOutcome, BeaconNodeError>,
+{
+    let mut delay = PUBLISH_RETRY_DELAY;
+    let mut retries = 0;
+    loop {
+        match publish() {
+            Err(e) if e.is_retryable() && retries < max_retries => {
+                retries += 1;
+                warn!(
+                    log,
+                    "Retrying publication";
+                    "message" => message,
+                    "retry" => retries,
+                    "max_retries" => max_retries,
+                    "error" => format!("{:?}", e),
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            Err(e) => {
+                crit!(
+                    log,
+                    "Unable to publish";
+                    "message" => message,
+                    "attempts" => retries + 1,
+                    "error" => format!("{:?}", e),
+                );
+                return Err(e);
+            }
+            outcome => return outcome,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Returns a publish function which fails `failures` times, then returns `outcome`, along
+    /// with a count of the calls made.
+    fn flaky(
+        failures: u64,
+        outcome: PublishOutcome,
+    ) -> (
+        std::rc::Rc<std::cell::Cell<u64>>,
+        impl FnMut() -> Result<PublishOutcome, BeaconNodeError>,
+    ) {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let publish = move || {
+            counter.set(counter.get() + 1);
+            if counter.get() <= failures {
+                Err(BeaconNodeError::RemoteFailure("unavailable".into()))
+            } else {
+                Ok(outcome.clone())
+            }
+        };
+        (calls, publish)
+    }
+
+    #[test]
+    fn transient_failures_retried() {
+        let (calls, publish) = flaky(2, PublishOutcome::Valid);
+        assert_eq!(
+            publish_with_retries(publish, 3, "block", &null_logger()),
+            Ok(PublishOutcome::Valid)
+        );
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_max_retries() {
+        let (calls, publish) = flaky(5, PublishOutcome::Valid);
+        assert_eq!(
+            publish_with_retries(publish, 2, "block", &null_logger()),
+            Err(BeaconNodeError::RemoteFailure("unavailable".into()))
+        );
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn rejection_not_retried() {
+        let rejected = PublishOutcome::InvalidAttestation("invalid".into());
+        let (calls, publish) = flaky(0, rejected.clone());
+        assert_eq!(
+            publish_with_retries(publish, 3, "attestation", &null_logger()),
+            Ok(rejected)
+        );
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn bad_request_not_retried() {
+        let mut calls = 0;
+        let publish = || {
+            calls += 1;
+            Err(BeaconNodeError::BadRequest("invalid ssz".into()))
+        };
+        assert_eq!(
+            publish_with_retries(publish, 3, "block", &null_logger()),
+            Err(BeaconNodeError::BadRequest("invalid ssz".into()))
+        );
+        assert_eq!(calls, 1);
+    }
+}
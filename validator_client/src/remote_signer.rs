@@ -0,0 +1,230 @@
+use crate::signer::Signer;
+use serde_derive::{Deserialize, Serialize};
+use slog::warn;
+use std::fmt;
+use std::time::Duration;
+use types::{PublicKey, Signature};
+
+/// The body of a request to the remote signer.
+#[derive(Serialize)]
+struct SignRequest {
+    /// The `0x`-prefixed, hex-encoded message to sign.
+    message: String,
+    domain: u64,
+}
+
+/// The body of a successful response from the remote signer.
+#[derive(Deserialize)]
+struct SignResponse {
+    signature: Signature,
+}
+
+/// Signs messages using an external signing service, so that the private key is never held by the
+/// validator client.
+///
+/// Each message is POSTed as JSON to `<url>/sign/<public key>`. Any failure, including a signature
+/// which does not verify against the public key, is logged and treated as a refusal to sign.
+#[derive(Clone)]
+pub struct RemoteSigner {
+    public_key: PublicKey,
+    url: String,
+    client: reqwest::Client,
+    log: slog::Logger,
+}
+
+impl RemoteSigner {
+    /// Creates a signer for `public_key`, served at `url`. Requests which take longer than
+    /// `timeout` fail.
+    pub fn new(
+        public_key: PublicKey,
+        url: &str,
+        timeout: Duration,
+        log: slog::Logger,
+    ) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| format!("Unable to build remote signer client: {:?}", e))?;
+
+        Ok(Self {
+            public_key,
+            url: url.trim_end_matches('/').to_string(),
+            client,
+            log,
+        })
+    }
+
+    /// Requests a signature over `message` from the remote signer.
+    fn request_signature(&self, message: &[u8], domain: u64) -> Result<Signature, String> {
+        let url = format!("{}/sign/{}", self.url, self.public_key.as_hex_string());
+        let request = SignRequest {
+            message: hex_encode(message),
+            domain,
+        };
+
+        let response: SignResponse = self
+            .client
+            .post(&url)
+            .json(&request)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|mut response| response.json())
+            .map_err(|e| format!("Remote signer request failed: {:?}", e))?;
+
+        if response.signature.verify(message, domain, &self.public_key) {
+            Ok(response.signature)
+        } else {
+            Err("Remote signer returned an invalid signature".into())
+        }
+    }
+}
+
+impl fmt::Display for RemoteSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.public_key)
+    }
+}
+
+impl Signer for RemoteSigner {
+    fn sign_message(&self, message: &[u8], domain: u64) -> Option<Signature> {
+        match self.request_signature(message, domain) {
+            Ok(signature) => Some(signature),
+            Err(e) => {
+                warn!(
+                    self.log,
+                    "Remote signer failed";
+                    "validator" => format!("{}", self),
+                    "error" => e,
+                );
+                None
+            }
+        }
+    }
+
+    fn to_public(&self) -> PublicKey {
+        self.public_key.clone()
+    }
+}
+
+/// Returns `bytes` as a `0x`-prefixed hex string.
+fn hex_encode(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+    use types::Keypair;
+
+    /// Serves a single HTTP request with `status` and the body returned by `respond`, returning
+    /// the URL of the server and a handle which yields the raw request.
+    fn mock_signer<F>(status: &'static str, respond: F) -> (String, JoinHandle<String>)
+    where
+        F: FnOnce() -> String + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            // Read until the headers and a body of the advertised length have arrived.
+            loop {
+                let read = stream.read(&mut buf).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(headers_end) = text.find("\r\n\r\n") {
+                    let content_length = text[..headers_end]
+                        .lines()
+                        .filter_map(|line| {
+                            let line = line.to_lowercase();
+                            if line.starts_with("content-length:") {
+                                line["content-length:".len()..].trim().parse::<usize>().ok()
+                            } else {
+                                None
+                            }
+                        })
+                        .next()
+                        .unwrap_or(0);
+                    if request.len() >= headers_end + 4 + content_length {
+                        break;
+                    }
+                }
+            }
+            let request = String::from_utf8(request).unwrap();
+            let body = respond();
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            )
+            .unwrap();
+            request
+        });
+
+        (url, handle)
+    }
+
+    fn remote_signer(keypair: &Keypair, url: &str) -> RemoteSigner {
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        RemoteSigner::new(keypair.pk.clone(), url, Duration::from_secs(5), log).unwrap()
+    }
+
+    #[test]
+    fn signature_obtained_from_remote_signer() {
+        let keypair = Keypair::random();
+        let secret_key = keypair.sk.clone();
+        let (message, domain) = (b"message".to_vec(), 7);
+
+        let signed = message.clone();
+        let (url, server) = mock_signer("200 OK", move || {
+            let signature = Signature::new(&signed, domain, &secret_key);
+            format!("{{\"signature\": \"{}\"}}", signature_hex(&signature))
+        });
+
+        let signer = remote_signer(&keypair, &url);
+        let signature = signer.sign_message(&message, domain).unwrap();
+        assert!(signature.verify(&message, domain, &keypair.pk));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with(&format!("POST /sign/{} ", keypair.pk.as_hex_string())));
+        assert!(request.contains(&hex_encode(&message)));
+    }
+
+    #[test]
+    fn failures_treated_as_refusal_to_sign() {
+        let keypair = Keypair::random();
+
+        let (url, server) = mock_signer("500 Internal Server Error", || "{}".to_string());
+        let signer = remote_signer(&keypair, &url);
+        assert_eq!(signer.sign_message(b"message", 7), None);
+        server.join().unwrap();
+
+        // A signature by a different key is rejected.
+        let other = Keypair::random();
+        let (url, server) = mock_signer("200 OK", move || {
+            let signature = Signature::new(b"message", 7, &other.sk);
+            format!("{{\"signature\": \"{}\"}}", signature_hex(&signature))
+        });
+        let signer = remote_signer(&keypair, &url);
+        assert_eq!(signer.sign_message(b"message", 7), None);
+        server.join().unwrap();
+    }
+
+    fn signature_hex(signature: &Signature) -> String {
+        serde_json::to_value(signature)
+            .unwrap()
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+}
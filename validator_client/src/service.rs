@@ -8,110 +8,268 @@
 /// When a validator needs to either produce a block or sign an attestation, it requests the
 /// data from the beacon node and performs the signing before publishing the block to the beacon
 /// node.
-use crate::attestation_producer::AttestationProducer;
-use crate::block_producer::{BeaconBlockGrpcClient, BlockProducer};
-use crate::config::Config as ValidatorConfig;
-use crate::duties::{BeaconNodeDuties, DutiesManager, EpochDutiesMap};
+use crate::attestation_producer::{
+    AggregationProducer, AttestationGrpcClient, AttestationProducer, AttestedHeads,
+    SelectionProofCache,
+};
+use crate::audit_log::{audited, AuditLog, RequestOptions};
+use crate::block_producer::{BeaconBlockGrpcClient, BlockFailureTracker, BlockProducer};
+use crate::config::{
+    read_fallback_servers, ClockSkewAction, Config as ValidatorConfig, NodeClockAheadAction,
+    SlotFraction, SlotSummaryLevel, ATTESTATION_TIME,
+};
+use crate::doppelganger::DOPPELGANGER_DETECTION_EPOCHS;
+use crate::duties::{
+    load_duties, BeaconNodeDuties, DutiesManager, EpochDutiesMap, RewardTotals,
+    ValidatorGrpcClient, WorkInfo, DUTIES_BATCH_SIZE,
+};
 use crate::error as error_chain;
-use crate::signer::Signer;
-use bls::Keypair;
+use crate::fallback::BeaconNodeFallback;
+use crate::fork_schedule::ForkSchedule;
+use crate::key_lock::KeyLock;
+use crate::observer::{NoOpObserver, ProductionObserver};
+use crate::remote_signer::RemoteSigner;
+use crate::signer::{Signer, ValidatorSigner};
+use crate::signing_pool::SigningPool;
+use crate::skip_reason::{SkipCounters, SkipReason};
+use crate::slashing_protection::SlashingDatabase;
+use crate::spans::{FileSpanExporter, Tracer};
 use eth2_config::Eth2Config;
-use grpcio::{ChannelBuilder, EnvBuilder};
-use protos::services::Empty;
+use futures::future::{self, Either, Loop};
+use futures::sync::oneshot;
+use grpcio::{
+    Channel, ChannelBuilder, ChannelCredentialsBuilder, EnvBuilder, Environment, RpcStatus,
+    RpcStatusCode,
+};
+use protos::services::{Empty, Fork as ProtoFork, NodeInfoResponse};
 use protos::services_grpc::{
     AttestationServiceClient, BeaconBlockServiceClient, BeaconNodeServiceClient,
     ValidatorServiceClient,
 };
-use slog::{crit, error, info, trace, warn};
+use slog::{crit, debug, error, info, o, trace, warn};
 use slot_clock::{SlotClock, SystemTimeSlotClock};
+use std::cell::RefCell;
+use std::fs;
 use std::marker::PhantomData;
-use std::sync::Arc;
-use std::sync::RwLock;
-use std::time::{Duration, Instant};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::prelude::*;
 use tokio::runtime::Builder;
-use tokio::timer::Interval;
+use tokio::timer::{Delay, Interval};
 use tokio_timer::clock::Clock;
-use types::{ChainSpec, Epoch, EthSpec, Fork, Slot};
+use types::{AttestationDuty, ChainSpec, Epoch, EthSpec, Fork, Slot};
+
+/// The longest time to wait for in-flight duties to complete when shutting down.
+const SHUTDOWN_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The time to wait after the first failed attempt to connect to the beacon node. The delay doubles
+/// after each subsequent failure.
+const CONNECT_INITIAL_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest time to wait between attempts to connect to the beacon node.
+const CONNECT_MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The greatest difference, in slots, between the slot clock and the slot expected from the
+/// number of slot ticks which is not reported as drift.
+const MAX_CLOCK_SKEW_SLOTS: u64 = 1;
 
-/// A fixed amount of time after a slot to perform operations. This gives the node time to complete
-/// per-slot processes.
-const TIME_DELAY_FROM_SLOT: Duration = Duration::from_millis(100);
+/// The number of slots for which the slot clock may fail to advance before the service stops.
+const MAX_STALLED_SLOTS: u32 = 8;
 
 /// The validator service. This is the main thread that executes and maintains validator
 /// duties.
+///
+/// The slot clock is generic so that tests may set the slot, rather than waiting for it.
 //TODO: Generalize the BeaconNode types to use testing
-pub struct Service<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> {
-    /// The node's current fork version we are processing on.
-    fork: Fork,
+pub struct Service<
+    B: BeaconNodeDuties + 'static,
+    S: Signer + 'static,
+    E: EthSpec,
+    T: SlotClock = SystemTimeSlotClock,
+> {
+    /// The node's current fork, extended by any configured shadow forks.
+    fork_schedule: ForkSchedule,
     /// The slot clock for this service.
-    slot_clock: SystemTimeSlotClock,
+    slot_clock: T,
+    /// The genesis time reported by the beacon node, in seconds since the UNIX epoch.
+    genesis_time: u64,
+    /// The genesis slot reported by the beacon node.
+    genesis_slot: Slot,
+    /// The time after the start of a slot to perform operations. This gives the node time to
+    /// complete per-slot processes.
+    slot_processing_delay: Duration,
     /// The slot that is currently, or was previously processed by the service.
     current_slot: Option<Slot>,
+    /// Compares the slot clock with the number of slot ticks.
+    clock_drift: ClockDrift,
+    /// When the slot clock last advanced to a new slot.
+    clock_advanced_at: Option<Instant>,
+    /// The number of slots by which the beacon node's clock was ahead of the local clock when
+    /// last compared.
+    node_clock_lead: u64,
+    /// The response to a beacon node whose clock is ahead of the local clock.
+    node_clock_ahead: NodeClockAheadAction,
+    /// The number of slots whose processing failed with a transient error.
+    slot_errors: Arc<AtomicU64>,
     slots_per_epoch: u64,
     /// The chain specification for this clients instance.
     spec: Arc<ChainSpec>,
     /// The duties manager which maintains the state of when to perform actions.
     duties_manager: Arc<DutiesManager<B, S>>,
     // GRPC Clients
-    /// The beacon block GRPC client.
-    beacon_block_client: Arc<BeaconBlockGrpcClient>,
-    /// The attester GRPC client.
-    attestation_client: Arc<AttestationServiceClient>,
+    /// The beacon node GRPC clients, used to refresh the fork.
+    beacon_node_client: Arc<BeaconNodeFallback<BeaconNodeServiceClient>>,
+    /// If set, replaces the nodes of every client when the fallback servers file changes.
+    server_reload: Option<ServerReload>,
+    /// If set, records every request made with `beacon_node_client`.
+    audit_log: Option<Arc<AuditLog>>,
+    /// The options with which each request is made using `beacon_node_client`.
+    request_options: RequestOptions,
+    /// The beacon block GRPC clients.
+    beacon_block_client: Arc<BeaconNodeFallback<BeaconBlockGrpcClient>>,
+    /// The attester GRPC clients.
+    attestation_client: Arc<BeaconNodeFallback<AttestationGrpcClient>>,
+    /// Requests and publishes aggregates. The same nodes as `attestation_client`, unless separate
+    /// aggregate servers are configured.
+    aggregation_client: Arc<BeaconNodeFallback<AttestationGrpcClient>>,
     /// The validator client logger.
     log: slog::Logger,
+    /// Notified of the outcome of block and attestation production.
+    observer: Arc<dyn ProductionObserver<E>>,
+    /// The level at which a summary of each slot is logged.
+    slot_summary: SlotSummaryLevel,
+    /// If set, a file which is re-written at the end of each successfully processed slot.
+    heartbeat_file: Option<PathBuf>,
+    /// If set, a file which is re-written with the service's counters at the end of each
+    /// successfully processed slot.
+    metrics_file: Option<PathBuf>,
+    /// Starts the spans of each slot's work, exporting them if a trace file is configured.
+    tracer: Tracer,
+    /// The graffiti included in proposed blocks.
+    graffiti: [u8; 32],
+    /// The number of times a block or attestation publication is retried.
+    max_publish_retries: u64,
+    /// If true, blocks and attestations are logged rather than signed and published.
+    dry_run: bool,
+    /// If set, the tolerance of the attestation data freshness check, in slots.
+    attestation_freshness_tolerance: Option<u64>,
+    /// If set, attestations are withheld whilst the node's head is more than this many slots
+    /// behind.
+    attestation_syncing_distance: Option<u64>,
+    /// The point in each slot at which aggregates are produced.
+    aggregation_time: SlotFraction,
+    /// Alerts when block proposals repeatedly fail.
+    block_failures: Arc<BlockFailureTracker>,
+    /// The highest head slot at which each validator has attested.
+    attested_heads: Arc<AttestedHeads>,
+    /// The aggregation selection proofs signed for each validator.
+    selection_proofs: Arc<SelectionProofCache>,
+    /// The number of producer threads which have panicked.
+    producer_panics: Arc<AtomicU64>,
+    /// Counts skipped duties by reason.
+    skip_counters: Arc<SkipCounters>,
+    /// Consulted by the producers before any message is signed.
+    slashing_protection: Arc<SlashingDatabase>,
+    /// Tracks the producers which have not yet completed.
+    in_flight: Arc<InFlightDuties>,
+    /// The threads on which producers run.
+    signing_pool: Arc<SigningPool>,
     _phantom: PhantomData<E>,
 }
 
-impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B, S, E> {
+impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec, T: SlotClock>
+    Service<B, S, E, T>
+{
     ///  Initial connection to the beacon node to determine its properties.
     ///
     ///  This tries to connect to a beacon node. Once connected, it initialised the gRPC clients
     ///  and returns an instance of the service.
+    ///
+    ///  A set of clients is created for each configured beacon node, and each request is sent
+    ///  to the healthiest node which responds.
     fn initialize_service(
         client_config: ValidatorConfig,
         eth2_config: Eth2Config,
+        observer: Arc<dyn ProductionObserver<E>>,
         log: slog::Logger,
-    ) -> error_chain::Result<Service<ValidatorServiceClient, Keypair, E>> {
-        let server_url = format!(
-            "{}:{}",
-            client_config.server, client_config.server_grpc_port
-        );
+    ) -> error_chain::Result<Service<BeaconNodeFallback<ValidatorGrpcClient>, ValidatorSigner, E>>
+    {
+        let servers = client_config.grpc_servers();
 
-        let env = Arc::new(EnvBuilder::new().build());
-        // Beacon node gRPC beacon node endpoints.
-        let beacon_node_client = {
-            let ch = ChannelBuilder::new(env.clone()).connect(&server_url);
-            BeaconNodeServiceClient::new(ch)
+        let audit_log = match &client_config.audit_log_file {
+            Some(path) => Some(Arc::new(AuditLog::open(
+                path,
+                client_config.audit_log_max_bytes,
+                log.clone(),
+            )?)),
+            None => None,
+        };
+        let request_options = RequestOptions {
+            timeout: Duration::from_millis(client_config.request_timeout_ms),
+            api_token: client_config.api_token.clone(),
         };
 
-        // retrieve node information and validate the beacon node
-        let node_info = loop {
-            match beacon_node_client.info(&Empty::new()) {
-                Err(e) => {
-                    let retry_seconds = 5;
-                    warn!(
-                        log,
-                        "Could not connect to beacon node";
-                        "error" => format!("{:?}", e),
-                        "retry_in" => format!("{} seconds", retry_seconds),
-                    );
-                    std::thread::sleep(Duration::from_secs(retry_seconds));
-                    continue;
-                }
-                Ok(info) => {
-                    // verify the node's network id
-                    if eth2_config.spec.network_id != info.network_id as u8 {
-                        error!(
-                            log,
-                            "Beacon Node's genesis time is in the future. No work to do.\n Exiting"
-                        );
-                        return Err(format!("Beacon node has the wrong chain id. Expected chain id: {}, node's chain id: {}", eth2_config.spec.network_id, info.network_id).into());
-                    }
-                    break info;
-                }
-            };
+        let connector = Connector {
+            env: Arc::new(EnvBuilder::new().build()),
+            root_cert: client_config.tls_root_cert()?,
         };
+        if let Some(path) = &client_config.tls_ca_file {
+            info!(log, "Connecting to beacon nodes over TLS"; "ca_file" => format!("{:?}", path));
+        }
+        // Beacon node gRPC beacon node endpoints.
+        let beacon_node_client = Arc::new(connect_each(
+            &servers,
+            &connector,
+            &log,
+            BeaconNodeServiceClient::new,
+        ));
+
+        // retrieve node information and validate the beacon node, before the service's runtime
+        // exists
+        let node_info = tokio::runtime::current_thread::Runtime::new()
+            .map_err(|e| format!("Unable to start the connection runtime: {:?}", e))?
+            .block_on(retry_until_connected(
+                || {
+                    request_node_info(
+                        &beacon_node_client,
+                        audit_log.as_ref().map(Arc::as_ref),
+                        &request_options,
+                    )
+                },
+                client_config.max_connect_attempts,
+                CONNECT_INITIAL_RETRY_DELAY,
+                CONNECT_MAX_RETRY_DELAY,
+                log.clone(),
+            ))?;
+
+        // verify the node's network id
+        if let Err(e) = verify_network_id(eth2_config.spec.network_id, node_info.network_id) {
+            error!(log, "Beacon node is on a different network"; "error" => &e);
+            return Err(e.into());
+        }
+
+        // a fallback node on another network would have the validators sign for the wrong chain
+        if servers.len() > 1 {
+            verify_node_networks(
+                beacon_node_client.nodes().iter(),
+                eth2_config.spec.network_id,
+                audit_log.as_ref().map(Arc::as_ref),
+                &request_options,
+                &log,
+            )?;
+        }
+
+        // a badly skewed clock guarantees missed or slashable duties
+        let local_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        if let Err(e) = verify_clock_skew(&node_info, local_time, &client_config, &log) {
+            error!(log, "Local clock differs from the beacon node's"; "error" => &e);
+            return Err(e.into());
+        }
 
         // build requisite objects to form Self
         let genesis_time = node_info.get_genesis_time();
@@ -125,39 +283,130 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
             "genesis_time" => genesis_time
         );
 
-        let proto_fork = node_info.get_fork();
-        let mut previous_version: [u8; 4] = [0; 4];
-        let mut current_version: [u8; 4] = [0; 4];
-        previous_version.copy_from_slice(&proto_fork.get_previous_version()[..4]);
-        current_version.copy_from_slice(&proto_fork.get_current_version()[..4]);
-        let fork = Fork {
-            previous_version,
-            current_version,
-            epoch: Epoch::from(proto_fork.get_epoch()),
-        };
+        let fork = fork_from_proto(node_info.get_fork())?;
+        info!(
+            log,
+            "Fork obtained from beacon node";
+            "previous_version" => format!("{:?}", fork.previous_version),
+            "current_version" => format!("{:?}", fork.current_version),
+            "epoch" => fork.epoch,
+        );
+        let fork_schedule = ForkSchedule::new(fork, client_config.shadow_forks.clone())?;
 
         // initialize the RPC clients
 
         // Beacon node gRPC beacon block endpoints.
-        let beacon_block_client = {
-            let ch = ChannelBuilder::new(env.clone()).connect(&server_url);
-            let beacon_block_service_client = Arc::new(BeaconBlockServiceClient::new(ch));
+        let beacon_block_client = Arc::new(connect_each(&servers, &connector, &log, |ch| {
             // a wrapper around the service client to implement the beacon block node trait
-            Arc::new(BeaconBlockGrpcClient::new(beacon_block_service_client))
-        };
+            BeaconBlockGrpcClient::new(
+                Arc::new(BeaconBlockServiceClient::new(ch)),
+                audit_log.clone(),
+                request_options.clone(),
+            )
+        }));
 
         // Beacon node gRPC validator endpoints.
-        let validator_client = {
-            let ch = ChannelBuilder::new(env.clone()).connect(&server_url);
-            Arc::new(ValidatorServiceClient::new(ch))
-        };
+        let validator_client = Arc::new(connect_each(&servers, &connector, &log, |ch| {
+            ValidatorGrpcClient::new(
+                ValidatorServiceClient::new(ch.clone()),
+                BeaconNodeServiceClient::new(ch),
+                audit_log.clone(),
+                request_options.clone(),
+            )
+        }));
 
         //Beacon node gRPC attester endpoints.
-        let attestation_client = {
-            let ch = ChannelBuilder::new(env.clone()).connect(&server_url);
-            Arc::new(AttestationServiceClient::new(ch))
+        let attestation_client = Arc::new(connect_each(&servers, &connector, &log, |ch| {
+            AttestationGrpcClient::new(
+                Arc::new(AttestationServiceClient::new(ch.clone())),
+                BeaconNodeServiceClient::new(ch),
+                audit_log.clone(),
+                request_options.clone(),
+            )
+        }));
+
+        // Beacon node gRPC attester endpoints to which aggregates are published.
+        let aggregation_client = if client_config.aggregate_servers.is_empty() {
+            attestation_client.clone()
+        } else {
+            info!(
+                log,
+                "Aggregates published to separate beacon nodes";
+                "servers" => client_config.aggregate_servers.join(", "),
+            );
+            Arc::new(connect_each(
+                &client_config.aggregate_servers,
+                &connector,
+                &log,
+                |ch| {
+                    AttestationGrpcClient::new(
+                        Arc::new(AttestationServiceClient::new(ch.clone())),
+                        BeaconNodeServiceClient::new(ch),
+                        audit_log.clone(),
+                        request_options.clone(),
+                    )
+                },
+            ))
         };
 
+        let server_reload = client_config.fallback_servers_file.clone().map(|path| {
+            let primary = servers[0].clone();
+            let network_id = eth2_config.spec.network_id;
+            let (connector, audit_log, log) = (connector.clone(), audit_log.clone(), log.clone());
+            let options = request_options.clone();
+            let beacon_node_client = beacon_node_client.clone();
+            let beacon_block_client = beacon_block_client.clone();
+            let validator_client = validator_client.clone();
+            let attestation_client = attestation_client.clone();
+
+            let reconnect = move |fallbacks: Vec<String>| -> Result<(), String> {
+                let servers: Vec<String> =
+                    std::iter::once(primary.clone()).chain(fallbacks).collect();
+                let clients = connect_clients(&servers, &connector, BeaconNodeServiceClient::new);
+                verify_node_networks(
+                    clients
+                        .iter()
+                        .map(|(server, client)| (server.as_str(), client)),
+                    network_id,
+                    audit_log.as_ref().map(Arc::as_ref),
+                    &options,
+                    &log,
+                )?;
+
+                beacon_node_client.replace_nodes(clients);
+                beacon_block_client.replace_nodes(connect_clients(&servers, &connector, |ch| {
+                    BeaconBlockGrpcClient::new(
+                        Arc::new(BeaconBlockServiceClient::new(ch)),
+                        audit_log.clone(),
+                        options.clone(),
+                    )
+                }));
+                validator_client.replace_nodes(connect_clients(&servers, &connector, |ch| {
+                    ValidatorGrpcClient::new(
+                        ValidatorServiceClient::new(ch.clone()),
+                        BeaconNodeServiceClient::new(ch),
+                        audit_log.clone(),
+                        options.clone(),
+                    )
+                }));
+                attestation_client.replace_nodes(connect_clients(&servers, &connector, |ch| {
+                    AttestationGrpcClient::new(
+                        Arc::new(AttestationServiceClient::new(ch.clone())),
+                        BeaconNodeServiceClient::new(ch),
+                        audit_log.clone(),
+                        options.clone(),
+                    )
+                }));
+                Ok(())
+            };
+
+            ServerReload {
+                modified: file_modified(&path),
+                path,
+                reconnect: Box::new(reconnect),
+            }
+        });
+
         // build the validator slot clock
         let slot_clock = SystemTimeSlotClock::new(
             genesis_slot,
@@ -167,8 +416,69 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
 
         /* Generate the duties manager */
 
-        // Load generated keypairs
-        let keypairs = Arc::new(client_config.fetch_keys(&log)?);
+        // Load generated keypairs, and the validators which are signed for remotely
+        let remote_signers = client_config
+            .remote_signers
+            .iter()
+            .map(|remote| {
+                RemoteSigner::new(
+                    remote.public_key.clone(),
+                    &remote.url,
+                    client_config.remote_signer_timeout(remote, &log),
+                    log.clone(),
+                )
+                .map(|signer| (signer, remote.local_fallback))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut keypairs = match client_config.fetch_keys(&log) {
+            Ok(keypairs) => keypairs,
+            // Remote signers alone are sufficient.
+            Err(e) if !remote_signers.is_empty() => {
+                info!(log, "No local keypairs loaded"; "reason" => e);
+                vec![]
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // A validator whose remote signer falls back to its local key is signed for only once.
+        let remote_signers = remote_signers
+            .into_iter()
+            .map(|(signer, local_fallback)| {
+                if !local_fallback {
+                    return Ok(ValidatorSigner::Remote(signer));
+                }
+                let public_key = signer.to_public();
+                let index = keypairs
+                    .iter()
+                    .position(|keypair| keypair.pk == public_key)
+                    .ok_or_else(|| {
+                        format!(
+                            "No local key for the remote signer fallback of {}",
+                            public_key.as_hex_string()
+                        )
+                    })?;
+                warn!(
+                    log,
+                    "Remote signer falls back to the local key";
+                    "validator" => public_key.as_hex_string(),
+                );
+                Ok(ValidatorSigner::RemoteWithFallback(
+                    signer,
+                    keypairs.remove(index),
+                ))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        info!(
+            log,
+            "Loaded validators";
+            "local" => keypairs.len(),
+            "remote" => remote_signers.len(),
+        );
+        let signers: Vec<ValidatorSigner> = keypairs
+            .into_iter()
+            .map(ValidatorSigner::Local)
+            .chain(remote_signers)
+            .collect();
+        let signers = Arc::new(signers);
 
         let slots_per_epoch = E::slots_per_epoch();
 
@@ -179,53 +489,200 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
         // Builds a mapping of Epoch -> Map(PublicKey, EpochDuty)
         // where EpochDuty contains slot numbers and attestation data that each validator needs to
         // produce work on.
-        let duties_map = RwLock::new(EpochDutiesMap::new(slots_per_epoch));
+        let duties_map = match &client_config.duties_cache_path {
+            Some(path) => {
+                let current_epoch = slot_clock
+                    .now()
+                    .unwrap_or(genesis_slot)
+                    .epoch(slots_per_epoch);
+                match load_duties(path, slots_per_epoch, current_epoch) {
+                    Ok(duties_map) => {
+                        info!(log, "Loaded cached duties"; "epochs" => duties_map.len());
+                        duties_map
+                    }
+                    Err(e) => {
+                        warn!(log, "Unable to load cached duties"; "error" => e);
+                        EpochDutiesMap::new(slots_per_epoch)
+                    }
+                }
+            }
+            None => EpochDutiesMap::new(slots_per_epoch),
+        };
+        let duties_map = RwLock::new(duties_map);
 
         // builds a manager which maintains the list of current duties for all known validators
         // and can check when a validator needs to perform a task.
         let duties_manager = Arc::new(DutiesManager {
             duties_map,
             // these are abstract objects capable of signing
-            signers: keypairs,
+            signers,
             beacon_node: validator_client,
+            batch_size: DUTIES_BATCH_SIZE,
+            max_concurrent_requests: client_config.max_concurrent_duties_requests,
+            cache_path: client_config.duties_cache_path.clone(),
+            validator_statuses: <_>::default(),
+            doppelganger: <_>::default(),
+            monitor_balances: client_config.balance_warnings || client_config.reward_logging,
+            validator_balances: <_>::default(),
+            log_rewards: client_config.reward_logging,
+            validator_rewards: <_>::default(),
+            validator_indices: <_>::default(),
+            syncing_distance: client_config.syncing_distance,
         });
 
+        if client_config.doppelganger_protection {
+            for signer in duties_manager.signers.iter() {
+                duties_manager.doppelganger.register(signer.to_public());
+            }
+            info!(
+                log,
+                "Doppelganger protection enabled";
+                "validators" => duties_manager.signers.len(),
+                "epochs" => DOPPELGANGER_DETECTION_EPOCHS,
+            );
+        }
+
+        if client_config.dry_run {
+            warn!(
+                log,
+                "Dry run enabled";
+                "msg" => "blocks and attestations will be logged, but not signed or published",
+            );
+        }
+
+        let slashing_protection = Arc::new(SlashingDatabase::open(&client_config, log.clone())?);
+
+        let slot_processing_delay =
+            client_config.slot_processing_delay(eth2_config.spec.milliseconds_per_slot)?;
+
         let spec = Arc::new(eth2_config.spec);
+        let heartbeat_file = client_config.heartbeat_file.clone();
+        let metrics_file = client_config.metrics_file.clone();
+        let tracer = match &client_config.trace_file {
+            Some(path) => Tracer::new(Arc::new(FileSpanExporter::open(path)?)),
+            None => Tracer::default(),
+        };
+        let slot_summary = client_config.slot_summary;
+        let block_failures = Arc::new(BlockFailureTracker::new(
+            client_config.block_failure_alert_threshold,
+        ));
 
-        Ok(Service {
-            fork,
+        let mut service = Service {
+            fork_schedule,
             slot_clock,
+            genesis_time,
+            genesis_slot,
+            slot_processing_delay,
             current_slot: None,
+            clock_drift: ClockDrift::default(),
+            clock_advanced_at: None,
+            node_clock_lead: 0,
+            node_clock_ahead: client_config.node_clock_ahead,
+            slot_errors: Arc::new(AtomicU64::new(0)),
             slots_per_epoch,
             spec,
             duties_manager,
+            beacon_node_client,
+            server_reload,
+            audit_log,
+            request_options,
             beacon_block_client,
             attestation_client,
+            aggregation_client,
             log,
+            observer,
+            slot_summary,
+            heartbeat_file,
+            tracer,
+            metrics_file,
+            graffiti: client_config.graffiti,
+            max_publish_retries: client_config.max_publish_retries,
+            dry_run: client_config.dry_run,
+            attestation_freshness_tolerance: client_config.attestation_freshness_tolerance,
+            attestation_syncing_distance: if client_config.attest_while_syncing {
+                None
+            } else {
+                Some(client_config.syncing_distance)
+            },
+            aggregation_time: client_config.aggregation_time,
+            block_failures,
+            attested_heads: Arc::new(AttestedHeads::default()),
+            selection_proofs: Arc::new(SelectionProofCache::new(
+                client_config.selection_proof_cache,
+                slots_per_epoch,
+            )),
+            producer_panics: Arc::new(AtomicU64::new(0)),
+            skip_counters: Arc::new(SkipCounters::default()),
+            slashing_protection,
+            in_flight: Arc::new(InFlightDuties::default()),
+            signing_pool: Arc::new(SigningPool::new(client_config.max_concurrent_signings)),
             _phantom: PhantomData,
-        })
+        };
+        service.record_node_time(&node_info, local_time);
+
+        Ok(service)
+    }
+
+    /// Connects to the beacon node and returns the duties of all validators for the next `epochs`
+    /// epochs, as JSON. The duties are not performed.
+    pub fn export_schedule(
+        client_config: ValidatorConfig,
+        eth2_config: Eth2Config,
+        epochs: u64,
+        log: slog::Logger,
+    ) -> error_chain::Result<String> {
+        let service =
+            Self::initialize_service(client_config, eth2_config, Arc::new(NoOpObserver), log)?;
+
+        let start_epoch = service
+            .slot_clock
+            .now()
+            .unwrap_or(service.genesis_slot)
+            .epoch(service.slots_per_epoch);
+        let schedule = service
+            .duties_manager
+            .schedule(start_epoch, epochs, |slot| service.slot_timestamp(slot))
+            .map_err(|e| format!("Unable to fetch duties: {:?}", e))?;
+
+        serde_json::to_string_pretty(&schedule)
+            .map_err(|e| format!("Unable to serialize schedule: {:?}", e).into())
     }
 
-    /// Initialise the service then run the core thread.
     // TODO: Improve handling of generic BeaconNode types, to stub grpcClient
+    /// Initialise the service then run the core thread.
+    ///
+    /// The `observer` is notified each time a block or attestation is produced.
+    ///
+    /// Runs until ctrl-c, then stops scheduling new slots and waits for in-flight duties to
+    /// complete before returning.
     pub fn start(
         client_config: ValidatorConfig,
         eth2_config: Eth2Config,
+        observer: Arc<dyn ProductionObserver<E>>,
         log: slog::Logger,
     ) -> error_chain::Result<()> {
+        let worker_threads = client_config.runtime_worker_threads;
+        let cpu_affinity = client_config.cpu_affinity.clone();
+        let data_dir = client_config
+            .full_data_dir()
+            .ok_or_else::<error_chain::Error, _>(|| "Unable to determine data directory".into())?;
+
         // connect to the node and retrieve its properties and initialize the gRPC clients
-        let mut service = Service::<ValidatorServiceClient, Keypair, E>::initialize_service(
-            client_config,
-            eth2_config,
-            log.clone(),
-        )?;
+        let service = Self::initialize_service(client_config, eth2_config, observer, log.clone())?;
+
+        // refuse to sign for any validator which another validator client is already using. The
+        // locks are held until in-flight duties have drained, after the service is dropped.
+        let key_locks = service
+            .duties_manager
+            .signers
+            .iter()
+            .map(|signer| KeyLock::acquire(&data_dir, &signer.to_public()))
+            .collect::<Result<Vec<_>, _>>()?;
 
         // we have connected to a node and established its parameters. Spin up the core service
 
         // set up the validator service runtime
-        let mut runtime = Builder::new()
-            .clock(Clock::system())
-            .name_prefix("validator-client-")
+        let mut runtime = runtime_builder(worker_threads, cpu_affinity, log.clone())
             .build()
             .map_err(|e| format!("Tokio runtime failed: {}", e))?;
 
@@ -257,54 +714,404 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
             "seconds_to_wait" => duration_to_next_slot.as_secs()
         );
 
+        // run the service until ctrl-c
+        let (ctrlc_send, ctrlc_oneshot) = oneshot::channel();
+        let ctrlc_send_c = RefCell::new(Some(ctrlc_send));
+        ctrlc::set_handler(move || {
+            if let Some(ctrlc_send) = ctrlc_send_c.try_borrow_mut().unwrap().take() {
+                // the service may already have stopped on a fatal error
+                let _ = ctrlc_send.send(());
+            }
+        })
+        .map_err(|e| format!("Could not set ctrlc handler: {:?}", e))?;
+
+        let in_flight = service.in_flight.clone();
+
         /* kick off the core service */
-        runtime.block_on(
-            interval
-                .for_each(move |_| {
-                    // wait for node to process
-                    std::thread::sleep(TIME_DELAY_FROM_SLOT);
-                    // if a non-fatal error occurs, proceed to the next slot.
-                    let _ignore_error = service.per_slot_execution();
-                    // completed a slot process
-                    Ok(())
-                })
-                .map_err(|e| format!("Service thread failed: {:?}", e)),
-        )?;
+        let slots = service.run_each_slot(interval);
+        let shutdown = ctrlc_oneshot.map_err(|e| format!("Ctrlc oneshot failed: {:?}", e));
+
+        // Dropping the slot future drops the service, so no further slots are scheduled. Duties
+        // are drained even if the service stopped on a fatal error.
+        let result = runtime.block_on(slots.select(shutdown).map(|_| ()).map_err(|(e, _)| e));
+
+        info!(
+            log,
+            "Shutting down validator client";
+            "in_flight_duties" => in_flight.count(),
+        );
+        let remaining = in_flight.wait_until_idle(SHUTDOWN_DRAIN_TIMEOUT);
+        if remaining > 0 {
+            warn!(
+                log,
+                "Shut down with duties still in flight";
+                "in_flight_duties" => remaining,
+            );
+        }
+        drop(key_locks);
+
         // validator client exited
-        Ok(())
+        result.map_err(Into::into)
+    }
+
+    /// Performs the duties of each slot as the `ticks` arrive, until a fatal error occurs.
+    ///
+    /// Transient errors are logged and counted, and the service proceeds to the next slot.
+    fn run_each_slot<St>(mut self, ticks: St) -> impl Future<Item = (), Error = String>
+    where
+        St: Stream,
+        St::Error: std::fmt::Debug,
+    {
+        ticks
+            .map_err(|e| format!("Service thread failed: {:?}", e))
+            .for_each(move |_| {
+                // wait for node to process
+                std::thread::sleep(self.slot_processing_delay);
+                match self.per_slot_execution() {
+                    Ok(()) => Ok(()),
+                    Err(SlotError::Transient(e)) => {
+                        let total_errors = self.slot_errors.fetch_add(1, Ordering::SeqCst) + 1;
+                        warn!(
+                            self.log,
+                            "Slot processing failed";
+                            "error" => e,
+                            "total_errors" => total_errors,
+                        );
+                        Ok(())
+                    }
+                    Err(SlotError::Fatal(e)) => {
+                        crit!(self.log, "Stopping after a fatal error"; "error" => &e);
+                        Err(e)
+                    }
+                }
+            })
     }
 
     /// The execution logic that runs every slot.
     // Errors are logged to output, and core execution continues unless fatal errors occur.
-    fn per_slot_execution(&mut self) -> error_chain::Result<()> {
+    fn per_slot_execution(&mut self) -> Result<(), SlotError> {
+        /* there are no duties prior to genesis, so wait for it without treating it as an error */
+        if let Some(remaining) = time_until_genesis(&self.slot_clock) {
+            info!(
+                self.log,
+                "Waiting for genesis";
+                "seconds_remaining" => remaining.as_secs(),
+            );
+            return Ok(());
+        }
+
         /* get the new current slot and epoch */
+        let previous_epoch = self
+            .current_slot
+            .map(|slot| slot.epoch(self.slots_per_epoch));
         self.update_current_slot()?;
+        let _slot_span = self.current_slot.map(|slot| self.tracer.span("slot", slot));
+        self.reload_servers();
+
+        /* pick up any change of fork at the start of each epoch */
+        if let Some(current_slot) = self.current_slot {
+            let current_epoch = current_slot.epoch(self.slots_per_epoch);
+            if previous_epoch != Some(current_epoch) {
+                self.refresh_fork(current_epoch)?;
+                self.duties_manager
+                    .refresh_validator_status(current_epoch, &self.log);
+                self.duties_manager.check_balances(&self.log);
+            } else if self.halted_for_node_clock() {
+                // compare the clocks each slot, so that duties resume once the clock is corrected
+                self.refresh_fork(current_epoch)?;
+            }
+
+            // late attestations are included in the epoch after their own, so check every slot
+            let epoch_ending = (current_slot + 1).epoch(self.slots_per_epoch) != current_epoch;
+            self.duties_manager
+                .check_doppelgangers(current_epoch, epoch_ending, &self.log);
+        }
+
+        if self.halted_for_node_clock() {
+            warn!(
+                self.log,
+                "Duties halted whilst the local clock is behind the beacon node's";
+                "lead_slots" => self.node_clock_lead,
+            );
+            if let Some(current_slot) = self.current_slot {
+                let work = self.duties_manager.get_current_work(current_slot);
+                for (signer_index, _) in work.unwrap_or_default() {
+                    self.skip_counters.record(
+                        "all",
+                        &format!("{}", self.duties_manager.signers[signer_index]),
+                        current_slot,
+                        SkipReason::ClockHalt,
+                        &self.log,
+                    );
+                }
+            }
+            return Ok(());
+        }
 
         /* check for new duties */
+        let duties_fetch_started = Instant::now();
+        let duties_fetch_span = self
+            .current_slot
+            .map(|slot| self.tracer.span("duties_fetch", slot));
         self.check_for_duties();
+        drop(duties_fetch_span);
+        let duties_fetch_time = duties_fetch_started.elapsed();
 
         /* process any required duties for validators */
-        self.process_duties();
+        let mut summary = self.process_duties();
+        summary.duties_fetch_time = duties_fetch_time;
+        self.log_slot_summary(&summary);
 
         trace!(
             self.log,
             "Per slot execution finished";
         );
 
+        if let (Some(path), Some(slot)) = (&self.heartbeat_file, self.current_slot) {
+            if let Err(e) = write_heartbeat(path, slot) {
+                warn!(
+                    self.log,
+                    "Unable to write heartbeat file";
+                    "error" => e,
+                    "path" => format!("{:?}", path),
+                );
+            }
+        }
+
+        if let Some(path) = &self.metrics_file {
+            if let Err(e) = replace_file(path, &self.metrics()) {
+                warn!(
+                    self.log,
+                    "Unable to write metrics file";
+                    "error" => format!("{:?}", e),
+                    "path" => format!("{:?}", path),
+                );
+            }
+        }
+
         Ok(())
     }
 
-    /// Updates the known current slot and epoch.
-    fn update_current_slot(&mut self) -> error_chain::Result<()> {
-        let wall_clock_slot = self
-            .slot_clock
-            .now()
-            .ok_or_else::<error_chain::Error, _>(|| {
-                "Genesis is not in the past. Exiting.".into()
-            })?;
+    /// Returns the service's counters, in the Prometheus text format.
+    fn metrics(&self) -> String {
+        let mut metrics = String::new();
+        let mut metric = |name: &str, kind: &str, samples: Vec<(String, u64)>| {
+            metrics.push_str(&format!("# TYPE {} {}\n", name, kind));
+            for (labels, value) in samples {
+                metrics.push_str(&format!("{}{} {}\n", name, labels, value));
+            }
+        };
+
+        metric(
+            "validator_duties_skipped_total",
+            "counter",
+            self.skip_counters
+                .counts()
+                .into_iter()
+                .map(|(reason, count)| (format!("{{reason=\"{}\"}}", reason.code()), count))
+                .collect(),
+        );
+        metric(
+            "validator_block_proposal_consecutive_failures",
+            "gauge",
+            vec![(String::new(), self.block_failures.consecutive_failures())],
+        );
+        metric(
+            "validator_producer_panics_total",
+            "counter",
+            vec![(String::new(), self.producer_panics.load(Ordering::SeqCst))],
+        );
+        metric(
+            "validator_slot_errors_total",
+            "counter",
+            vec![(String::new(), self.slot_errors.load(Ordering::SeqCst))],
+        );
+        metric(
+            "validator_message_sink_dropped_total",
+            "counter",
+            vec![(String::new(), self.observer.dropped_messages())],
+        );
+        metric(
+            "validator_message_sink_queued_bytes",
+            "gauge",
+            vec![(String::new(), self.observer.queued_message_bytes())],
+        );
+
+        let mut balances: Vec<_> = self
+            .duties_manager
+            .validator_balances
+            .read()
+            .map(|balances| {
+                balances
+                    .iter()
+                    .map(|(pubkey, (_, balance))| {
+                        (
+                            format!("{{validator=\"{}\"}}", pubkey.as_hex_string()),
+                            *balance,
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        balances.sort();
+        metric("validator_balance_gwei", "gauge", balances);
+
+        let rewards = self
+            .duties_manager
+            .validator_rewards
+            .read()
+            .map(|rewards| {
+                let mut rewards: Vec<_> = rewards
+                    .iter()
+                    .map(|(pubkey, totals)| (pubkey.as_hex_string(), *totals))
+                    .collect();
+                rewards.sort_by(|a, b| a.0.cmp(&b.0));
+                rewards
+            })
+            .unwrap_or_default();
+        let totals = |total: fn(&RewardTotals) -> u64| -> Vec<(String, u64)> {
+            rewards
+                .iter()
+                .map(|(pubkey, totals)| (format!("{{validator=\"{}\"}}", pubkey), total(totals)))
+                .collect()
+        };
+        metric(
+            "validator_rewards_gwei_total",
+            "counter",
+            totals(|totals| totals.rewards_gwei),
+        );
+        metric(
+            "validator_penalties_gwei_total",
+            "counter",
+            totals(|totals| totals.penalties_gwei),
+        );
+
+        metrics
+    }
+
+    /// Logs `summary` as a single line, at the configured level.
+    fn log_slot_summary(&self, summary: &SlotSummary) {
+        let slot = match self.current_slot {
+            Some(slot) => slot,
+            None => return,
+        };
+        let log = self.log.new(o!(
+            "slot" => slot.as_u64(),
+            "epoch" => slot.epoch(self.slots_per_epoch).as_u64(),
+            "blocks" => summary.blocks,
+            "attestations" => summary.attestations,
+            "skipped" => summary.skipped,
+            "duties_fetch_ms" => summary.duties_fetch_time.as_millis() as u64,
+            "clock_skew_slots" => self.clock_drift.skew,
+        ));
+
+        match self.slot_summary {
+            SlotSummaryLevel::Off => {}
+            SlotSummaryLevel::Info => info!(log, "Slot processed"),
+            SlotSummaryLevel::Debug => debug!(log, "Slot processed"),
+        }
+    }
+
+    /// Returns the start of `slot`, in seconds since the UNIX epoch.
+    fn slot_timestamp(&self, slot: Slot) -> u64 {
+        let slots_since_genesis = slot.as_u64().saturating_sub(self.genesis_slot.as_u64());
+        self.genesis_time + slots_since_genesis * self.spec.milliseconds_per_slot / 1000
+    }
+
+    /// Compares the time reported by the beacon node with `local_time` (since the UNIX epoch),
+    /// logging whenever the number of whole slots by which the node's clock is ahead changes. A
+    /// node which does not report its time is not compared.
+    ///
+    /// The lead is the difference between the two times, rather than between the slots they fall
+    /// in, so a node which is only a few milliseconds ahead at a slot boundary has no lead.
+    fn record_node_time(&mut self, node_info: &NodeInfoResponse, local_time: Duration) {
+        let node_time_ms = node_info.get_current_time_ms();
+        if node_time_ms == 0 {
+            return;
+        }
+
+        let local_time_ms = local_time.as_millis() as u64;
+        // there are no duties before genesis, so the clocks are compared after it
+        let node_clock_lead = if local_time_ms >= self.genesis_time * 1000 {
+            node_time_ms.saturating_sub(local_time_ms) / self.spec.milliseconds_per_slot
+        } else {
+            0
+        };
+
+        if node_clock_lead != self.node_clock_lead {
+            if node_clock_lead > 0 {
+                warn!(
+                    self.log,
+                    "Beacon node clock is ahead of the local clock";
+                    "lead_slots" => node_clock_lead,
+                    "action" => format!("{:?}", self.node_clock_ahead),
+                    "hint" => "check that the system clock is synchronised (e.g., by NTP)",
+                );
+            } else {
+                info!(self.log, "Local clock has caught up with the beacon node's");
+            }
+        }
+        self.node_clock_lead = node_clock_lead;
+    }
+
+    /// Returns `true` if duties are halted because the beacon node's clock is ahead of the local
+    /// clock.
+    /// Replaces the fallback beacon nodes if the fallback servers file has changed since it was
+    /// last read. The current nodes are kept if the file cannot be read, or names a node on
+    /// another network.
+    fn reload_servers(&mut self) {
+        let reload = match &mut self.server_reload {
+            Some(reload) => reload,
+            None => return,
+        };
+        let modified = file_modified(&reload.path);
+        if modified == reload.modified {
+            return;
+        }
+        reload.modified = modified;
+
+        if let Err(e) =
+            read_fallback_servers(&reload.path).and_then(|servers| (reload.reconnect)(servers))
+        {
+            warn!(
+                self.log,
+                "Unable to replace the fallback beacon nodes";
+                "file" => format!("{:?}", reload.path),
+                "error" => e,
+            );
+        }
+    }
+
+    fn halted_for_node_clock(&self) -> bool {
+        self.node_clock_ahead == NodeClockAheadAction::Halt && self.node_clock_lead > 0
+    }
+
+    /// Updates the known current slot and epoch. If the beacon node's clock is ahead and trusted,
+    /// the node's slot is used.
+    ///
+    /// Returns a fatal error if the slot clock has not advanced for `MAX_STALLED_SLOTS` slots.
+    fn update_current_slot(&mut self) -> Result<(), SlotError> {
+        let local_slot = self.slot_clock.now().ok_or_else(|| {
+            SlotError::Transient("Unable to determine the current slot".to_string())
+        })?;
+        let wall_clock_slot = match self.node_clock_ahead {
+            NodeClockAheadAction::Trust => local_slot + self.node_clock_lead,
+            NodeClockAheadAction::Halt => local_slot,
+        };
 
         let wall_clock_epoch = wall_clock_slot.epoch(self.slots_per_epoch);
 
+        if let Some(expected_slot) = self.clock_drift.tick(wall_clock_slot) {
+            warn!(
+                self.log,
+                "Slot clock drift detected";
+                "skew_slots" => self.clock_drift.skew,
+                "expected_slot" => expected_slot.as_u64(),
+                "wall_clock_slot" => wall_clock_slot.as_u64(),
+                "hint" => "check that the system clock is synchronised (e.g., by NTP)",
+            );
+        }
+
         // this is a non-fatal error. If the slot clock repeats, the node could
         // have been slow to process the previous slot and is now duplicating tasks.
         // We ignore duplicated but raise a critical error.
@@ -314,22 +1121,79 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
                     self.log,
                     "The validator tried to duplicate a slot. Likely missed the previous slot"
                 );
-                return Err("Duplicate slot".into());
+                let max_stall =
+                    Duration::from_millis(self.spec.milliseconds_per_slot) * MAX_STALLED_SLOTS;
+                return match self.clock_advanced_at {
+                    Some(advanced_at) if advanced_at.elapsed() > max_stall => {
+                        Err(SlotError::Fatal(format!(
+                            "Slot clock has not advanced past slot {} for {} slots",
+                            current_slot, MAX_STALLED_SLOTS
+                        )))
+                    }
+                    _ => Err(SlotError::Transient("Duplicate slot".to_string())),
+                };
             }
         }
         self.current_slot = Some(wall_clock_slot);
+        self.clock_advanced_at = Some(Instant::now());
         info!(self.log, "Processing"; "slot" => wall_clock_slot.as_u64(), "epoch" => wall_clock_epoch.as_u64());
         Ok(())
     }
 
+    /// Re-queries the beacon node's fork, so that a fork scheduled after startup is used for
+    /// signing. On failure, the previous fork continues to be used.
+    ///
+    /// Returns a fatal error if the beacon node is now on a different network.
+    fn refresh_fork(&mut self, current_epoch: Epoch) -> Result<(), SlotError> {
+        let node_info = request_node_info(
+            &self.beacon_node_client,
+            self.audit_log.as_ref().map(Arc::as_ref),
+            &self.request_options,
+        );
+
+        // the node may have been restarted on another network
+        if let Ok(node_info) = &node_info {
+            verify_network_id(self.spec.network_id, node_info.network_id)
+                .map_err(SlotError::Fatal)?;
+            let local_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            self.record_node_time(node_info, local_time);
+        }
+        let fork = node_info.and_then(|node_info| fork_from_proto(node_info.get_fork()));
+
+        match fork {
+            Ok(fork) => {
+                let versions = format!("{:?} -> {:?}", fork.previous_version, fork.current_version);
+                let fork_epoch = fork.epoch;
+                if self.fork_schedule.set_base(fork) {
+                    warn!(
+                        self.log,
+                        "Beacon node fork changed";
+                        "versions" => versions,
+                        "fork_epoch" => fork_epoch,
+                        "current_epoch" => current_epoch,
+                    );
+                }
+            }
+            Err(e) => warn!(
+                self.log,
+                "Unable to refresh fork";
+                "error" => e,
+                "current_epoch" => current_epoch,
+            ),
+        }
+        Ok(())
+    }
+
     /// For all known validator keypairs, update any known duties from the beacon node.
     fn check_for_duties(&mut self) {
         let cloned_manager = self.duties_manager.clone();
         let cloned_log = self.log.clone();
-        let current_epoch = self
+        let current_slot = self
             .current_slot
-            .expect("The current slot must be updated before checking for duties")
-            .epoch(self.slots_per_epoch);
+            .expect("The current slot must be updated before checking for duties");
+        let current_epoch = current_slot.epoch(self.slots_per_epoch);
 
         trace!(
             self.log,
@@ -340,73 +1204,175 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
         // spawn a new thread separate to the runtime
         // TODO: Handle thread termination/timeout
         // TODO: Add duties thread back in, with channel to process duties in duty change.
-        // leave sequential for now.
-        //std::thread::spawn(move || {
-        // the return value is a future which returns ready.
-        // built to be compatible with the tokio runtime.
-        let _empty = cloned_manager.run_update(current_epoch, cloned_log.clone());
-        //});
-    }
-
-    /// If there are any duties to process, spawn a separate thread and perform required actions.
-    fn process_duties(&mut self) {
-        if let Some(work) = self.duties_manager.get_current_work(
-            self.current_slot
-                .expect("The current slot must be updated before processing duties"),
-        ) {
+        // The duties themselves are requested from the beacon node in concurrent batches.
+        let _changes = cloned_manager.run_update(current_epoch, current_slot, cloned_log.clone());
+
+        match self.duties_manager.prune_duties(current_epoch) {
+            Ok(0) => {}
+            Ok(pruned) => debug!(self.log, "Pruned expired duties"; "epochs" => pruned),
+            Err(e) => error!(self.log, "Unable to prune duties"; "error" => format!("{:?}", e)),
+        }
+    }
+
+    /// If there are any duties to process, queue them on the signing pool to perform the required
+    /// actions.
+    ///
+    /// Returns a summary of the duties started.
+    fn process_duties(&mut self) -> SlotSummary {
+        let current_slot = self
+            .current_slot
+            .expect("The current slot must be updated before processing duties");
+        let unknown_duties = self.duties_manager.unknown_duties(current_slot);
+        for &signer_index in &unknown_duties {
+            self.skip_counters.record(
+                "all",
+                &format!("{}", self.duties_manager.signers[signer_index]),
+                current_slot,
+                SkipReason::UnknownDuties,
+                &self.log,
+            );
+        }
+        let blocked_work = self.duties_manager.blocked_work(current_slot);
+        for &(signer_index, reason) in &blocked_work {
+            self.skip_counters.record(
+                "all",
+                &format!("{}", self.duties_manager.signers[signer_index]),
+                current_slot,
+                reason,
+                &self.log,
+            );
+        }
+        let mut summary = SlotSummary {
+            skipped: unknown_duties.len() + blocked_work.len(),
+            ..SlotSummary::default()
+        };
+
+        if let Some(work) = self.duties_manager.get_current_work(current_slot) {
             trace!(
                 self.log,
                 "Processing duties";
                 "work_items" => work.len()
             );
+            summary.record_work(&work);
 
+            // Blocks are queued first, so that they are not delayed behind attestations waiting
+            // for the block of this slot.
+            let mut attestation_duties = vec![];
             for (signer_index, work_type) in work {
                 if work_type.produce_block {
                     // we need to produce a block
-                    // spawns a thread to produce a beacon block
+                    // queues a job to produce a beacon block
                     let signers = self.duties_manager.signers.clone(); // this is an arc
-                    let fork = self.fork.clone();
-                    let slot = self
-                        .current_slot
-                        .expect("The current slot must be updated before processing duties");
+                    let slot = current_slot;
+                    let validator_index = work_type.validator_index;
+                    let fork = self.fork_schedule.fork_at(slot.epoch(self.slots_per_epoch));
                     let spec = self.spec.clone();
                     let beacon_node = self.beacon_block_client.clone();
                     let log = self.log.clone();
                     let slots_per_epoch = self.slots_per_epoch;
-                    std::thread::spawn(move || {
-                        info!(
-                            log,
-                            "Producing a block";
-                            "validator"=> format!("{}", signers[signer_index]),
-                            "slot"=> slot
-                        );
-                        let signer = &signers[signer_index];
-                        let mut block_producer = BlockProducer {
-                            fork,
+                    let observer = self.observer.clone();
+                    let block_failures = self.block_failures.clone();
+                    let skip_counters = self.skip_counters.clone();
+                    let slashing_protection = self.slashing_protection.clone();
+                    let graffiti = self.graffiti;
+                    let max_publish_retries = self.max_publish_retries;
+                    let dry_run = self.dry_run;
+                    spawn_producer(
+                        ProducerContext {
+                            duty: "block",
+                            validator: format!("{}", signers[signer_index]),
                             slot,
-                            spec,
-                            beacon_node,
-                            signer,
-                            slots_per_epoch,
-                            _phantom: PhantomData::<E>,
-                            log,
-                        };
-                        block_producer.handle_produce_block();
-                    });
+                            tracer: self.tracer.clone(),
+                        },
+                        None,
+                        self.producer_panics.clone(),
+                        &self.in_flight,
+                        &self.signing_pool,
+                        self.log.clone(),
+                        move || {
+                            info!(
+                                log,
+                                "Producing a block";
+                                "validator"=> format!("{}", signers[signer_index]),
+                                "slot"=> slot
+                            );
+                            let signer = &signers[signer_index];
+                            let mut block_producer = BlockProducer {
+                                fork,
+                                slot,
+                                validator_index,
+                                spec,
+                                beacon_node,
+                                signer,
+                                slots_per_epoch,
+                                _phantom: PhantomData::<E>,
+                                log: log.clone(),
+                                observer,
+                                slashing_protection,
+                                graffiti,
+                                max_publish_retries,
+                                dry_run,
+                            };
+                            let skipped = block_producer.handle_produce_block();
+                            block_failures.record(slot, skipped.is_none(), &log);
+                            if let Some(reason) = skipped {
+                                skip_counters.record(
+                                    "block",
+                                    &format!("{}", signer),
+                                    slot,
+                                    reason,
+                                    &log,
+                                );
+                            }
+                        },
+                    );
                 }
-                if work_type.attestation_duty.is_some() {
-                    // we need to produce an attestation
-                    // spawns a thread to produce and sign an attestation
-                    let slot = self
-                        .current_slot
-                        .expect("The current slot must be updated before processing duties");
-                    let signers = self.duties_manager.signers.clone(); // this is an arc
-                    let fork = self.fork.clone();
-                    let spec = self.spec.clone();
-                    let beacon_node = self.attestation_client.clone();
-                    let log = self.log.clone();
-                    let slots_per_epoch = self.slots_per_epoch;
-                    std::thread::spawn(move || {
+                let validator_index = work_type.validator_index;
+                attestation_duties.extend(
+                    work_type
+                        .attestation_duties
+                        .into_iter()
+                        .map(|duty| (signer_index, validator_index, duty)),
+                );
+            }
+            for (signer_index, validator_index, duty) in attestation_duties {
+                if let Some(validator_index) = validator_index {
+                    self.spawn_aggregation(signer_index, validator_index, duty);
+                }
+                // we need to produce an attestation
+                // queues a job to produce and sign an attestation
+                let slot = current_slot;
+                let signers = self.duties_manager.signers.clone(); // this is an arc
+                let fork = self.fork_schedule.fork_at(slot.epoch(self.slots_per_epoch));
+                let spec = self.spec.clone();
+                let beacon_node = self.attestation_client.clone();
+                let log = self.log.clone();
+                let slots_per_epoch = self.slots_per_epoch;
+                let observer = self.observer.clone();
+                let skip_counters = self.skip_counters.clone();
+                let slashing_protection = self.slashing_protection.clone();
+                let max_publish_retries = self.max_publish_retries;
+                let dry_run = self.dry_run;
+                let freshness_tolerance = self.attestation_freshness_tolerance;
+                let syncing_distance = self.attestation_syncing_distance;
+                let attested_heads = self.attested_heads.clone();
+                let selection_proofs = validator_index.map(|_| self.selection_proofs.clone());
+                let slot_start = UNIX_EPOCH + Duration::from_secs(self.slot_timestamp(slot));
+                // give the block for this slot time to arrive before attesting
+                let attest_at = Instant::now() + self.attestation_wait();
+                spawn_producer(
+                    ProducerContext {
+                        duty: "attestation",
+                        validator: format!("{}", signers[signer_index]),
+                        slot,
+                        tracer: self.tracer.clone(),
+                    },
+                    Some(attest_at),
+                    self.producer_panics.clone(),
+                    &self.in_flight,
+                    &self.signing_pool,
+                    self.log.clone(),
+                    move || {
                         info!(
                             log,
                             "Producing an attestation";
@@ -415,18 +1381,2007 @@ impl<B: BeaconNodeDuties + 'static, S: Signer + 'static, E: EthSpec> Service<B,
                         );
                         let signer = &signers[signer_index];
                         let mut attestation_producer = AttestationProducer {
-                            fork,
-                            duty: work_type.attestation_duty.expect("Should never be none"),
-                            spec,
+                            fork: fork.clone(),
+                            duty,
+                            spec: spec.clone(),
                             beacon_node,
                             signer,
                             slots_per_epoch,
                             _phantom: PhantomData::<E>,
+                            observer,
+                            slashing_protection,
+                            max_publish_retries,
+                            dry_run,
+                            freshness_tolerance,
+                            attested_heads,
+                            syncing_distance,
                         };
-                        attestation_producer.handle_produce_attestation(log);
-                    });
-                }
+                        if let Some(reason) =
+                            attestation_producer.handle_produce_attestation(log.clone())
+                        {
+                            if reason == SkipReason::Expired {
+                                // the validator was too slow, so the lateness aids diagnosis
+                                let lateness = SystemTime::now()
+                                    .duration_since(slot_start)
+                                    .unwrap_or_default();
+                                warn!(
+                                    log,
+                                    "Attestation published too late to be included";
+                                    "validator" => format!("{}", signer),
+                                    "slot" => slot,
+                                    "lateness_ms" => lateness.as_millis() as u64,
+                                );
+                            }
+                            skip_counters.record(
+                                "attestation",
+                                &format!("{}", signer),
+                                slot,
+                                reason,
+                                &log,
+                            );
+                        }
+                        // sign the selection proof whilst waiting to aggregate, so that it is
+                        // ready by the time the committee's attestations are aggregated
+                        if let Some(selection_proofs) = selection_proofs {
+                            selection_proofs.get_or_sign(signer, slot, &fork, &spec);
+                        }
+                    },
+                );
             }
         }
+
+        summary
+    }
+
+    /// Queues a job to aggregate the attestations of the committee of `duty`, should the
+    /// validator be selected as one of its aggregators.
+    fn spawn_aggregation(&self, signer_index: usize, validator_index: u64, duty: AttestationDuty) {
+        let slot = duty.slot;
+        let signers = self.duties_manager.signers.clone(); // this is an arc
+        let fork = self.fork_schedule.fork_at(slot.epoch(self.slots_per_epoch));
+        let spec = self.spec.clone();
+        let beacon_node = self.aggregation_client.clone();
+        let log = self.log.clone();
+        let slots_per_epoch = self.slots_per_epoch;
+        let skip_counters = self.skip_counters.clone();
+        let max_publish_retries = self.max_publish_retries;
+        let dry_run = self.dry_run;
+        let selection_proofs = self.selection_proofs.clone();
+        // give the committee's attestations time to reach the node before aggregating them
+        let aggregate_at = Instant::now() + self.aggregation_wait();
+        spawn_producer(
+            ProducerContext {
+                duty: "aggregate",
+                validator: format!("{}", signers[signer_index]),
+                slot,
+                tracer: self.tracer.clone(),
+            },
+            Some(aggregate_at),
+            self.producer_panics.clone(),
+            &self.in_flight,
+            &self.signing_pool,
+            self.log.clone(),
+            move || {
+                let signer = &signers[signer_index];
+                let mut aggregation_producer = AggregationProducer {
+                    fork,
+                    duty,
+                    validator_index,
+                    spec,
+                    beacon_node,
+                    signer,
+                    slots_per_epoch,
+                    _phantom: PhantomData::<E>,
+                    max_publish_retries,
+                    dry_run,
+                    selection_proofs,
+                };
+                if let Some(reason) = aggregation_producer.handle_produce_aggregate(log.clone()) {
+                    skip_counters.record("aggregate", &format!("{}", signer), slot, reason, &log);
+                }
+            },
+        );
+    }
+
+    /// Returns the time remaining until attestations should be produced in the current slot.
+    fn attestation_wait(&self) -> Duration {
+        attestation_wait(
+            self.slot_clock.slot_duration(),
+            time_into_slot(&self.slot_clock),
+        )
+    }
+
+    /// Returns the time remaining until aggregates should be produced in the current slot.
+    fn aggregation_wait(&self) -> Duration {
+        duty_wait(
+            self.aggregation_time,
+            self.slot_clock.slot_duration(),
+            time_into_slot(&self.slot_clock),
+        )
+    }
+}
+
+/// Compares the slot reported by the slot clock at each slot tick with the slot expected from the
+/// number of ticks, to detect a system clock which has drifted or jumped.
+#[derive(Default)]
+struct ClockDrift {
+    /// The slot reported at the first tick since the last reported drift.
+    baseline: Option<Slot>,
+    /// The number of ticks since `baseline`.
+    ticks: u64,
+    /// The most recently measured difference between the reported and expected slots.
+    skew: i64,
+}
+
+impl ClockDrift {
+    /// Records a tick at which the slot clock reported `slot`.
+    ///
+    /// Returns the expected slot if the skew exceeds `MAX_CLOCK_SKEW_SLOTS`. The measurement then
+    /// restarts from `slot`, so that a single jump of the clock is only reported once.
+    fn tick(&mut self, slot: Slot) -> Option<Slot> {
+        let expected = self.baseline.unwrap_or(slot) + self.ticks;
+        self.skew = slot.as_u64() as i64 - expected.as_u64() as i64;
+
+        if self.skew.abs() as u64 > MAX_CLOCK_SKEW_SLOTS {
+            self.baseline = Some(slot);
+            self.ticks = 1;
+            Some(expected)
+        } else {
+            self.baseline.get_or_insert(slot);
+            self.ticks += 1;
+            None
+        }
+    }
+}
+
+/// An error which prevented the duties of a slot from being performed.
+#[derive(Debug, PartialEq)]
+enum SlotError {
+    /// The service cannot safely continue, so stops.
+    Fatal(String),
+    /// Only the current slot is affected, and the service proceeds to the next slot.
+    Transient(String),
+}
+
+/// Returns the time to wait before attesting, once `time_into_slot` has elapsed.
+///
+/// Attestations are produced one third of the way through the slot, as in the honest validator
+/// spec. Blocks are still produced at the start of the slot.
+fn attestation_wait(slot_duration: Duration, time_into_slot: Duration) -> Duration {
+    duty_wait(ATTESTATION_TIME, slot_duration, time_into_slot)
+}
+
+/// Returns the time to wait until `time` in the slot is reached, once `time_into_slot` has
+/// elapsed. Returns zero if it has already passed.
+///
+/// Aggregates are produced at their configured time, two thirds of the way through the slot by
+/// default, leaving the time after attesting for the committee's attestations to reach the
+/// beacon node.
+fn duty_wait(time: SlotFraction, slot_duration: Duration, time_into_slot: Duration) -> Duration {
+    time.of(slot_duration)
+        .checked_sub(time_into_slot)
+        .unwrap_or_default()
+}
+
+/// Returns the time which has elapsed since the start of the current slot of `slot_clock`.
+fn time_into_slot<T: SlotClock>(slot_clock: &T) -> Duration {
+    let slot_duration = slot_clock.slot_duration();
+    slot_clock
+        .duration_to_next_slot()
+        .and_then(|remaining| slot_duration.checked_sub(remaining))
+        .unwrap_or_default()
+}
+
+/// The work started by the service during a single slot.
+///
+/// Producers run on their own threads, so this counts the duties started in the slot rather than
+/// those which have completed.
+#[derive(Debug, Default, PartialEq)]
+struct SlotSummary {
+    blocks: usize,
+    attestations: usize,
+    /// Validators whose duties are unknown, so could not be performed.
+    skipped: usize,
+    duties_fetch_time: Duration,
+}
+
+impl SlotSummary {
+    /// Adds the blocks and attestations in `work` to the summary.
+    fn record_work(&mut self, work: &[(usize, WorkInfo)]) {
+        for (_, work_info) in work {
+            if work_info.produce_block {
+                self.blocks += 1;
+            }
+            self.attestations += work_info.attestation_duties.len();
+        }
+    }
+}
+
+/// Identifies the duty being performed by a producer thread.
+struct ProducerContext {
+    duty: &'static str,
+    validator: String,
+    slot: Slot,
+    /// Starts the span of the producer, which lasts until it completes.
+    tracer: Tracer,
+}
+
+/// Counts the producers which have been queued but not yet completed.
+#[derive(Default)]
+struct InFlightDuties {
+    count: Mutex<usize>,
+    completed: Condvar,
+}
+
+/// Marks a duty as in flight until dropped.
+struct InFlightGuard(Arc<InFlightDuties>);
+
+impl InFlightDuties {
+    fn start(in_flight: &Arc<Self>) -> InFlightGuard {
+        *in_flight.count.lock().expect("in-flight lock poisoned") += 1;
+        InFlightGuard(in_flight.clone())
+    }
+
+    fn count(&self) -> usize {
+        *self.count.lock().expect("in-flight lock poisoned")
+    }
+
+    /// Blocks until no duties are in flight or `timeout` elapses, returning the number of duties
+    /// still in flight.
+    fn wait_until_idle(&self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut count = self.count.lock().expect("in-flight lock poisoned");
+        while *count > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            count = self
+                .completed
+                .wait_timeout(count, deadline - now)
+                .expect("in-flight lock poisoned")
+                .0;
+        }
+        *count
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        *self.0.count.lock().expect("in-flight lock poisoned") -= 1;
+        self.0.completed.notify_all();
+    }
+}
+
+/// Runs `produce` on the `pool`, counted in `in_flight` from now until it completes.
+///
+/// If `start_at` is in the future, `produce` is only queued on the `pool` once that time is
+/// reached, so that waiting does not occupy a signing thread. This must then be called from
+/// within the service's runtime.
+///
+/// A panic is caught, logged with the `context` and counted in `panics`, rather than silently
+/// ending the thread.
+fn spawn_producer<F>(
+    context: ProducerContext,
+    start_at: Option<Instant>,
+    panics: Arc<AtomicU64>,
+    in_flight: &Arc<InFlightDuties>,
+    pool: &Arc<SigningPool>,
+    log: slog::Logger,
+    produce: F,
+) where
+    F: FnOnce() + Send + 'static,
+{
+    let in_flight = InFlightDuties::start(in_flight);
+    let job = move || {
+        let _in_flight = in_flight;
+        let _span = context
+            .tracer
+            .span(context.duty, context.slot)
+            .with_attribute("validator", context.validator.clone());
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(produce)) {
+            let total_panics = panics.fetch_add(1, Ordering::SeqCst) + 1;
+            let message = payload
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown".to_string());
+            crit!(
+                log,
+                "Producer thread panicked";
+                "duty" => context.duty,
+                "validator" => context.validator,
+                "slot" => context.slot,
+                "panic" => message,
+                "total_panics" => total_panics,
+            );
+        }
+    };
+
+    match start_at {
+        Some(start_at) if start_at > Instant::now() => {
+            let pool = pool.clone();
+            tokio::spawn(Delay::new(start_at).then(move |_| {
+                pool.execute(job);
+                Ok::<_, ()>(())
+            }));
+        }
+        _ => pool.execute(job),
+    }
+}
+
+/// Returns the time remaining until genesis, or `None` once the clock reports a slot.
+fn time_until_genesis<T: SlotClock>(slot_clock: &T) -> Option<Duration> {
+    match slot_clock.now() {
+        Some(_) => None,
+        // Prior to genesis, the next slot is the genesis slot.
+        None => slot_clock.duration_to_next_slot(),
+    }
+}
+
+/// Returns an error if the network id reported by the beacon node is not `expected`.
+///
+/// The reported id is compared in full, so an id which only matches once truncated to a byte is
+/// still rejected.
+fn verify_network_id(expected: u8, reported: u32) -> Result<(), String> {
+    if u32::from(expected) == reported {
+        Ok(())
+    } else {
+        Err(format!(
+            "Beacon node has the wrong chain id. Expected chain id: {}, node's chain id: {}",
+            expected, reported
+        ))
+    }
+}
+
+/// Compares `local_time` (since the Unix epoch) with the time reported by the beacon node,
+/// warning or returning an error, per `config`, if they differ by more than the maximum skew.
+///
+/// A node which does not report its time is not checked. The node's time is taken when the
+/// request is handled, so the skew includes the request latency.
+fn verify_clock_skew(
+    node_info: &NodeInfoResponse,
+    local_time: Duration,
+    config: &ValidatorConfig,
+    log: &slog::Logger,
+) -> Result<(), String> {
+    let node_time_ms = node_info.get_current_time_ms();
+    if node_time_ms == 0 {
+        return Ok(());
+    }
+
+    let local_time_ms = local_time.as_millis() as u64;
+    let skew_ms = local_time_ms.max(node_time_ms) - local_time_ms.min(node_time_ms);
+    if skew_ms <= config.max_clock_skew_ms {
+        return Ok(());
+    }
+
+    let local_clock = if local_time_ms > node_time_ms {
+        "ahead"
+    } else {
+        "behind"
+    };
+    match config.clock_skew_action {
+        ClockSkewAction::Warn => {
+            warn!(
+                log,
+                "Local clock differs from the beacon node's";
+                "local_clock" => local_clock,
+                "skew_ms" => skew_ms,
+                "max_skew_ms" => config.max_clock_skew_ms,
+            );
+            Ok(())
+        }
+        ClockSkewAction::Reject => Err(format!(
+            "Local clock is {}ms {} the beacon node's, more than the maximum of {}ms",
+            skew_ms, local_clock, config.max_clock_skew_ms
+        )),
+    }
+}
+
+/// Converts the fork reported by the beacon node, returning an error if either version is not
+/// exactly four bytes.
+fn fork_from_proto(proto_fork: &ProtoFork) -> Result<Fork, String> {
+    let version = |bytes: &[u8], name: &str| {
+        if bytes.len() == 4 {
+            let mut version = [0; 4];
+            version.copy_from_slice(bytes);
+            Ok(version)
+        } else {
+            Err(format!(
+                "Beacon node returned a {} fork version of {} bytes, expected 4",
+                name,
+                bytes.len()
+            ))
+        }
+    };
+
+    Ok(Fork {
+        previous_version: version(proto_fork.get_previous_version(), "previous")?,
+        current_version: version(proto_fork.get_current_version(), "current")?,
+        epoch: Epoch::from(proto_fork.get_epoch()),
+    })
+}
+
+/// Creates a client for each of `servers`, in the same order.
+fn connect_each<C, F>(
+    servers: &[String],
+    connector: &Connector,
+    log: &slog::Logger,
+    new_client: F,
+) -> BeaconNodeFallback<C>
+where
+    F: Fn(Channel) -> C,
+{
+    BeaconNodeFallback::new(connect_clients(servers, connector, new_client), log.clone())
+}
+
+/// Returns a `(server, client)` pair for each of `servers`, in the same order.
+fn connect_clients<C, F>(
+    servers: &[String],
+    connector: &Connector,
+    new_client: F,
+) -> Vec<(String, C)>
+where
+    F: Fn(Channel) -> C,
+{
+    servers
+        .iter()
+        .map(|server| (server.clone(), new_client(connector.channel(server))))
+        .collect()
+}
+
+/// Opens the channels to the beacon nodes.
+#[derive(Clone)]
+struct Connector {
+    env: Arc<Environment>,
+    /// If set, channels use TLS, trusting only the PEM-encoded certificate authority given.
+    root_cert: Option<Vec<u8>>,
+}
+
+impl Connector {
+    fn channel(&self, server: &str) -> Channel {
+        let builder = ChannelBuilder::new(self.env.clone());
+        match &self.root_cert {
+            Some(root_cert) => {
+                let credentials = ChannelCredentialsBuilder::new()
+                    .root_cert(root_cert.clone())
+                    .build();
+                builder.secure_connect(server, credentials)
+            }
+            None => builder.connect(server),
+        }
+    }
+}
+
+/// Returns an error if any of `clients` which can be reached is on a network other than
+/// `network_id`, since a fallback node on another network would have the validators sign for the
+/// wrong chain.
+fn verify_node_networks<'a, I>(
+    clients: I,
+    network_id: u8,
+    audit_log: Option<&AuditLog>,
+    options: &RequestOptions,
+    log: &slog::Logger,
+) -> Result<(), String>
+where
+    I: Iterator<Item = (&'a str, &'a BeaconNodeServiceClient)>,
+{
+    for (server, client) in clients {
+        let info = audited(
+            audit_log,
+            options,
+            "BeaconNodeService/Info",
+            &Empty::new(),
+            |req, opt| client.info_opt(req, opt),
+        );
+        match info {
+            Ok(info) => {
+                if let Err(e) = verify_network_id(network_id, info.network_id) {
+                    error!(
+                        log,
+                        "Beacon node is on a different network";
+                        "node" => server,
+                        "error" => &e,
+                    );
+                    return Err(e);
+                }
+            }
+            Err(e) => warn!(
+                log,
+                "Fallback beacon node is unavailable";
+                "node" => server,
+                "error" => format!("{:?}", e),
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the fallback nodes of every client whenever the fallback servers file changes.
+struct ServerReload {
+    path: PathBuf,
+    /// When the file was last modified, as of when it was last read.
+    modified: Option<SystemTime>,
+    /// Connects to the fallback servers, given in order of preference, replacing the nodes of
+    /// every client.
+    reconnect: Box<dyn Fn(Vec<String>) -> Result<(), String> + Send + Sync>,
+}
+
+/// Returns when the file at `path` was last modified, or `None` if it cannot be read.
+fn file_modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Requests the node's information from the first beacon node to respond.
+fn request_node_info(
+    clients: &BeaconNodeFallback<BeaconNodeServiceClient>,
+    audit_log: Option<&AuditLog>,
+    options: &RequestOptions,
+) -> Result<NodeInfoResponse, String> {
+    clients.first_success(
+        |client| {
+            audited(
+                audit_log,
+                options,
+                "BeaconNodeService/Info",
+                &Empty::new(),
+                |req, opt| client.info_opt(req, opt),
+            )
+            .map_err(|e| match e {
+                grpcio::Error::RpcFailure(RpcStatus {
+                    status: RpcStatusCode::Unauthenticated,
+                    ..
+                }) => format!(
+                    "The beacon node refused the API token, check that --api-token matches the \
+                     token configured on the beacon node: {:?}",
+                    e
+                ),
+                e => format!("{:?}", e),
+            })
+        },
+        |_| true,
+        |e| e,
+    )
+}
+
+/// Returns a future which calls `connect` until it succeeds, waiting on a timer after each
+/// failure.
+///
+/// The first retry waits for `initial_delay`, doubling after each failure up to `max_delay`.
+/// Resolves to an error once `max_attempts` attempts have failed. If `max_attempts` is zero,
+/// retries forever. The attempt number and backoff are carried between iterations of the loop.
+fn retry_until_connected<T, U, F>(
+    connect: F,
+    max_attempts: u64,
+    initial_delay: Duration,
+    max_delay: Duration,
+    log: slog::Logger,
+) -> impl Future<Item = T, Error = error_chain::Error>
+where
+    U: std::fmt::Debug,
+    F: FnMut() -> Result<T, U>,
+{
+    let initial_state = (connect, log, 1, initial_delay.min(max_delay));
+    future::loop_fn(
+        initial_state,
+        move |(mut connect, log, attempt, retry_delay): (F, slog::Logger, u64, Duration)| {
+            match connect() {
+                Ok(value) => Either::A(future::ok(Loop::Break(value))),
+                Err(e) if max_attempts != 0 && attempt >= max_attempts => {
+                    error!(
+                        log,
+                        "Could not connect to beacon node, giving up";
+                        "error" => format!("{:?}", e),
+                        "attempts" => attempt,
+                    );
+                    Either::A(future::err(
+                        format!(
+                            "Unable to connect to beacon node after {} attempts",
+                            attempt
+                        )
+                        .into(),
+                    ))
+                }
+                Err(e) => {
+                    warn!(
+                        log,
+                        "Could not connect to beacon node";
+                        "error" => format!("{:?}", e),
+                        "attempt" => attempt,
+                        "retry_in" => format!("{} ms", retry_delay.as_millis()),
+                    );
+                    let next_delay = next_retry_delay(retry_delay, max_delay);
+                    Either::B(
+                        Delay::new(Instant::now() + retry_delay)
+                            .map_err(|e| format!("Connection retry timer failed: {:?}", e).into())
+                            .map(move |()| Loop::Continue((connect, log, attempt + 1, next_delay))),
+                    )
+                }
+            }
+        },
+    )
+}
+
+/// Returns double `delay`, capped at `max_delay`.
+fn next_retry_delay(delay: Duration, max_delay: Duration) -> Duration {
+    delay
+        .checked_mul(2)
+        .map_or(max_delay, |delay| delay.min(max_delay))
+}
+
+/// Atomically replaces the file at `path` with the given `slot` and the current UNIX time, in
+/// seconds.
+fn write_heartbeat(path: &Path, slot: Slot) -> Result<(), String> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| format!("Unable to read system time: {:?}", e))?
+        .as_secs();
+
+    replace_file(path, &format!("{} {}\n", slot.as_u64(), timestamp))
+        .map_err(|e| format!("Unable to write heartbeat: {:?}", e))
+}
+
+/// Atomically replaces the file at `path` with `contents`, so that a reader never sees a partial
+/// file.
+fn replace_file(path: &Path, contents: &str) -> std::io::Result<()> {
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(&temp_path, path)
+}
+
+/// Returns a builder for the validator service runtime, with `worker_threads` threads (if
+/// specified) each pinned to the `cpu_affinity` cores (if specified).
+fn runtime_builder(
+    worker_threads: Option<usize>,
+    cpu_affinity: Option<Vec<usize>>,
+    log: slog::Logger,
+) -> Builder {
+    let mut builder = Builder::new();
+    builder
+        .clock(Clock::system())
+        .name_prefix("validator-client-");
+
+    if let Some(threads) = worker_threads {
+        builder.core_threads(threads);
+    }
+
+    if let Some(cores) = cpu_affinity {
+        builder.after_start(move || {
+            if let Err(e) = pin_current_thread(&cores) {
+                warn!(
+                    log,
+                    "Unable to set CPU affinity";
+                    "cores" => format!("{:?}", cores),
+                    "error" => e
+                );
+            }
+        });
+    }
+
+    builder
+}
+
+/// Restricts the current thread to run only on the given CPU `cores`.
+#[cfg(target_os = "linux")]
+fn pin_current_thread(cores: &[usize]) -> Result<(), String> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for core in cores {
+            libc::CPU_SET(*core, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(format!("{}", std::io::Error::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// CPU affinity is only supported on Linux.
+#[cfg(not(target_os = "linux"))]
+fn pin_current_thread(_cores: &[usize]) -> Result<(), String> {
+    Err("CPU affinity is only supported on Linux".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_producer::BeaconNodeError;
+    use crate::config::DEFAULT_AGGREGATION_TIME;
+    use crate::duties::test_node::TestBeaconNode;
+    use crate::duties::{BeaconNodeDutiesError, EpochDuties, EpochDuty};
+    use crate::spans::InMemorySpanExporter;
+    use futures::Future;
+    use grpcio::{RpcContext, Server, ServerBuilder, ServerCredentialsBuilder, UnarySink};
+    use protos::services::{
+        Attestation as GrpcAttestation, AttestationData as GrpcAttestationData,
+        ProduceAggregateAttestationRequest, ProduceAggregateAttestationResponse,
+        ProduceAttestationDataRequest, ProduceAttestationDataResponse,
+        PublishAggregateAndProofRequest, PublishAttestationRequest, PublishAttestationResponse,
+    };
+    use protos::services_grpc::{
+        create_attestation_service, create_beacon_node_service, AttestationService,
+        BeaconNodeService,
+    };
+    use slot_clock::TestingSlotClock;
+    use ssz::Encode;
+    use types::{
+        AggregateSignature, Attestation, AttestationData, BitList, Checkpoint, Crosslink, Hash256,
+        Keypair, MinimalEthSpec,
+    };
+
+    fn single_node<C>(client: C) -> BeaconNodeFallback<C> {
+        BeaconNodeFallback::new(vec![("127.0.0.1:1".to_string(), client)], null_logger())
+    }
+
+    fn null_logger() -> slog::Logger {
+        slog::Logger::root(slog::Discard, slog::o!())
+    }
+
+    /// Returns a service for `keypairs` which requests duties from `beacon_node` and whose slot is
+    /// set manually. The gRPC clients connect to an address with no server.
+    fn test_service(
+        keypairs: Vec<Keypair>,
+        beacon_node: Arc<TestBeaconNode>,
+    ) -> Service<TestBeaconNode, Keypair, MinimalEthSpec, TestingSlotClock> {
+        let spec = MinimalEthSpec::default_spec();
+        let slots_per_epoch = MinimalEthSpec::slots_per_epoch();
+        let env = Arc::new(EnvBuilder::new().build());
+        let channel = || ChannelBuilder::new(env.clone()).connect("127.0.0.1:1");
+        let options = RequestOptions {
+            timeout: Duration::from_secs(1),
+            api_token: None,
+        };
+
+        Service {
+            fork_schedule: ForkSchedule::new(Fork::default(), vec![]).unwrap(),
+            slot_clock: TestingSlotClock::new(
+                Slot::new(0),
+                Duration::from_secs(0),
+                Duration::from_millis(spec.milliseconds_per_slot),
+            ),
+            genesis_time: 0,
+            genesis_slot: Slot::new(0),
+            slot_processing_delay: Duration::from_secs(0),
+            current_slot: None,
+            clock_drift: ClockDrift::default(),
+            clock_advanced_at: None,
+            node_clock_lead: 0,
+            node_clock_ahead: <_>::default(),
+            slot_errors: Arc::new(AtomicU64::new(0)),
+            slots_per_epoch,
+            spec: Arc::new(spec),
+            duties_manager: Arc::new(DutiesManager {
+                duties_map: RwLock::new(EpochDutiesMap::new(slots_per_epoch)),
+                signers: Arc::new(keypairs),
+                beacon_node,
+                batch_size: DUTIES_BATCH_SIZE,
+                max_concurrent_requests: 1,
+                cache_path: None,
+                validator_statuses: <_>::default(),
+                doppelganger: <_>::default(),
+                monitor_balances: false,
+                validator_balances: <_>::default(),
+                log_rewards: false,
+                validator_rewards: <_>::default(),
+                validator_indices: <_>::default(),
+                syncing_distance: 0,
+            }),
+            beacon_node_client: Arc::new(single_node(BeaconNodeServiceClient::new(channel()))),
+            server_reload: None,
+            audit_log: None,
+            request_options: options.clone(),
+            beacon_block_client: Arc::new(single_node(BeaconBlockGrpcClient::new(
+                Arc::new(BeaconBlockServiceClient::new(channel())),
+                None,
+                options.clone(),
+            ))),
+            attestation_client: Arc::new(single_node(AttestationGrpcClient::new(
+                Arc::new(AttestationServiceClient::new(channel())),
+                BeaconNodeServiceClient::new(channel()),
+                None,
+                options.clone(),
+            ))),
+            aggregation_client: Arc::new(single_node(AttestationGrpcClient::new(
+                Arc::new(AttestationServiceClient::new(channel())),
+                BeaconNodeServiceClient::new(channel()),
+                None,
+                options,
+            ))),
+            log: null_logger(),
+            observer: Arc::new(NoOpObserver),
+            slot_summary: SlotSummaryLevel::Off,
+            heartbeat_file: None,
+            tracer: Tracer::default(),
+            metrics_file: None,
+            graffiti: [0; 32],
+            max_publish_retries: 0,
+            dry_run: false,
+            attestation_freshness_tolerance: None,
+            attestation_syncing_distance: None,
+            aggregation_time: DEFAULT_AGGREGATION_TIME,
+            block_failures: Arc::new(BlockFailureTracker::new(0)),
+            attested_heads: Arc::new(AttestedHeads::default()),
+            selection_proofs: Arc::new(SelectionProofCache::new(true, E::slots_per_epoch())),
+            producer_panics: Arc::new(AtomicU64::new(0)),
+            skip_counters: Arc::new(SkipCounters::default()),
+            slashing_protection: Arc::new(SlashingDatabase::in_memory()),
+            in_flight: Arc::new(InFlightDuties::default()),
+            signing_pool: Arc::new(SigningPool::new(1)),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[test]
+    fn manual_slot_clock_drives_each_slot() {
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        // With the duties unknown, each slot's duties are skipped and counted.
+        beacon_node
+            .set_next_duties_result(Err(BeaconNodeDutiesError::RemoteFailure("syncing".into())));
+        let mut service = test_service(vec![Keypair::random()], beacon_node.clone());
+        let request_count = || *beacon_node.request_count.lock().unwrap();
+
+        for slot in 0..3 {
+            service.slot_clock.set_slot(slot);
+            assert!(service.per_slot_execution().is_ok());
+
+            assert_eq!(service.current_slot, Some(Slot::new(slot)));
+            assert_eq!(request_count(), slot as usize + 1);
+            assert_eq!(
+                service.skip_counters.count(SkipReason::UnknownDuties),
+                slot + 1
+            );
+        }
+        let (epoch, _) = beacon_node
+            .request_duties_input
+            .read()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(epoch, Epoch::new(0));
+
+        // A repeated or earlier slot is refused, without fetching or processing duties.
+        assert!(service.per_slot_execution().is_err());
+        service.slot_clock.set_slot(1);
+        assert!(service.per_slot_execution().is_err());
+        assert_eq!(service.current_slot, Some(Slot::new(2)));
+        assert_eq!(request_count(), 3);
+        assert_eq!(service.skip_counters.count(SkipReason::UnknownDuties), 3);
+
+        // Processing resumes at the next slot.
+        service.slot_clock.set_slot(3);
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.current_slot, Some(Slot::new(3)));
+        assert_eq!(request_count(), 4);
+    }
+
+    #[test]
+    fn attestation_duties_in_two_slots_both_started() {
+        let keypair = Keypair::random();
+        let duty = |slot| AttestationDuty {
+            slot: Slot::new(slot),
+            shard: slot,
+            committee_index: 0,
+            committee_len: 1,
+        };
+        let mut duties = EpochDuties::new();
+        duties.insert(
+            keypair.pk.clone(),
+            Some(EpochDuty {
+                block_production_slot: None,
+                attestation_duties: vec![duty(1), duty(2)],
+                validator_index: None,
+            }),
+        );
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        beacon_node.set_next_duties_result(Ok(duties));
+
+        let mut service = test_service(vec![keypair], beacon_node);
+        let messages = Arc::new(std::sync::Mutex::new(vec![]));
+        service.log = slog::Logger::root(CaptureDrain(messages.clone()), slog::o!());
+        let produced = || {
+            messages
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|message| *message == "Producing an attestation")
+                .count()
+        };
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        for slot in 1..3 {
+            service.slot_clock.set_slot(slot);
+            assert!(runtime
+                .block_on(futures::future::lazy(|| service.per_slot_execution()))
+                .is_ok());
+        }
+        // Waits for the attestations which are delayed until a third of the way into the slot.
+        runtime.run().unwrap();
+        assert_eq!(service.in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+
+        // Neither duty overwrote the other.
+        assert_eq!(produced(), 2);
+    }
+
+    #[test]
+    fn transient_slot_errors_do_not_stop_service() {
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        beacon_node
+            .set_next_duties_result(Err(BeaconNodeDutiesError::RemoteFailure("syncing".into())));
+        let service = test_service(vec![Keypair::random()], beacon_node);
+        let slot_errors = service.slot_errors.clone();
+
+        // The clock stays at the genesis slot, so each tick after the first is a duplicate slot.
+        let ticks = futures::stream::iter_ok::<_, ()>(vec![(); 3]);
+        assert_eq!(service.run_each_slot(ticks).wait(), Ok(()));
+        assert_eq!(slot_errors.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fatal_slot_error_stops_service() {
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        let mut service = test_service(vec![Keypair::random()], beacon_node);
+        let slot_errors = service.slot_errors.clone();
+
+        // The clock last advanced long ago, and is still at the previous slot.
+        let slot_duration = Duration::from_millis(service.spec.milliseconds_per_slot);
+        service.current_slot = Some(Slot::new(0));
+        service.clock_advanced_at = Some(Instant::now() - slot_duration * (MAX_STALLED_SLOTS + 1));
+
+        let ticks_seen = Arc::new(AtomicU64::new(0));
+        let ticks = {
+            let ticks_seen = ticks_seen.clone();
+            futures::stream::iter_ok::<_, ()>(vec![(); 3]).inspect(move |_| {
+                ticks_seen.fetch_add(1, Ordering::SeqCst);
+            })
+        };
+        assert!(service.run_each_slot(ticks).wait().is_err());
+        assert_eq!(ticks_seen.load(Ordering::SeqCst), 1);
+        assert_eq!(slot_errors.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn node_clock_ahead_trusted_or_halted() {
+        let beacon_node = || {
+            let beacon_node = Arc::new(TestBeaconNode::default());
+            beacon_node.set_next_duties_result(Err(BeaconNodeDutiesError::RemoteFailure(
+                "syncing".into(),
+            )));
+            beacon_node
+        };
+        let ms_per_slot = MinimalEthSpec::default_spec().milliseconds_per_slot;
+        let node_info = |slot: u64| {
+            let mut node_info = NodeInfoResponse::new();
+            node_info.set_current_time_ms(slot * ms_per_slot + 1);
+            node_info
+        };
+        let local_time = |slot: u64| Duration::from_millis(slot * ms_per_slot);
+
+        // The beacon node's slot is trusted.
+        let trusting_node = beacon_node();
+        let mut service = test_service(vec![Keypair::random()], trusting_node.clone());
+        service.node_clock_ahead = NodeClockAheadAction::Trust;
+        service.slot_clock.set_slot(10);
+        service.record_node_time(&node_info(12), local_time(10));
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.current_slot, Some(Slot::new(12)));
+        assert_eq!(*trusting_node.request_count.lock().unwrap(), 1);
+
+        // No duties are performed whilst the local clock is behind, by default.
+        let halting_node = beacon_node();
+        let keypair = Keypair::random();
+        let mut service = test_service(vec![keypair.clone()], halting_node.clone());
+        assert_eq!(service.node_clock_ahead, NodeClockAheadAction::Halt);
+        let mut duties = EpochDuties::new();
+        duties.insert(
+            keypair.pk.clone(),
+            Some(EpochDuty {
+                block_production_slot: Some(Slot::new(10)),
+                attestation_duties: vec![],
+                validator_index: None,
+            }),
+        );
+        service
+            .duties_manager
+            .duties_map
+            .write()
+            .unwrap()
+            .insert(Epoch::new(1), duties);
+        service.slot_clock.set_slot(10);
+        service.record_node_time(&node_info(12), local_time(10));
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.current_slot, Some(Slot::new(10)));
+        assert_eq!(*halting_node.request_count.lock().unwrap(), 0);
+        // The proposal which fell in the halted slot is counted as skipped.
+        assert_eq!(service.skip_counters.count(SkipReason::ClockHalt), 1);
+
+        // Duties resume once the local clock has caught up.
+        service.slot_clock.set_slot(11);
+        service.record_node_time(&node_info(11), local_time(11));
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.current_slot, Some(Slot::new(11)));
+        assert_eq!(*halting_node.request_count.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn node_clock_jitter_across_slot_boundary_ignored() {
+        let ms_per_slot = MinimalEthSpec::default_spec().milliseconds_per_slot;
+        let mut node_info = NodeInfoResponse::new();
+        // The node is in slot 10, but only 1ms ahead of the local clock, which is still in slot 9.
+        node_info.set_current_time_ms(10 * ms_per_slot);
+        let local_time = Duration::from_millis(10 * ms_per_slot - 1);
+
+        for &action in &[NodeClockAheadAction::Trust, NodeClockAheadAction::Halt] {
+            let beacon_node = Arc::new(TestBeaconNode::default());
+            beacon_node.set_next_duties_result(Err(BeaconNodeDutiesError::RemoteFailure(
+                "syncing".into(),
+            )));
+            let mut service = test_service(vec![Keypair::random()], beacon_node.clone());
+            service.node_clock_ahead = action;
+            service.slot_clock.set_slot(9);
+
+            service.record_node_time(&node_info, local_time);
+            assert_eq!(service.node_clock_lead, 0);
+            assert!(service.per_slot_execution().is_ok());
+            assert_eq!(service.current_slot, Some(Slot::new(9)));
+            assert_eq!(*beacon_node.request_count.lock().unwrap(), 1);
+        }
+
+        // A lead of a whole slot is still detected.
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        let mut service = test_service(vec![Keypair::random()], beacon_node);
+        node_info.set_current_time_ms(10 * ms_per_slot + ms_per_slot - 1);
+        service.record_node_time(&node_info, local_time);
+        assert_eq!(service.node_clock_lead, 1);
+    }
+
+    #[test]
+    fn clock_jump_reported_as_drift() {
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        beacon_node
+            .set_next_duties_result(Err(BeaconNodeDutiesError::RemoteFailure("syncing".into())));
+        let mut service = test_service(vec![Keypair::random()], beacon_node);
+
+        for slot in 0..2 {
+            service.slot_clock.set_slot(slot);
+            assert!(service.per_slot_execution().is_ok());
+            assert_eq!(service.clock_drift.skew, 0);
+        }
+
+        // The clock jumps forward three slots in a single tick.
+        service.slot_clock.set_slot(5);
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.current_slot, Some(Slot::new(5)));
+        assert_eq!(service.clock_drift.skew, 3);
+
+        // The jump is reported once, and ticks are measured from the new slot.
+        service.slot_clock.set_slot(6);
+        assert!(service.per_slot_execution().is_ok());
+        assert_eq!(service.clock_drift.skew, 0);
+    }
+
+    #[test]
+    fn small_skew_not_reported() {
+        let mut drift = ClockDrift::default();
+
+        assert_eq!(drift.tick(Slot::new(10)), None);
+        // A single skipped slot is within tolerance...
+        assert_eq!(drift.tick(Slot::new(12)), None);
+        assert_eq!(drift.skew, 1);
+        // ...but drift accumulates across ticks.
+        assert_eq!(drift.tick(Slot::new(14)), Some(Slot::new(12)));
+        assert_eq!(drift.skew, 2);
+    }
+
+    #[test]
+    fn heartbeat_updates_each_slot() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("heartbeat");
+
+        let read_slot = || -> u64 {
+            let contents = fs::read_to_string(&path).unwrap();
+            contents.split_whitespace().next().unwrap().parse().unwrap()
+        };
+
+        write_heartbeat(&path, Slot::new(1)).unwrap();
+        assert_eq!(read_slot(), 1);
+
+        write_heartbeat(&path, Slot::new(2)).unwrap();
+        assert_eq!(read_slot(), 2);
+    }
+
+    #[test]
+    fn metrics_include_counters() {
+        let service = test_service(vec![], Arc::new(TestBeaconNode::default()));
+        let log = null_logger();
+        let skip = |reason| {
+            service
+                .skip_counters
+                .record("attestation", "0x00", Slot::new(1), reason, &log)
+        };
+        skip(SkipReason::Expired);
+        skip(SkipReason::Expired);
+        skip(SkipReason::BeaconNodeError);
+        service.block_failures.record(Slot::new(1), false, &log);
+        service.slot_errors.fetch_add(3, Ordering::SeqCst);
+        let pubkey = Keypair::random().pk;
+        let totals = RewardTotals {
+            rewards_gwei: 1_500,
+            penalties_gwei: 20,
+        };
+        let mut rewards = service.duties_manager.validator_rewards.write().unwrap();
+        rewards.insert(pubkey.clone(), totals);
+        drop(rewards);
+        let labels = format!("{{validator=\"{}\"}}", pubkey.as_hex_string());
+        let rewards = format!("validator_rewards_gwei_total{} 1500", labels);
+        let penalties = format!("validator_penalties_gwei_total{} 20", labels);
+
+        let metrics = service.metrics();
+        let lines: Vec<_> = metrics.lines().collect();
+        for expected in &[
+            "# TYPE validator_duties_skipped_total counter",
+            "validator_duties_skipped_total{reason=\"beacon_node_error\"} 1",
+            "validator_duties_skipped_total{reason=\"expired\"} 2",
+            "validator_block_proposal_consecutive_failures 1",
+            "validator_producer_panics_total 0",
+            "validator_slot_errors_total 3",
+            "validator_message_sink_dropped_total 0",
+            "validator_message_sink_queued_bytes 0",
+            "# TYPE validator_balance_gwei gauge",
+            rewards.as_str(),
+            penalties.as_str(),
+        ] {
+            assert!(
+                lines.contains(expected),
+                "missing {:?} in {}",
+                expected,
+                metrics
+            );
+        }
+    }
+
+    #[test]
+    fn genesis_wait_ends_at_genesis() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let slot_clock = SystemTimeSlotClock::new(
+            Slot::new(0),
+            now + Duration::from_millis(200),
+            Duration::from_millis(100),
+        );
+
+        let remaining = time_until_genesis(&slot_clock).expect("genesis is in the future");
+        assert!(remaining <= Duration::from_millis(200));
+
+        std::thread::sleep(remaining + Duration::from_millis(10));
+        assert_eq!(time_until_genesis(&slot_clock), None);
+        assert!(slot_clock.now().is_some());
+    }
+
+    #[test]
+    fn attestations_wait_until_a_third_of_the_slot() {
+        let slot_duration = Duration::from_secs(12);
+
+        assert_eq!(
+            attestation_wait(slot_duration, Duration::from_secs(0)),
+            Duration::from_secs(4)
+        );
+        assert_eq!(
+            attestation_wait(slot_duration, Duration::from_millis(100)),
+            Duration::from_millis(3_900)
+        );
+        // Attest immediately if a third of the slot has already passed.
+        assert_eq!(
+            attestation_wait(slot_duration, Duration::from_secs(5)),
+            Duration::from_secs(0)
+        );
+    }
+
+    /// A slot clock which is always `time_into_slot` into a slot of `slot_duration`.
+    struct FixedTimeSlotClock {
+        slot_duration: Duration,
+        time_into_slot: Duration,
+    }
+
+    impl SlotClock for FixedTimeSlotClock {
+        fn new(_genesis_slot: Slot, _genesis_duration: Duration, slot_duration: Duration) -> Self {
+            Self {
+                slot_duration,
+                time_into_slot: Duration::from_secs(0),
+            }
+        }
+
+        fn now(&self) -> Option<Slot> {
+            Some(Slot::new(1))
+        }
+
+        fn duration_to_next_slot(&self) -> Option<Duration> {
+            self.slot_duration.checked_sub(self.time_into_slot)
+        }
+
+        fn slot_duration(&self) -> Duration {
+            self.slot_duration
+        }
+    }
+
+    #[test]
+    fn aggregates_wait_until_the_aggregation_time() {
+        let slot_clock = |time_into_slot| FixedTimeSlotClock {
+            slot_duration: Duration::from_secs(12),
+            time_into_slot,
+        };
+        let waits = |elapsed, aggregation_time| {
+            let slot_clock = slot_clock(elapsed);
+            let elapsed = time_into_slot(&slot_clock);
+            (
+                attestation_wait(slot_clock.slot_duration(), elapsed),
+                duty_wait(aggregation_time, slot_clock.slot_duration(), elapsed),
+            )
+        };
+        let three_quarters = SlotFraction {
+            numerator: 3,
+            denominator: 4,
+        };
+
+        // At the start of the slot, aggregates wait until the two-thirds mark, after attestations.
+        assert_eq!(
+            waits(Duration::from_secs(0), DEFAULT_AGGREGATION_TIME),
+            (Duration::from_secs(4), Duration::from_secs(8))
+        );
+        assert_eq!(
+            waits(Duration::from_millis(5_500), DEFAULT_AGGREGATION_TIME),
+            (Duration::from_secs(0), Duration::from_millis(2_500))
+        );
+        // Aggregate immediately once the aggregation time has passed.
+        assert_eq!(
+            waits(Duration::from_secs(9), DEFAULT_AGGREGATION_TIME),
+            (Duration::from_secs(0), Duration::from_secs(0))
+        );
+        // A configured time is used in place of the default.
+        assert_eq!(
+            waits(Duration::from_secs(1), three_quarters),
+            (Duration::from_secs(3), Duration::from_secs(8))
+        );
+    }
+
+    #[test]
+    fn mismatched_network_id_rejected() {
+        assert_eq!(verify_network_id(2, 2), Ok(()));
+        assert_eq!(
+            verify_network_id(2, 1),
+            Err(
+                "Beacon node has the wrong chain id. Expected chain id: 2, node's chain id: 1"
+                    .to_string()
+            )
+        );
+        // 258 truncates to 2.
+        assert!(verify_network_id(2, 258).is_err());
+    }
+
+    #[test]
+    fn clock_skew_beyond_threshold_rejected_when_configured() {
+        let log = null_logger();
+        let mut node_info = NodeInfoResponse::new();
+        node_info.set_current_time_ms(1_000_000);
+        let mut config = ValidatorConfig::default();
+        config.max_clock_skew_ms = 500;
+
+        let local_time = Duration::from_millis;
+
+        // Within the threshold either side.
+        config.clock_skew_action = ClockSkewAction::Reject;
+        assert_eq!(
+            verify_clock_skew(&node_info, local_time(1_000_500), &config, &log),
+            Ok(())
+        );
+        assert_eq!(
+            verify_clock_skew(&node_info, local_time(999_500), &config, &log),
+            Ok(())
+        );
+
+        assert_eq!(
+            verify_clock_skew(&node_info, local_time(998_000), &config, &log),
+            Err(
+                "Local clock is 2000ms behind the beacon node's, more than the maximum of 500ms"
+                    .to_string()
+            )
+        );
+
+        // Only a warning is logged unless configured to reject.
+        config.clock_skew_action = ClockSkewAction::Warn;
+        assert_eq!(
+            verify_clock_skew(&node_info, local_time(998_000), &config, &log),
+            Ok(())
+        );
+
+        // A node which does not report its time is not checked.
+        config.clock_skew_action = ClockSkewAction::Reject;
+        assert_eq!(
+            verify_clock_skew(&NodeInfoResponse::new(), local_time(998_000), &config, &log),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn fork_converted_from_beacon_node_response() {
+        let mut proto_fork = ProtoFork::new();
+        proto_fork.set_previous_version(vec![0, 0, 0, 1]);
+        proto_fork.set_current_version(vec![0, 0, 0, 2]);
+        proto_fork.set_epoch(10);
+
+        assert_eq!(
+            fork_from_proto(&proto_fork),
+            Ok(Fork {
+                previous_version: [0, 0, 0, 1],
+                current_version: [0, 0, 0, 2],
+                epoch: Epoch::new(10),
+            })
+        );
+
+        // A malformed version is an error, rather than a panic or a truncated version.
+        proto_fork.set_current_version(vec![0, 0, 2]);
+        assert!(fork_from_proto(&proto_fork).is_err());
+        proto_fork.set_current_version(vec![0, 0, 0, 0, 2]);
+        assert!(fork_from_proto(&proto_fork).is_err());
+    }
+
+    #[test]
+    fn connect_gives_up_after_max_attempts() {
+        let mut calls = 0;
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let result: error_chain::Result<()> = runtime.block_on(retry_until_connected(
+            || {
+                calls += 1;
+                Err("unreachable")
+            },
+            3,
+            Duration::from_millis(1),
+            Duration::from_millis(2),
+            null_logger(),
+        ));
+
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn connect_retries_until_success() {
+        let mut calls = 0;
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        let started = Instant::now();
+        let result = runtime.block_on(retry_until_connected(
+            || {
+                calls += 1;
+                if calls < 3 {
+                    Err("unreachable")
+                } else {
+                    Ok(calls)
+                }
+            },
+            0,
+            Duration::from_millis(10),
+            Duration::from_millis(20),
+            null_logger(),
+        ));
+
+        assert_eq!(result.unwrap(), 3);
+        // The retries waited 10 ms, then 20 ms.
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn connect_retry_delay_backs_off_exponentially() {
+        let max_delay = CONNECT_MAX_RETRY_DELAY;
+        let delays: Vec<u64> = (0..7)
+            .scan(CONNECT_INITIAL_RETRY_DELAY, |delay, _| {
+                let current = *delay;
+                *delay = next_retry_delay(current, max_delay);
+                Some(current.as_secs())
+            })
+            .collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 30, 30]);
+    }
+
+    #[test]
+    fn runtime_uses_configured_worker_threads() {
+        use std::collections::HashSet;
+        use std::sync::{Barrier, Mutex};
+
+        const THREADS: usize = 3;
+
+        let runtime = runtime_builder(Some(THREADS), None, null_logger())
+            .build()
+            .unwrap();
+
+        // Each task blocks its worker until all of them are running, so this only completes if
+        // there are at least `THREADS` workers.
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let workers = Arc::new(Mutex::new(HashSet::new()));
+        let tasks = (0..THREADS)
+            .map(|_| {
+                let barrier = barrier.clone();
+                let workers = workers.clone();
+                futures::future::lazy(move || {
+                    workers.lock().unwrap().insert(std::thread::current().id());
+                    barrier.wait();
+                    Ok::<_, ()>(())
+                })
+            })
+            .map(|task| futures::sync::oneshot::spawn(task, &runtime.executor()))
+            .collect::<Vec<_>>();
+
+        futures::future::join_all(tasks).wait().unwrap();
+        runtime.shutdown_now().wait().unwrap();
+
+        assert_eq!(workers.lock().unwrap().len(), THREADS);
+    }
+
+    /// Records the message of each log record.
+    struct CaptureDrain(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl slog::Drain for CaptureDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(&self, record: &slog::Record, _: &slog::OwnedKVList) -> Result<(), slog::Never> {
+            self.0.lock().unwrap().push(record.msg().to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn producer_panic_is_caught_logged_and_counted() {
+        let messages = Arc::new(std::sync::Mutex::new(vec![]));
+        let log = slog::Logger::root(CaptureDrain(messages.clone()), slog::o!());
+        let panics = Arc::new(AtomicU64::new(0));
+
+        let context = || ProducerContext {
+            duty: "block",
+            validator: "validator".to_string(),
+            slot: Slot::new(1),
+            tracer: Tracer::default(),
+        };
+
+        let in_flight = Arc::new(InFlightDuties::default());
+        let pool = Arc::new(SigningPool::new(1));
+
+        spawn_producer(
+            context(),
+            None,
+            panics.clone(),
+            &in_flight,
+            &pool,
+            log.clone(),
+            || panic!("bad duty"),
+        );
+        assert_eq!(in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+        assert_eq!(*messages.lock().unwrap(), vec!["Producer thread panicked"]);
+
+        // The panic did not escape the pool's only thread, and producers which complete normally
+        // are not counted.
+        spawn_producer(
+            context(),
+            None,
+            panics.clone(),
+            &in_flight,
+            &pool,
+            log,
+            || {},
+        );
+        assert_eq!(in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+        assert_eq!(panics.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn shutdown_waits_for_in_flight_duties() {
+        let in_flight = Arc::new(InFlightDuties::default());
+        let (release_send, release) = std::sync::mpsc::channel::<()>();
+        let pool = Arc::new(SigningPool::new(1));
+
+        spawn_producer(
+            ProducerContext {
+                duty: "attestation",
+                validator: "validator".to_string(),
+                slot: Slot::new(1),
+                tracer: Tracer::default(),
+            },
+            None,
+            Arc::new(AtomicU64::new(0)),
+            &in_flight,
+            &pool,
+            null_logger(),
+            move || {
+                release.recv().unwrap();
+            },
+        );
+
+        assert_eq!(in_flight.count(), 1);
+        assert_eq!(in_flight.wait_until_idle(Duration::from_millis(10)), 1);
+
+        release_send.send(()).unwrap();
+        assert_eq!(in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+    }
+
+    #[test]
+    fn delayed_producer_does_not_occupy_signing_thread() {
+        let in_flight = Arc::new(InFlightDuties::default());
+        let completed = Arc::new(std::sync::Mutex::new(vec![]));
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let spawn = {
+            let in_flight = in_flight.clone();
+            let completed = completed.clone();
+            let pool = Arc::new(SigningPool::new(1));
+            move |duty, start_at| {
+                let completed = completed.clone();
+                spawn_producer(
+                    ProducerContext {
+                        duty,
+                        validator: "validator".to_string(),
+                        slot: Slot::new(1),
+                        tracer: Tracer::default(),
+                    },
+                    start_at,
+                    Arc::new(AtomicU64::new(0)),
+                    &in_flight,
+                    &pool,
+                    null_logger(),
+                    move || completed.lock().unwrap().push(duty),
+                );
+            }
+        };
+        runtime
+            .block_on(futures::future::lazy(move || {
+                spawn(
+                    "attestation",
+                    Some(Instant::now() + Duration::from_millis(200)),
+                );
+                spawn("block", None);
+                Ok::<_, ()>(())
+            }))
+            .unwrap();
+
+        // The pool's only thread produces the block whilst the attestation waits.
+        assert_eq!(in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+        assert_eq!(*completed.lock().unwrap(), vec!["block", "attestation"]);
+    }
+
+    #[test]
+    fn slot_summary_counts_mixed_work() {
+        let attestation_duty = |shard| AttestationDuty {
+            slot: Slot::new(1),
+            shard,
+            committee_index: 0,
+            committee_len: 1,
+        };
+        let work = vec![
+            (
+                0,
+                WorkInfo {
+                    produce_block: true,
+                    validator_index: None,
+                    attestation_duties: vec![attestation_duty(0)],
+                },
+            ),
+            (
+                1,
+                WorkInfo {
+                    produce_block: false,
+                    validator_index: None,
+                    attestation_duties: vec![attestation_duty(1), attestation_duty(2)],
+                },
+            ),
+        ];
+
+        let mut summary = SlotSummary {
+            skipped: 1,
+            ..SlotSummary::default()
+        };
+        summary.record_work(&work);
+
+        assert_eq!(
+            summary,
+            SlotSummary {
+                blocks: 1,
+                attestations: 3,
+                skipped: 1,
+                duties_fetch_time: Duration::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn fallback_servers_reloaded_when_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fallback_servers");
+        fs::write(&path, "10.0.0.2:5052\n").unwrap();
+
+        let mut service = test_service(vec![], Arc::new(TestBeaconNode::default()));
+        let reloads = Arc::new(Mutex::new(vec![]));
+        let recorded = reloads.clone();
+        service.server_reload = Some(ServerReload {
+            path: path.clone(),
+            modified: None,
+            reconnect: Box::new(move |servers| {
+                recorded.lock().unwrap().push(servers);
+                Ok(())
+            }),
+        });
+
+        service.reload_servers();
+        assert_eq!(
+            *reloads.lock().unwrap(),
+            vec![vec!["10.0.0.2:5052".to_string()]]
+        );
+
+        // The file is only read again once it changes.
+        service.reload_servers();
+        assert_eq!(reloads.lock().unwrap().len(), 1);
+
+        // A file which cannot be parsed keeps the current nodes.
+        fs::write(&path, "10.0.0.3\n").unwrap();
+        service.server_reload.as_mut().unwrap().modified = None;
+        service.reload_servers();
+        assert_eq!(reloads.lock().unwrap().len(), 1);
+    }
+
+    /// Answers every attestation request for epoch 0, recording the name of each request.
+    #[derive(Clone, Default)]
+    struct MockAttestationNode {
+        requests: Arc<std::sync::Mutex<Vec<&'static str>>>,
+    }
+
+    impl MockAttestationNode {
+        fn attestation(shard: u64) -> Attestation<MinimalEthSpec> {
+            let mut aggregation_bits = BitList::with_capacity(1).unwrap();
+            aggregation_bits.set(0, true).unwrap();
+            Attestation {
+                aggregation_bits,
+                data: AttestationData {
+                    beacon_block_root: Hash256::zero(),
+                    source: Checkpoint::default(),
+                    target: Checkpoint::default(),
+                    crosslink: Crosslink {
+                        shard,
+                        ..Crosslink::default()
+                    },
+                },
+                custody_bits: BitList::with_capacity(1).unwrap(),
+                signature: AggregateSignature::new(),
+            }
+        }
+
+        fn published(&self, ctx: RpcContext, sink: UnarySink<PublishAttestationResponse>) {
+            let mut reply = PublishAttestationResponse::new();
+            reply.set_success(true);
+            ctx.spawn(sink.success(reply).map_err(|_| ()))
+        }
+    }
+
+    impl AttestationService for MockAttestationNode {
+        fn produce_attestation_data(
+            &mut self,
+            ctx: RpcContext,
+            req: ProduceAttestationDataRequest,
+            sink: UnarySink<ProduceAttestationDataResponse>,
+        ) {
+            self.requests.lock().unwrap().push("ProduceAttestationData");
+            let mut data = GrpcAttestationData::new();
+            data.set_ssz(Self::attestation(req.get_shard()).data.as_ssz_bytes());
+            let mut reply = ProduceAttestationDataResponse::new();
+            reply.set_attestation_data(data);
+            ctx.spawn(sink.success(reply).map_err(|_| ()))
+        }
+
+        fn publish_attestation(
+            &mut self,
+            ctx: RpcContext,
+            _req: PublishAttestationRequest,
+            sink: UnarySink<PublishAttestationResponse>,
+        ) {
+            self.requests.lock().unwrap().push("PublishAttestation");
+            self.published(ctx, sink)
+        }
+
+        fn produce_aggregate_attestation(
+            &mut self,
+            ctx: RpcContext,
+            req: ProduceAggregateAttestationRequest,
+            sink: UnarySink<ProduceAggregateAttestationResponse>,
+        ) {
+            self.requests
+                .lock()
+                .unwrap()
+                .push("ProduceAggregateAttestation");
+            let mut aggregate = GrpcAttestation::new();
+            aggregate.set_ssz(Self::attestation(req.get_shard()).as_ssz_bytes());
+            let mut reply = ProduceAggregateAttestationResponse::new();
+            reply.set_aggregate(aggregate);
+            ctx.spawn(sink.success(reply).map_err(|_| ()))
+        }
+
+        fn publish_aggregate_and_proof(
+            &mut self,
+            ctx: RpcContext,
+            _req: PublishAggregateAndProofRequest,
+            sink: UnarySink<PublishAttestationResponse>,
+        ) {
+            self.requests
+                .lock()
+                .unwrap()
+                .push("PublishAggregateAndProof");
+            self.published(ctx, sink)
+        }
+    }
+
+    /// Returns a client of `node`, and the server it is served by.
+    fn mock_attestation_client(
+        env: &Arc<Environment>,
+        node: MockAttestationNode,
+    ) -> (Server, BeaconNodeFallback<AttestationGrpcClient>) {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_attestation_service(node))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let address = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+        let channel = ChannelBuilder::new(env.clone()).connect(&address);
+        let client = AttestationGrpcClient::new(
+            Arc::new(AttestationServiceClient::new(channel.clone())),
+            BeaconNodeServiceClient::new(channel),
+            None,
+            RequestOptions {
+                timeout: Duration::from_secs(5),
+                api_token: None,
+            },
+        );
+        (server, single_node(client))
+    }
+
+    #[test]
+    fn aggregates_published_to_aggregate_servers() {
+        let keypair = Keypair::random();
+        let mut duties = EpochDuties::new();
+        duties.insert(
+            keypair.pk.clone(),
+            Some(EpochDuty {
+                block_production_slot: None,
+                attestation_duties: vec![AttestationDuty {
+                    slot: Slot::new(1),
+                    shard: 1,
+                    committee_index: 0,
+                    committee_len: 1,
+                }],
+                validator_index: Some(0),
+            }),
+        );
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        beacon_node.set_next_duties_result(Ok(duties));
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let attestation_node = MockAttestationNode::default();
+        let aggregate_node = MockAttestationNode::default();
+        let (_attestation_server, attestation_client) =
+            mock_attestation_client(&env, attestation_node.clone());
+        let (_aggregate_server, aggregation_client) =
+            mock_attestation_client(&env, aggregate_node.clone());
+
+        let mut service = test_service(vec![keypair], beacon_node);
+        service.attestation_client = Arc::new(attestation_client);
+        service.aggregation_client = Arc::new(aggregation_client);
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        service.slot_clock.set_slot(1);
+        assert!(runtime
+            .block_on(futures::future::lazy(|| service.per_slot_execution()))
+            .is_ok());
+        runtime.run().unwrap();
+        assert_eq!(service.in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+
+        // Every member of a committee of one aggregates it.
+        assert_eq!(
+            *attestation_node.requests.lock().unwrap(),
+            vec!["ProduceAttestationData", "PublishAttestation"]
+        );
+        assert_eq!(
+            *aggregate_node.requests.lock().unwrap(),
+            vec!["ProduceAggregateAttestation", "PublishAggregateAndProof"]
+        );
+    }
+
+    #[test]
+    fn spans_exported_for_each_phase_of_a_slot() {
+        let keypair = Keypair::random();
+        let mut duties = EpochDuties::new();
+        duties.insert(
+            keypair.pk.clone(),
+            Some(EpochDuty {
+                block_production_slot: None,
+                attestation_duties: vec![AttestationDuty {
+                    slot: Slot::new(1),
+                    shard: 1,
+                    committee_index: 0,
+                    committee_len: 1,
+                }],
+                validator_index: Some(0),
+            }),
+        );
+        let beacon_node = Arc::new(TestBeaconNode::default());
+        beacon_node.set_next_duties_result(Ok(duties));
+
+        let env = Arc::new(EnvBuilder::new().build());
+        let (_server, client) = mock_attestation_client(&env, MockAttestationNode::default());
+        let client = Arc::new(client);
+        let exporter = Arc::new(InMemorySpanExporter::default());
+
+        let mut service = test_service(vec![keypair.clone()], beacon_node);
+        service.attestation_client = client.clone();
+        service.aggregation_client = client;
+        service.tracer = Tracer::new(exporter.clone());
+
+        let mut runtime = tokio::runtime::current_thread::Runtime::new().unwrap();
+        service.slot_clock.set_slot(1);
+        assert!(runtime
+            .block_on(futures::future::lazy(|| service.per_slot_execution()))
+            .is_ok());
+        runtime.run().unwrap();
+        assert_eq!(service.in_flight.wait_until_idle(Duration::from_secs(5)), 0);
+
+        // The producers run on their own threads, so may end before the slot's span.
+        let mut names = exporter.names();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["aggregate", "attestation", "duties_fetch", "slot"]
+        );
+        let spans = exporter.spans.lock().unwrap();
+        assert!(spans.iter().all(|span| span.slot == Slot::new(1)));
+        let validator = format!("{}", keypair);
+        for span in spans.iter().filter(|span| span.name == "attestation") {
+            assert_eq!(span.attributes, vec![("validator", validator.clone())]);
+        }
+    }
+
+    /// Answers `Info` requests. If `api_token` is set, requests without it as a bearer token are
+    /// refused.
+    #[derive(Clone)]
+    struct MockBeaconNode {
+        api_token: Option<String>,
+    }
+
+    impl BeaconNodeService for MockBeaconNode {
+        fn info(&mut self, ctx: RpcContext, _req: Empty, sink: UnarySink<NodeInfoResponse>) {
+            let authorized = self.api_token.as_ref().map_or(true, |token| {
+                let expected = format!("Bearer {}", token);
+                ctx.request_headers()
+                    .iter()
+                    .any(|(key, value)| key == "authorization" && value == expected.as_bytes())
+            });
+            let reply = if authorized {
+                sink.success(NodeInfoResponse::new())
+            } else {
+                sink.fail(RpcStatus::new(RpcStatusCode::Unauthenticated, None))
+            };
+            ctx.spawn(reply.map_err(|_| ()))
+        }
+    }
+
+    /// Returns a client of the plaintext `node`, and the server it is served by.
+    fn mock_node_client(
+        env: &Arc<Environment>,
+        node: MockBeaconNode,
+    ) -> (Server, BeaconNodeServiceClient) {
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_beacon_node_service(node))
+            .bind("127.0.0.1", 0)
+            .build()
+            .unwrap();
+        server.start();
+        let address = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+        let client =
+            BeaconNodeServiceClient::new(ChannelBuilder::new(env.clone()).connect(&address));
+        (server, client)
+    }
+
+    /// Starts a `MockBeaconNode` serving TLS with the certificate in `tests/tls`, returning the
+    /// server and its address.
+    fn tls_mock_node(env: &Arc<Environment>) -> (Server, String) {
+        let tls_file = |name| {
+            fs::read(
+                PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+                    .join("tests/tls")
+                    .join(name),
+            )
+            .unwrap()
+        };
+        let credentials = ServerCredentialsBuilder::new()
+            .add_cert(tls_file("server.pem"), tls_file("server.key"))
+            .build();
+        let mut server = ServerBuilder::new(env.clone())
+            .register_service(create_beacon_node_service(MockBeaconNode {
+                api_token: None,
+            }))
+            .bind_secure("127.0.0.1", 0, credentials)
+            .build()
+            .unwrap();
+        server.start();
+        let address = format!("127.0.0.1:{}", server.bind_addrs()[0].1);
+        (server, address)
+    }
+
+    #[test]
+    fn tls_connection_trusts_only_the_configured_ca() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let (_server, address) = tls_mock_node(&env);
+        let request_options = RequestOptions {
+            timeout: Duration::from_secs(5),
+            api_token: None,
+        };
+
+        let mut config = ValidatorConfig::default();
+        let request_info = |config: &ValidatorConfig| {
+            let connector = Connector {
+                env: env.clone(),
+                root_cert: config.tls_root_cert().unwrap(),
+            };
+            let clients = connect_each(
+                &[address.clone()],
+                &connector,
+                &null_logger(),
+                BeaconNodeServiceClient::new,
+            );
+            request_node_info(&clients, None, &request_options)
+        };
+
+        let ca_file = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/tls/ca.pem");
+        config.tls_ca_file = Some(ca_file.clone());
+        assert_eq!(request_info(&config), Ok(NodeInfoResponse::new()));
+
+        // A certificate from another authority is refused, as is a plaintext connection.
+        config.tls_ca_file = Some(ca_file.with_file_name("other_ca.pem"));
+        assert!(request_info(&config).is_err());
+        config.tls_ca_file = None;
+        assert!(request_info(&config).is_err());
+    }
+
+    #[test]
+    fn api_token_sent_and_refusal_distinguished() {
+        let env = Arc::new(EnvBuilder::new().build());
+        let node = MockBeaconNode {
+            api_token: Some("secret".to_string()),
+        };
+        let (_server, client) = mock_node_client(&env, node);
+        let info = |api_token: Option<&str>| {
+            let options = RequestOptions {
+                timeout: Duration::from_secs(5),
+                api_token: api_token.map(str::to_string),
+            };
+            audited(None, &options, "info", &Empty::new(), |req, opt| {
+                client.info_opt(req, opt)
+            })
+        };
+
+        assert_eq!(info(Some("secret")).unwrap(), NodeInfoResponse::new());
+        for refused in &[Some("wrong"), None] {
+            match BeaconNodeError::from(info(*refused).unwrap_err()) {
+                BeaconNodeError::Unauthorized(_) => {}
+                other => panic!("expected an unauthorized error, got {:?}", other),
+            }
+        }
+
+        let clients =
+            BeaconNodeFallback::new(vec![("node".to_string(), client.clone())], null_logger());
+        let options = RequestOptions {
+            timeout: Duration::from_secs(5),
+            api_token: Some("wrong".to_string()),
+        };
+        assert!(request_node_info(&clients, None, &options)
+            .unwrap_err()
+            .contains("refused the API token"));
     }
 }
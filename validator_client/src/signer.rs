@@ -1,4 +1,5 @@
-use std::fmt::Display;
+use crate::remote_signer::RemoteSigner;
+use std::fmt::{self, Display};
 use types::{Keypair, PublicKey, Signature};
 
 /// Signs message using an internally-maintained private key.
@@ -19,3 +20,44 @@ impl Signer for Keypair {
         Some(Signature::new(message, domain, &self.sk))
     }
 }
+
+/// The signer of a single validator, which either holds the private key or delegates signing to a
+/// remote service.
+#[derive(Clone)]
+pub enum ValidatorSigner {
+    Local(Keypair),
+    Remote(RemoteSigner),
+    /// Delegates signing to the remote service, signing with the local key only when the remote
+    /// signer fails.
+    RemoteWithFallback(RemoteSigner, Keypair),
+}
+
+impl Display for ValidatorSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidatorSigner::Local(keypair) => keypair.fmt(f),
+            ValidatorSigner::Remote(signer) => signer.fmt(f),
+            ValidatorSigner::RemoteWithFallback(signer, _) => signer.fmt(f),
+        }
+    }
+}
+
+impl Signer for ValidatorSigner {
+    fn to_public(&self) -> PublicKey {
+        match self {
+            ValidatorSigner::Local(keypair) => keypair.to_public(),
+            ValidatorSigner::Remote(signer) => signer.to_public(),
+            ValidatorSigner::RemoteWithFallback(signer, _) => signer.to_public(),
+        }
+    }
+
+    fn sign_message(&self, message: &[u8], domain: u64) -> Option<Signature> {
+        match self {
+            ValidatorSigner::Local(keypair) => keypair.sign_message(message, domain),
+            ValidatorSigner::Remote(signer) => signer.sign_message(message, domain),
+            ValidatorSigner::RemoteWithFallback(signer, keypair) => signer
+                .sign_message(message, domain)
+                .or_else(|| keypair.sign_message(message, domain)),
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of threads on which blocks and attestations are produced and signed.
+///
+/// Jobs submitted whilst every thread is busy are queued and run in the order they were
+/// submitted, so no more than `size` jobs ever run at once and none are dropped. Once the pool is
+/// dropped, each thread stops after the queue is empty.
+pub struct SigningPool {
+    sender: Mutex<Sender<Job>>,
+}
+
+impl SigningPool {
+    /// Starts `size` worker threads. At least one thread is always started.
+    pub fn new(size: usize) -> Self {
+        let (sender, receiver) = channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for index in 0..size.max(1) {
+            let receiver = receiver.clone();
+            std::thread::Builder::new()
+                .name(format!("signing-{}", index))
+                .spawn(move || run_worker(&receiver))
+                .expect("should spawn signing thread");
+        }
+
+        Self {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    /// Runs `job` on the first worker thread to become free.
+    pub fn execute<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // The workers only stop once the sender is dropped, so sending cannot fail.
+        if let Ok(sender) = self.sender.lock() {
+            let _ = sender.send(Box::new(job));
+        }
+    }
+}
+
+/// Runs jobs from `receiver` until the pool is dropped.
+fn run_worker(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok(job) => job(),
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn concurrent_jobs_capped_at_pool_size() {
+        const SIZE: usize = 3;
+        const JOBS: usize = 20;
+
+        let pool = SigningPool::new(SIZE);
+        // The number of jobs currently running, and the most seen running at once.
+        let running = Arc::new(Mutex::new((0_usize, 0_usize)));
+        let (done_send, done) = channel();
+
+        for _ in 0..JOBS {
+            let running = running.clone();
+            let done_send = done_send.clone();
+            pool.execute(move || {
+                {
+                    let mut running = running.lock().unwrap();
+                    running.0 += 1;
+                    running.1 = running.1.max(running.0);
+                }
+                std::thread::sleep(Duration::from_millis(10));
+                running.lock().unwrap().0 -= 1;
+                done_send.send(()).unwrap();
+            });
+        }
+
+        // Every job is queued rather than dropped.
+        for _ in 0..JOBS {
+            done.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        let (_, max_running) = *running.lock().unwrap();
+        assert!(max_running > 0 && max_running <= SIZE);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::block_producer::ValidatorEvent;
+use slog::warn;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use types::Slot;
+
+/// The reason a duty was not performed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SkipReason {
+    /// A request to the beacon node failed.
+    BeaconNodeError,
+    /// The beacon node was unable to produce a block.
+    BeaconNodeUnableToProduce,
+    /// The signer refused to sign the message.
+    SignerRejection,
+    /// Slashing protection refused the message.
+    SlashingProtection,
+    /// The block or attestation data returned by the beacon node did not match the duty.
+    InconsistentData,
+    /// The beacon node rejected the signed message.
+    PublishRejected,
+    /// The beacon node rejected the attestation as too old to be included in a block, so it was
+    /// produced too slowly.
+    Expired,
+    /// The beacon node was unable to publish the signed message.
+    PublishFailed,
+    /// The validator's duties for the epoch are unknown.
+    UnknownDuties,
+    /// Duties were halted because the beacon node's clock is ahead of the local clock.
+    ClockHalt,
+    /// The validator may not sign until doppelganger protection has observed it, or it was
+    /// detected to be active elsewhere.
+    Doppelganger,
+    /// The beacon node reports the validator as not active.
+    Inactive,
+    /// The beacon node's head was too far behind to be attested to.
+    NodeSyncing,
+}
+
+impl SkipReason {
+    /// Returns the reason for a `ValidatorEvent`, or `None` if the duty was performed.
+    pub fn from_event(event: &ValidatorEvent) -> Option<Self> {
+        match event {
+            ValidatorEvent::BlockProduced(_)
+            | ValidatorEvent::AttestationProduced(_)
+            | ValidatorEvent::AggregateProduced(_)
+            | ValidatorEvent::NotAggregator(_)
+            | ValidatorEvent::DryRun(_) => None,
+            ValidatorEvent::SlashableBlockNotProduced(_)
+            | ValidatorEvent::IndexedAttestationNotProduced(_) => {
+                Some(SkipReason::SlashingProtection)
+            }
+            ValidatorEvent::BeaconNodeUnableToProduceBlock(_) | ValidatorEvent::NoAggregate(_) => {
+                Some(SkipReason::BeaconNodeUnableToProduce)
+            }
+            ValidatorEvent::SignerRejection(_) => Some(SkipReason::SignerRejection),
+            ValidatorEvent::PublishAttestationFailed => Some(SkipReason::PublishFailed),
+            ValidatorEvent::InvalidAttestation | ValidatorEvent::BlockRejected(_) => {
+                Some(SkipReason::PublishRejected)
+            }
+            ValidatorEvent::AttestationExpired(_) => Some(SkipReason::Expired),
+            ValidatorEvent::NodeSyncing(_) => Some(SkipReason::NodeSyncing),
+            ValidatorEvent::InconsistentAttestationData(_)
+            | ValidatorEvent::InconsistentBlock(_) => Some(SkipReason::InconsistentData),
+        }
+    }
+
+    /// A stable, machine-readable code for the reason.
+    pub fn code(self) -> &'static str {
+        match self {
+            SkipReason::BeaconNodeError => "beacon_node_error",
+            SkipReason::BeaconNodeUnableToProduce => "beacon_node_unable_to_produce",
+            SkipReason::SignerRejection => "signer_rejection",
+            SkipReason::SlashingProtection => "slashing_protection",
+            SkipReason::InconsistentData => "inconsistent_data",
+            SkipReason::PublishRejected => "publish_rejected",
+            SkipReason::Expired => "expired",
+            SkipReason::PublishFailed => "publish_failed",
+            SkipReason::UnknownDuties => "unknown_duties",
+            SkipReason::ClockHalt => "clock_halt",
+            SkipReason::Doppelganger => "doppelganger",
+            SkipReason::Inactive => "inactive",
+            SkipReason::NodeSyncing => "node_syncing",
+        }
+    }
+}
+
+/// Counts skipped duties by reason.
+///
+/// The count for the reason is included in each "Duty skipped" log.
+#[derive(Default)]
+pub struct SkipCounters {
+    counts: Mutex<HashMap<SkipReason, u64>>,
+}
+
+impl SkipCounters {
+    /// Counts a skipped `duty` and logs it with the reason code.
+    pub fn record(
+        &self,
+        duty: &'static str,
+        validator: &str,
+        slot: Slot,
+        reason: SkipReason,
+        log: &slog::Logger,
+    ) {
+        let total = self
+            .counts
+            .lock()
+            .map(|mut counts| {
+                let count = counts.entry(reason).or_insert(0);
+                *count += 1;
+                *count
+            })
+            .unwrap_or(0);
+
+        warn!(
+            log,
+            "Duty skipped";
+            "duty" => duty,
+            "validator" => validator,
+            "slot" => slot,
+            "reason" => reason.code(),
+            "total_for_reason" => total,
+        );
+    }
+
+    /// Returns the number of duties skipped for each reason which has occurred, ordered by code.
+    pub fn counts(&self) -> Vec<(SkipReason, u64)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .lock()
+            .map(|counts| counts.iter().map(|(r, c)| (*r, *c)).collect())
+            .unwrap_or_default();
+        counts.sort_by_key(|(reason, _)| reason.code());
+        counts
+    }
+
+    /// Returns the number of duties skipped for `reason`.
+    pub fn count(&self, reason: SkipReason) -> u64 {
+        self.counts
+            .lock()
+            .map(|counts| counts.get(&reason).cloned().unwrap_or(0))
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation_producer::InvalidAttestationData;
+    use crate::block_producer::InvalidBlock;
+    use types::Epoch;
+
+    #[test]
+    fn events_map_to_reason_codes() {
+        let slot = Slot::new(1);
+        let reason = |event| SkipReason::from_event(&event).map(SkipReason::code);
+
+        assert_eq!(reason(ValidatorEvent::BlockProduced(slot)), None);
+        assert_eq!(reason(ValidatorEvent::AttestationProduced(slot)), None);
+        assert_eq!(
+            reason(ValidatorEvent::SlashableBlockNotProduced(slot)),
+            Some("slashing_protection")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::SignerRejection(slot)),
+            Some("signer_rejection")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::InconsistentAttestationData(
+                InvalidAttestationData::SourceAfterTarget {
+                    source: Epoch::new(1),
+                    target: Epoch::new(0),
+                }
+            )),
+            Some("inconsistent_data")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::InconsistentBlock(
+                InvalidBlock::ZeroParentRoot
+            )),
+            Some("inconsistent_data")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::InvalidAttestation),
+            Some("publish_rejected")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::BlockRejected(slot)),
+            Some("publish_rejected")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::AttestationExpired(slot)),
+            Some("expired")
+        );
+        assert_eq!(
+            reason(ValidatorEvent::NodeSyncing(slot)),
+            Some("node_syncing")
+        );
+    }
+
+    #[test]
+    fn counts_are_kept_per_reason() {
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let counters = SkipCounters::default();
+
+        counters.record(
+            "block",
+            "v",
+            Slot::new(1),
+            SkipReason::SignerRejection,
+            &log,
+        );
+        counters.record(
+            "block",
+            "v",
+            Slot::new(2),
+            SkipReason::SignerRejection,
+            &log,
+        );
+        counters.record(
+            "attestation",
+            "v",
+            Slot::new(2),
+            SkipReason::InconsistentData,
+            &log,
+        );
+
+        assert_eq!(counters.count(SkipReason::SignerRejection), 2);
+        assert_eq!(counters.count(SkipReason::InconsistentData), 1);
+        assert_eq!(counters.count(SkipReason::SlashingProtection), 0);
+    }
+}
@@ -0,0 +1,149 @@
+use super::{
+    check_attestation, check_block_proposal, NotSafe, SignedAttestation, SlashingProtectionBackend,
+    ValidatorHistory,
+};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use types::{Epoch, PublicKey, Slot};
+
+/// A slashing protection backend which stores all records in a single JSON file.
+///
+/// The entire file is re-written after each insertion, so this backend is only suitable for a
+/// modest number of validators.
+pub struct FileBackend {
+    path: PathBuf,
+    /// Maps the hex-encoded public key of a validator to its signing history.
+    histories: Mutex<HashMap<String, ValidatorHistory>>,
+}
+
+impl FileBackend {
+    /// Open the file at `path`, creating a new, empty file if one does not already exist.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let histories = if path.exists() {
+            let file = File::open(path)
+                .map_err(|e| format!("Unable to open slashing protection file: {:?}", e))?;
+            serde_json::from_reader(file)
+                .map_err(|e| format!("Unable to parse slashing protection file: {:?}", e))?
+        } else {
+            HashMap::new()
+        };
+
+        let backend = Self {
+            path: path.to_path_buf(),
+            histories: Mutex::new(histories),
+        };
+
+        backend.persist(&backend.histories.lock().map_err(|_| "Lock poisoned")?)?;
+
+        Ok(backend)
+    }
+
+    /// Writes `histories` to disk, replacing the previous file atomically.
+    fn persist(&self, histories: &HashMap<String, ValidatorHistory>) -> Result<(), String> {
+        let temp_path = self.path.with_extension("tmp");
+
+        let file = File::create(&temp_path)
+            .map_err(|e| format!("Unable to create slashing protection file: {:?}", e))?;
+        serde_json::to_writer(&file, histories)
+            .map_err(|e| format!("Unable to write slashing protection file: {:?}", e))?;
+        file.sync_all()
+            .map_err(|e| format!("Unable to sync slashing protection file: {:?}", e))?;
+
+        fs::rename(&temp_path, &self.path)
+            .map_err(|e| format!("Unable to replace slashing protection file: {:?}", e))
+    }
+
+    /// Applies `check` to the history of `pubkey`, then applies `insert` and persists the result
+    /// if the check passes. The lock is held throughout, making the operation atomic.
+    fn check_and_insert<C, I>(&self, pubkey: &PublicKey, check: C, insert: I) -> Result<(), NotSafe>
+    where
+        C: FnOnce(&ValidatorHistory) -> Result<(), NotSafe>,
+        I: FnOnce(&mut ValidatorHistory),
+    {
+        let mut histories = self
+            .histories
+            .lock()
+            .map_err(|_| NotSafe::BackendError("Lock poisoned".into()))?;
+
+        let key = pubkey.as_hex_string();
+        let mut history = histories.get(&key).cloned().unwrap_or_default();
+
+        check(&history)?;
+        insert(&mut history);
+
+        let mut updated = histories.clone();
+        updated.insert(key, history);
+        self.persist(&updated).map_err(NotSafe::BackendError)?;
+
+        // Only update the in-memory records once they are safely on disk.
+        *histories = updated;
+
+        Ok(())
+    }
+}
+
+impl SlashingProtectionBackend for FileBackend {
+    fn check_and_insert_block_proposal(
+        &self,
+        pubkey: &PublicKey,
+        slot: Slot,
+    ) -> Result<(), NotSafe> {
+        self.check_and_insert(
+            pubkey,
+            |history| {
+                check_block_proposal(history.blocks.iter().cloned(), &history.watermark, slot)
+            },
+            |history| history.blocks.push(slot),
+        )
+    }
+
+    fn check_and_insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe> {
+        self.check_and_insert(
+            pubkey,
+            |history| {
+                check_attestation(
+                    history.attestations.iter().cloned(),
+                    &history.watermark,
+                    source,
+                    target,
+                )
+            },
+            |history| {
+                history.attestations.push(SignedAttestation {
+                    source_epoch: source,
+                    target_epoch: target,
+                })
+            },
+        )
+    }
+
+    fn export_histories(&self) -> Result<Vec<(String, ValidatorHistory)>, NotSafe> {
+        let histories = self
+            .histories
+            .lock()
+            .map_err(|_| NotSafe::BackendError("Lock poisoned".into()))?;
+
+        let mut exported: Vec<_> = histories
+            .iter()
+            .map(|(key, history)| (key.clone(), history.clone()))
+            .collect();
+        exported.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(exported)
+    }
+
+    fn import_history(
+        &self,
+        pubkey: &PublicKey,
+        history: &ValidatorHistory,
+    ) -> Result<(), NotSafe> {
+        self.check_and_insert(pubkey, |_| Ok(()), |existing| existing.merge(history))
+    }
+}
@@ -0,0 +1,595 @@
+//! Import and export of slashing protection records in the EIP-3076 interchange format, so that
+//! validators may be moved between clients without risk of signing a slashable message.
+use super::{NotSafe, SignedAttestation, SlashingDatabase, ValidatorHistory, Watermark};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use types::{Epoch, Hash256, PublicKey, Slot};
+
+/// The only version of the interchange format which is supported.
+pub const INTERCHANGE_FORMAT_VERSION: &str = "5";
+
+/// An error encountered whilst importing or exporting an interchange file.
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    /// The file was not valid JSON, or did not match the interchange format.
+    InvalidFormat(String),
+    /// The file uses a version of the format other than `INTERCHANGE_FORMAT_VERSION`.
+    UnsupportedVersion(String),
+    /// The file was produced for a different chain.
+    GenesisValidatorsRootMismatch { expected: Hash256, found: Hash256 },
+    /// The file contains an attestation with a source epoch later than its target epoch.
+    InvalidAttestation {
+        pubkey: PublicKey,
+        source: Epoch,
+        target: Epoch,
+    },
+    /// A public key held by the database could not be decoded.
+    InvalidPubkey(String),
+    /// The slashing protection backend could not be read or written.
+    Backend(NotSafe),
+}
+
+impl From<NotSafe> for Error {
+    fn from(e: NotSafe) -> Error {
+        Error::Backend(e)
+    }
+}
+
+/// An EIP-3076 interchange file.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Interchange {
+    pub metadata: InterchangeMetadata,
+    pub data: Vec<InterchangeData>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InterchangeMetadata {
+    pub interchange_format_version: String,
+    pub genesis_validators_root: Hash256,
+}
+
+/// The messages signed by a single validator.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InterchangeData {
+    pub pubkey: PublicKey,
+    pub signed_blocks: Vec<SignedBlock>,
+    pub signed_attestations: Vec<InterchangeAttestation>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct SignedBlock {
+    #[serde(with = "quoted_u64")]
+    pub slot: Slot,
+    /// Signing roots are accepted but not recorded, since slashing protection does not use them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_root: Option<Hash256>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct InterchangeAttestation {
+    #[serde(with = "quoted_u64")]
+    pub source_epoch: Epoch,
+    #[serde(with = "quoted_u64")]
+    pub target_epoch: Epoch,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_root: Option<Hash256>,
+}
+
+/// The interchange format encodes integers as decimal strings.
+mod quoted_u64 {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        T: Copy + Into<u64>,
+        S: Serializer,
+    {
+        serializer.serialize_str(&(*value).into().to_string())
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: From<u64>,
+        D: Deserializer<'de>,
+    {
+        let string = String::deserialize(deserializer)?;
+        string
+            .parse::<u64>()
+            .map(T::from)
+            .map_err(|e| D::Error::custom(format!("invalid integer {:?}: {:?}", string, e)))
+    }
+}
+
+/// Merges the records in the interchange `json` into `db`.
+///
+/// The whole file is validated before any record is imported, so a rejected file leaves `db`
+/// unchanged. Existing records are never removed, so importing the same file twice (or a file
+/// which overlaps the existing records) is safe.
+///
+/// The file may only hold each validator's latest messages, so `db` also keeps the highest
+/// imported slot and epochs (see `Watermark`) and refuses anything below them.
+pub fn import(
+    db: &SlashingDatabase,
+    json: &str,
+    genesis_validators_root: Hash256,
+) -> Result<(), Error> {
+    let interchange: Interchange =
+        serde_json::from_str(json).map_err(|e| Error::InvalidFormat(format!("{:?}", e)))?;
+
+    let metadata = &interchange.metadata;
+    if metadata.interchange_format_version != INTERCHANGE_FORMAT_VERSION {
+        return Err(Error::UnsupportedVersion(
+            metadata.interchange_format_version.clone(),
+        ));
+    }
+    if metadata.genesis_validators_root != genesis_validators_root {
+        return Err(Error::GenesisValidatorsRootMismatch {
+            expected: genesis_validators_root,
+            found: metadata.genesis_validators_root,
+        });
+    }
+
+    // A validator may appear more than once, so combine its entries before importing them.
+    let mut histories: BTreeMap<String, (PublicKey, ValidatorHistory)> = BTreeMap::new();
+    for data in &interchange.data {
+        let mut history = ValidatorHistory {
+            blocks: data.signed_blocks.iter().map(|block| block.slot).collect(),
+            ..ValidatorHistory::default()
+        };
+
+        for attestation in &data.signed_attestations {
+            if attestation.source_epoch > attestation.target_epoch {
+                return Err(Error::InvalidAttestation {
+                    pubkey: data.pubkey.clone(),
+                    source: attestation.source_epoch,
+                    target: attestation.target_epoch,
+                });
+            }
+            history.attestations.push(SignedAttestation {
+                source_epoch: attestation.source_epoch,
+                target_epoch: attestation.target_epoch,
+            });
+        }
+        history.watermark = Watermark::of(&history);
+
+        histories
+            .entry(data.pubkey.as_hex_string())
+            .or_insert_with(|| (data.pubkey.clone(), ValidatorHistory::default()))
+            .1
+            .merge(&history);
+    }
+
+    for (pubkey, history) in histories.values() {
+        db.backend.import_history(pubkey, history)?;
+    }
+
+    Ok(())
+}
+
+/// Returns every record in `db` as an interchange file.
+pub fn export(
+    db: &SlashingDatabase,
+    genesis_validators_root: Hash256,
+) -> Result<Interchange, Error> {
+    let data = db
+        .backend
+        .export_histories()?
+        .into_iter()
+        .map(|(key, history)| {
+            let pubkey = hex::decode(key.trim_start_matches("0x"))
+                .ok()
+                .and_then(|bytes| PublicKey::from_bytes(&bytes).ok())
+                .ok_or_else(|| Error::InvalidPubkey(key.clone()))?;
+
+            Ok(InterchangeData {
+                pubkey,
+                signed_blocks: history
+                    .blocks
+                    .into_iter()
+                    .map(|slot| SignedBlock {
+                        slot,
+                        signing_root: None,
+                    })
+                    .collect(),
+                signed_attestations: history
+                    .attestations
+                    .into_iter()
+                    .map(|attestation| InterchangeAttestation {
+                        source_epoch: attestation.source_epoch,
+                        target_epoch: attestation.target_epoch,
+                        signing_root: None,
+                    })
+                    .collect(),
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+
+    Ok(Interchange {
+        metadata: InterchangeMetadata {
+            interchange_format_version: INTERCHANGE_FORMAT_VERSION.to_string(),
+            genesis_validators_root,
+        },
+        data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slashing_protection::FileBackend;
+    use std::fs;
+    use std::path::PathBuf;
+    use types::Keypair;
+
+    /// A test case in the format of the EIP-3076 interchange test suite.
+    #[derive(Debug, Deserialize)]
+    struct TestCase {
+        name: String,
+        genesis_validators_root: Hash256,
+        steps: Vec<TestStep>,
+    }
+
+    /// An interchange file to import, followed by messages to check against the database.
+    #[derive(Debug, Deserialize)]
+    struct TestStep {
+        should_succeed: bool,
+        interchange: serde_json::Value,
+        blocks: Vec<TestBlock>,
+        attestations: Vec<TestAttestation>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TestBlock {
+        pubkey: PublicKey,
+        #[serde(with = "quoted_u64")]
+        slot: Slot,
+        should_succeed: bool,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct TestAttestation {
+        pubkey: PublicKey,
+        #[serde(with = "quoted_u64")]
+        source_epoch: Epoch,
+        #[serde(with = "quoted_u64")]
+        target_epoch: Epoch,
+        should_succeed: bool,
+    }
+
+    fn root(byte: u8) -> Hash256 {
+        Hash256::from_slice(&[byte; 32])
+    }
+
+    /// Returns an interchange file in the format of the EIP-3076 test vectors, for a single
+    /// validator with the given blocks and (source, target) attestations.
+    fn interchange_json(
+        pubkey: &PublicKey,
+        genesis_validators_root: Hash256,
+        blocks: &[u64],
+        attestations: &[(u64, u64)],
+    ) -> String {
+        let blocks: Vec<_> = blocks
+            .iter()
+            .map(|slot| serde_json::json!({ "slot": slot.to_string() }))
+            .collect();
+        let attestations: Vec<_> = attestations
+            .iter()
+            .map(|(source, target)| {
+                serde_json::json!({
+                    "source_epoch": source.to_string(),
+                    "target_epoch": target.to_string(),
+                    "signing_root": format!("{:?}", root(7)),
+                })
+            })
+            .collect();
+
+        serde_json::json!({
+            "metadata": {
+                "interchange_format_version": "5",
+                "genesis_validators_root": format!("{:?}", genesis_validators_root),
+            },
+            "data": [{
+                "pubkey": pubkey.as_hex_string(),
+                "signed_blocks": blocks,
+                "signed_attestations": attestations,
+            }],
+        })
+        .to_string()
+    }
+
+    /// Runs each test case in `tests/interchange`, importing each step's interchange into the
+    /// same database before checking its messages.
+    ///
+    /// Messages below an imported watermark are refused, so the outcome given by
+    /// `should_succeed` is expected rather than `should_succeed_complete`.
+    #[test]
+    fn interchange_test_vectors() {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/interchange");
+        let mut paths: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths {
+            let test_case: TestCase =
+                serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+            let name = &test_case.name;
+            let db = SlashingDatabase::in_memory();
+
+            for (index, step) in test_case.steps.iter().enumerate() {
+                let imported = import(
+                    &db,
+                    &step.interchange.to_string(),
+                    test_case.genesis_validators_root,
+                );
+                assert_eq!(
+                    imported.is_ok(),
+                    step.should_succeed,
+                    "{}: step {} import: {:?}",
+                    name,
+                    index,
+                    imported
+                );
+
+                for block in &step.blocks {
+                    let result = db.check_and_insert_block_proposal(&block.pubkey, block.slot);
+                    assert_eq!(
+                        result.is_ok(),
+                        block.should_succeed,
+                        "{}: step {} block at slot {}: {:?}",
+                        name,
+                        index,
+                        block.slot,
+                        result
+                    );
+                }
+
+                for attestation in &step.attestations {
+                    let result = db.check_and_insert_attestation(
+                        &attestation.pubkey,
+                        attestation.source_epoch,
+                        attestation.target_epoch,
+                    );
+                    assert_eq!(
+                        result.is_ok(),
+                        attestation.should_succeed,
+                        "{}: step {} attestation {} -> {}: {:?}",
+                        name,
+                        index,
+                        attestation.source_epoch,
+                        attestation.target_epoch,
+                        result
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn export_round_trips() {
+        let db = SlashingDatabase::in_memory();
+        let pubkeys: Vec<_> = (0..2).map(|_| Keypair::random().pk).collect();
+        for pubkey in &pubkeys {
+            db.check_and_insert_block_proposal(pubkey, Slot::new(3))
+                .unwrap();
+            db.check_and_insert_attestation(pubkey, Epoch::new(0), Epoch::new(1))
+                .unwrap();
+            db.check_and_insert_attestation(pubkey, Epoch::new(1), Epoch::new(2))
+                .unwrap();
+        }
+
+        let exported = export(&db, root(1)).unwrap();
+        assert_eq!(exported.data.len(), 2);
+
+        let json = serde_json::to_string(&exported).unwrap();
+        let imported = SlashingDatabase::in_memory();
+        import(&imported, &json, root(1)).unwrap();
+
+        assert_eq!(export(&imported, root(1)).unwrap(), exported);
+    }
+
+    #[test]
+    fn imported_records_refuse_slashable_messages() {
+        let db = SlashingDatabase::in_memory();
+        let pubkey = Keypair::random().pk;
+        let json = interchange_json(&pubkey, root(1), &[10], &[(2, 3), (3, 10)]);
+
+        import(&db, &json, root(1)).unwrap();
+
+        assert_eq!(
+            db.check_and_insert_block_proposal(&pubkey, Slot::new(10)),
+            Err(NotSafe::DoubleBlockProposal(Slot::new(10)))
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(&pubkey, Epoch::new(1), Epoch::new(3)),
+            Err(NotSafe::DoubleVote(Epoch::new(3)))
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(&pubkey, Epoch::new(4), Epoch::new(5)),
+            Err(NotSafe::SurroundedVote {
+                source: Epoch::new(3),
+                target: Epoch::new(10)
+            })
+        );
+        assert_eq!(
+            db.check_and_insert_attestation(&pubkey, Epoch::new(10), Epoch::new(11)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn minimal_import_refuses_messages_below_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let databases = vec![
+            SlashingDatabase::in_memory(),
+            SlashingDatabase::new(
+                Box::new(FileBackend::open(&dir.path().join("slashing_protection.json")).unwrap()),
+                log,
+            ),
+        ];
+
+        for db in databases {
+            let pubkey = Keypair::random().pk;
+            let json = interchange_json(&pubkey, root(1), &[10], &[(3, 10)]);
+            import(&db, &json, root(1)).unwrap();
+
+            let watermark = Watermark {
+                slot: Some(Slot::new(10)),
+                source_epoch: Some(Epoch::new(3)),
+                target_epoch: Some(Epoch::new(10)),
+            };
+            assert_eq!(
+                db.check_and_insert_block_proposal(&pubkey, Slot::new(5)),
+                Err(NotSafe::BlockBelowWatermark {
+                    slot: Slot::new(5),
+                    watermark: Slot::new(10)
+                })
+            );
+            assert_eq!(
+                db.check_and_insert_attestation(&pubkey, Epoch::new(1), Epoch::new(2)),
+                Err(NotSafe::AttestationBelowWatermark {
+                    source: Epoch::new(1),
+                    target: Epoch::new(2),
+                    watermark
+                })
+            );
+            // The source may not go below the watermark, even with a later target.
+            assert_eq!(
+                db.check_and_insert_attestation(&pubkey, Epoch::new(2), Epoch::new(11)),
+                Err(NotSafe::AttestationBelowWatermark {
+                    source: Epoch::new(2),
+                    target: Epoch::new(11),
+                    watermark
+                })
+            );
+            assert_eq!(
+                db.check_and_insert_block_proposal(&pubkey, Slot::new(11)),
+                Ok(())
+            );
+            assert_eq!(
+                db.check_and_insert_attestation(&pubkey, Epoch::new(10), Epoch::new(11)),
+                Ok(())
+            );
+
+            // A later import never lowers the watermark.
+            let json = interchange_json(&pubkey, root(1), &[2], &[(0, 1)]);
+            import(&db, &json, root(1)).unwrap();
+            assert_eq!(
+                db.check_and_insert_block_proposal(&pubkey, Slot::new(6)),
+                Err(NotSafe::BlockBelowWatermark {
+                    slot: Slot::new(6),
+                    watermark: Slot::new(10)
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn import_merges_with_existing_records() {
+        let db = SlashingDatabase::in_memory();
+        let pubkey = Keypair::random().pk;
+        db.check_and_insert_block_proposal(&pubkey, Slot::new(1))
+            .unwrap();
+        db.check_and_insert_attestation(&pubkey, Epoch::new(0), Epoch::new(2))
+            .unwrap();
+
+        // Overlaps the existing records, including a conflicting vote for epoch 2.
+        let json = interchange_json(&pubkey, root(1), &[1, 2], &[(1, 2), (2, 3)]);
+        import(&db, &json, root(1)).unwrap();
+        // Importing the same file again is harmless.
+        import(&db, &json, root(1)).unwrap();
+
+        let exported = export(&db, root(1)).unwrap();
+        let data = &exported.data[0];
+        assert_eq!(
+            data.signed_blocks
+                .iter()
+                .map(|block| block.slot)
+                .collect::<Vec<_>>(),
+            vec![Slot::new(1), Slot::new(2)]
+        );
+        assert_eq!(
+            data.signed_attestations
+                .iter()
+                .map(|a| (a.source_epoch, a.target_epoch))
+                .collect::<Vec<_>>(),
+            vec![
+                (Epoch::new(0), Epoch::new(2)),
+                (Epoch::new(2), Epoch::new(3))
+            ]
+        );
+    }
+
+    #[test]
+    fn wrong_genesis_validators_root_rejected() {
+        let db = SlashingDatabase::in_memory();
+        let pubkey = Keypair::random().pk;
+        let json = interchange_json(&pubkey, root(2), &[10], &[]);
+
+        assert_eq!(
+            import(&db, &json, root(1)),
+            Err(Error::GenesisValidatorsRootMismatch {
+                expected: root(1),
+                found: root(2)
+            })
+        );
+        assert_eq!(
+            db.check_and_insert_block_proposal(&pubkey, Slot::new(10)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn unsupported_version_rejected() {
+        let db = SlashingDatabase::in_memory();
+        let json =
+            interchange_json(&Keypair::random().pk, root(1), &[], &[]).replace("\"5\"", "\"4\"");
+
+        assert_eq!(
+            import(&db, &json, root(1)),
+            Err(Error::UnsupportedVersion("4".into()))
+        );
+    }
+
+    #[test]
+    fn invalid_files_rejected() {
+        let db = SlashingDatabase::in_memory();
+        let pubkey = Keypair::random().pk;
+
+        // Integers must be quoted.
+        let unquoted = interchange_json(&pubkey, root(1), &[10], &[]).replace("\"10\"", "10");
+        assert!(match import(&db, &unquoted, root(1)) {
+            Err(Error::InvalidFormat(_)) => true,
+            _ => false,
+        });
+
+        let bad_pubkey = interchange_json(&pubkey, root(1), &[10], &[])
+            .replace(&pubkey.as_hex_string(), "0x1234");
+        assert!(match import(&db, &bad_pubkey, root(1)) {
+            Err(Error::InvalidFormat(_)) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn source_greater_than_target_rejected() {
+        let db = SlashingDatabase::in_memory();
+        let pubkey = Keypair::random().pk;
+        let json = interchange_json(&pubkey, root(1), &[10], &[(2, 3), (5, 4)]);
+
+        assert_eq!(
+            import(&db, &json, root(1)),
+            Err(Error::InvalidAttestation {
+                pubkey: pubkey.clone(),
+                source: Epoch::new(5),
+                target: Epoch::new(4)
+            })
+        );
+        // Nothing from a rejected file is imported.
+        assert!(export(&db, root(1)).unwrap().data.is_empty());
+    }
+}
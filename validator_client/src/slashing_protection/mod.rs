@@ -0,0 +1,528 @@
+mod file_backend;
+pub mod interchange;
+mod replication;
+mod sqlite_backend;
+mod validator_locks;
+
+pub use self::file_backend::FileBackend;
+pub use self::replication::{ReplicatingBackend, ReplicationRecord, REPLICATION_QUEUE_CAPACITY};
+pub use self::sqlite_backend::SqliteBackend;
+use self::validator_locks::ValidatorLocks;
+use crate::config::{Config as ValidatorConfig, SlashingProtectionKind};
+use serde_derive::{Deserialize, Serialize};
+use slog::crit;
+use std::path::Path;
+use types::{Epoch, PublicKey, Slot};
+
+/// The reason a message was refused by slashing protection.
+#[derive(Debug, PartialEq, Clone)]
+pub enum NotSafe {
+    /// The validator has already signed a block at this slot.
+    DoubleBlockProposal(Slot),
+    /// The validator has already signed an attestation with this target epoch.
+    DoubleVote(Epoch),
+    /// The attestation would surround a previously signed attestation.
+    SurroundingVote { source: Epoch, target: Epoch },
+    /// The attestation would be surrounded by a previously signed attestation.
+    SurroundedVote { source: Epoch, target: Epoch },
+    /// The attestation has a source epoch later than its target epoch.
+    InvalidAttestation { source: Epoch, target: Epoch },
+    /// The block is at or below the highest slot imported for the validator, so it may conflict
+    /// with a block which the import left out.
+    BlockBelowWatermark { slot: Slot, watermark: Slot },
+    /// The attestation has a source or target epoch below the highest imported for the
+    /// validator, so it may conflict with an attestation which the import left out.
+    AttestationBelowWatermark {
+        source: Epoch,
+        target: Epoch,
+        watermark: Watermark,
+    },
+    /// The backend was unable to read or write its records.
+    BackendError(String),
+}
+
+/// An attestation previously signed by a validator, as recorded by slashing protection.
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct SignedAttestation {
+    pub source_epoch: Epoch,
+    pub target_epoch: Epoch,
+}
+
+/// The highest slot and epochs of the messages imported for a validator.
+///
+/// An interchange file need not list every message a validator has signed, so nothing at or
+/// below the watermark is signed once a file has been imported.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Serialize, Deserialize)]
+pub struct Watermark {
+    pub slot: Option<Slot>,
+    pub source_epoch: Option<Epoch>,
+    pub target_epoch: Option<Epoch>,
+}
+
+impl Watermark {
+    /// Returns the watermark of the messages in `history`.
+    pub fn of(history: &ValidatorHistory) -> Self {
+        Self {
+            slot: history.blocks.iter().max().cloned(),
+            source_epoch: history.attestations.iter().map(|a| a.source_epoch).max(),
+            target_epoch: history.attestations.iter().map(|a| a.target_epoch).max(),
+        }
+    }
+
+    /// Raises the watermark to include `other`.
+    pub fn merge(&mut self, other: &Watermark) {
+        self.slot = self.slot.max(other.slot);
+        self.source_epoch = self.source_epoch.max(other.source_epoch);
+        self.target_epoch = self.target_epoch.max(other.target_epoch);
+    }
+}
+
+/// The messages signed by a single validator.
+#[derive(Debug, Default, PartialEq, Clone, Serialize, Deserialize)]
+pub struct ValidatorHistory {
+    pub blocks: Vec<Slot>,
+    pub attestations: Vec<SignedAttestation>,
+    /// Set once messages have been imported for the validator.
+    #[serde(default)]
+    pub watermark: Watermark,
+}
+
+impl ValidatorHistory {
+    /// Adds the messages in `other` which are not already recorded, and raises the watermark to
+    /// include `other`'s.
+    ///
+    /// An attestation with the same target epoch as a recorded attestation is discarded, since
+    /// the recorded attestation already prevents any other vote for that target.
+    pub fn merge(&mut self, other: &ValidatorHistory) {
+        self.watermark.merge(&other.watermark);
+
+        for slot in &other.blocks {
+            if !self.blocks.contains(slot) {
+                self.blocks.push(*slot);
+            }
+        }
+
+        for attestation in &other.attestations {
+            if !self
+                .attestations
+                .iter()
+                .any(|prev| prev.target_epoch == attestation.target_epoch)
+            {
+                self.attestations.push(*attestation);
+            }
+        }
+    }
+}
+
+/// Provides persistent storage of the messages signed by each validator.
+///
+/// Each `check_and_insert_*` function must be atomic: the check against existing records and the
+/// insertion of the new record may not be interleaved with any other call on the same backend.
+pub trait SlashingProtectionBackend: Send + Sync {
+    /// Returns `Ok(())` and records the proposal if signing a block at `slot` is safe.
+    fn check_and_insert_block_proposal(
+        &self,
+        pubkey: &PublicKey,
+        slot: Slot,
+    ) -> Result<(), NotSafe>;
+
+    /// Returns `Ok(())` and records the vote if signing an attestation with the given `source` and
+    /// `target` epochs is safe.
+    fn check_and_insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe>;
+
+    /// Returns the messages recorded for every validator, keyed by hex-encoded public key and
+    /// ordered by key.
+    fn export_histories(&self) -> Result<Vec<(String, ValidatorHistory)>, NotSafe>;
+
+    /// Merges `history` into the records of `pubkey` (see `ValidatorHistory::merge`), including
+    /// its watermark.
+    ///
+    /// The imported messages are not checked against the existing records, since they have
+    /// already been signed.
+    fn import_history(&self, pubkey: &PublicKey, history: &ValidatorHistory)
+        -> Result<(), NotSafe>;
+}
+
+/// Opens the backend of the given `kind` at `path`, creating it if it does not already exist.
+///
+/// `parallelism` is the number of validators which may be checked concurrently. It is ignored
+/// by the file backend, which always serializes checks.
+pub fn open_backend(
+    kind: SlashingProtectionKind,
+    path: &Path,
+    parallelism: usize,
+) -> Result<Box<dyn SlashingProtectionBackend>, String> {
+    match kind {
+        SlashingProtectionKind::File => Ok(Box::new(FileBackend::open(path)?)),
+        SlashingProtectionKind::Sqlite => Ok(Box::new(SqliteBackend::open(path, parallelism)?)),
+    }
+}
+
+/// The slashing protection store consulted before any block or attestation is signed.
+///
+/// Every refusal is logged as critical, since it means the validator was asked to sign a
+/// slashable message (or the store could not be read).
+pub struct SlashingDatabase {
+    backend: Box<dyn SlashingProtectionBackend>,
+    log: slog::Logger,
+}
+
+impl SlashingDatabase {
+    pub fn new(backend: Box<dyn SlashingProtectionBackend>, log: slog::Logger) -> Self {
+        Self { backend, log }
+    }
+
+    /// Opens the store configured in `config`, under the validator data directory.
+    pub fn open(config: &ValidatorConfig, log: slog::Logger) -> Result<Self, String> {
+        let path = config
+            .slashing_protection_path()
+            .ok_or("Unable to find the slashing protection database path")?;
+        let mut backend = open_backend(
+            config.slashing_protection,
+            &path,
+            config.slashing_protection_parallelism,
+        )?;
+
+        if let Some(replica_path) = &config.slashing_protection_replica {
+            backend = Box::new(ReplicatingBackend::open(
+                backend,
+                replica_path,
+                REPLICATION_QUEUE_CAPACITY,
+                log.clone(),
+            )?);
+        }
+
+        Ok(Self::new(backend, log))
+    }
+
+    /// Returns `Ok(())` and records the proposal if signing a block at `slot` is safe.
+    pub fn check_and_insert_block_proposal(
+        &self,
+        pubkey: &PublicKey,
+        slot: Slot,
+    ) -> Result<(), NotSafe> {
+        self.backend
+            .check_and_insert_block_proposal(pubkey, slot)
+            .map_err(|e| {
+                crit!(
+                    self.log,
+                    "Refused to sign block";
+                    "validator" => pubkey.as_hex_string(),
+                    "slot" => slot,
+                    "reason" => format!("{:?}", e),
+                );
+                e
+            })
+    }
+
+    /// Returns `Ok(())` and records the vote if signing an attestation with the given `source` and
+    /// `target` epochs is safe.
+    pub fn check_and_insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe> {
+        self.backend
+            .check_and_insert_attestation(pubkey, source, target)
+            .map_err(|e| {
+                crit!(
+                    self.log,
+                    "Refused to sign attestation";
+                    "validator" => pubkey.as_hex_string(),
+                    "source_epoch" => source,
+                    "target_epoch" => target,
+                    "reason" => format!("{:?}", e),
+                );
+                e
+            })
+    }
+
+    /// Returns an empty database which is discarded when dropped.
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        let backend =
+            SqliteBackend::open(Path::new(":memory:"), 1).expect("should open in-memory database");
+        Self::new(
+            Box::new(backend),
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+    }
+}
+
+/// Returns an error if a block at `slot` conflicts with any of the `signed_slots`, or is at or
+/// below the `watermark`.
+fn check_block_proposal<I>(
+    signed_slots: I,
+    watermark: &Watermark,
+    slot: Slot,
+) -> Result<(), NotSafe>
+where
+    I: IntoIterator<Item = Slot>,
+{
+    if signed_slots.into_iter().any(|signed| signed == slot) {
+        return Err(NotSafe::DoubleBlockProposal(slot));
+    }
+
+    check_block_watermark(watermark, slot)
+}
+
+/// Returns an error if `slot` is at or below the slot of the `watermark`.
+fn check_block_watermark(watermark: &Watermark, slot: Slot) -> Result<(), NotSafe> {
+    match watermark.slot {
+        Some(watermark) if slot <= watermark => {
+            Err(NotSafe::BlockBelowWatermark { slot, watermark })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Returns an error if an attestation with `source` and `target` conflicts with any of the
+/// `signed` attestations, or has an epoch below the `watermark`.
+fn check_attestation<I>(
+    signed: I,
+    watermark: &Watermark,
+    source: Epoch,
+    target: Epoch,
+) -> Result<(), NotSafe>
+where
+    I: IntoIterator<Item = SignedAttestation>,
+{
+    if source > target {
+        return Err(NotSafe::InvalidAttestation { source, target });
+    }
+
+    let signed: Vec<SignedAttestation> = signed.into_iter().collect();
+
+    if signed.iter().any(|prev| prev.target_epoch == target) {
+        return Err(NotSafe::DoubleVote(target));
+    }
+
+    if let Some(prev) = signed
+        .iter()
+        .find(|prev| source < prev.source_epoch && prev.target_epoch < target)
+    {
+        return Err(NotSafe::SurroundingVote {
+            source: prev.source_epoch,
+            target: prev.target_epoch,
+        });
+    }
+
+    if let Some(prev) = signed
+        .iter()
+        .find(|prev| prev.source_epoch < source && target < prev.target_epoch)
+    {
+        return Err(NotSafe::SurroundedVote {
+            source: prev.source_epoch,
+            target: prev.target_epoch,
+        });
+    }
+
+    check_attestation_watermark(watermark, source, target)
+}
+
+/// Returns an error if `source` or `target` is below the corresponding epoch of the `watermark`.
+fn check_attestation_watermark(
+    watermark: &Watermark,
+    source: Epoch,
+    target: Epoch,
+) -> Result<(), NotSafe> {
+    let below = |epoch: Epoch, watermark: Option<Epoch>| watermark.map_or(false, |w| epoch < w);
+
+    if below(source, watermark.source_epoch) || below(target, watermark.target_epoch) {
+        Err(NotSafe::AttestationBelowWatermark {
+            source,
+            target,
+            watermark: *watermark,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use types::Keypair;
+
+    fn test_impl(backend: impl SlashingProtectionBackend) {
+        let pubkey = Keypair::random().pk;
+        let other_pubkey = Keypair::random().pk;
+        assert_ne!(pubkey, other_pubkey);
+
+        // Block proposals.
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&pubkey, Slot::new(10)),
+            Ok(())
+        );
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&pubkey, Slot::new(10)),
+            Err(NotSafe::DoubleBlockProposal(Slot::new(10)))
+        );
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&pubkey, Slot::new(11)),
+            Ok(())
+        );
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&other_pubkey, Slot::new(10)),
+            Ok(())
+        );
+
+        // Attestations.
+        let epoch = Epoch::new;
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(2), epoch(3)),
+            Ok(())
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(1), epoch(3)),
+            Err(NotSafe::DoubleVote(epoch(3)))
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(1), epoch(4)),
+            Err(NotSafe::SurroundingVote {
+                source: epoch(2),
+                target: epoch(3)
+            })
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(5), epoch(4)),
+            Err(NotSafe::InvalidAttestation {
+                source: epoch(5),
+                target: epoch(4)
+            })
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(3), epoch(10)),
+            Ok(())
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, epoch(4), epoch(5)),
+            Err(NotSafe::SurroundedVote {
+                source: epoch(3),
+                target: epoch(10)
+            })
+        );
+        assert_eq!(
+            backend.check_and_insert_attestation(&other_pubkey, epoch(4), epoch(5)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn file_backend() {
+        let dir = tempdir().unwrap();
+        let backend = FileBackend::open(&dir.path().join("slashing_protection.json")).unwrap();
+
+        test_impl(backend);
+    }
+
+    #[test]
+    fn sqlite_backend() {
+        let dir = tempdir().unwrap();
+        let backend =
+            SqliteBackend::open(&dir.path().join("slashing_protection.sqlite"), 4).unwrap();
+
+        test_impl(backend);
+    }
+
+    #[test]
+    fn file_backend_persists() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("slashing_protection.json");
+        let pubkey = PublicKey::default();
+
+        {
+            let backend = FileBackend::open(&path).unwrap();
+            backend
+                .check_and_insert_block_proposal(&pubkey, Slot::new(1))
+                .unwrap();
+        }
+
+        let backend = FileBackend::open(&path).unwrap();
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&pubkey, Slot::new(1)),
+            Err(NotSafe::DoubleBlockProposal(Slot::new(1)))
+        );
+    }
+
+    #[test]
+    fn sqlite_backend_persists() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("slashing_protection.sqlite");
+        let pubkey = PublicKey::default();
+
+        {
+            let backend = SqliteBackend::open(&path, 1).unwrap();
+            backend
+                .check_and_insert_attestation(&pubkey, Epoch::new(1), Epoch::new(2))
+                .unwrap();
+        }
+
+        let backend = SqliteBackend::open(&path, 1).unwrap();
+        assert_eq!(
+            backend.check_and_insert_attestation(&pubkey, Epoch::new(0), Epoch::new(2)),
+            Err(NotSafe::DoubleVote(Epoch::new(2)))
+        );
+    }
+
+    #[test]
+    fn concurrent_consecutive_block_proposals_recorded() {
+        let dir = tempdir().unwrap();
+        let backend = std::sync::Arc::new(
+            SqliteBackend::open(&dir.path().join("slashing_protection.sqlite"), 4).unwrap(),
+        );
+        let pubkey = PublicKey::default();
+
+        // Duties for consecutive slots run on separate threads and may overlap.
+        let handles: Vec<_> = (9..11)
+            .map(|slot| {
+                let backend = backend.clone();
+                let pubkey = pubkey.clone();
+                std::thread::spawn(move || {
+                    backend.check_and_insert_block_proposal(&pubkey, Slot::new(slot))
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(()));
+        }
+
+        for slot in 9..11 {
+            assert_eq!(
+                backend.check_and_insert_block_proposal(&pubkey, Slot::new(slot)),
+                Err(NotSafe::DoubleBlockProposal(Slot::new(slot)))
+            );
+        }
+    }
+
+    #[test]
+    fn slashing_database_opened_from_config_survives_restart() {
+        let dir = tempdir().unwrap();
+        let mut config = ValidatorConfig::default();
+        config.data_dir = dir.path().to_path_buf();
+        let log = slog::Logger::root(slog::Discard, slog::o!());
+        let pubkey = PublicKey::default();
+
+        {
+            let db = SlashingDatabase::open(&config, log.clone()).unwrap();
+            assert_eq!(
+                db.check_and_insert_block_proposal(&pubkey, Slot::new(5)),
+                Ok(())
+            );
+        }
+
+        assert!(dir.path().join("slashing_protection.sqlite").exists());
+        let db = SlashingDatabase::open(&config, log).unwrap();
+        assert_eq!(
+            db.check_and_insert_block_proposal(&pubkey, Slot::new(5)),
+            Err(NotSafe::DoubleBlockProposal(Slot::new(5)))
+        );
+    }
+}
@@ -0,0 +1,255 @@
+use super::{NotSafe, SlashingProtectionBackend, ValidatorHistory};
+use serde_derive::{Deserialize, Serialize};
+use slog::crit;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread::JoinHandle;
+use types::{Epoch, PublicKey, Slot};
+
+/// The number of records which may wait to be written before further records are dropped.
+pub const REPLICATION_QUEUE_CAPACITY: usize = 1024;
+
+/// A message signed by a validator, as written to the replica.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReplicationRecord {
+    Block {
+        pubkey: String,
+        slot: Slot,
+    },
+    Attestation {
+        pubkey: String,
+        source_epoch: Epoch,
+        target_epoch: Epoch,
+    },
+}
+
+/// Wraps a backend, appending each successfully inserted record to a replica file as a line of
+/// JSON, so that a standby host may be kept up to date.
+///
+/// A record is only queued once the inner backend has accepted it, and is written and synced by a
+/// background thread so that signing never waits on the replica. Failing to write a record, or
+/// dropping it because the queue is full, raises a critical alert but does not prevent signing,
+/// since the inner backend remains the source of truth.
+pub struct ReplicatingBackend {
+    backend: Box<dyn SlashingProtectionBackend>,
+    /// Taken on drop, so that the writer thread finishes once the queue is empty.
+    sender: Option<SyncSender<ReplicationRecord>>,
+    writer: Option<JoinHandle<()>>,
+    /// The number of records dropped because the queue was full.
+    dropped: AtomicU64,
+    log: slog::Logger,
+}
+
+impl ReplicatingBackend {
+    /// Wraps `backend`, appending records to the file at `replica_path`, with at most
+    /// `capacity` records waiting to be written.
+    pub fn open(
+        backend: Box<dyn SlashingProtectionBackend>,
+        replica_path: &Path,
+        capacity: usize,
+        log: slog::Logger,
+    ) -> Result<Self, String> {
+        let replica = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(replica_path)
+            .map_err(|e| format!("Unable to open slashing protection replica: {:?}", e))?;
+
+        let (sender, receiver) = sync_channel(capacity);
+        let writer_log = log.clone();
+        let writer = std::thread::Builder::new()
+            .name("slashing-protection-replica".into())
+            .spawn(move || run_writer(replica, &receiver, &writer_log))
+            .map_err(|e| format!("Unable to start replica writer: {:?}", e))?;
+
+        Ok(Self {
+            backend,
+            sender: Some(sender),
+            writer: Some(writer),
+            dropped: AtomicU64::new(0),
+            log,
+        })
+    }
+
+    /// Returns the number of records dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    fn replicate(&self, record: ReplicationRecord) {
+        let result = match &self.sender {
+            Some(sender) => sender.try_send(record),
+            None => return,
+        };
+        if let Err(e) = result {
+            let dropped = self.dropped.fetch_add(1, Ordering::SeqCst) + 1;
+            let (reason, record) = match e {
+                TrySendError::Full(record) => ("the queue is full", record),
+                TrySendError::Disconnected(record) => ("the writer has stopped", record),
+            };
+            crit!(
+                self.log,
+                "Slashing protection record not replicated";
+                "reason" => reason,
+                "dropped" => dropped,
+                "record" => format!("{:?}", record),
+            );
+        }
+    }
+}
+
+impl Drop for ReplicatingBackend {
+    /// Waits for the queued records to be written.
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+/// Writes and syncs records from `receiver` to `replica` until the backend is dropped.
+fn run_writer(mut replica: File, receiver: &Receiver<ReplicationRecord>, log: &slog::Logger) {
+    for record in receiver.iter() {
+        if let Err(e) = write_record(&mut replica, &record) {
+            crit!(
+                log,
+                "Unable to replicate slashing protection record";
+                "error" => e,
+                "record" => format!("{:?}", record),
+            );
+        }
+    }
+}
+
+fn write_record(replica: &mut File, record: &ReplicationRecord) -> Result<(), String> {
+    let mut line = serde_json::to_string(record)
+        .map_err(|e| format!("Unable to serialize record: {:?}", e))?;
+    line.push('\n');
+
+    replica
+        .write_all(line.as_bytes())
+        .and_then(|()| replica.sync_data())
+        .map_err(|e| format!("Unable to write replica: {:?}", e))
+}
+
+impl SlashingProtectionBackend for ReplicatingBackend {
+    fn check_and_insert_block_proposal(
+        &self,
+        pubkey: &PublicKey,
+        slot: Slot,
+    ) -> Result<(), NotSafe> {
+        self.backend.check_and_insert_block_proposal(pubkey, slot)?;
+        self.replicate(ReplicationRecord::Block {
+            pubkey: pubkey.as_hex_string(),
+            slot,
+        });
+        Ok(())
+    }
+
+    fn check_and_insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe> {
+        self.backend
+            .check_and_insert_attestation(pubkey, source, target)?;
+        self.replicate(ReplicationRecord::Attestation {
+            pubkey: pubkey.as_hex_string(),
+            source_epoch: source,
+            target_epoch: target,
+        });
+        Ok(())
+    }
+
+    fn export_histories(&self) -> Result<Vec<(String, ValidatorHistory)>, NotSafe> {
+        self.backend.export_histories()
+    }
+
+    fn import_history(
+        &self,
+        pubkey: &PublicKey,
+        history: &ValidatorHistory,
+    ) -> Result<(), NotSafe> {
+        self.backend.import_history(pubkey, history)?;
+
+        // The standby needs the imported records as well, to refuse the same messages.
+        for slot in &history.blocks {
+            self.replicate(ReplicationRecord::Block {
+                pubkey: pubkey.as_hex_string(),
+                slot: *slot,
+            });
+        }
+        for attestation in &history.attestations {
+            self.replicate(ReplicationRecord::Attestation {
+                pubkey: pubkey.as_hex_string(),
+                source_epoch: attestation.source_epoch,
+                target_epoch: attestation.target_epoch,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::FileBackend;
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn each_sign_emits_a_replication_record() {
+        let dir = tempdir().unwrap();
+        let replica_path = dir.path().join("replica.jsonl");
+        let backend = FileBackend::open(&dir.path().join("slashing_protection.json")).unwrap();
+        let backend = ReplicatingBackend::open(
+            Box::new(backend),
+            &replica_path,
+            REPLICATION_QUEUE_CAPACITY,
+            slog::Logger::root(slog::Discard, slog::o!()),
+        )
+        .unwrap();
+        let pubkey = PublicKey::default();
+
+        backend
+            .check_and_insert_block_proposal(&pubkey, Slot::new(3))
+            .unwrap();
+        backend
+            .check_and_insert_attestation(&pubkey, Epoch::new(1), Epoch::new(2))
+            .unwrap();
+
+        // Refused messages are not replicated.
+        assert!(backend
+            .check_and_insert_block_proposal(&pubkey, Slot::new(3))
+            .is_err());
+        assert_eq!(backend.dropped(), 0);
+        // Waits for the queued records to be written.
+        drop(backend);
+
+        let records: Vec<ReplicationRecord> = fs::read_to_string(&replica_path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                ReplicationRecord::Block {
+                    pubkey: pubkey.as_hex_string(),
+                    slot: Slot::new(3),
+                },
+                ReplicationRecord::Attestation {
+                    pubkey: pubkey.as_hex_string(),
+                    source_epoch: Epoch::new(1),
+                    target_epoch: Epoch::new(2),
+                },
+            ]
+        );
+    }
+}
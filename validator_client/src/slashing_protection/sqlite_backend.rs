@@ -0,0 +1,408 @@
+use super::{
+    check_attestation_watermark, check_block_watermark, NotSafe, SignedAttestation,
+    SlashingProtectionBackend, ValidatorHistory, ValidatorLocks, Watermark,
+};
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use types::{Epoch, PublicKey, Slot};
+
+/// The maximum time a connection will wait for another connection's transaction to complete.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A slashing protection backend which stores records in an SQLite database.
+///
+/// Each check-and-insert is performed inside an immediate transaction, so the database remains
+/// consistent if the process crashes or another process opens the same file.
+///
+/// Checks for different validators are independent, so they are spread across a pool of
+/// connections and only the checks for the same validator are serialized. The database is kept in
+/// WAL mode so that one connection's open transaction never blocks the others from reading, and
+/// writes are only serialized for as long as each insert takes to commit.
+pub struct SqliteBackend {
+    conns: Vec<Mutex<Connection>>,
+    locks: ValidatorLocks,
+}
+
+impl SqliteBackend {
+    /// Open the database at `path`, creating a new database if one does not already exist.
+    ///
+    /// Up to `parallelism` validators may be checked concurrently (a value of zero is treated as
+    /// one).
+    pub fn open(path: &Path, parallelism: usize) -> Result<Self, String> {
+        let conns = (0..parallelism.max(1))
+            .map(|_| {
+                let conn = Connection::open(path)
+                    .map_err(|e| format!("Unable to open slashing protection database: {:?}", e))?;
+                conn.busy_timeout(BUSY_TIMEOUT).map_err(|e| {
+                    format!("Unable to configure slashing protection database: {:?}", e)
+                })?;
+                Ok(Mutex::new(conn))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let conn = conns[0].lock().map_err(|_| "Lock poisoned")?;
+        // The journal mode is stored in the database file, so it applies to every connection.
+        conn.query_row("PRAGMA journal_mode = WAL", params![], |row| {
+            row.get::<_, String>(0)
+        })
+        .map_err(|e| format!("Unable to configure slashing protection database: {:?}", e))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS signed_blocks (
+                pubkey TEXT NOT NULL,
+                slot INTEGER NOT NULL,
+                UNIQUE (pubkey, slot)
+            );
+            CREATE TABLE IF NOT EXISTS signed_attestations (
+                pubkey TEXT NOT NULL,
+                source_epoch INTEGER NOT NULL,
+                target_epoch INTEGER NOT NULL,
+                UNIQUE (pubkey, target_epoch)
+            );
+            CREATE TABLE IF NOT EXISTS watermarks (
+                pubkey TEXT PRIMARY KEY,
+                slot INTEGER,
+                source_epoch INTEGER,
+                target_epoch INTEGER
+            );",
+        )
+        .map_err(|e| format!("Unable to create slashing protection tables: {:?}", e))?;
+        drop(conn);
+
+        Ok(Self {
+            conns,
+            locks: ValidatorLocks::default(),
+        })
+    }
+
+    /// Returns the first idle connection, or waits for the first connection if all are busy.
+    fn connection(&self) -> Result<MutexGuard<Connection>, NotSafe> {
+        self.conns
+            .iter()
+            .find_map(|conn| conn.try_lock().ok())
+            .map(Ok)
+            .unwrap_or_else(|| self.conns[0].lock())
+            .map_err(|_| NotSafe::BackendError("Lock poisoned".into()))
+    }
+
+    /// Records a block proposal for `pubkey`. The caller must hold the lock for `pubkey`.
+    fn insert_block_proposal(&self, pubkey: &PublicKey, slot: Slot) -> Result<(), NotSafe> {
+        let mut conn = self.connection()?;
+        let txn = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let pubkey = pubkey.as_hex_string();
+        let slot_i64 = slot.as_u64() as i64;
+
+        let existing: Option<i64> = txn
+            .query_row(
+                "SELECT slot FROM signed_blocks WHERE pubkey = ?1 AND slot = ?2",
+                params![pubkey, slot_i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        if existing.is_some() {
+            return Err(NotSafe::DoubleBlockProposal(slot));
+        }
+
+        check_block_watermark(&read_watermark(&txn, &pubkey)?, slot)?;
+
+        txn.execute(
+            "INSERT INTO signed_blocks (pubkey, slot) VALUES (?1, ?2)",
+            params![pubkey, slot_i64],
+        )?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Records an attestation for `pubkey`. The caller must hold the lock for `pubkey`.
+    fn insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe> {
+        let mut conn = self.connection()?;
+        let txn = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let pubkey = pubkey.as_hex_string();
+        let source_i64 = source.as_u64() as i64;
+        let target_i64 = target.as_u64() as i64;
+
+        let double_vote: Option<i64> = txn
+            .query_row(
+                "SELECT target_epoch FROM signed_attestations
+                 WHERE pubkey = ?1 AND target_epoch = ?2",
+                params![pubkey, target_i64],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        if double_vote.is_some() {
+            return Err(NotSafe::DoubleVote(target));
+        }
+
+        let surrounded_by_new: Option<(i64, i64)> = txn
+            .query_row(
+                "SELECT source_epoch, target_epoch FROM signed_attestations
+                 WHERE pubkey = ?1 AND source_epoch > ?2 AND target_epoch < ?3
+                 LIMIT 1",
+                params![pubkey, source_i64, target_i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((prev_source, prev_target)) = surrounded_by_new {
+            return Err(NotSafe::SurroundingVote {
+                source: Epoch::new(prev_source as u64),
+                target: Epoch::new(prev_target as u64),
+            });
+        }
+
+        let surrounding_new: Option<(i64, i64)> = txn
+            .query_row(
+                "SELECT source_epoch, target_epoch FROM signed_attestations
+                 WHERE pubkey = ?1 AND source_epoch < ?2 AND target_epoch > ?3
+                 LIMIT 1",
+                params![pubkey, source_i64, target_i64],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((prev_source, prev_target)) = surrounding_new {
+            return Err(NotSafe::SurroundedVote {
+                source: Epoch::new(prev_source as u64),
+                target: Epoch::new(prev_target as u64),
+            });
+        }
+
+        check_attestation_watermark(&read_watermark(&txn, &pubkey)?, source, target)?;
+
+        txn.execute(
+            "INSERT INTO signed_attestations (pubkey, source_epoch, target_epoch)
+             VALUES (?1, ?2, ?3)",
+            params![pubkey, source_i64, target_i64],
+        )?;
+        txn.commit()?;
+
+        Ok(())
+    }
+
+    /// Records the messages in `history` for `pubkey`, ignoring any with the same slot or target
+    /// epoch as an existing record. The caller must hold the lock for `pubkey`.
+    fn insert_history(
+        &self,
+        pubkey: &PublicKey,
+        history: &ValidatorHistory,
+    ) -> Result<(), NotSafe> {
+        let mut conn = self.connection()?;
+        let txn = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let pubkey = pubkey.as_hex_string();
+
+        for slot in &history.blocks {
+            txn.execute(
+                "INSERT OR IGNORE INTO signed_blocks (pubkey, slot) VALUES (?1, ?2)",
+                params![pubkey, slot.as_u64() as i64],
+            )?;
+        }
+
+        for attestation in &history.attestations {
+            txn.execute(
+                "INSERT OR IGNORE INTO signed_attestations (pubkey, source_epoch, target_epoch)
+                 VALUES (?1, ?2, ?3)",
+                params![
+                    pubkey,
+                    attestation.source_epoch.as_u64() as i64,
+                    attestation.target_epoch.as_u64() as i64
+                ],
+            )?;
+        }
+
+        let mut watermark = read_watermark(&txn, &pubkey)?;
+        watermark.merge(&history.watermark);
+        txn.execute(
+            "INSERT OR REPLACE INTO watermarks (pubkey, slot, source_epoch, target_epoch)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                pubkey,
+                watermark.slot.map(|slot| slot.as_u64() as i64),
+                watermark.source_epoch.map(|epoch| epoch.as_u64() as i64),
+                watermark.target_epoch.map(|epoch| epoch.as_u64() as i64)
+            ],
+        )?;
+        txn.commit()?;
+
+        Ok(())
+    }
+}
+
+impl SlashingProtectionBackend for SqliteBackend {
+    fn check_and_insert_block_proposal(
+        &self,
+        pubkey: &PublicKey,
+        slot: Slot,
+    ) -> Result<(), NotSafe> {
+        self.locks
+            .with_lock(pubkey, || self.insert_block_proposal(pubkey, slot))
+    }
+
+    fn check_and_insert_attestation(
+        &self,
+        pubkey: &PublicKey,
+        source: Epoch,
+        target: Epoch,
+    ) -> Result<(), NotSafe> {
+        if source > target {
+            return Err(NotSafe::InvalidAttestation { source, target });
+        }
+
+        self.locks
+            .with_lock(pubkey, || self.insert_attestation(pubkey, source, target))
+    }
+
+    fn export_histories(&self) -> Result<Vec<(String, ValidatorHistory)>, NotSafe> {
+        let conn = self.connection()?;
+        let mut histories: BTreeMap<String, ValidatorHistory> = BTreeMap::new();
+
+        let mut blocks = conn.prepare("SELECT pubkey, slot FROM signed_blocks ORDER BY slot")?;
+        let rows = blocks.query_map(params![], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?))
+        })?;
+        for row in rows {
+            let (pubkey, slot) = row?;
+            histories
+                .entry(pubkey)
+                .or_default()
+                .blocks
+                .push(Slot::new(slot as u64));
+        }
+
+        let mut attestations = conn.prepare(
+            "SELECT pubkey, source_epoch, target_epoch FROM signed_attestations
+             ORDER BY target_epoch",
+        )?;
+        let rows = attestations.query_map(params![], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, i64>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (pubkey, source, target) = row?;
+            histories
+                .entry(pubkey)
+                .or_default()
+                .attestations
+                .push(SignedAttestation {
+                    source_epoch: Epoch::new(source as u64),
+                    target_epoch: Epoch::new(target as u64),
+                });
+        }
+
+        let mut watermarks =
+            conn.prepare("SELECT pubkey, slot, source_epoch, target_epoch FROM watermarks")?;
+        let rows = watermarks.query_map(params![], |row| {
+            Ok((row.get::<_, String>(0)?, watermark_from_row(row)?))
+        })?;
+        for row in rows {
+            let (pubkey, watermark) = row?;
+            histories.entry(pubkey).or_default().watermark = watermark;
+        }
+
+        Ok(histories.into_iter().collect())
+    }
+
+    fn import_history(
+        &self,
+        pubkey: &PublicKey,
+        history: &ValidatorHistory,
+    ) -> Result<(), NotSafe> {
+        self.locks
+            .with_lock(pubkey, || self.insert_history(pubkey, history))
+    }
+}
+
+/// Returns the watermark of the messages imported for the hex-encoded `pubkey`.
+fn read_watermark(conn: &Connection, pubkey: &str) -> Result<Watermark, NotSafe> {
+    let watermark = conn
+        .query_row(
+            "SELECT pubkey, slot, source_epoch, target_epoch FROM watermarks WHERE pubkey = ?1",
+            params![pubkey],
+            watermark_from_row,
+        )
+        .optional()?;
+
+    Ok(watermark.unwrap_or_default())
+}
+
+/// Reads a watermark from a row of `pubkey, slot, source_epoch, target_epoch`.
+fn watermark_from_row(row: &rusqlite::Row) -> rusqlite::Result<Watermark> {
+    Ok(Watermark {
+        slot: row.get::<_, Option<i64>>(1)?.map(|s| Slot::new(s as u64)),
+        source_epoch: row.get::<_, Option<i64>>(2)?.map(|e| Epoch::new(e as u64)),
+        target_epoch: row.get::<_, Option<i64>>(3)?.map(|e| Epoch::new(e as u64)),
+    })
+}
+
+impl From<rusqlite::Error> for NotSafe {
+    fn from(e: rusqlite::Error) -> NotSafe {
+        NotSafe::BackendError(format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::tempdir;
+    use types::Keypair;
+
+    /// Shorter than `BUSY_TIMEOUT`, so a check that had to wait for another transaction fails.
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn different_validators_proceed_in_parallel() {
+        let dir = tempdir().unwrap();
+        let backend = Arc::new(
+            SqliteBackend::open(&dir.path().join("slashing_protection.sqlite"), 2).unwrap(),
+        );
+        let (a, b) = (Keypair::random().pk, Keypair::random().pk);
+        assert_ne!(a, b);
+        let (tx, rx) = channel();
+
+        backend
+            .locks
+            .with_lock(&a, || {
+                // Part way through a check for `a`, with a transaction open on one connection.
+                let mut conn = backend.connection()?;
+                let txn = conn.transaction_with_behavior(TransactionBehavior::Deferred)?;
+                txn.query_row("SELECT COUNT(*) FROM signed_blocks", params![], |row| {
+                    row.get::<_, i64>(0)
+                })?;
+
+                let backend = backend.clone();
+                let b = b.clone();
+                thread::spawn(move || {
+                    tx.send(backend.check_and_insert_block_proposal(&b, Slot::new(1)))
+                        .unwrap()
+                });
+
+                // The check for `b` completes on the other connection in the meantime.
+                assert_eq!(rx.recv_timeout(TIMEOUT), Ok(Ok(())));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(
+            backend.check_and_insert_block_proposal(&b, Slot::new(1)),
+            Err(NotSafe::DoubleBlockProposal(Slot::new(1)))
+        );
+    }
+}
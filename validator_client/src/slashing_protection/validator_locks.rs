@@ -0,0 +1,88 @@
+use super::NotSafe;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use types::PublicKey;
+
+/// Provides a lock for each validator, so that slashing protection checks for different
+/// validators may run in parallel whilst checks for the same validator are serialized.
+#[derive(Default)]
+pub struct ValidatorLocks {
+    locks: Mutex<HashMap<PublicKey, Arc<Mutex<()>>>>,
+}
+
+impl ValidatorLocks {
+    /// Runs `f` whilst holding the lock for `pubkey`.
+    pub fn with_lock<T, F>(&self, pubkey: &PublicKey, f: F) -> Result<T, NotSafe>
+    where
+        F: FnOnce() -> Result<T, NotSafe>,
+    {
+        let lock = self
+            .locks
+            .lock()
+            .map_err(|_| NotSafe::BackendError("Lock poisoned".into()))?
+            .entry(pubkey.clone())
+            .or_insert_with(<_>::default)
+            .clone();
+
+        let _guard = lock
+            .lock()
+            .map_err(|_| NotSafe::BackendError("Lock poisoned".into()))?;
+
+        f()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::Duration;
+    use types::Keypair;
+
+    const TIMEOUT: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn different_validators_run_in_parallel() {
+        let locks = Arc::new(ValidatorLocks::default());
+        let (a, b) = (Keypair::random().pk, Keypair::random().pk);
+        assert_ne!(a, b);
+        let (tx, rx) = channel();
+
+        locks
+            .with_lock(&a, || {
+                let locks = locks.clone();
+                let tx = tx.clone();
+                thread::spawn(move || locks.with_lock(&b, || Ok(tx.send(()).unwrap())));
+
+                // The other validator's lock is acquired whilst this one is held.
+                assert!(rx.recv_timeout(TIMEOUT * 10).is_ok());
+                Ok(())
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn same_validator_is_serialized() {
+        let locks = Arc::new(ValidatorLocks::default());
+        let a = PublicKey::default();
+        let (tx, rx) = channel();
+
+        let handle = locks
+            .with_lock(&a, || {
+                let locks = locks.clone();
+                let a = a.clone();
+                let tx = tx.clone();
+                let handle =
+                    thread::spawn(move || locks.with_lock(&a, || Ok(tx.send(()).unwrap())));
+
+                // The second check must wait for the first to complete.
+                assert!(rx.recv_timeout(TIMEOUT).is_err());
+                Ok(handle)
+            })
+            .unwrap();
+
+        assert!(rx.recv_timeout(TIMEOUT * 10).is_ok());
+        handle.join().unwrap().unwrap();
+    }
+}
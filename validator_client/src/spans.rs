@@ -0,0 +1,224 @@
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use types::Slot;
+
+/// A completed phase of the work for a slot.
+///
+/// Every span of a slot has the same trace id, so the duties fetch and the producers queued for
+/// the slot are linked, even though the producers finish after the slot's own span.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Span {
+    pub name: &'static str,
+    pub slot: Slot,
+    /// Unique within the process.
+    pub span_id: u64,
+    pub start_time_unix_nano: u64,
+    pub end_time_unix_nano: u64,
+    pub attributes: Vec<(&'static str, String)>,
+}
+
+impl Span {
+    /// The OpenTelemetry trace id of the slot, as 32 hex characters.
+    pub fn trace_id(&self) -> String {
+        format!("{:032x}", self.slot.as_u64())
+    }
+}
+
+/// Receives each span as it ends.
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: Span);
+}
+
+/// Appends each span to a file as a line of JSON, with the fields of an OpenTelemetry span, for
+/// a collector to read.
+pub struct FileSpanExporter {
+    file: Mutex<File>,
+}
+
+impl FileSpanExporter {
+    /// Opens the file at `path`, appending to it if it already exists.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Unable to open trace file {:?}: {:?}", path, e))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl SpanExporter for FileSpanExporter {
+    fn export(&self, span: Span) {
+        let mut attributes = serde_json::Map::new();
+        attributes.insert("slot".to_string(), json!(span.slot.as_u64()));
+        for (key, value) in &span.attributes {
+            attributes.insert(key.to_string(), json!(value));
+        }
+        let line = json!({
+            "traceId": span.trace_id(),
+            "spanId": format!("{:016x}", span.span_id),
+            "name": span.name,
+            "startTimeUnixNano": span.start_time_unix_nano,
+            "endTimeUnixNano": span.end_time_unix_nano,
+            "attributes": attributes,
+        });
+        // tracing is best-effort, so a failed write must not interrupt duties
+        if let Ok(mut file) = self.file.lock() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+/// Keeps every span in memory.
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemorySpanExporter {
+    pub spans: Mutex<Vec<Span>>,
+}
+
+#[cfg(test)]
+impl InMemorySpanExporter {
+    /// Returns the names of the exported spans, in the order they ended.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.spans.lock().unwrap().iter().map(|s| s.name).collect()
+    }
+}
+
+#[cfg(test)]
+impl SpanExporter for InMemorySpanExporter {
+    fn export(&self, span: Span) {
+        self.spans.lock().unwrap().push(span);
+    }
+}
+
+/// Starts spans, if an exporter is configured. The default tracer records nothing.
+#[derive(Clone, Default)]
+pub struct Tracer {
+    exporter: Option<Arc<dyn SpanExporter>>,
+    next_span_id: Arc<AtomicU64>,
+}
+
+impl Tracer {
+    pub fn new(exporter: Arc<dyn SpanExporter>) -> Self {
+        Self {
+            exporter: Some(exporter),
+            next_span_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+
+    /// Starts a span named `name` in the trace of `slot`. It ends when dropped.
+    pub fn span(&self, name: &'static str, slot: Slot) -> ActiveSpan {
+        let span_id = match self.exporter {
+            Some(_) => self.next_span_id.fetch_add(1, Ordering::Relaxed),
+            None => 0,
+        };
+        ActiveSpan {
+            exporter: self.exporter.clone(),
+            name,
+            slot,
+            span_id,
+            start_time: SystemTime::now(),
+            started: Instant::now(),
+            attributes: vec![],
+        }
+    }
+}
+
+/// A span which has not yet ended.
+pub struct ActiveSpan {
+    exporter: Option<Arc<dyn SpanExporter>>,
+    name: &'static str,
+    slot: Slot,
+    span_id: u64,
+    start_time: SystemTime,
+    started: Instant,
+    attributes: Vec<(&'static str, String)>,
+}
+
+impl ActiveSpan {
+    /// Adds an attribute to the span.
+    pub fn with_attribute(mut self, key: &'static str, value: String) -> Self {
+        if self.exporter.is_some() {
+            self.attributes.push((key, value));
+        }
+        self
+    }
+}
+
+impl Drop for ActiveSpan {
+    fn drop(&mut self) {
+        if let Some(exporter) = &self.exporter {
+            let start_time_unix_nano = self
+                .start_time
+                .duration_since(UNIX_EPOCH)
+                .map(|start| start.as_nanos() as u64)
+                .unwrap_or(0);
+            exporter.export(Span {
+                name: self.name,
+                slot: self.slot,
+                span_id: self.span_id,
+                start_time_unix_nano,
+                end_time_unix_nano: start_time_unix_nano + self.started.elapsed().as_nanos() as u64,
+                attributes: std::mem::replace(&mut self.attributes, vec![]),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn spans_exported_when_ended() {
+        let exporter = Arc::new(InMemorySpanExporter::default());
+        let tracer = Tracer::new(exporter.clone());
+
+        let outer = tracer.span("slot", Slot::new(4));
+        {
+            let _inner = tracer
+                .span("attestation", Slot::new(4))
+                .with_attribute("validator", "0xab".to_string());
+            assert!(exporter.names().is_empty());
+        }
+        assert_eq!(exporter.names(), vec!["attestation"]);
+        drop(outer);
+        assert_eq!(exporter.names(), vec!["attestation", "slot"]);
+
+        let spans = exporter.spans.lock().unwrap();
+        assert_eq!(spans[0].trace_id(), spans[1].trace_id());
+        assert_ne!(spans[0].span_id, spans[1].span_id);
+        assert!(spans[1].start_time_unix_nano <= spans[0].start_time_unix_nano);
+        assert!(spans[0].end_time_unix_nano <= spans[1].end_time_unix_nano);
+        assert_eq!(spans[0].attributes, vec![("validator", "0xab".to_string())]);
+    }
+
+    #[test]
+    fn file_exporter_writes_json_lines() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("spans.json");
+        let tracer = Tracer::new(Arc::new(FileSpanExporter::open(&path).unwrap()));
+
+        drop(tracer.span("duties_fetch", Slot::new(3)));
+        drop(tracer.span("slot", Slot::new(3)));
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = contents
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["name"], "duties_fetch");
+        assert_eq!(lines[0]["traceId"], format!("{:032x}", 3));
+        assert_eq!(lines[0]["attributes"]["slot"], 3);
+        assert_eq!(lines[1]["name"], "slot");
+    }
+}